@@ -5,20 +5,36 @@ CRATEDIR=`dirname $0`/..
 cat > src/ffi.rs << HERE
 #![allow(non_camel_case_types)]
 use casper_sys::cap_channel_t;
-use libc::sockaddr;
+use libc::{addrinfo, hostent, sockaddr};
 HERE
 
 bindgen --allowlist-function 'cap_bind' \
+	--allowlist-function 'cap_getaddrinfo' \
+	--allowlist-function 'cap_getnameinfo' \
+	--allowlist-function 'cap_gethostbyname' \
+	--allowlist-function 'cap_gethostbyname2' \
 	--allowlist-function 'cap_connect' \
 	--allowlist-function 'cap_net_limit_init' \
 	--allowlist-function 'cap_net_limit_bind' \
 	--allowlist-function 'cap_net_limit_connect' \
+	--allowlist-function 'cap_net_limit_name2addr' \
+	--allowlist-function 'cap_net_limit_addr2name' \
+	--allowlist-function 'cap_net_limit_addr2name_family' \
+	--allowlist-function 'cap_net_limit_name2addr_family' \
 	--allowlist-function 'cap_net_limit' \
+	--allowlist-function 'cap_net_free' \
+	--allowlist-item '.*CAPNET_ADDR2NAME' \
+	--allowlist-item '.*CAPNET_NAME2ADDR' \
+	--allowlist-item '.*CAPNET_DEPRECATED_ADDR2NAME' \
+	--allowlist-item '.*CAPNET_DEPRECATED_NAME2ADDR' \
 	--allowlist-item '.*CAPNET_BIND' \
 	--allowlist-item '.*CAPNET_CONNECT' \
+	--allowlist-item '.*CAPNET_CONNECTDNS' \
 	--opaque-type 'cap_net_limit_t' \
 	--blocklist-type 'cap_channel' \
 	--blocklist-type 'cap_channel_t' \
+	--blocklist-type 'addrinfo' \
+	--blocklist-type 'hostent' \
 	--blocklist-type 'sockaddr' \
 	--blocklist-type 'sa_family_t' \
 	${CRATEDIR}/bindgen/wrapper.h >> ${CRATEDIR}/src/ffi.rs
@@ -0,0 +1,564 @@
+// vim: tw=80
+//! In-process access control for bind and connect operations
+//!
+//! Casper limits can only be narrowed, never widened, and a violation is
+//! reported as a bare `ENOTCAPABLE`.  An [`AccessList`] installed with
+//! [`CapNetAgent::set_access_list`](crate::CapNetAgent::set_access_list) is a
+//! second layer of control that may be changed at any time, even from another
+//! thread, and its denials say which operation and address were refused.
+//!
+//! The access list is consulted before every bind or connect operation that
+//! targets an IPv4 or IPv6 address.  Rules are evaluated in order and the
+//! first match wins.  If no rule matches, the list's default action applies.
+//! Unix-domain sockets can be confined to directories with
+//! [`AccessList::allow_unix_prefix`].
+//!
+//! # Example
+//! ```
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{
+//!     acl::{AccessList, Action, Rule},
+//!     CasperExt,
+//!     LimitFlags,
+//! };
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! let acl = AccessList::new(Action::Deny);
+//! acl.push(Rule::allow(LimitFlags::CONNECT, "192.0.2.0/24".parse().unwrap())
+//!     .ports(443..=443));
+//! cap_net.set_access_list(Some(acl.clone()));
+//!
+//! // Later, perhaps in response to a configuration change
+//! acl.push(Rule::allow(LimitFlags::BIND, "127.0.0.1".parse().unwrap()));
+//! ```
+//!
+//! For the common case of allowing whole subnets and denying everything else,
+//! a [`CidrPolicy`] builds the access list.  Unlike Casper's own limits, which
+//! must list each address exactly, it works at subnet granularity.  For the
+//! opposite case, allowing everything except a few subnets, use a
+//! [`DenyList`].
+use std::{
+    error,
+    fmt,
+    io,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use crate::{CapNetAgent, LimitFlags};
+
+/// What to do with an operation that matches a [`Rule`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Let the operation proceed to Casper
+    Allow,
+    /// Fail the operation without consulting Casper
+    Deny,
+}
+
+/// An IPv4 or IPv6 network, like "192.0.2.0/24"
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IpNet {
+    addr:       IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// Create a network from an address and prefix length.
+    ///
+    /// Returns `None` if the prefix is too long for the address family.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<Self> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        (prefix_len <= max).then_some(IpNet { addr, prefix_len })
+    }
+
+    /// Does this network include `ip`?
+    ///
+    /// An IPv4-mapped IPv6 address, like `::ffff:192.0.2.1`, is in both the
+    /// IPv4 networks that include its IPv4 address and the IPv6 networks that
+    /// include it directly, since a dual-stack socket will reach the same
+    /// host either way.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.contains_exact(ip) || self.contains_exact(&ip.to_canonical())
+    }
+
+    fn contains_exact(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for IpNet {
+    /// A network containing only `addr`
+    fn from(addr: IpAddr) -> Self {
+        let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        IpNet { addr, prefix_len }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = io::Error;
+
+    /// Parse either a CIDR network like "192.0.2.0/24" or a bare address.
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid network {s:?}"),
+            )
+        };
+        match s.split_once('/') {
+            Some((addr, len)) => {
+                let addr = addr.parse().map_err(|_| invalid())?;
+                let len = len.parse().map_err(|_| invalid())?;
+                IpNet::new(addr, len).ok_or_else(invalid)
+            }
+            None => s.parse::<IpAddr>().map(IpNet::from).map_err(|_| invalid()),
+        }
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// A single access list entry
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    /// What to do with matching operations
+    pub action: Action,
+    /// Which operations this rule applies to
+    pub ops:    LimitFlags,
+    /// Which addresses this rule applies to
+    pub net:    IpNet,
+    /// Which ports this rule applies to.  `None` means all ports.
+    pub ports:  Option<RangeInclusive<u16>>,
+}
+
+impl Rule {
+    /// A rule that allows `ops` on any address within `net`
+    pub fn allow(ops: LimitFlags, net: IpNet) -> Self {
+        Rule {
+            action: Action::Allow,
+            ops,
+            net,
+            ports: None,
+        }
+    }
+
+    /// A rule that denies `ops` on any address within `net`
+    pub fn deny(ops: LimitFlags, net: IpNet) -> Self {
+        Rule {
+            action: Action::Deny,
+            ops,
+            net,
+            ports: None,
+        }
+    }
+
+    /// Restrict this rule to the given range of ports.
+    pub fn ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.ports = Some(ports);
+        self
+    }
+
+    fn matches(&self, op: LimitFlags, addr: &SocketAddr) -> bool {
+        self.ops.intersects(op)
+            && self.net.contains(&addr.ip())
+            && self
+                .ports
+                .as_ref()
+                .map_or(true, |p| p.contains(&addr.port()))
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    rules:         Vec<Rule>,
+    default:       Action,
+    unix_prefixes: Vec<(LimitFlags, PathBuf)>,
+}
+
+/// An ordered list of [`Rule`]s that may be updated at runtime.
+///
+/// Cloning an `AccessList` produces another handle to the same rules, so an
+/// application can keep one handle to update the rules while the agent
+/// consults another.
+#[derive(Clone, Debug)]
+pub struct AccessList(Arc<RwLock<Inner>>);
+
+impl AccessList {
+    /// Create an empty access list with the given default action.
+    pub fn new(default: Action) -> Self {
+        AccessList(Arc::new(RwLock::new(Inner {
+            rules: Vec::new(),
+            default,
+            unix_prefixes: Vec::new(),
+        })))
+    }
+
+    /// Append a rule to the end of the list.
+    pub fn push(&self, rule: Rule) {
+        self.0.write().unwrap().rules.push(rule);
+    }
+
+    /// Insert a rule at position `index`, shifting later rules down.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is greater than the number of rules.
+    pub fn insert(&self, index: usize, rule: Rule) {
+        self.0.write().unwrap().rules.insert(index, rule);
+    }
+
+    /// Replace all of the rules at once.
+    pub fn set_rules(&self, rules: Vec<Rule>) {
+        self.0.write().unwrap().rules = rules;
+    }
+
+    /// Remove all of the rules.
+    pub fn clear(&self) {
+        self.0.write().unwrap().rules.clear();
+    }
+
+    /// A copy of the current rules
+    pub fn rules(&self) -> Vec<Rule> {
+        self.0.read().unwrap().rules.clone()
+    }
+
+    /// Change the action for operations that match no rule.
+    pub fn set_default(&self, default: Action) {
+        self.0.write().unwrap().default = default;
+    }
+
+    /// Allow `ops` on unix-domain sockets whose paths lie under `prefix`.
+    ///
+    /// Unix-domain sockets aren't subject to the list's rules or its default
+    /// action.  Instead, once any prefix has been allowed for an operation,
+    /// that operation is denied on every path outside of all such prefixes.
+    /// Prefixes match whole path components, so `/var/run/app` matches
+    /// `/var/run/app/sock` but not `/var/run/application`.
+    pub fn allow_unix_prefix<P: Into<PathBuf>>(
+        &self,
+        ops: LimitFlags,
+        prefix: P,
+    ) {
+        self.0
+            .write()
+            .unwrap()
+            .unix_prefixes
+            .push((ops, prefix.into()));
+    }
+
+    /// Remove all unix-domain path prefixes, once again allowing operations on
+    /// any path.
+    pub fn clear_unix_prefixes(&self) {
+        self.0.write().unwrap().unix_prefixes.clear();
+    }
+
+    /// Decide whether operation `op` on the unix-domain socket at `path` is
+    /// allowed.
+    ///
+    /// If prefixes are configured for `op`, a path containing `..` is always
+    /// denied, since it could escape from its prefix.
+    pub fn check_path(&self, op: LimitFlags, path: &Path) -> Action {
+        let inner = self.0.read().unwrap();
+        let mut prefixes = inner
+            .unix_prefixes
+            .iter()
+            .filter(|(ops, _)| ops.intersects(op))
+            .map(|(_, prefix)| prefix)
+            .peekable();
+        if prefixes.peek().is_none() {
+            Action::Allow
+        } else if path.components().any(|c| c == Component::ParentDir) {
+            Action::Deny
+        } else if prefixes.any(|prefix| path.starts_with(prefix)) {
+            Action::Allow
+        } else {
+            Action::Deny
+        }
+    }
+
+    /// Decide whether operation `op` on `addr` is allowed.
+    pub fn check(&self, op: LimitFlags, addr: &SocketAddr) -> Action {
+        let inner = self.0.read().unwrap();
+        inner
+            .rules
+            .iter()
+            .find(|r| r.matches(op, addr))
+            .map_or(inner.default, |r| r.action)
+    }
+}
+
+/// Subnets that binds and connects are confined to
+///
+/// Every bind or connect to an IPv4 or IPv6 address outside of the allowed
+/// networks is denied without consulting Casper.  Unix-domain sockets aren't
+/// affected.
+///
+/// # Example
+/// ```
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{acl::CidrPolicy, CasperExt};
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+///
+/// let mut policy = CidrPolicy::new();
+/// policy
+///     .allow_connect("10.0.0.0/8".parse().unwrap())
+///     .allow_bind("127.0.0.0/8".parse().unwrap());
+/// policy.apply(&mut cap_net);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CidrPolicy {
+    rules: Vec<Rule>,
+}
+
+/// The most Casper entries that [`CidrPolicy::apply_and_limit`] will create
+/// for one operation
+const MAX_EXPANSION: usize = 1024;
+
+impl CidrPolicy {
+    /// A policy that denies every bind and connect.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow binding to any address within `net`.
+    pub fn allow_bind(&mut self, net: IpNet) -> &mut Self {
+        self.rules.push(Rule::allow(LimitFlags::BIND, net));
+        self
+    }
+
+    /// Allow binding to any address within `net`, but only on `ports`.
+    ///
+    /// This suits servers that pick their ports from a pool, like
+    /// `8000..=8100`.
+    pub fn allow_bind_ports(
+        &mut self,
+        net: IpNet,
+        ports: RangeInclusive<u16>,
+    ) -> &mut Self {
+        self.rules
+            .push(Rule::allow(LimitFlags::BIND, net).ports(ports));
+        self
+    }
+
+    /// Allow connecting to any address within `net`.
+    pub fn allow_connect(&mut self, net: IpNet) -> &mut Self {
+        self.rules.push(Rule::allow(LimitFlags::CONNECT, net));
+        self
+    }
+
+    /// Allow connecting to any address within `net`, but only on `ports`.
+    pub fn allow_connect_ports(
+        &mut self,
+        net: IpNet,
+        ports: RangeInclusive<u16>,
+    ) -> &mut Self {
+        self.rules
+            .push(Rule::allow(LimitFlags::CONNECT, net).ports(ports));
+        self
+    }
+
+    /// The equivalent [`AccessList`], whose default action is to deny.
+    ///
+    /// Additional rules may be added to it before it is installed.
+    pub fn to_access_list(&self) -> AccessList {
+        let acl = AccessList::new(Action::Deny);
+        acl.set_rules(self.rules.clone());
+        acl
+    }
+
+    /// Confine `agent` to this policy, replacing any existing access list.
+    pub fn apply(&self, agent: &mut CapNetAgent) {
+        agent.set_access_list(Some(self.to_access_list()));
+    }
+
+    /// The exact socket addresses that `op` is allowed on, if there are few
+    /// enough to list them all.
+    fn expand(&self, op: LimitFlags) -> Option<Vec<SocketAddr>> {
+        let mut addrs = Vec::new();
+        for rule in self.rules.iter().filter(|r| r.ops.contains(op)) {
+            if IpNet::from(rule.net.addr) != rule.net {
+                return None;
+            }
+            let ports = rule.ports.clone()?;
+            if addrs.len() + ports.len() > MAX_EXPANSION {
+                return None;
+            }
+            addrs.extend(ports.map(|p| SocketAddr::new(rule.net.addr, p)));
+        }
+        Some(addrs)
+    }
+
+    /// Like [`CidrPolicy::apply`], but also enforce as much of the policy as
+    /// possible with Casper limits.
+    ///
+    /// Only the operations in `flags` will be permitted by Casper.  For each
+    /// of bind and connect, if every rule names a single address and a port
+    /// range, and there are few enough combinations, each is added to the
+    /// Casper limit.  Otherwise that operation is enforced only by the access
+    /// list.
+    pub fn apply_and_limit(
+        &self,
+        agent: &mut CapNetAgent,
+        flags: LimitFlags,
+    ) -> io::Result<()> {
+        let bind = self.expand(LimitFlags::BIND);
+        let connect = self.expand(LimitFlags::CONNECT);
+        let mut limit = agent.limit(flags)?;
+        if flags.contains(LimitFlags::BIND) {
+            for addr in bind.into_iter().flatten() {
+                limit.bind_std(addr);
+            }
+        }
+        if flags.contains(LimitFlags::CONNECT) {
+            for addr in connect.into_iter().flatten() {
+                limit.connect_std(addr);
+            }
+        }
+        limit.limit()?;
+        self.apply(agent);
+        Ok(())
+    }
+}
+
+/// A set of subnets to deny, allowing everything else
+///
+/// Casper limits can only list what is allowed.  A `DenyList` expresses
+/// policies like "connect anywhere except the cloud metadata service" by
+/// installing an [`AccessList`] whose default action is to allow.  Casper's
+/// own limits, if any, still apply to whatever the list allows.
+///
+/// # Example
+/// ```
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{acl::DenyList, CasperExt};
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+///
+/// let mut deny = DenyList::new();
+/// deny.deny_connect("169.254.169.254".parse().unwrap())
+///     .deny_connect("fd00:ec2::254".parse().unwrap());
+/// deny.apply(&mut cap_net);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DenyList {
+    rules: Vec<Rule>,
+}
+
+impl DenyList {
+    /// A list that denies nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deny binding to any address within `net`.
+    pub fn deny_bind(&mut self, net: IpNet) -> &mut Self {
+        self.rules.push(Rule::deny(LimitFlags::BIND, net));
+        self
+    }
+
+    /// Deny binding to any address within `net`, but only on `ports`.
+    pub fn deny_bind_ports(
+        &mut self,
+        net: IpNet,
+        ports: RangeInclusive<u16>,
+    ) -> &mut Self {
+        self.rules
+            .push(Rule::deny(LimitFlags::BIND, net).ports(ports));
+        self
+    }
+
+    /// Deny connecting to any address within `net`.
+    pub fn deny_connect(&mut self, net: IpNet) -> &mut Self {
+        self.rules.push(Rule::deny(LimitFlags::CONNECT, net));
+        self
+    }
+
+    /// Deny connecting to any address within `net`, but only on `ports`.
+    pub fn deny_connect_ports(
+        &mut self,
+        net: IpNet,
+        ports: RangeInclusive<u16>,
+    ) -> &mut Self {
+        self.rules
+            .push(Rule::deny(LimitFlags::CONNECT, net).ports(ports));
+        self
+    }
+
+    /// The equivalent [`AccessList`], whose default action is to allow.
+    ///
+    /// Additional rules may be added to it before it is installed.
+    pub fn to_access_list(&self) -> AccessList {
+        let acl = AccessList::new(Action::Allow);
+        acl.set_rules(self.rules.clone());
+        acl
+    }
+
+    /// Confine `agent` to this list, replacing any existing access list.
+    pub fn apply(&self, agent: &mut CapNetAgent) {
+        agent.set_access_list(Some(self.to_access_list()));
+    }
+}
+
+/// The error returned when an [`AccessList`] denies an operation.
+///
+/// The std and tokio interfaces return this wrapped in an `io::Error` of
+/// kind `PermissionDenied`, so it may be recovered with
+/// `io::Error::get_ref`.  The low-level interfaces that return a nix `Errno`
+/// report `EACCES` instead.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AccessDenied {
+    /// The denied operation, either `LimitFlags::BIND` or
+    /// `LimitFlags::CONNECT`
+    pub op:   LimitFlags,
+    /// The address that the operation targeted
+    pub addr: SocketAddr,
+}
+
+impl fmt::Display for AccessDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = if self.op == LimitFlags::BIND {
+            "bind to"
+        } else {
+            "connect to"
+        };
+        write!(f, "{} {} denied by access list", op, self.addr)
+    }
+}
+
+impl error::Error for AccessDenied {}
+
+impl From<AccessDenied> for io::Error {
+    fn from(e: AccessDenied) -> Self {
+        io::Error::new(io::ErrorKind::PermissionDenied, e)
+    }
+}
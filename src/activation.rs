@@ -0,0 +1,229 @@
+// vim: tw=80
+//! Socket activation, with a `cap_bind` fallback
+//!
+//! A supervisor or rc script may bind a daemon's sockets itself and pass them
+//! down as inherited file descriptors, using the same environment convention
+//! as systemd: `LISTEN_FDS` gives the number of descriptors, starting at 3,
+//! `LISTEN_PID` optionally names the process that they're meant for, and
+//! `LISTEN_FDNAMES` optionally gives each a colon-separated name.
+//!
+//! [`Inherited`] collects any such descriptors.  Its getters return an
+//! inherited socket if one matches the requested address, and otherwise bind a
+//! new one with `cap_bind`.  So the same binary works whether it was
+//! socket-activated or not.
+//!
+//! # Example
+//! ```no_run
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, activation::Inherited};
+//!
+//! // Must happen before any other threads are spawned
+//! let mut inherited = Inherited::from_env().unwrap();
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let listener = inherited.tcp_listener(&mut cap_net, "0.0.0.0:80").unwrap();
+//! ```
+use std::{
+    env,
+    io,
+    net::{SocketAddr, TcpListener, UdpSocket},
+    os::{
+        fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixListener,
+    },
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use nix::sys::socket::{
+    getsockname,
+    getsockopt,
+    sockopt::SockType as SockTypeOpt,
+    SockType,
+    SockaddrStorage,
+};
+
+use crate::{
+    sockaddr,
+    std::{TcpListenerExt, UdpSocketExt, UnixListenerExt},
+    CapNetAgent,
+    CapToSocketAddrs,
+};
+
+/// The first inherited descriptor, after stdin, stdout, and stderr
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Ensures that inherited descriptors are only ever taken once
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+struct InheritedFd {
+    name: Option<String>,
+    fd:   OwnedFd,
+}
+
+/// Sockets inherited from a supervisor
+#[derive(Debug, Default)]
+pub struct Inherited {
+    fds: Vec<InheritedFd>,
+}
+
+impl Inherited {
+    /// Take ownership of any sockets passed in the environment.
+    ///
+    /// The `LISTEN_*` environment variables are removed, so that child
+    /// processes don't mistake the descriptors for their own.  Only the first
+    /// call in a process can find any sockets; later calls return an empty
+    /// set.  Because it modifies the environment, this should be called before
+    /// any other threads are started.
+    ///
+    /// Fails with `EBADF` if `LISTEN_FDS` counts more descriptors than are
+    /// actually open.  In that case, none of them are taken.
+    pub fn from_env() -> io::Result<Self> {
+        if TAKEN.swap(true, Ordering::SeqCst) {
+            return Ok(Self::default());
+        }
+        let nfds = env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|s| s.parse::<RawFd>().ok())
+            .unwrap_or(0);
+        let for_us = env::var("LISTEN_PID")
+            .ok()
+            .and_then(|s| s.parse::<u32>().ok())
+            .map_or(true, |pid| pid == std::process::id());
+        let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDNAMES");
+        if !for_us || nfds <= 0 {
+            return Ok(Self::default());
+        }
+        let end = LISTEN_FDS_START.checked_add(nfds).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "LISTEN_FDS is too large",
+            )
+        })?;
+        // Check that every descriptor is open before taking ownership of any,
+        // lest a wrong count make us close somebody else's.
+        for raw in LISTEN_FDS_START..end {
+            if unsafe { libc::fcntl(raw, libc::F_GETFD) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let mut names = names.split(':');
+        let fds = (LISTEN_FDS_START..end)
+            .map(|raw| {
+                let name = names.next().filter(|n| !n.is_empty());
+                // Safe because LISTEN_FDS says that these descriptors were
+                // passed to us, F_GETFD confirmed that they're open, and TAKEN
+                // ensures we only claim them once.
+                let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+                let r = unsafe {
+                    libc::fcntl(raw, libc::F_SETFD, libc::FD_CLOEXEC)
+                };
+                if r < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(InheritedFd {
+                    name: name.map(str::to_owned),
+                    fd,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Inherited { fds })
+    }
+
+    /// The number of inherited sockets not yet claimed
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    /// Have all inherited sockets been claimed, or were there none?
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// Claim an inherited socket by its `LISTEN_FDNAMES` name.
+    pub fn take_named(&mut self, name: &str) -> Option<OwnedFd> {
+        let i = self
+            .fds
+            .iter()
+            .position(|f| f.name.as_deref() == Some(name))?;
+        Some(self.fds.remove(i).fd)
+    }
+
+    /// Claim the first inherited socket of type `ty` whose local address
+    /// satisfies `pred`.
+    fn take_matching<F>(&mut self, ty: SockType, pred: F) -> Option<OwnedFd>
+    where
+        F: Fn(&SockaddrStorage) -> bool,
+    {
+        let i = self.fds.iter().position(|f| {
+            getsockopt(&f.fd.as_fd(), SockTypeOpt).ok() == Some(ty)
+                && getsockname::<SockaddrStorage>(f.fd.as_raw_fd())
+                    .is_ok_and(|ss| pred(&ss))
+        })?;
+        Some(self.fds.remove(i).fd)
+    }
+
+    fn take_inet(
+        &mut self,
+        ty: SockType,
+        addrs: &[SocketAddr],
+    ) -> Option<OwnedFd> {
+        self.take_matching(ty, |ss| {
+            sockaddr::to_std(ss).is_some_and(|a| addrs.contains(&a))
+        })
+    }
+
+    /// Return an inherited TCP listener bound to one of `addrs`, or else bind
+    /// a new one with [`TcpListenerExt::cap_bind`].
+    pub fn tcp_listener<A: CapToSocketAddrs>(
+        &mut self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<TcpListener> {
+        let addrs = addrs.cap_to_socket_addrs(agent)?;
+        match self.take_inet(SockType::Stream, &addrs) {
+            Some(fd) => Ok(TcpListener::from(fd)),
+            None => TcpListener::cap_bind(agent, &addrs[..]),
+        }
+    }
+
+    /// Return an inherited UDP socket bound to one of `addrs`, or else bind a
+    /// new one with [`UdpSocketExt::cap_bind`].
+    pub fn udp_socket<A: CapToSocketAddrs>(
+        &mut self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<UdpSocket> {
+        let addrs = addrs.cap_to_socket_addrs(agent)?;
+        match self.take_inet(SockType::Datagram, &addrs) {
+            Some(fd) => Ok(UdpSocket::from(fd)),
+            None => UdpSocket::cap_bind(agent, &addrs[..]),
+        }
+    }
+
+    /// Return an inherited unix-domain listener bound to `path`, or else bind
+    /// a new one with [`UnixListenerExt::cap_bind`].
+    pub fn unix_listener<P: AsRef<Path>>(
+        &mut self,
+        agent: &mut CapNetAgent,
+        path: P,
+    ) -> io::Result<UnixListener> {
+        let path = path.as_ref();
+        let fd = self.take_matching(SockType::Stream, |ss| {
+            ss.as_unix_addr().and_then(|u| u.path()) == Some(path)
+        });
+        match fd {
+            Some(fd) => Ok(UnixListener::from(fd)),
+            None => UnixListener::cap_bind(agent, path),
+        }
+    }
+}
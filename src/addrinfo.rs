@@ -0,0 +1,249 @@
+// vim: tw=80
+//! Options for `getaddrinfo`-style name resolution
+//!
+//! By default [`CapNetAgent::resolve`](crate::CapNetAgent::resolve) returns
+//! every address of a host, of either family, once each.  Pass an
+//! [`AddrInfoHints`] to
+//! [`CapNetAgent::resolve_with`](crate::CapNetAgent::resolve_with) to filter
+//! them, or to change how the name is interpreted.
+//!
+//! # Example
+//! ```no_run
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, addrinfo::{AddrInfoFlags, AddrInfoHints}};
+//! use nix::sys::socket::{AddressFamily, SockType};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let hints = AddrInfoHints::new()
+//!     .family(AddressFamily::Inet6)
+//!     .socktype(SockType::Stream)
+//!     .flags(AddrInfoFlags::ADDRCONFIG);
+//! let addrs = cap_net.resolve_with("www.freebsd.org", 443, &hints).unwrap();
+//! ```
+//!
+//! To reorder or filter results by address family after the fact, as
+//! [`Resolver`](crate::resolver::Resolver) and
+//! [`TcpStreamBuilder`](crate::std::TcpStreamBuilder) can, use a
+//! [`FamilyPreference`].
+//!
+//! Callers that need more than the socket addresses, such as the socket type
+//! and protocol of each result, can use
+//! [`CapNetAgent::getaddrinfo`](crate::CapNetAgent::getaddrinfo) instead.
+//! It returns an [`AddrInfoList`] that iterates over the raw results.
+use std::{ffi::CStr, net::SocketAddr};
+
+use bitflags::bitflags;
+use nix::sys::socket::{
+    AddressFamily,
+    SockProtocol,
+    SockType,
+    SockaddrLike,
+    SockaddrStorage,
+};
+
+bitflags! {
+    /// The `AI_*` flags of
+    /// [getaddrinfo(3)](https://man.freebsd.org/cgi/man.cgi?query=getaddrinfo)
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct AddrInfoFlags: libc::c_int {
+        /// Return addresses suitable for binding, if the host is empty
+        const PASSIVE = libc::AI_PASSIVE;
+        /// Look up the host's canonical name
+        const CANONNAME = libc::AI_CANONNAME;
+        /// The host must be a numeric address; never use DNS
+        const NUMERICHOST = libc::AI_NUMERICHOST;
+        /// Only return addresses of families configured on a local interface
+        const ADDRCONFIG = libc::AI_ADDRCONFIG;
+        /// With an `Inet6` family, return IPv4-mapped addresses if there
+        /// are no IPv6 ones
+        const V4MAPPED = libc::AI_V4MAPPED;
+        /// With `V4MAPPED`, return IPv4-mapped addresses as well as IPv6 ones
+        const ALL = libc::AI_ALL;
+    }
+}
+
+/// Restrictions on the results of a name lookup, like the `hints` argument
+/// of getaddrinfo(3).
+///
+/// Every field defaults to unrestricted.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AddrInfoHints {
+    family:   Option<AddressFamily>,
+    socktype: Option<SockType>,
+    protocol: Option<SockProtocol>,
+    flags:    AddrInfoFlags,
+}
+
+impl AddrInfoHints {
+    /// Hints that permit any result.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only return addresses of this family, usually `Inet` or `Inet6`.
+    pub fn family(mut self, family: AddressFamily) -> Self {
+        self.family = Some(family);
+        self
+    }
+
+    /// Only return addresses usable with this socket type.
+    ///
+    /// Without a socket type, each address may appear once per protocol.
+    pub fn socktype(mut self, socktype: SockType) -> Self {
+        self.socktype = Some(socktype);
+        self
+    }
+
+    /// Only return addresses usable with this protocol.
+    pub fn protocol(mut self, protocol: SockProtocol) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Set the `AI_*` flags.
+    pub fn flags(mut self, flags: AddrInfoFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// The equivalent C structure.  `extra` flags are added to the ones set.
+    pub(crate) fn to_raw(self, extra: libc::c_int) -> libc::addrinfo {
+        // Safe because addrinfo is a plain C struct
+        let mut hints: libc::addrinfo = unsafe { std::mem::zeroed() };
+        hints.ai_flags = self.flags.bits() | extra;
+        hints.ai_family = self.family.map_or(libc::AF_UNSPEC, |f| f as i32);
+        hints.ai_socktype = self.socktype.map_or(0, |t| t as i32);
+        hints.ai_protocol = self.protocol.map_or(0, |p| p as i32);
+        hints
+    }
+}
+
+/// Which address families to use, and in what order
+///
+/// getaddrinfo's own order follows RFC 6724 and the system's
+/// [ip6addrctl(8)](https://man.freebsd.org/cgi/man.cgi?query=ip6addrctl)
+/// policy, which isn't always what a sandboxed service wants.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum FamilyPreference {
+    /// Keep the resolver's order
+    #[default]
+    Any,
+    /// Put IPv6 addresses first, otherwise keeping the resolver's order
+    PreferIpv6,
+    /// Put IPv4 addresses first, otherwise keeping the resolver's order
+    PreferIpv4,
+    /// Discard all but IPv4 addresses
+    Ipv4Only,
+    /// Discard all but IPv6 addresses
+    Ipv6Only,
+}
+
+impl FamilyPreference {
+    /// Reorder or filter `addrs` according to this preference.
+    pub fn apply(self, addrs: &mut Vec<SocketAddr>) {
+        match self {
+            FamilyPreference::Any => (),
+            FamilyPreference::PreferIpv6 => addrs.sort_by_key(|a| a.is_ipv4()),
+            FamilyPreference::PreferIpv4 => addrs.sort_by_key(|a| a.is_ipv6()),
+            FamilyPreference::Ipv4Only => addrs.retain(SocketAddr::is_ipv4),
+            FamilyPreference::Ipv6Only => addrs.retain(SocketAddr::is_ipv6),
+        }
+    }
+}
+
+/// A single result of a name lookup
+#[derive(Clone, Copy, Debug)]
+pub struct AddrInfo {
+    /// The address family
+    pub family:   AddressFamily,
+    /// The socket type that this address is usable with, if known
+    pub socktype: Option<SockType>,
+    /// The protocol that this address is usable with, or 0 for any
+    pub protocol: libc::c_int,
+    /// The socket address itself
+    pub addr:     SockaddrStorage,
+}
+
+/// The results of
+/// [`CapNetAgent::getaddrinfo`](crate::CapNetAgent::getaddrinfo), in the
+/// resolver's order.
+///
+/// Each result is converted only as it is iterated.  The underlying list is
+/// freed on drop.
+#[derive(Debug)]
+pub struct AddrInfoList {
+    head: *mut libc::addrinfo,
+    cur:  *const libc::addrinfo,
+}
+
+// Safe because the list is exclusively owned, and never shared with C code.
+unsafe impl Send for AddrInfoList {}
+
+impl AddrInfoList {
+    /// Take ownership of a list returned by getaddrinfo(3).
+    ///
+    /// # Safety
+    ///
+    /// `head` must be null or a valid list that nothing else will free.
+    pub(crate) unsafe fn from_raw(head: *mut libc::addrinfo) -> Self {
+        AddrInfoList { head, cur: head }
+    }
+
+    /// The host's canonical name.
+    ///
+    /// Only present if the lookup used [`AddrInfoFlags::CANONNAME`].
+    pub fn canonname(&self) -> Option<&str> {
+        if self.head.is_null() {
+            return None;
+        }
+        // Safe because we own the list.  getaddrinfo only sets ai_canonname
+        // on the first entry.
+        let name = unsafe { (*self.head).ai_canonname };
+        if name.is_null() {
+            None
+        } else {
+            unsafe { CStr::from_ptr(name) }.to_str().ok()
+        }
+    }
+}
+
+impl Iterator for AddrInfoList {
+    type Item = AddrInfo;
+
+    fn next(&mut self) -> Option<AddrInfo> {
+        while !self.cur.is_null() {
+            // Safe because getaddrinfo returned a valid list, which we own
+            let info = unsafe { &*self.cur };
+            self.cur = info.ai_next;
+            // Safe because ai_addr points to a sockaddr of ai_addrlen bytes
+            let addr = unsafe {
+                SockaddrStorage::from_raw(info.ai_addr, Some(info.ai_addrlen))
+            };
+            let Some(addr) = addr else { continue };
+            let Some(family) = addr.family() else {
+                continue;
+            };
+            return Some(AddrInfo {
+                family,
+                socktype: SockType::try_from(info.ai_socktype).ok(),
+                protocol: info.ai_protocol,
+                addr,
+            });
+        }
+        None
+    }
+}
+
+impl Drop for AddrInfoList {
+    fn drop(&mut self) {
+        if !self.head.is_null() {
+            // Safe because we own the list
+            unsafe { libc::freeaddrinfo(self.head) };
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// vim: tw=80
+//! Batched datagram I/O with `sendmmsg(2)` and `recvmmsg(2)`
+//!
+//! High-packet-rate services can amortize the cost of system calls by sending
+//! or receiving many datagrams at once.  Both functions work in capability
+//! mode, as long as the socket has been connected with `cap_connect` before
+//! sending.
+//!
+//! # Example
+//! ```no_run
+//! use std::net::UdpSocket;
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, batch, std::UdpSocketExt};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let socket = UdpSocket::cap_bind(&mut cap_net, "0.0.0.0:0").unwrap();
+//! socket.cap_connect(&mut cap_net, "192.0.2.1:8125").unwrap();
+//! let sent = batch::send(&socket, &[b"a:1|c", b"b:2|c"]).unwrap();
+//! ```
+use std::{
+    io,
+    mem,
+    net::SocketAddr,
+    os::fd::{AsFd, AsRawFd},
+    ptr,
+};
+
+use crate::sockaddr;
+
+/// Metadata about one datagram received by [`recv`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Received {
+    /// Number of bytes received
+    pub len:       usize,
+    /// The sender's address, if it was an IPv4 or IPv6 socket
+    pub addr:      Option<SocketAddr>,
+    /// Was the datagram too large for its buffer?
+    pub truncated: bool,
+}
+
+/// Send each of `bufs` as a separate datagram on a connected socket.
+///
+/// Returns the number of datagrams sent, which may be fewer than `bufs.len()`.
+pub fn send<F, B>(sock: &F, bufs: &[B]) -> io::Result<usize>
+where
+    F: AsFd,
+    B: AsRef<[u8]>,
+{
+    let mut iovs = bufs
+        .iter()
+        .map(|b| libc::iovec {
+            iov_base: b.as_ref().as_ptr() as *mut libc::c_void,
+            iov_len:  b.as_ref().len(),
+        })
+        .collect::<Vec<_>>();
+    let mut msgs = iovs
+        .iter_mut()
+        .map(|iov| {
+            // Safe because mmsghdr is a plain C struct
+            let mut m: libc::mmsghdr = unsafe { mem::zeroed() };
+            m.msg_hdr.msg_iov = iov;
+            m.msg_hdr.msg_iovlen = 1;
+            m
+        })
+        .collect::<Vec<_>>();
+    let r = unsafe {
+        libc::sendmmsg(
+            sock.as_fd().as_raw_fd(),
+            msgs.as_mut_ptr(),
+            msgs.len(),
+            0,
+        )
+    };
+    if r < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(r as usize)
+    }
+}
+
+/// Receive up to `bufs.len()` datagrams, one into each buffer.
+///
+/// Blocks until at least one datagram is available, unless the socket is
+/// nonblocking, and then returns as many as are immediately available.
+pub fn recv<F>(sock: &F, bufs: &mut [&mut [u8]]) -> io::Result<Vec<Received>>
+where
+    F: AsFd,
+{
+    let mut iovs = bufs
+        .iter_mut()
+        .map(|b| libc::iovec {
+            iov_base: b.as_mut_ptr().cast(),
+            iov_len:  b.len(),
+        })
+        .collect::<Vec<_>>();
+    // Safe because sockaddr_storage is a plain C struct
+    let mut addrs: Vec<libc::sockaddr_storage> =
+        vec![unsafe { mem::zeroed() }; bufs.len()];
+    let mut msgs = iovs
+        .iter_mut()
+        .zip(addrs.iter_mut())
+        .map(|(iov, ss)| {
+            // Safe because mmsghdr is a plain C struct
+            let mut m: libc::mmsghdr = unsafe { mem::zeroed() };
+            m.msg_hdr.msg_name = (ss as *mut libc::sockaddr_storage).cast();
+            m.msg_hdr.msg_namelen =
+                mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            m.msg_hdr.msg_iov = iov;
+            m.msg_hdr.msg_iovlen = 1;
+            m
+        })
+        .collect::<Vec<_>>();
+    let r = unsafe {
+        libc::recvmmsg(
+            sock.as_fd().as_raw_fd(),
+            msgs.as_mut_ptr(),
+            msgs.len(),
+            libc::MSG_WAITFORONE,
+            ptr::null(),
+        )
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(msgs[..r as usize]
+        .iter()
+        .zip(addrs.iter())
+        .map(|(m, ss)| Received {
+            len:       m.msg_len as usize,
+            addr:      sockaddr::from_raw(
+                (ss as *const libc::sockaddr_storage).cast(),
+                m.msg_hdr.msg_namelen,
+            ),
+            truncated: m.msg_hdr.msg_flags & libc::MSG_TRUNC != 0,
+        })
+        .collect())
+}
@@ -0,0 +1,306 @@
+// vim: tw=80
+//! Accept connections in one process and serve them in sandboxed workers
+//!
+//! A common privilege-separated design is for a small, less-sandboxed process
+//! to own the listening socket, while fully sandboxed workers handle each
+//! connection.  The [`Broker`] accepts connections, restricts their capability
+//! rights, and forwards them over unix-domain sockets with `SCM_RIGHTS`.  Each
+//! [`Worker`] receives the connections and dispatches them to a handler.
+//! Workers need no Casper service at all.
+//!
+//! # Example
+//! ```no_run
+//! use std::{net::TcpListener, os::unix::net::UnixStream, thread};
+//!
+//! use capsicum_net::broker::{Broker, Worker};
+//!
+//! let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
+//! let (broker_end, worker_end) = UnixStream::pair().unwrap();
+//! thread::spawn(move || {
+//!     // Normally this would be a separate, sandboxed process.
+//!     Worker::new(worker_end)
+//!         .run(|stream, peer| println!("connection from {peer}"))
+//!         .unwrap();
+//! });
+//! let mut broker = Broker::new(listener);
+//! broker.add_worker(broker_end);
+//! broker.run().unwrap();
+//! ```
+use std::{
+    io,
+    mem,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+        SocketAddrV6,
+        TcpListener,
+        TcpStream,
+    },
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    ptr,
+};
+
+use capsicum::{CapRights, FileRights};
+
+use crate::std::default_stream_rights;
+
+/// Length of the peer address record sent along with each connection: a family
+/// byte, 16 address bytes, a port, and an IPv6 scope id.
+const RECORD_LEN: usize = 1 + 16 + 2 + 4;
+
+fn encode(addr: SocketAddr) -> [u8; RECORD_LEN] {
+    let mut rec = [0u8; RECORD_LEN];
+    match addr {
+        SocketAddr::V4(sin) => {
+            rec[0] = 4;
+            rec[1..5].copy_from_slice(&sin.ip().octets());
+        }
+        SocketAddr::V6(sin6) => {
+            rec[0] = 6;
+            rec[1..17].copy_from_slice(&sin6.ip().octets());
+            rec[19..23].copy_from_slice(&sin6.scope_id().to_be_bytes());
+        }
+    }
+    rec[17..19].copy_from_slice(&addr.port().to_be_bytes());
+    rec
+}
+
+fn decode(rec: &[u8; RECORD_LEN]) -> io::Result<SocketAddr> {
+    let port = u16::from_be_bytes([rec[17], rec[18]]);
+    match rec[0] {
+        4 => {
+            let ip = Ipv4Addr::new(rec[1], rec[2], rec[3], rec[4]);
+            Ok(SocketAddr::new(IpAddr::V4(ip), port))
+        }
+        6 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&rec[1..17]);
+            let scope =
+                u32::from_be_bytes([rec[19], rec[20], rec[21], rec[22]]);
+            let ip = Ipv6Addr::from(octets);
+            Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope)))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed broker record",
+        )),
+    }
+}
+
+/// Send a connected stream, and its peer's address, over `chan`.
+///
+/// This is the primitive that [`Broker`] uses.  It's useful on its own for
+/// brokers that decide themselves which worker gets each connection.
+pub fn send_stream(
+    chan: &UnixStream,
+    stream: &TcpStream,
+    peer: SocketAddr,
+) -> io::Result<()> {
+    let rec = encode(peer);
+    let mut iov = libc::iovec {
+        iov_base: rec.as_ptr() as *mut libc::c_void,
+        iov_len:  rec.len(),
+    };
+    let space =
+        unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize;
+    let mut cbuf = vec![0u8; space];
+    // Safe because msghdr is a plain C struct
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cbuf.as_mut_ptr().cast();
+    msg.msg_controllen = space as libc::socklen_t;
+    // Safe because msg_control points to a buffer with room for one fd
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        ptr::write_unaligned(
+            libc::CMSG_DATA(cmsg).cast::<RawFd>(),
+            stream.as_raw_fd(),
+        );
+    }
+    let r = unsafe { libc::sendmsg(chan.as_raw_fd(), &msg, 0) };
+    if r < 0 {
+        Err(io::Error::last_os_error())
+    } else if r as usize != RECORD_LEN {
+        Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            "short write to worker",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Receive a stream sent by [`send_stream`].
+///
+/// Returns `None` when the broker has closed the channel.
+pub fn recv_stream(
+    chan: &UnixStream,
+) -> io::Result<Option<(TcpStream, SocketAddr)>> {
+    let mut rec = [0u8; RECORD_LEN];
+    let mut iov = libc::iovec {
+        iov_base: rec.as_mut_ptr().cast(),
+        iov_len:  rec.len(),
+    };
+    let space =
+        unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize;
+    let mut cbuf = vec![0u8; space];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cbuf.as_mut_ptr().cast();
+    msg.msg_controllen = space as libc::socklen_t;
+    let r = unsafe {
+        libc::recvmsg(chan.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC)
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    } else if r == 0 {
+        return Ok(None);
+    }
+    let mut fd = None;
+    // Safe because the kernel filled in msg_control
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET
+                && (*cmsg).cmsg_type == libc::SCM_RIGHTS
+            {
+                let raw =
+                    ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>());
+                fd = Some(OwnedFd::from_raw_fd(raw));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    let fd = fd.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "no descriptor received")
+    })?;
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "control message truncated",
+        ));
+    }
+    if (r as usize) < RECORD_LEN {
+        // The rest of the record follows on the stream, without any ancillary
+        // data.
+        io::Read::read_exact(&mut &*chan, &mut rec[r as usize..])?;
+    }
+    Ok(Some((TcpStream::from(fd), decode(&rec)?)))
+}
+
+/// Accepts connections and forwards them to [`Worker`]s, round-robin.
+#[derive(Debug)]
+pub struct Broker {
+    listener: TcpListener,
+    workers:  Vec<UnixStream>,
+    rights:   FileRights,
+    next:     usize,
+}
+
+impl Broker {
+    /// Create a broker for `listener`, with no workers yet.
+    ///
+    /// Forwarded connections are restricted to
+    /// [`default_stream_rights`].
+    pub fn new(listener: TcpListener) -> Self {
+        Broker {
+            listener,
+            workers: Vec::new(),
+            rights: default_stream_rights(),
+            next: 0,
+        }
+    }
+
+    /// Restrict forwarded connections to `rights` instead of the default.
+    pub fn with_rights(mut self, rights: FileRights) -> Self {
+        self.rights = rights;
+        self
+    }
+
+    /// Add a channel to a worker.
+    pub fn add_worker(&mut self, chan: UnixStream) {
+        self.workers.push(chan);
+    }
+
+    /// The number of workers still accepting connections
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Accept a single connection and forward it to the next worker.
+    ///
+    /// Workers that have gone away are dropped, and the connection is offered
+    /// to the next one.  Fails with `ErrorKind::NotConnected` if no workers
+    /// remain.  Returns the peer's address.
+    pub fn forward_one(&mut self) -> io::Result<SocketAddr> {
+        let (stream, peer) = self.listener.accept()?;
+        self.rights.limit(&stream)?;
+        while !self.workers.is_empty() {
+            let i = self.next % self.workers.len();
+            match send_stream(&self.workers[i], &stream, peer) {
+                Ok(()) => {
+                    self.next = i + 1;
+                    return Ok(peer);
+                }
+                Err(e)
+                    if e.kind() == io::ErrorKind::BrokenPipe
+                        || e.kind() == io::ErrorKind::ConnectionReset =>
+                {
+                    self.workers.remove(i);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "no workers remain",
+        ))
+    }
+
+    /// Forward connections until an error occurs, or until no workers remain.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            self.forward_one()?;
+        }
+    }
+}
+
+/// Receives connections forwarded by a [`Broker`].
+#[derive(Debug)]
+pub struct Worker {
+    chan: UnixStream,
+}
+
+impl Worker {
+    /// Create a worker that receives connections over `chan`.
+    pub fn new(chan: UnixStream) -> Self {
+        Worker { chan }
+    }
+
+    /// Receive a single connection, or `None` if the broker has gone away.
+    pub fn recv(&self) -> io::Result<Option<(TcpStream, SocketAddr)>> {
+        recv_stream(&self.chan)
+    }
+
+    /// Call `handler` for every connection received, until the broker closes
+    /// the channel.
+    pub fn run<F>(&self, mut handler: F) -> io::Result<()>
+    where
+        F: FnMut(TcpStream, SocketAddr),
+    {
+        while let Some((stream, peer)) = self.recv()? {
+            handler(stream, peer);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+// vim: tw=80
+//! `tokio_util` codec integration for Casper-connected UDP sockets
+//!
+//! `tokio_util::udp::UdpFramed` sends each frame with `sendto(2)`, which may
+//! not specify a destination address in capability mode.  Instead,
+//! [`ConnectedUdpFramed`] uses a socket that has been connected to a single
+//! peer with `cap_connect`, and sends and receives with plain `send(2)` and
+//! `recv(2)`.  It provides the familiar `Sink` and `Stream` interface.
+//!
+//! # Example
+//! ```no_run
+//! use std::io;
+//!
+//! use bytes::Bytes;
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, codec::ConnectedUdpFramed, tokio::UdpSocketExt};
+//! use futures::{SinkExt, StreamExt};
+//! use tokio::net::UdpSocket;
+//! use tokio_util::codec::BytesCodec;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> io::Result<()> {
+//!     // Safe because we are single-threaded
+//!     let mut casper = unsafe { Casper::new().unwrap() };
+//!     let mut cap_net = casper.net().unwrap();
+//!
+//!     capsicum::enter();
+//!
+//!     let socket = UdpSocket::cap_bind(&mut cap_net, "0.0.0.0:0")?;
+//!     let mut framed = ConnectedUdpFramed::connect(
+//!         &mut cap_net,
+//!         socket,
+//!         "192.0.2.1:5353",
+//!         BytesCodec::new(),
+//!     )
+//!     .await?;
+//!     framed.send(Bytes::from_static(b"ping")).await?;
+//!     let reply = framed.next().await.unwrap()?;
+//!     Ok(())
+//! }
+//! ```
+#![cfg_attr(docsrs, doc(cfg(feature = "codec")))]
+use std::{
+    io,
+    net::SocketAddr,
+    os::fd::AsFd,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::BytesMut;
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio::{io::ReadBuf, net::UdpSocket};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{CapNetAgent, CapToSocketAddrs};
+
+/// Largest possible UDP datagram
+const RD_CAPACITY: usize = 64 * 1024;
+
+/// A unified `Stream` and `Sink` interface to a connected UDP socket, using
+/// the `Encoder` and `Decoder` traits to encode and decode frames.
+///
+/// Each datagram is decoded as a whole, like with `tokio_util::udp::UdpFramed`.
+/// Unlike `UdpFramed`, frames carry no address, because all of them are
+/// exchanged with the socket's connected peer.
+#[derive(Debug)]
+pub struct ConnectedUdpFramed<C> {
+    socket:      UdpSocket,
+    codec:       C,
+    rd:          BytesMut,
+    wr:          BytesMut,
+    flushed:     bool,
+    is_readable: bool,
+}
+
+impl<C> ConnectedUdpFramed<C> {
+    /// Wrap a UDP socket that is already connected to its peer.
+    pub fn new(socket: UdpSocket, codec: C) -> Self {
+        ConnectedUdpFramed {
+            socket,
+            codec,
+            rd: BytesMut::with_capacity(RD_CAPACITY),
+            wr: BytesMut::new(),
+            flushed: true,
+            is_readable: false,
+        }
+    }
+
+    /// Connect a cap-bound UDP socket to `peer` using a `cap_net` service,
+    /// and wrap it.
+    ///
+    /// If the agent has a [connect rate
+    /// limit](crate::CapNetAgent::set_connect_rate_limit), this waits for it
+    /// without blocking the runtime.
+    pub async fn connect<A: CapToSocketAddrs>(
+        agent: &mut CapNetAgent,
+        socket: UdpSocket,
+        peer: A,
+        codec: C,
+    ) -> io::Result<Self> {
+        let mut last_err = None;
+        for addr in peer.cap_to_socket_addrs(agent)? {
+            let permit = agent.connect_permit_async().await;
+            match agent.connect_std_fd_with(socket.as_fd(), addr, permit) {
+                Ok(()) => return Ok(Self::new(socket, codec)),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            )
+        }))
+    }
+
+    /// The address of the connected peer
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+
+    /// Returns a reference to the underlying socket.
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Returns a reference to the codec.
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Returns a mutable reference to the codec.
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Consume the `ConnectedUdpFramed`, returning the underlying socket.
+    pub fn into_inner(self) -> UdpSocket {
+        self.socket
+    }
+}
+
+impl<C: Decoder + Unpin> Stream for ConnectedUdpFramed<C> {
+    type Item = Result<C::Item, C::Error>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let pin = self.get_mut();
+        loop {
+            if pin.is_readable {
+                if let Some(frame) = pin.codec.decode_eof(&mut pin.rd)? {
+                    return Poll::Ready(Some(Ok(frame)));
+                }
+                // The datagram was fully consumed
+                pin.is_readable = false;
+                pin.rd.clear();
+            }
+            pin.rd.resize(RD_CAPACITY, 0);
+            let mut buf = ReadBuf::new(&mut pin.rd[..]);
+            let r = ready!(pin.socket.poll_recv(cx, &mut buf));
+            let n = buf.filled().len();
+            pin.rd.truncate(n);
+            r?;
+            pin.is_readable = true;
+        }
+    }
+}
+
+impl<I, C: Encoder<I> + Unpin> Sink<I> for ConnectedUdpFramed<C> {
+    type Error = C::Error;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        if !self.flushed {
+            ready!(self.poll_flush(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let pin = self.get_mut();
+        pin.codec.encode(item, &mut pin.wr)?;
+        pin.flushed = false;
+        Ok(())
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        let pin = self.get_mut();
+        if pin.flushed {
+            return Poll::Ready(Ok(()));
+        }
+        let n = ready!(pin.socket.poll_send(cx, &pin.wr))?;
+        let wrote_all = n == pin.wr.len();
+        pin.wr.clear();
+        pin.flushed = true;
+        if wrote_all {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Ready(Err(io::Error::other(
+                "failed to write entire datagram to socket",
+            )
+            .into()))
+        }
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
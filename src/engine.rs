@@ -0,0 +1,321 @@
+// vim: tw=80
+//! A richer, in-process policy layered over Casper's limits
+//!
+//! Casper can only allow-list exact addresses, and an [`AccessList`] only
+//! considers IP networks and ports.  A [`PolicyAgent`] owns a [`CapNetAgent`]
+//! and evaluates an ordered list of [`PolicyRule`]s before delegating each
+//! operation to it.  Each rule may combine any number of [`Matcher`]s, by
+//! network, port, address family, or unix-domain path, and matches only if
+//! all of them do.  The first matching rule wins.  If none match, the agent's
+//! default action applies.
+//!
+//! Casper's own limits and any [`AccessList`] installed on the inner agent
+//! still apply to whatever the policy allows, giving defense in depth.
+//!
+//! [`AccessList`]: crate::acl::AccessList
+//!
+//! # Example
+//! ```no_run
+//! use std::net::TcpStream;
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{
+//!     acl::Action,
+//!     engine::{PolicyAgent, PolicyRule},
+//!     CasperExt,
+//!     LimitFlags,
+//! };
+//! use nix::sys::socket::AddressFamily;
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let cap_net = casper.net().unwrap();
+//!
+//! let mut agent = PolicyAgent::new(cap_net, Action::Deny);
+//! agent
+//!     .push(PolicyRule::deny(LimitFlags::CONNECT)
+//!         .net("169.254.0.0/16".parse().unwrap()))
+//!     .push(PolicyRule::allow(LimitFlags::CONNECT)
+//!         .family(AddressFamily::Inet)
+//!         .ports(443..=443))
+//!     .push(PolicyRule::allow(LimitFlags::BIND).unix_path("/var/run/app"));
+//!
+//! capsicum::enter();
+//!
+//! let stream: TcpStream = agent.tcp_stream("192.0.2.1:443").unwrap();
+//! ```
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    ops::RangeInclusive,
+    os::{fd::AsFd, unix::net::UnixListener},
+    path::{Component, Path, PathBuf},
+};
+
+use nix::{
+    errno::Errno,
+    sys::socket::{AddressFamily, SockaddrLike},
+    Result,
+};
+
+use crate::{
+    acl::{AccessDenied, Action, IpNet},
+    sockaddr,
+    std::{TcpListenerExt, TcpStreamExt, UdpSocketExt, UnixListenerExt},
+    CapNetAgent,
+    CapToSocketAddrs,
+    LimitFlags,
+};
+
+/// One condition of a [`PolicyRule`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Matcher {
+    /// An IPv4 or IPv6 address within this network
+    Net(IpNet),
+    /// An IPv4 or IPv6 address with a port in this range
+    Ports(RangeInclusive<u16>),
+    /// An address of this family
+    Family(AddressFamily),
+    /// A unix-domain socket whose path lies under this prefix.  Prefixes
+    /// match whole path components.
+    UnixPath(PathBuf),
+}
+
+impl Matcher {
+    fn matches(&self, addr: &dyn SockaddrLike) -> bool {
+        match self {
+            Matcher::Net(net) => {
+                sockaddr::to_std(addr).is_some_and(|a| net.contains(&a.ip()))
+            }
+            Matcher::Ports(ports) => sockaddr::to_std(addr)
+                .is_some_and(|a| ports.contains(&a.port())),
+            Matcher::Family(family) => addr.family() == Some(*family),
+            Matcher::UnixPath(prefix) => {
+                unix_path(addr).is_some_and(|p| p.starts_with(prefix))
+            }
+        }
+    }
+}
+
+/// A single entry of a [`PolicyAgent`]'s rule list
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PolicyRule {
+    /// What to do with matching operations
+    pub action:   Action,
+    /// Which operations this rule applies to
+    pub ops:      LimitFlags,
+    /// Conditions that must all hold.  An empty list matches every address.
+    pub matchers: Vec<Matcher>,
+}
+
+impl PolicyRule {
+    /// A rule that allows `ops` on any address
+    pub fn allow(ops: LimitFlags) -> Self {
+        PolicyRule {
+            action: Action::Allow,
+            ops,
+            matchers: Vec::new(),
+        }
+    }
+
+    /// A rule that denies `ops` on any address
+    pub fn deny(ops: LimitFlags) -> Self {
+        PolicyRule {
+            action: Action::Deny,
+            ops,
+            matchers: Vec::new(),
+        }
+    }
+
+    /// Restrict this rule to addresses within `net`.
+    pub fn net(self, net: IpNet) -> Self {
+        self.matcher(Matcher::Net(net))
+    }
+
+    /// Restrict this rule to the given range of ports.
+    pub fn ports(self, ports: RangeInclusive<u16>) -> Self {
+        self.matcher(Matcher::Ports(ports))
+    }
+
+    /// Restrict this rule to addresses of the given family.
+    pub fn family(self, family: AddressFamily) -> Self {
+        self.matcher(Matcher::Family(family))
+    }
+
+    /// Restrict this rule to unix-domain sockets under `prefix`.
+    pub fn unix_path<P: Into<PathBuf>>(self, prefix: P) -> Self {
+        self.matcher(Matcher::UnixPath(prefix.into()))
+    }
+
+    /// Add an arbitrary condition to this rule.
+    pub fn matcher(mut self, matcher: Matcher) -> Self {
+        self.matchers.push(matcher);
+        self
+    }
+
+    fn matches(&self, op: LimitFlags, addr: &dyn SockaddrLike) -> bool {
+        self.ops.intersects(op) && self.matchers.iter().all(|m| m.matches(addr))
+    }
+}
+
+/// The path of a unix-domain socket address, if it has one
+fn unix_path(addr: &dyn SockaddrLike) -> Option<PathBuf> {
+    let ss = crate::template::to_storage(addr);
+    ss.as_unix_addr()?.path().map(Path::to_path_buf)
+}
+
+/// A [`CapNetAgent`] that consults an ordered list of [`PolicyRule`]s before
+/// every bind or connect
+#[derive(Debug)]
+pub struct PolicyAgent {
+    agent:   CapNetAgent,
+    rules:   Vec<PolicyRule>,
+    default: Action,
+}
+
+impl PolicyAgent {
+    /// Wrap `agent`, with no rules and the given default action.
+    pub fn new(agent: CapNetAgent, default: Action) -> Self {
+        PolicyAgent {
+            agent,
+            rules: Vec::new(),
+            default,
+        }
+    }
+
+    /// Append a rule to the end of the list.
+    pub fn push(&mut self, rule: PolicyRule) -> &mut Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// The current rules, in order
+    pub fn rules(&self) -> &[PolicyRule] {
+        &self.rules
+    }
+
+    /// The inner agent.
+    ///
+    /// Operations performed directly through it bypass the policy.
+    pub fn agent_mut(&mut self) -> &mut CapNetAgent {
+        &mut self.agent
+    }
+
+    /// Unwrap the inner agent, discarding the policy.
+    pub fn into_inner(self) -> CapNetAgent {
+        self.agent
+    }
+
+    /// Decide whether operation `op` on `addr` is allowed.
+    ///
+    /// A unix-domain path containing `..` is always denied, since it could
+    /// escape from a prefix.
+    pub fn check(&self, op: LimitFlags, addr: &dyn SockaddrLike) -> Action {
+        if unix_path(addr)
+            .is_some_and(|p| p.components().any(|c| c == Component::ParentDir))
+        {
+            return Action::Deny;
+        }
+        self.rules
+            .iter()
+            .find(|r| r.matches(op, addr))
+            .map_or(self.default, |r| r.action)
+    }
+
+    /// Bind a socket to an address, like [`CapNetAgent::bind`].
+    ///
+    /// Fails with `EACCES` if the policy denies it.
+    pub fn bind<F: AsFd>(
+        &mut self,
+        sock: &F,
+        addr: &dyn SockaddrLike,
+    ) -> Result<()> {
+        match self.check(LimitFlags::BIND, addr) {
+            Action::Allow => self.agent.bind(sock, addr),
+            Action::Deny => Err(Errno::EACCES),
+        }
+    }
+
+    /// Connect a socket to an address, like [`CapNetAgent::connect`].
+    ///
+    /// Fails with `EACCES` if the policy denies it.
+    pub fn connect<F: AsFd>(
+        &mut self,
+        sock: &F,
+        addr: &dyn SockaddrLike,
+    ) -> Result<()> {
+        match self.check(LimitFlags::CONNECT, addr) {
+            Action::Allow => self.agent.connect(sock, addr),
+            Action::Deny => Err(Errno::EACCES),
+        }
+    }
+
+    /// Resolve `addrs` and keep only those that the policy allows for `op`.
+    ///
+    /// If it allows none, fails with an [`AccessDenied`] for the first.
+    fn allowed<A: CapToSocketAddrs>(
+        &mut self,
+        op: LimitFlags,
+        addrs: A,
+    ) -> io::Result<Vec<SocketAddr>> {
+        let addrs = addrs.cap_to_socket_addrs(&mut self.agent)?;
+        let allowed: Vec<SocketAddr> = addrs
+            .iter()
+            .copied()
+            .filter(|a| {
+                self.check(op, &sockaddr::from_std(*a)) == Action::Allow
+            })
+            .collect();
+        match (allowed.is_empty(), addrs.first()) {
+            (true, Some(&addr)) => Err(AccessDenied { op, addr }.into()),
+            _ => Ok(allowed),
+        }
+    }
+
+    /// Bind a new TCP listener, like [`TcpListenerExt::cap_bind`].
+    ///
+    /// Addresses that the policy denies are skipped.
+    pub fn tcp_listener<A>(&mut self, addrs: A) -> io::Result<TcpListener>
+    where
+        A: CapToSocketAddrs,
+    {
+        let addrs = self.allowed(LimitFlags::BIND, addrs)?;
+        TcpListener::cap_bind(&mut self.agent, &addrs[..])
+    }
+
+    /// Connect a new TCP stream, like [`TcpStreamExt::cap_connect`].
+    ///
+    /// Addresses that the policy denies are skipped.
+    pub fn tcp_stream<A>(&mut self, addrs: A) -> io::Result<TcpStream>
+    where
+        A: CapToSocketAddrs,
+    {
+        let addrs = self.allowed(LimitFlags::CONNECT, addrs)?;
+        TcpStream::cap_connect(&mut self.agent, &addrs[..])
+    }
+
+    /// Bind a new UDP socket, like [`UdpSocketExt::cap_bind`].
+    ///
+    /// Addresses that the policy denies are skipped.
+    pub fn udp_socket<A>(&mut self, addrs: A) -> io::Result<UdpSocket>
+    where
+        A: CapToSocketAddrs,
+    {
+        let addrs = self.allowed(LimitFlags::BIND, addrs)?;
+        UdpSocket::cap_bind(&mut self.agent, &addrs[..])
+    }
+
+    /// Bind a new unix-domain listener, like [`UnixListenerExt::cap_bind`].
+    ///
+    /// Fails with `EACCES` if the policy denies it.
+    pub fn unix_listener<P>(&mut self, path: P) -> io::Result<UnixListener>
+    where
+        P: AsRef<Path>,
+    {
+        let sa = nix::sys::socket::UnixAddr::new(path.as_ref())?;
+        if self.check(LimitFlags::BIND, &sa) == Action::Deny {
+            return Err(Errno::EACCES.into());
+        }
+        UnixListener::cap_bind(&mut self.agent, path)
+    }
+}
@@ -1,10 +1,15 @@
 #![allow(non_camel_case_types)]
 use casper_sys::cap_channel_t;
-use libc::sockaddr;
+use libc::{addrinfo, hostent, sockaddr};
 /* automatically generated by rust-bindgen 0.69.1 */
 
+pub const CAPNET_ADDR2NAME: u32 = 1;
+pub const CAPNET_NAME2ADDR: u32 = 2;
+pub const CAPNET_DEPRECATED_ADDR2NAME: u32 = 4;
+pub const CAPNET_DEPRECATED_NAME2ADDR: u32 = 8;
 pub const CAPNET_CONNECT: u32 = 16;
 pub const CAPNET_BIND: u32 = 32;
+pub const CAPNET_CONNECTDNS: u32 = 64;
 pub type __uint8_t = ::std::os::raw::c_uchar;
 pub type __uint32_t = ::std::os::raw::c_uint;
 pub type __sa_family_t = __uint8_t;
@@ -36,6 +41,9 @@ extern "C" {
 extern "C" {
     pub fn cap_net_limit(limit: *mut cap_net_limit_t) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn cap_net_free(limit: *mut cap_net_limit_t);
+}
 extern "C" {
     pub fn cap_net_limit_connect(
         limit: *mut cap_net_limit_t,
@@ -50,3 +58,65 @@ extern "C" {
         salen: socklen_t,
     ) -> *mut cap_net_limit_t;
 }
+extern "C" {
+    pub fn cap_net_limit_addr2name(
+        limit: *mut cap_net_limit_t,
+        sa: *const sockaddr,
+        salen: socklen_t,
+    ) -> *mut cap_net_limit_t;
+}
+extern "C" {
+    pub fn cap_net_limit_name2addr(
+        limit: *mut cap_net_limit_t,
+        name: *const ::std::os::raw::c_char,
+        serv: *const ::std::os::raw::c_char,
+    ) -> *mut cap_net_limit_t;
+}
+extern "C" {
+    pub fn cap_net_limit_addr2name_family(
+        limit: *mut cap_net_limit_t,
+        family: *mut ::std::os::raw::c_int,
+        size: usize,
+    ) -> *mut cap_net_limit_t;
+}
+extern "C" {
+    pub fn cap_net_limit_name2addr_family(
+        limit: *mut cap_net_limit_t,
+        family: *mut ::std::os::raw::c_int,
+        size: usize,
+    ) -> *mut cap_net_limit_t;
+}
+extern "C" {
+    pub fn cap_getaddrinfo(
+        chan: *mut cap_channel_t,
+        hostname: *const ::std::os::raw::c_char,
+        servname: *const ::std::os::raw::c_char,
+        hints: *const addrinfo,
+        res: *mut *mut addrinfo,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn cap_getnameinfo(
+        chan: *mut cap_channel_t,
+        sa: *const sockaddr,
+        salen: socklen_t,
+        host: *mut ::std::os::raw::c_char,
+        hostlen: usize,
+        serv: *mut ::std::os::raw::c_char,
+        servlen: usize,
+        flags: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn cap_gethostbyname(
+        chan: *mut cap_channel_t,
+        name: *const ::std::os::raw::c_char,
+    ) -> *mut hostent;
+}
+extern "C" {
+    pub fn cap_gethostbyname2(
+        chan: *mut cap_channel_t,
+        name: *const ::std::os::raw::c_char,
+        af: ::std::os::raw::c_int,
+    ) -> *mut hostent;
+}
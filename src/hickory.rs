@@ -0,0 +1,241 @@
+// vim: tw=80
+//! Run a [`hickory-resolver`](https://docs.rs/hickory-resolver) inside the
+//! sandbox
+//!
+//! hickory's DNS client opens its own UDP and TCP sockets, which it can't do
+//! in capability mode.  [`CapRuntimeProvider`] creates them through a
+//! `cap_net` service instead, using Tokio for everything else.  UDP sockets
+//! are connected to their name server, because capability mode forbids
+//! `sendto(2)` with an explicit destination.
+//!
+//! # Example
+//! ```no_run
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, hickory::CapRuntimeProvider};
+//! use hickory_resolver::{config::ResolverConfig, Resolver};
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() {
+//!     // Safe because we are single-threaded
+//!     let mut casper = unsafe { Casper::new().unwrap() };
+//!     let cap_net = casper.net().unwrap();
+//!
+//!     capsicum::enter();
+//!
+//!     let resolver = Resolver::builder_with_config(
+//!         ResolverConfig::cloudflare(),
+//!         CapRuntimeProvider::new(cap_net).into_connector(),
+//!     )
+//!     .build();
+//!     let lookup = resolver.lookup_ip("www.freebsd.org").await.unwrap();
+//! }
+//! ```
+#![cfg_attr(docsrs, doc(cfg(feature = "hickory")))]
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    os::fd::AsFd,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use hickory_resolver::{
+    name_server::GenericConnector,
+    proto::{
+        runtime::{
+            iocompat::AsyncIoTokioAsStd,
+            RuntimeProvider,
+            TokioHandle,
+            TokioTime,
+        },
+        udp::DnsUdpSocket,
+    },
+};
+use nix::sys::socket::{AddressFamily, SockFlag, SockType};
+use tokio::{
+    io::ReadBuf,
+    net::{TcpStream, UdpSocket},
+};
+
+use crate::CapNetAgent;
+
+/// hickory's own default TCP connect timeout
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Create a nonblocking socket, bind it to `local` if that's a specific
+/// address, and start connecting it to `peer`, waiting asynchronously for the
+/// agent's connect rate limiter.
+async fn cap_socket(
+    agent: &Mutex<CapNetAgent>,
+    ty: SockType,
+    local: Option<SocketAddr>,
+    peer: SocketAddr,
+) -> io::Result<std::os::fd::OwnedFd> {
+    let wait = agent
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .connect_permit_async();
+    let permit = wait.await;
+    let family = if peer.is_ipv4() {
+        AddressFamily::Inet
+    } else {
+        AddressFamily::Inet6
+    };
+    let sock =
+        nix::sys::socket::socket(family, ty, SockFlag::SOCK_NONBLOCK, None)?;
+    let mut agent = agent.lock().unwrap_or_else(|e| e.into_inner());
+    // With no local address, connect(2) picks a random ephemeral port, which
+    // saves a Casper request and needs no bind limit.
+    if let Some(local) = local.filter(|l| !l.ip().is_unspecified()) {
+        agent.bind_std_fd(sock.as_fd(), local)?;
+    }
+    match agent.connect_std_fd_with(sock.as_fd(), peer, permit) {
+        Err(e) if e.raw_os_error() != Some(libc::EINPROGRESS) => Err(e),
+        _ => Ok(sock),
+    }
+}
+
+/// A UDP socket connected to a single name server
+#[derive(Debug)]
+pub struct CapUdpSocket {
+    socket: UdpSocket,
+    peer:   SocketAddr,
+}
+
+impl DnsUdpSocket for CapUdpSocket {
+    type Time = TokioTime;
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(self.socket.poll_recv(cx, &mut buf))?;
+        Poll::Ready(Ok((buf.filled().len(), self.peer)))
+    }
+
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        if target != self.peer {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "socket is connected to a different name server",
+            )));
+        }
+        self.socket.poll_send(cx, buf)
+    }
+}
+
+/// A hickory `RuntimeProvider` that creates its sockets through Casper.
+///
+/// Clones share the same agent.
+#[derive(Clone)]
+pub struct CapRuntimeProvider {
+    agent:  Arc<Mutex<CapNetAgent>>,
+    handle: TokioHandle,
+}
+
+impl CapRuntimeProvider {
+    /// Create sockets with `agent`.  It must permit connecting to the
+    /// configured name servers.
+    pub fn new(agent: CapNetAgent) -> Self {
+        Self::from(Arc::new(Mutex::new(agent)))
+    }
+
+    /// Wrap this provider for use with `hickory_resolver::Resolver`.
+    pub fn into_connector(self) -> GenericConnector<Self> {
+        GenericConnector::new(self)
+    }
+}
+
+impl From<Arc<Mutex<CapNetAgent>>> for CapRuntimeProvider {
+    /// Share an agent that is also used elsewhere.
+    fn from(agent: Arc<Mutex<CapNetAgent>>) -> Self {
+        CapRuntimeProvider {
+            agent,
+            handle: TokioHandle::default(),
+        }
+    }
+}
+
+impl fmt::Debug for CapRuntimeProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CapRuntimeProvider")
+            .field("agent", &self.agent)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RuntimeProvider for CapRuntimeProvider {
+    type Handle = TokioHandle;
+    type Tcp = AsyncIoTokioAsStd<TcpStream>;
+    type Timer = TokioTime;
+    type Udp = CapUdpSocket;
+
+    fn create_handle(&self) -> Self::Handle {
+        self.handle.clone()
+    }
+
+    fn connect_tcp(
+        &self,
+        server_addr: SocketAddr,
+        bind_addr: Option<SocketAddr>,
+        timeout: Option<Duration>,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Tcp>>>> {
+        let agent = self.agent.clone();
+        Box::pin(async move {
+            let sock =
+                cap_socket(&agent, SockType::Stream, bind_addr, server_addr)
+                    .await?;
+            let stream = TcpStream::from_std(std::net::TcpStream::from(sock))?;
+            stream.set_nodelay(true)?;
+            let wait = timeout.unwrap_or(CONNECT_TIMEOUT);
+            let connected = async {
+                stream.writable().await?;
+                match stream.take_error()? {
+                    Some(e) => Err(e),
+                    None => Ok(()),
+                }
+            };
+            match tokio::time::timeout(wait, connected).await {
+                Ok(Ok(())) => Ok(AsyncIoTokioAsStd(stream)),
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("connection to {server_addr} timed out"),
+                )),
+            }
+        })
+    }
+
+    fn bind_udp(
+        &self,
+        local_addr: SocketAddr,
+        server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Udp>>>> {
+        let agent = self.agent.clone();
+        Box::pin(async move {
+            let sock = cap_socket(
+                &agent,
+                SockType::Datagram,
+                Some(local_addr),
+                server_addr,
+            )
+            .await?;
+            let socket = UdpSocket::from_std(std::net::UdpSocket::from(sock))?;
+            Ok(CapUdpSocket {
+                socket,
+                peer: server_addr,
+            })
+        })
+    }
+}
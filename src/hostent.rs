@@ -0,0 +1,132 @@
+// vim: tw=80
+//! Owned results of the legacy `gethostbyname` interface
+//!
+//! New code should use [`CapNetAgent::resolve`](crate::CapNetAgent::resolve)
+//! instead.  These wrappers exist for sandboxing old code that was written
+//! around
+//! [gethostbyname(3)](https://man.freebsd.org/cgi/man.cgi?query=gethostbyname),
+//! and require the `deprecated-dns` feature.
+//!
+//! # Example
+//! ```no_run
+//! use capsicum::casper::Casper;
+//! use capsicum_net::CasperExt;
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let hostent = cap_net.gethostbyname("www.freebsd.org").unwrap();
+//! println!("{} has addresses {:?}", hostent.name, hostent.addrs);
+//! ```
+#![cfg_attr(docsrs, doc(cfg(feature = "deprecated-dns")))]
+use std::{
+    ffi::CStr,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::raw::{c_char, c_int},
+};
+
+// From <netdb.h>, but not yet in libc
+const HOST_NOT_FOUND: c_int = 1;
+const TRY_AGAIN: c_int = 2;
+const NO_DATA: c_int = 4;
+
+extern "C" {
+    // The function behind the h_errno macro
+    fn __h_errno() -> *mut c_int;
+}
+
+/// A host's entry in the name database, like a C `struct hostent`
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct HostEnt {
+    /// The host's official name
+    pub name:    String,
+    /// Alternative names for the host
+    pub aliases: Vec<String>,
+    /// The host's addresses, all of the same family
+    pub addrs:   Vec<IpAddr>,
+}
+
+/// Collect a NULL-terminated array of pointers.
+///
+/// # Safety
+///
+/// `list` must be null or a valid NULL-terminated array.
+unsafe fn collect<T>(
+    list: *mut *mut c_char,
+    f: impl Fn(*const c_char) -> T,
+) -> Vec<T> {
+    let mut v = Vec::new();
+    if list.is_null() {
+        return v;
+    }
+    let mut p = list;
+    while !(*p).is_null() {
+        v.push(f(*p));
+        p = p.add(1);
+    }
+    v
+}
+
+impl HostEnt {
+    /// Copy a hostent returned by one of the `cap_gethostbyname` functions,
+    /// or convert `h_errno` into an error if it is null.
+    ///
+    /// # Safety
+    ///
+    /// `he` must be null or point to a valid hostent.
+    pub(crate) unsafe fn from_raw(
+        he: *const libc::hostent,
+    ) -> io::Result<Self> {
+        if he.is_null() {
+            return Err(h_error(*__h_errno()));
+        }
+        let he = &*he;
+        let name = CStr::from_ptr(he.h_name).to_string_lossy().into_owned();
+        let aliases = collect(he.h_aliases, |a| {
+            CStr::from_ptr(a).to_string_lossy().into_owned()
+        });
+        let addrs = match (he.h_addrtype, he.h_length) {
+            (libc::AF_INET, 4) => collect(he.h_addr_list, |a| {
+                let mut octets = [0u8; 4];
+                octets.copy_from_slice(std::slice::from_raw_parts(a.cast(), 4));
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }),
+            (libc::AF_INET6, 16) => collect(he.h_addr_list, |a| {
+                let mut octets = [0u8; 16];
+                octets
+                    .copy_from_slice(std::slice::from_raw_parts(a.cast(), 16));
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unsupported address family",
+                ))
+            }
+        };
+        Ok(HostEnt {
+            name,
+            aliases,
+            addrs,
+        })
+    }
+}
+
+/// Convert an `h_errno` value into an `io::Error`.
+fn h_error(code: c_int) -> io::Error {
+    // Safe because hstrerror always returns a static string
+    let msg = unsafe { CStr::from_ptr(libc::hstrerror(code)) }
+        .to_string_lossy()
+        .into_owned();
+    let kind = match code {
+        HOST_NOT_FOUND | NO_DATA => io::ErrorKind::NotFound,
+        TRY_AGAIN => io::ErrorKind::WouldBlock,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, msg)
+}
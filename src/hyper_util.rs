@@ -0,0 +1,84 @@
+// vim: tw=80
+//! A [`hyper-util`](https://docs.rs/hyper-util) DNS resolver that works in
+//! capability mode
+//!
+//! hyper-util's `HttpConnector` resolves hostnames with its `GaiResolver`,
+//! which calls the libc resolver on a blocking thread.  That can't work once
+//! the process has entered capability mode.  [`CapGaiResolver`] is a drop-in
+//! replacement that resolves them through a `cap_net` service instead.  As
+//! with [`reqwest`](crate::reqwest), this only handles name resolution; the
+//! connector's connections must still be permitted.
+//!
+//! # Example
+//! ```no_run
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, hyper_util::CapGaiResolver};
+//! use hyper_util::client::legacy::connect::HttpConnector;
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let resolver = CapGaiResolver::new(casper.net().unwrap());
+//!
+//! let connector = HttpConnector::new_with_resolver(resolver);
+//! ```
+#![cfg_attr(docsrs, doc(cfg(feature = "hyper-util")))]
+use std::{
+    future::{ready, Ready},
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    vec,
+};
+
+use ::hyper_util::client::legacy::connect::dns::Name;
+use tower_service::Service;
+
+use crate::{resolver::Resolver, CapNetAgent};
+
+/// Implements `tower::Service<Name>` with a [`Resolver`], like hyper-util's
+/// `GaiResolver`.
+///
+/// Each lookup is a synchronous Casper request, made when the service is
+/// called.
+#[derive(Clone, Debug)]
+pub struct CapGaiResolver {
+    resolver: Arc<Mutex<Resolver>>,
+}
+
+impl CapGaiResolver {
+    /// Resolve names with `agent`, which must permit
+    /// [`LimitFlags::NAME2ADDR`](crate::LimitFlags::NAME2ADDR).
+    pub fn new(agent: CapNetAgent) -> Self {
+        Self::from(Resolver::new(agent))
+    }
+}
+
+impl From<Resolver> for CapGaiResolver {
+    fn from(resolver: Resolver) -> Self {
+        CapGaiResolver {
+            resolver: Arc::new(Mutex::new(resolver)),
+        }
+    }
+}
+
+impl Service<Name> for CapGaiResolver {
+    type Error = io::Error;
+    type Future = Ready<io::Result<Self::Response>>;
+    type Response = vec::IntoIter<SocketAddr>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        // HttpConnector fills in the port itself
+        let addrs = self
+            .resolver
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .lookup_host(name.as_str(), 0)
+            .map(Vec::into_iter);
+        ready(addrs)
+    }
+}
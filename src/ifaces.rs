@@ -0,0 +1,255 @@
+// vim: tw=80
+//! Network interface enumeration that works in capability mode
+//!
+//! [getifaddrs(3)](https://man.freebsd.org/cgi/man.cgi?query=getifaddrs) uses a
+//! sysctl that is not available in capability mode.  Instead, [`interfaces`]
+//! queries the kernel over netlink, which is.  On FreeBSD versions that lack
+//! netlink it falls back to `getifaddrs`, which will only work prior to
+//! entering capability mode.
+//!
+//! # Example
+//! ```
+//! use capsicum_net::ifaces::{interfaces, InterfaceFlags};
+//!
+//! let ifaces = interfaces().unwrap();
+//! let lo0 = ifaces.iter().find(|i| i.name == "lo0").unwrap();
+//! assert!(lo0.flags.contains(InterfaceFlags::IFF_LOOPBACK));
+//! ```
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    os::fd::AsFd,
+};
+
+pub use nix::net::if_::InterfaceFlags;
+use nix::sys::socket::{AddressFamily, SockFlag, SockType};
+
+use crate::{netlink::NetlinkRoute, CapNetAgent};
+
+/// An address assigned to a network interface
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InterfaceAddr {
+    /// The address itself
+    pub addr:       IpAddr,
+    /// Length of the network prefix, in bits
+    pub prefix_len: u8,
+}
+
+/// A network interface
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Interface {
+    /// Interface name, like "lo0"
+    pub name:  String,
+    /// Interface index
+    pub index: u32,
+    /// Interface flags
+    pub flags: InterfaceFlags,
+    /// IPv4 and IPv6 addresses assigned to this interface
+    pub addrs: Vec<InterfaceAddr>,
+}
+
+/// List all network interfaces along with their IP addresses.
+pub fn interfaces() -> io::Result<Vec<Interface>> {
+    match NetlinkRoute::new() {
+        Ok(nl) => interfaces_netlink(nl),
+        Err(e) if e.raw_os_error() == Some(libc::EAFNOSUPPORT) => {
+            interfaces_getifaddrs()
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn interfaces_netlink(mut nl: NetlinkRoute) -> io::Result<Vec<Interface>> {
+    let mut ifaces = nl
+        .links()?
+        .into_iter()
+        .map(|link| Interface {
+            name:  link.name,
+            index: link.index,
+            flags: InterfaceFlags::from_bits_truncate(link.flags as i32),
+            addrs: Vec::new(),
+        })
+        .collect::<Vec<_>>();
+    for a in nl.addresses()? {
+        if let Some(iface) = ifaces.iter_mut().find(|i| i.index == a.index) {
+            iface.addrs.push(InterfaceAddr {
+                addr:       a.addr,
+                prefix_len: a.prefix_len,
+            });
+        }
+    }
+    Ok(ifaces)
+}
+
+fn interfaces_getifaddrs() -> io::Result<Vec<Interface>> {
+    let mut ifaces: Vec<Interface> = Vec::new();
+    for ifa in nix::ifaddrs::getifaddrs()? {
+        let pos = match ifaces.iter().position(|i| i.name == ifa.interface_name)
+        {
+            Some(pos) => pos,
+            None => {
+                let index =
+                    nix::net::if_::if_nametoindex(ifa.interface_name.as_str())?;
+                ifaces.push(Interface {
+                    name: ifa.interface_name.clone(),
+                    index,
+                    flags: ifa.flags,
+                    addrs: Vec::new(),
+                });
+                ifaces.len() - 1
+            }
+        };
+        let Some(address) = ifa.address else { continue };
+        let (addr, prefix_len) = if let Some(sin) = address.as_sockaddr_in() {
+            let mask = ifa
+                .netmask
+                .as_ref()
+                .and_then(|m| m.as_sockaddr_in())
+                .map(|m| u32::from(m.ip()).count_ones())
+                .unwrap_or(32);
+            (IpAddr::V4(sin.ip()), mask as u8)
+        } else if let Some(sin6) = address.as_sockaddr_in6() {
+            let mask = ifa
+                .netmask
+                .as_ref()
+                .and_then(|m| m.as_sockaddr_in6())
+                .map(|m| u128::from(m.ip()).count_ones())
+                .unwrap_or(128);
+            (IpAddr::V6(sin6.ip()), mask as u8)
+        } else {
+            continue;
+        };
+        ifaces[pos].addrs.push(InterfaceAddr { addr, prefix_len });
+    }
+    Ok(ifaces)
+}
+
+/// The path that the kernel would use to send packets to a destination
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OutgoingRoute {
+    /// Name of the outgoing interface
+    pub name:    String,
+    /// Index of the outgoing interface
+    pub index:   u32,
+    /// Source address that the kernel would choose
+    pub source:  IpAddr,
+    /// Next hop, if the destination is not directly connected and the lookup
+    /// method could determine it
+    pub gateway: Option<IpAddr>,
+}
+
+/// Something that can look up the outgoing route to a destination.
+///
+/// This is implemented for [`NetlinkRoute`], which queries the routing table
+/// directly, and for [`CapNetAgent`], which asks the kernel to choose a source
+/// address by connecting a UDP socket.  The latter cannot report the gateway
+/// and requires that the agent's limits allow connecting to the destination.
+/// It still identifies the interface with [`interfaces`], so on FreeBSD
+/// versions that lack netlink it works only prior to entering capability mode.
+pub trait RouteLookup {
+    /// Find the interface and source address that would be used to reach
+    /// `dst`.
+    fn route_lookup(&mut self, dst: IpAddr) -> io::Result<OutgoingRoute>;
+}
+
+/// Is this an IPv6 link-local address?
+fn is_link_local(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(_) => false,
+        IpAddr::V6(a) => a.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+impl RouteLookup for NetlinkRoute {
+    fn route_lookup(&mut self, dst: IpAddr) -> io::Result<OutgoingRoute> {
+        let route = self.route_get(dst)?;
+        let index = route
+            .oif
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENETUNREACH))?;
+        let name = self
+            .links()?
+            .into_iter()
+            .find(|l| l.index == index)
+            .map(|l| l.name)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENXIO))?;
+        let source = match route.prefsrc {
+            Some(src) => src,
+            None => {
+                let candidates = self
+                    .addresses()?
+                    .into_iter()
+                    .filter(|a| a.index == index)
+                    .map(|a| a.addr)
+                    .filter(|a| a.is_ipv4() == dst.is_ipv4())
+                    .collect::<Vec<_>>();
+                // Don't pick a link-local source for a global destination
+                candidates
+                    .iter()
+                    .find(|a| is_link_local(a) == is_link_local(&dst))
+                    .or_else(|| candidates.first())
+                    .copied()
+                    .ok_or_else(|| {
+                        io::Error::from_raw_os_error(libc::EADDRNOTAVAIL)
+                    })?
+            }
+        };
+        Ok(OutgoingRoute {
+            name,
+            index,
+            source,
+            gateway: route.gateway,
+        })
+    }
+}
+
+impl RouteLookup for CapNetAgent {
+    fn route_lookup(&mut self, dst: IpAddr) -> io::Result<OutgoingRoute> {
+        let family = if dst.is_ipv4() {
+            AddressFamily::Inet
+        } else {
+            AddressFamily::Inet6
+        };
+        let sock = nix::sys::socket::socket(
+            family,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        // Connecting a UDP socket sends nothing, but makes the kernel choose a
+        // source address.  Port 9 is "discard".
+        self.connect_std_fd(sock.as_fd(), SocketAddr::new(dst, 9))?;
+        let source = UdpSocket::from(sock).local_addr()?.ip();
+        let iface = interfaces()?
+            .into_iter()
+            .find(|i| i.addrs.iter().any(|a| a.addr == source))
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENXIO))?;
+        Ok(OutgoingRoute {
+            name: iface.name,
+            index: iface.index,
+            source,
+            gateway: None,
+        })
+    }
+}
+
+/// Find the interface and source address that the kernel would use to reach
+/// `dst`.
+///
+/// Multi-homed daemons can use this to choose the correct address to bind.
+///
+/// # Examples
+/// ```no_run
+/// use std::net::Ipv4Addr;
+///
+/// use capsicum_net::{ifaces::route_lookup, netlink::NetlinkRoute};
+///
+/// let mut nl = NetlinkRoute::new().unwrap();
+/// let route = route_lookup(&mut nl, Ipv4Addr::new(8, 8, 8, 8).into()).unwrap();
+/// println!("via {} from {}", route.name, route.source);
+/// ```
+pub fn route_lookup<R>(via: &mut R, dst: IpAddr) -> io::Result<OutgoingRoute>
+where
+    R: RouteLookup + ?Sized,
+{
+    via.route_lookup(dst)
+}
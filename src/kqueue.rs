@@ -0,0 +1,168 @@
+// vim: tw=80
+//! kqueue-based helpers for nonblocking sockets
+//!
+//! Applications that don't use tokio can still perform nonblocking
+//! Casper-assisted networking.  Put the socket into nonblocking mode before
+//! calling `cap_connect`; if that fails with `EINPROGRESS`, use
+//! [`wait_connect`] to wait for the connection to complete.  Likewise, use
+//! [`wait_accept`] to wait until a nonblocking listener has a pending
+//! connection.  Both functions work in capability mode.
+//!
+//! # Example
+//! ```no_run
+//! use std::{
+//!     io,
+//!     net::{Ipv4Addr, SocketAddr, TcpListener},
+//!     str::FromStr,
+//!     time::Duration,
+//! };
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{kqueue::wait_accept, std::TcpListenerExt, CasperExt};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let addr = SocketAddr::from_str("127.0.0.1:8086").unwrap();
+//! let listener = TcpListener::cap_bind(&mut cap_net, addr).unwrap();
+//! listener.set_nonblocking(true).unwrap();
+//! wait_accept(&listener, Some(Duration::from_secs(5))).unwrap();
+//! let (stream, peer) = listener.accept().unwrap();
+//! ```
+use std::{
+    io,
+    mem,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    ptr,
+    time::{Duration, Instant},
+};
+
+use nix::sys::socket::{getsockopt, sockopt::SocketError};
+
+/// Register `changes` with a new kqueue, and wait for the first of them to
+/// trigger.
+///
+/// Returns the triggered event, or `None` if `timeout` elapses first.
+fn wait_events(
+    changes: &[libc::kevent],
+    timeout: Option<Duration>,
+) -> io::Result<Option<libc::kevent>> {
+    let kq = unsafe { libc::kqueue() };
+    if kq < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safe because kqueue just returned a new file descriptor
+    let kq = unsafe { OwnedFd::from_raw_fd(kq) };
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut nchanges = changes.len() as libc::c_int;
+    loop {
+        let ts = deadline.map(|d| {
+            let remaining = d.saturating_duration_since(Instant::now());
+            libc::timespec {
+                tv_sec:  remaining.as_secs() as libc::time_t,
+                tv_nsec: remaining.subsec_nanos() as libc::c_long,
+            }
+        });
+        let mut event: libc::kevent = unsafe { mem::zeroed() };
+        let r = unsafe {
+            libc::kevent(
+                kq.as_raw_fd(),
+                changes.as_ptr(),
+                nchanges,
+                &mut event,
+                1,
+                ts.as_ref().map_or(ptr::null(), |ts| ts as *const _),
+            )
+        };
+        if r < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                // The events are already registered; don't add them twice.
+                nchanges = 0;
+                continue;
+            }
+            return Err(e);
+        } else if r == 0 {
+            return Ok(None);
+        } else if event.flags & libc::EV_ERROR != 0 && event.data != 0 {
+            return Err(io::Error::from_raw_os_error(event.data as i32));
+        }
+        return Ok(Some(event));
+    }
+}
+
+/// A one-shot registration of `filter` on `fd`, tagged with `udata`
+fn oneshot(fd: BorrowedFd, filter: i16, udata: usize) -> libc::kevent {
+    // Safe because kevent is a plain C struct
+    let mut change: libc::kevent = unsafe { mem::zeroed() };
+    change.ident = fd.as_raw_fd() as libc::uintptr_t;
+    change.filter = filter;
+    change.flags = libc::EV_ADD | libc::EV_ONESHOT;
+    change.udata = udata as *mut libc::c_void;
+    change
+}
+
+/// Wait until `fd` triggers `filter`, or until `timeout` elapses.
+fn wait<F: AsFd>(
+    fd: &F,
+    filter: i16,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    match wait_events(&[oneshot(fd.as_fd(), filter, 0)], timeout)? {
+        Some(_) => Ok(()),
+        None => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "timed out waiting for socket",
+        )),
+    }
+}
+
+/// Wait for a nonblocking `connect` to complete.
+///
+/// Call this after `cap_connect` fails with `EINPROGRESS`.  Returns the
+/// connection's error status, if any, or `ErrorKind::TimedOut` if the
+/// connection does not complete within `timeout`.  A `timeout` of `None` waits
+/// forever.
+pub fn wait_connect<F: AsFd>(
+    sock: &F,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    wait(sock, libc::EVFILT_WRITE, timeout)?;
+    match getsockopt(sock, SocketError)? {
+        0 => Ok(()),
+        e => Err(io::Error::from_raw_os_error(e)),
+    }
+}
+
+/// Wait until a nonblocking listening socket has a connection ready to
+/// `accept`.
+///
+/// Fails with `ErrorKind::TimedOut` if no connection arrives within `timeout`.
+/// A `timeout` of `None` waits forever.
+pub fn wait_accept<F: AsFd>(
+    listener: &F,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    wait(listener, libc::EVFILT_READ, timeout)
+}
+
+/// Wait until any of `socks` becomes writable, as a nonblocking `connect`
+/// does when it completes or fails.
+///
+/// Returns the index of such a socket, or `None` if `timeout` elapses first.
+/// The caller must check the socket's `SO_ERROR` itself.
+pub(crate) fn wait_any_writable(
+    socks: &[BorrowedFd],
+    timeout: Option<Duration>,
+) -> io::Result<Option<usize>> {
+    let changes = socks
+        .iter()
+        .enumerate()
+        .map(|(i, fd)| oneshot(*fd, libc::EVFILT_WRITE, i))
+        .collect::<Vec<_>>();
+    let event = wait_events(&changes, timeout)?;
+    Ok(event.map(|e| e.udata as usize))
+}
@@ -0,0 +1,199 @@
+// vim: tw=80
+//! Kernel TLS offload for Casper-connected sockets
+//!
+//! After completing a [rustls](https://docs.rs/rustls) handshake over a socket,
+//! [`enable`] hands the negotiated session keys to FreeBSD's kernel TLS
+//! implementation, [ktls(4)](https://man.freebsd.org/cgi/man.cgi?query=ktls).
+//! From then on, plain `read` and `write` calls on the socket transparently
+//! decrypt and encrypt TLS records, and zero-copy transmission with
+//! `sendfile(2)` becomes possible.  ktls must be enabled with the
+//! `kern.ipc.tls.enable` sysctl.
+//!
+//! The rustls `ClientConfig` or `ServerConfig` must have
+//! `enable_secret_extraction` set.
+//!
+//! # Example
+//! ```no_run
+//! use std::{io::Write, net::TcpStream, sync::Arc};
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, ktls, std::TcpStreamExt};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! // Use whichever crypto provider the application installed.
+//! let provider = rustls::crypto::CryptoProvider::get_default()
+//!     .expect("no rustls crypto provider installed")
+//!     .clone();
+//! // Add trusted CA certificates here, for example from webpki-roots.
+//! let roots = rustls::RootCertStore::empty();
+//! let mut config = rustls::ClientConfig::builder_with_provider(provider)
+//!     .with_safe_default_protocol_versions()
+//!     .unwrap()
+//!     .with_root_certificates(roots)
+//!     .with_no_client_auth();
+//! config.enable_secret_extraction = true;
+//! let config = Arc::new(config);
+//!
+//! capsicum::enter();
+//!
+//! let mut sock = TcpStream::cap_connect(&mut cap_net, "192.0.2.1:443")
+//!     .unwrap();
+//! let name = "example.com".try_into().unwrap();
+//! let mut conn = rustls::ClientConnection::new(config, name).unwrap();
+//! while conn.is_handshaking() {
+//!     conn.complete_io(&mut sock).unwrap();
+//! }
+//! ktls::enable(&sock, conn).unwrap();
+//! sock.write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+//! ```
+use std::{
+    io,
+    mem,
+    os::fd::{AsFd, AsRawFd, BorrowedFd},
+    ptr,
+};
+
+use rustls::{
+    Connection,
+    ConnectionTrafficSecrets,
+    ExtractedSecrets,
+    ProtocolVersion,
+};
+
+// From FreeBSD's netinet/tcp.h
+const TCP_TXTLS_ENABLE: libc::c_int = 39;
+const TCP_RXTLS_ENABLE: libc::c_int = 41;
+
+// From FreeBSD's opencrypto/cryptodev.h
+const CRYPTO_AES_NIST_GCM_16: libc::c_int = 25;
+const CRYPTO_CHACHA20_POLY1305: libc::c_int = 41;
+
+// From FreeBSD's sys/ktls.h
+const TLS_MAJOR_VER_ONE: u8 = 3;
+const TLS_MINOR_VER_TWO: u8 = 3;
+const TLS_MINOR_VER_THREE: u8 = 4;
+/// Length of the implicit part of the nonce for TLS 1.2 AES-GCM
+const TLS_AEAD_GCM_LEN: usize = 4;
+
+/// FreeBSD's `struct tls_enable`, from sys/ktls.h
+#[repr(C)]
+struct TlsEnable {
+    cipher_key:       *const u8,
+    iv:               *const u8,
+    auth_key:         *const u8,
+    cipher_algorithm: libc::c_int,
+    cipher_key_len:   libc::c_int,
+    iv_len:           libc::c_int,
+    auth_algorithm:   libc::c_int,
+    auth_key_len:     libc::c_int,
+    flags:            libc::c_int,
+    tls_vmajor:       u8,
+    tls_vminor:       u8,
+    rec_seq:          [u8; 8],
+}
+
+/// Install one direction's session keys into the kernel.
+fn install(
+    fd: BorrowedFd,
+    name: libc::c_int,
+    minor: u8,
+    seq: u64,
+    secrets: &ConnectionTrafficSecrets,
+) -> io::Result<()> {
+    let (alg, key, iv) = match secrets {
+        ConnectionTrafficSecrets::Aes128Gcm { key, iv }
+        | ConnectionTrafficSecrets::Aes256Gcm { key, iv } => {
+            let iv = if minor == TLS_MINOR_VER_TWO {
+                // The kernel generates the explicit part of the nonce.
+                &iv.as_ref()[..TLS_AEAD_GCM_LEN]
+            } else {
+                iv.as_ref()
+            };
+            (CRYPTO_AES_NIST_GCM_16, key.as_ref(), iv)
+        }
+        ConnectionTrafficSecrets::Chacha20Poly1305 { key, iv } => {
+            (CRYPTO_CHACHA20_POLY1305, key.as_ref(), iv.as_ref())
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cipher suite not supported by ktls",
+            ))
+        }
+    };
+    let en = TlsEnable {
+        cipher_key:       key.as_ptr(),
+        iv:               iv.as_ptr(),
+        auth_key:         ptr::null(),
+        cipher_algorithm: alg,
+        cipher_key_len:   key.len() as libc::c_int,
+        iv_len:           iv.len() as libc::c_int,
+        auth_algorithm:   0,
+        auth_key_len:     0,
+        flags:            0,
+        tls_vmajor:       TLS_MAJOR_VER_ONE,
+        tls_vminor:       minor,
+        rec_seq:          seq.to_be_bytes(),
+    };
+    let r = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            name,
+            (&en as *const TlsEnable).cast(),
+            mem::size_of::<TlsEnable>() as libc::socklen_t,
+        )
+    };
+    if r < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Offload an established TLS session on `sock` to the kernel.
+///
+/// `conn` must have finished its handshake and must not have any buffered
+/// data waiting to be written or read.  Both the transmit and receive
+/// directions are offloaded.  On failure, `sock` should be closed, because
+/// the TLS session state is lost.
+pub fn enable<F, C>(sock: &F, conn: C) -> io::Result<()>
+where
+    F: AsFd,
+    C: Into<Connection>,
+{
+    let mut conn = conn.into();
+    if conn.is_handshaking() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "TLS handshake is not complete",
+        ));
+    }
+    if conn.wants_write()
+        || conn
+            .process_new_packets()
+            .map_or(true, |s| s.plaintext_bytes_to_read() > 0)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "TLS connection has buffered data",
+        ));
+    }
+    let minor = match conn.protocol_version() {
+        Some(ProtocolVersion::TLSv1_2) => TLS_MINOR_VER_TWO,
+        Some(ProtocolVersion::TLSv1_3) => TLS_MINOR_VER_THREE,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "TLS version not supported by ktls",
+            ))
+        }
+    };
+    let ExtractedSecrets { tx, rx } =
+        conn.dangerous_extract_secrets().map_err(io::Error::other)?;
+    install(sock.as_fd(), TCP_TXTLS_ENABLE, minor, tx.0, &tx.1)?;
+    install(sock.as_fd(), TCP_RXTLS_ENABLE, minor, rx.0, &rx.1)
+}
@@ -45,11 +45,12 @@
 #![cfg_attr(docsrs, feature(doc_cfg))]
 #![warn(missing_docs)]
 use ::std::{
+    ffi::{CStr, CString},
     io,
-    marker::PhantomData,
-    net::ToSocketAddrs,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
     os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd},
     path::Path,
+    sync::{Arc, Mutex},
 };
 use bitflags::bitflags;
 use capsicum::casper;
@@ -58,6 +59,7 @@ use nix::{
     sys::socket::{
         AddressFamily,
         SockFlag,
+        SockProtocol,
         SockType,
         SockaddrIn,
         SockaddrIn6,
@@ -66,22 +68,390 @@ use nix::{
     Result,
 };
 
+use crate::{
+    acl::{AccessDenied, AccessList, Action},
+    addrinfo::{AddrInfoHints, AddrInfoList},
+    ratelimit::RateLimiter,
+};
+
+#[cfg_attr(not(feature = "deprecated-dns"), allow(dead_code))]
 mod ffi;
+mod probes;
 
+pub mod acl;
+pub mod activation;
+pub mod addrinfo;
+pub mod batch;
+pub mod broker;
+#[cfg(feature = "codec")]
+pub mod codec;
+pub mod engine;
+#[cfg(feature = "hickory")]
+pub mod hickory;
+#[cfg(feature = "deprecated-dns")]
+pub mod hostent;
+#[cfg(feature = "hyper-util")]
+pub mod hyper_util;
+pub mod ifaces;
+pub mod kqueue;
+#[cfg(feature = "ktls")]
+#[cfg_attr(docsrs, doc(cfg(feature = "ktls")))]
+pub mod ktls;
+pub mod limited;
+pub mod listeners;
+pub mod netlink;
+pub mod ping;
+pub mod policy;
+pub mod privdrop;
+pub mod ratelimit;
+pub mod reconnect;
+pub mod registry;
+#[cfg(feature = "reqwest")]
+pub mod reqwest;
+pub mod resolver;
+pub mod route;
+pub mod sockaddr;
 pub mod std;
+#[cfg(feature = "stream")]
+pub mod stream;
+pub mod tcp;
+pub mod template;
 #[cfg(feature = "tokio")]
 pub mod tokio;
+#[cfg(feature = "ureq")]
+pub mod ureq;
+#[cfg(feature = "url")]
+pub mod url;
+
+/// Register this crate's USDT probes with DTrace.
+///
+/// Applications should call this once, early during startup.  See
+/// [the `usdt` crate](https://docs.rs/usdt) for details.
+#[cfg(feature = "usdt")]
+#[cfg_attr(docsrs, doc(cfg(feature = "usdt")))]
+pub use usdt::register_probes;
+
+/// A connection to the Casper
+/// [cap_net(3)](https://man.freebsd.org/cgi/man.cgi?query=cap_net) service.
+#[derive(Debug)]
+pub struct CapNetAgent {
+    chan:            casper::CapChannel,
+    acl:             Option<AccessList>,
+    connect_limiter: Option<Arc<Mutex<RateLimiter>>>,
+    applied:         Option<template::AppliedLimits>,
+    on_denied:       Option<DeniedHook>,
+}
+
+/// A callback for operations that Casper refused.  See
+/// [`CapNetAgent::on_denied`].
+pub type DeniedFn = dyn FnMut(LimitFlags, &SocketAddr, Errno) + Send;
+
+struct DeniedHook(Box<DeniedFn>);
+
+impl ::std::fmt::Debug for DeniedHook {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        f.write_str("DeniedHook")
+    }
+}
+
+/// Extension trait for [`::capsicum::casper::Casper`] that spawns this service.
+pub trait CasperExt {
+    /// Spawn the net service.
+    fn net(&mut self) -> io::Result<CapNetAgent>;
+
+    /// Spawn the net service, and limit it before returning it.
+    ///
+    /// Only the operations in `flags` will be permitted.  Each of `addrs` is
+    /// allowed for binding if `flags` includes [`LimitFlags::BIND`], and for
+    /// connecting if it includes [`LimitFlags::CONNECT`].  If the limit can't
+    /// be applied, the agent is closed and only the error is returned, so an
+    /// unlimited agent never escapes.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if `addrs` is empty but `flags`
+    /// includes `BIND` or `CONNECT`, because cap_net would then allow binding
+    /// or connecting to any address.
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::TcpListener;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, LimitFlags, std::TcpListenerExt};
+    ///
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let addr = "127.0.0.1:8088".parse().unwrap();
+    /// let mut cap_net = casper.net_limited(LimitFlags::BIND, &[addr]).unwrap();
+    /// let listener = TcpListener::cap_bind(&mut cap_net, addr).unwrap();
+    /// ```
+    fn net_limited(
+        &mut self,
+        flags: LimitFlags,
+        addrs: &[SocketAddr],
+    ) -> io::Result<CapNetAgent>;
+}
+
+impl CasperExt for casper::Casper {
+    fn net(&mut self) -> io::Result<CapNetAgent> {
+        self.service_open(c"system.net").map(CapNetAgent::new)
+    }
+
+    fn net_limited(
+        &mut self,
+        flags: LimitFlags,
+        addrs: &[SocketAddr],
+    ) -> io::Result<CapNetAgent> {
+        if addrs.is_empty()
+            && flags.intersects(LimitFlags::BIND | LimitFlags::CONNECT)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no addresses to allow for binding or connecting",
+            ));
+        }
+        let mut agent = self.net()?;
+        let mut limit = agent.limit(flags)?;
+        for addr in addrs {
+            if flags.contains(LimitFlags::BIND) {
+                limit.bind_std(*addr);
+            }
+            if flags.contains(LimitFlags::CONNECT) {
+                limit.connect_std(*addr);
+            }
+        }
+        limit.limit()?;
+        Ok(agent)
+    }
+}
+
+/// Values that can be resolved to socket addresses, using a `cap_net` service
+/// for any name lookups.
+///
+/// This takes the place of `std::net::ToSocketAddrs` throughout this crate,
+/// and is implemented for the same types.  Numeric addresses are converted
+/// directly, but hostnames are resolved with [`CapNetAgent::resolve`] on the
+/// same agent that will bind or connect the socket.  So unlike with
+/// `ToSocketAddrs`, strings like `"example.com:443"` work in capability mode.
+pub trait CapToSocketAddrs {
+    /// Convert this value into a list of socket addresses, resolving any
+    /// hostname with `agent`.
+    fn cap_to_socket_addrs(
+        &self,
+        agent: &mut CapNetAgent,
+    ) -> io::Result<Vec<SocketAddr>>;
+}
+
+macro_rules! numeric_to_socket_addrs {
+    ($($t:ty),*) => {
+        $(
+            impl CapToSocketAddrs for $t {
+                fn cap_to_socket_addrs(
+                    &self,
+                    _agent: &mut CapNetAgent,
+                ) -> io::Result<Vec<SocketAddr>> {
+                    Ok(self.to_socket_addrs()?.collect())
+                }
+            }
+        )*
+    };
+}
+
+numeric_to_socket_addrs!(
+    SocketAddr,
+    ::std::net::SocketAddrV4,
+    ::std::net::SocketAddrV6,
+    (IpAddr, u16),
+    (::std::net::Ipv4Addr, u16),
+    (::std::net::Ipv6Addr, u16)
+);
+
+impl CapToSocketAddrs for (&str, u16) {
+    fn cap_to_socket_addrs(
+        &self,
+        agent: &mut CapNetAgent,
+    ) -> io::Result<Vec<SocketAddr>> {
+        let (host, port) = *self;
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        agent.resolve(host, port)
+    }
+}
+
+impl CapToSocketAddrs for (String, u16) {
+    fn cap_to_socket_addrs(
+        &self,
+        agent: &mut CapNetAgent,
+    ) -> io::Result<Vec<SocketAddr>> {
+        (self.0.as_str(), self.1).cap_to_socket_addrs(agent)
+    }
+}
+
+impl CapToSocketAddrs for str {
+    fn cap_to_socket_addrs(
+        &self,
+        agent: &mut CapNetAgent,
+    ) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(addr) = self.parse::<SocketAddr>() {
+            return Ok(vec![addr]);
+        }
+        let (host, port) = self.rsplit_once(':').ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid socket address",
+            )
+        })?;
+        let port = port.parse::<u16>().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "invalid port value")
+        })?;
+        (host, port).cap_to_socket_addrs(agent)
+    }
+}
 
-casper::service_connection! {
-    /// A connection to the Casper
-    /// [cap_net(3)](https://man.freebsd.org/cgi/man.cgi?query=cap_net) service.
-    #[derive(Debug)]
-    pub CapNetAgent,
-    c"system.net",
-    net
+impl CapToSocketAddrs for String {
+    fn cap_to_socket_addrs(
+        &self,
+        agent: &mut CapNetAgent,
+    ) -> io::Result<Vec<SocketAddr>> {
+        self.as_str().cap_to_socket_addrs(agent)
+    }
+}
+
+impl CapToSocketAddrs for [SocketAddr] {
+    fn cap_to_socket_addrs(
+        &self,
+        _agent: &mut CapNetAgent,
+    ) -> io::Result<Vec<SocketAddr>> {
+        Ok(self.to_vec())
+    }
+}
+
+impl<T: CapToSocketAddrs + ?Sized> CapToSocketAddrs for &T {
+    fn cap_to_socket_addrs(
+        &self,
+        agent: &mut CapNetAgent,
+    ) -> io::Result<Vec<SocketAddr>> {
+        (**self).cap_to_socket_addrs(agent)
+    }
 }
 
 impl CapNetAgent {
+    fn new(chan: casper::CapChannel) -> Self {
+        CapNetAgent {
+            chan,
+            acl: None,
+            connect_limiter: None,
+            applied: None,
+            on_denied: None,
+        }
+    }
+
+    /// Check every bind and connect operation against an in-process access
+    /// list before attempting it.  `None` removes any existing list.
+    ///
+    /// See [`acl`] for details.
+    pub fn set_access_list(&mut self, acl: Option<AccessList>) {
+        self.acl = acl;
+    }
+
+    /// Consult the access list, if any, about operation `op` on a raw
+    /// sockaddr.  Fails with `EACCES` if it's denied.
+    fn check_access(
+        &self,
+        op: LimitFlags,
+        addr: *const libc::sockaddr,
+        len: libc::socklen_t,
+    ) -> Result<()> {
+        let Some(acl) = &self.acl else { return Ok(()) };
+        let action = if let Some(addr) = sockaddr::from_raw(addr, len) {
+            acl.check(op, &addr)
+        } else {
+            // Safe because our callers always pass a valid sockaddr and length.
+            let ss = unsafe {
+                nix::sys::socket::SockaddrStorage::from_raw(addr, Some(len))
+            };
+            match ss.as_ref().and_then(|ss| ss.as_unix_addr()?.path()) {
+                Some(path) => acl.check_path(op, path),
+                None => Action::Allow,
+            }
+        };
+        match action {
+            Action::Allow => Ok(()),
+            Action::Deny => Err(Errno::EACCES),
+        }
+    }
+
+    /// Call `hook` whenever Casper refuses a bind or connect with
+    /// `ENOTCAPABLE`.
+    ///
+    /// The hook receives the operation, either `LimitFlags::BIND` or
+    /// `LimitFlags::CONNECT`, its IPv4 or IPv6 address, and the error.  It lets
+    /// an application log or alert on policy violations without wrapping every
+    /// call site.  Operations on unix-domain sockets, and those denied by an
+    /// [`AccessList`], aren't reported.  `None` removes any existing hook.
+    /// Agents created by [`CapNetAgent::split`] don't inherit it.
+    ///
+    /// # Example
+    /// ```
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::CasperExt;
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// cap_net.on_denied(Some(Box::new(|op, addr, errno| {
+    ///     eprintln!("{op} {addr} refused: {errno}");
+    /// })));
+    /// ```
+    pub fn on_denied(&mut self, hook: Option<Box<DeniedFn>>) {
+        self.on_denied = hook.map(DeniedHook);
+    }
+
+    /// Report a failed operation to the `on_denied` hook, if it was refused
+    /// by Casper.
+    fn report_denied(
+        &mut self,
+        op: LimitFlags,
+        addr: *const libc::sockaddr,
+        len: libc::socklen_t,
+        res: &Result<()>,
+    ) {
+        let Some(hook) = self.on_denied.as_mut() else {
+            return;
+        };
+        if res != &Err(Errno::ENOTCAPABLE) {
+            return;
+        }
+        if let Some(addr) = sockaddr::from_raw(addr, len) {
+            (hook.0)(op, &addr, Errno::ENOTCAPABLE);
+        }
+    }
+
+    /// Throttle all connect operations performed through this agent.
+    ///
+    /// Synchronous connect methods sleep until the limiter allows them to
+    /// proceed.  Asynchronous ones, like
+    /// [`tokio::cap_connect`], wait without
+    /// blocking the runtime.  `None` removes any existing limit.
+    pub fn set_connect_rate_limit(&mut self, limiter: Option<RateLimiter>) {
+        self.connect_limiter = limiter.map(|l| Arc::new(Mutex::new(l)));
+    }
+
+    /// Wait for the connect rate limiter to allow one more connect operation,
+    /// sleeping if necessary.
+    fn connect_permit(&self) -> ratelimit::Permit {
+        ratelimit::Permit::acquire(self.connect_limiter.as_ref())
+    }
+
+    /// Like [`connect_permit`](Self::connect_permit), but wait asynchronously.
+    /// The returned future doesn't borrow the agent.
+    #[cfg(feature = "tokio")]
+    fn connect_permit_async(
+        &self,
+    ) -> impl ::std::future::Future<Output = ratelimit::Permit> + Send + 'static
+    {
+        ratelimit::Permit::acquire_async(self.connect_limiter.clone())
+    }
+
     /// A low-level bind(2) workalike, but in capability mode.
     ///
     /// # Examples
@@ -110,11 +480,37 @@ impl CapNetAgent {
     where
         F: AsFd,
     {
-        let fd = sock.as_fd().as_raw_fd();
-        let res = unsafe {
-            ffi::cap_bind(self.0.as_mut_ptr(), fd, addr.as_ptr(), addr.len())
-        };
-        Errno::result(res).map(drop)
+        self.bind_raw(sock.as_fd(), addr.as_ptr(), addr.len())
+    }
+
+    /// Helper that binds a raw socket to a raw sockaddr, after consulting the
+    /// access list.
+    fn bind_raw(
+        &mut self,
+        sock: BorrowedFd,
+        addr: *const libc::sockaddr,
+        len: libc::socklen_t,
+    ) -> Result<()> {
+        self.check_access(LimitFlags::BIND, addr, len)?;
+        self.bind_raw_unchecked(sock, addr, len)
+    }
+
+    /// Like `bind_raw`, but for callers that already consulted the access
+    /// list.  Every bind operation goes through here.
+    fn bind_raw_unchecked(
+        &mut self,
+        sock: BorrowedFd,
+        addr: *const libc::sockaddr,
+        len: libc::socklen_t,
+    ) -> Result<()> {
+        let fd = sock.as_raw_fd();
+        probes::bind__start!(|| (fd, probes::fmt_sockaddr(addr, len)));
+        let res =
+            unsafe { ffi::cap_bind(self.chan.as_mut_ptr(), fd, addr, len) };
+        let res = Errno::result(res).map(drop);
+        probes::bind__done!(|| (fd, probes::errno(&res)));
+        self.report_denied(LimitFlags::BIND, addr, len, &res);
+        res
     }
 
     /// Helper that binds a raw socket to a std sockaddr
@@ -123,9 +519,16 @@ impl CapNetAgent {
         sock: BorrowedFd,
         addr: ::std::net::SocketAddr,
     ) -> io::Result<()> {
-        let ap = self.0.as_mut_ptr();
-        let fd = sock.as_raw_fd();
-        let res = match addr {
+        if let Some(acl) = &self.acl {
+            if acl.check(LimitFlags::BIND, &addr) == Action::Deny {
+                return Err(AccessDenied {
+                    op: LimitFlags::BIND,
+                    addr,
+                }
+                .into());
+            }
+        }
+        match addr {
             // Even though std::net::SocketAddrV4 is probably stored identically
             // to libc::sockaddr_in, that isn't guaranteed, so we must convert
             // it.  Nix's representation _is_ guaranteed.  Ditto for
@@ -136,28 +539,73 @@ impl CapNetAgent {
             // tokio thread.
             ::std::net::SocketAddr::V4(addr) => {
                 let sin = SockaddrIn::from(addr);
-                unsafe { ffi::cap_bind(ap, fd, sin.as_ptr(), sin.len()) }
+                self.bind_raw_unchecked(sock, sin.as_ptr(), sin.len())
             }
             ::std::net::SocketAddr::V6(addr) => {
                 let sin6 = SockaddrIn6::from(addr);
-                unsafe { ffi::cap_bind(ap, fd, sin6.as_ptr(), sin6.len()) }
+                self.bind_raw_unchecked(sock, sin6.as_ptr(), sin6.len())
             }
-        };
-        if res == 0 {
-            Ok(())
-        } else {
-            Err(io::Error::last_os_error())
         }
+        .map_err(io::Error::from)
+    }
+
+    /// Create a new socket of type `sock_type` and bind it to an address.
+    ///
+    /// Each address that `addrs` resolves to is tried in turn, with a fresh
+    /// socket of the matching family, until one succeeds.  Returns the socket
+    /// and the address that it was bound to.  This is the generic form of
+    /// helpers like [`UdpSocketExt::cap_bind`], for socket kinds that have
+    /// none, like SCTP.
+    ///
+    /// [`UdpSocketExt::cap_bind`]: crate::std::UdpSocketExt::cap_bind
+    ///
+    /// # Examples
+    /// ```
+    /// use std::{net::{SocketAddr, UdpSocket}, os::fd::OwnedFd};
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::CasperExt;
+    /// use nix::sys::socket::SockType;
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// let (sock, addr): (OwnedFd, SocketAddr) = cap_net
+    ///     .bind_new("127.0.0.1:8097", SockType::Datagram, None)
+    ///     .unwrap();
+    /// let socket = UdpSocket::from(sock);
+    /// ```
+    pub fn bind_new<A, S>(
+        &mut self,
+        addrs: A,
+        sock_type: SockType,
+        protocol: Option<SockProtocol>,
+    ) -> io::Result<(S, ::std::net::SocketAddr)>
+    where
+        A: CapToSocketAddrs,
+        S: From<OwnedFd>,
+    {
+        self.bind_std_to_addrs(addrs, sock_type, protocol, |_, _| Ok(()))
     }
 
-    /// Private helper used by the std extension traits
-    fn bind_std_to_addrs<A, S>(&mut self, addrs: A) -> io::Result<S>
+    /// Private helper used by [`bind_new`](Self::bind_new) and the std
+    /// extension traits.  `prepare` is called on each new socket before
+    /// binding it, to set any socket options.
+    fn bind_std_to_addrs<A, S, F>(
+        &mut self,
+        addrs: A,
+        sock_type: SockType,
+        protocol: Option<SockProtocol>,
+        prepare: F,
+    ) -> io::Result<(S, ::std::net::SocketAddr)>
     where
-        A: ToSocketAddrs,
+        A: CapToSocketAddrs,
         S: From<OwnedFd>,
+        F: Fn(BorrowedFd, AddressFamily) -> io::Result<()>,
     {
         let mut last_err = None;
-        for addr in addrs.to_socket_addrs()? {
+        for addr in addrs.cap_to_socket_addrs(self)? {
             let family = if addr.is_ipv4() {
                 AddressFamily::Inet
             } else {
@@ -165,13 +613,15 @@ impl CapNetAgent {
             };
             let sock = nix::sys::socket::socket(
                 family,
-                SockType::Stream,
+                sock_type,
                 SockFlag::empty(),
-                None,
+                protocol,
             )
             .map_err(io::Error::from)?;
-            match self.bind_std_fd(sock.as_fd(), addr) {
-                Ok(()) => return Ok(S::from(sock)),
+            match prepare(sock.as_fd(), family)
+                .and_then(|()| self.bind_std_fd(sock.as_fd(), addr))
+            {
+                Ok(()) => return Ok((S::from(sock), addr)),
                 Err(e) => {
                     last_err = Some(e);
                 }
@@ -185,11 +635,13 @@ impl CapNetAgent {
         }))
     }
 
-    /// Helper that creates a new std socket and binds it to a unix path
+    /// Helper that creates a new std socket and binds it to a unix path,
+    /// optionally setting the socket file's mode first.
     fn bind_std_unix<P>(
         &mut self,
         sock_type: SockType,
         path: P,
+        mode: Option<libc::mode_t>,
     ) -> io::Result<OwnedFd>
     where
         P: AsRef<Path>,
@@ -199,13 +651,41 @@ impl CapNetAgent {
             sock_type,
             SockFlag::empty(),
             None,
-        )
-        .unwrap();
-        let want = nix::sys::socket::UnixAddr::new(path.as_ref()).unwrap();
+        )?;
+        let want = nix::sys::socket::UnixAddr::new(path.as_ref())?;
+        if let Some(mode) = mode {
+            // The file is created by the Casper service, which we can't
+            // chmod afterwards in capability mode.  But FreeBSD applies an
+            // unbound socket's own mode to the file that bind creates.
+            let r = unsafe { libc::fchmod(s.as_raw_fd(), mode) };
+            if r < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
         self.bind(&s, &want)?;
         Ok(s)
     }
 
+    /// Helper that creates a new std socket and connects it to a unix path
+    fn connect_std_unix<P>(
+        &mut self,
+        sock_type: SockType,
+        path: P,
+    ) -> io::Result<OwnedFd>
+    where
+        P: AsRef<Path>,
+    {
+        let s = nix::sys::socket::socket(
+            AddressFamily::Unix,
+            sock_type,
+            SockFlag::empty(),
+            None,
+        )?;
+        let want = nix::sys::socket::UnixAddr::new(path.as_ref())?;
+        self.connect(&s, &want)?;
+        Ok(s)
+    }
+
     /// A low-level connect(2) workalike, but in capability mode.
     ///
     /// # Examples
@@ -238,11 +718,41 @@ impl CapNetAgent {
     where
         F: AsFd,
     {
-        let fd = sock.as_fd().as_raw_fd();
-        let res = unsafe {
-            ffi::cap_connect(self.0.as_mut_ptr(), fd, addr.as_ptr(), addr.len())
-        };
-        Errno::result(res).map(drop)
+        let permit = self.connect_permit();
+        self.connect_raw(sock.as_fd(), addr.as_ptr(), addr.len(), permit)
+    }
+
+    /// Helper that connects a raw socket to a raw sockaddr, after consulting
+    /// the access list.
+    fn connect_raw(
+        &mut self,
+        sock: BorrowedFd,
+        addr: *const libc::sockaddr,
+        len: libc::socklen_t,
+        permit: ratelimit::Permit,
+    ) -> Result<()> {
+        self.check_access(LimitFlags::CONNECT, addr, len)?;
+        self.connect_raw_unchecked(sock, addr, len, permit)
+    }
+
+    /// Like `connect_raw`, but for callers that already consulted the access
+    /// list.  Every connect operation goes through here, with a permit already
+    /// acquired from the rate limiter.
+    fn connect_raw_unchecked(
+        &mut self,
+        sock: BorrowedFd,
+        addr: *const libc::sockaddr,
+        len: libc::socklen_t,
+        _permit: ratelimit::Permit,
+    ) -> Result<()> {
+        let fd = sock.as_raw_fd();
+        probes::connect__start!(|| (fd, probes::fmt_sockaddr(addr, len)));
+        let res =
+            unsafe { ffi::cap_connect(self.chan.as_mut_ptr(), fd, addr, len) };
+        let res = Errno::result(res).map(drop);
+        probes::connect__done!(|| (fd, probes::errno(&res)));
+        self.report_denied(LimitFlags::CONNECT, addr, len, &res);
+        res
     }
 
     /// Helper that connects a raw socket to a std sockaddr
@@ -251,9 +761,28 @@ impl CapNetAgent {
         sock: BorrowedFd,
         addr: ::std::net::SocketAddr,
     ) -> io::Result<()> {
-        let ap = self.0.as_mut_ptr();
-        let fd = sock.as_raw_fd();
-        let res = match addr {
+        let permit = self.connect_permit();
+        self.connect_std_fd_with(sock, addr, permit)
+    }
+
+    /// Like `connect_std_fd`, but with a rate limiter permit that the caller
+    /// already acquired.
+    fn connect_std_fd_with(
+        &mut self,
+        sock: BorrowedFd,
+        addr: ::std::net::SocketAddr,
+        permit: ratelimit::Permit,
+    ) -> io::Result<()> {
+        if let Some(acl) = &self.acl {
+            if acl.check(LimitFlags::CONNECT, &addr) == Action::Deny {
+                return Err(AccessDenied {
+                    op: LimitFlags::CONNECT,
+                    addr,
+                }
+                .into());
+            }
+        }
+        match addr {
             // Even though std::net::SocketAddrV4 is probably stored identically
             // to libc::sockaddr_in, that isn't guaranteed, so we must convert
             // it.  Nix's representation _is_ guaranteed.  Ditto for
@@ -263,33 +792,40 @@ impl CapNetAgent {
             // TODO: determine if Tokio should be using a thread for this.
             ::std::net::SocketAddr::V4(addr) => {
                 let sin = SockaddrIn::from(addr);
-                unsafe { ffi::cap_connect(ap, fd, sin.as_ptr(), sin.len()) }
+                self.connect_raw_unchecked(
+                    sock,
+                    sin.as_ptr(),
+                    sin.len(),
+                    permit,
+                )
             }
             ::std::net::SocketAddr::V6(addr) => {
                 let sin6 = SockaddrIn6::from(addr);
-                unsafe { ffi::cap_connect(ap, fd, sin6.as_ptr(), sin6.len()) }
+                self.connect_raw_unchecked(
+                    sock,
+                    sin6.as_ptr(),
+                    sin6.len(),
+                    permit,
+                )
             }
-        };
-        if res == 0 {
-            Ok(())
-        } else {
-            Err(io::Error::last_os_error())
         }
+        .map_err(io::Error::from)
     }
 
-    /// Private helper used by the std extension traits
+    /// Private helper used by the std extension traits.  Returns the address
+    /// that the socket was connected to.
     fn connect_std_to_addrs<A>(
         &mut self,
         sock: BorrowedFd,
         addrs: A,
-    ) -> io::Result<()>
+    ) -> io::Result<::std::net::SocketAddr>
     where
-        A: ToSocketAddrs,
+        A: CapToSocketAddrs,
     {
         let mut last_err = None;
-        for addr in addrs.to_socket_addrs()? {
+        for addr in addrs.cap_to_socket_addrs(self)? {
             match self.connect_std_fd(sock, addr) {
-                Ok(()) => return Ok(()),
+                Ok(()) => return Ok(addr),
                 Err(e) => {
                     last_err = Some(e);
                 }
@@ -303,92 +839,1105 @@ impl CapNetAgent {
         }))
     }
 
-    /// Return an opaque handle used to further limit the capabilities of the
+    /// Resolve `host` to a list of socket addresses with port `port`, using the
     /// `cap_net` service.
     ///
-    /// Each time a [`Limit`] is constructed and applied it can reduce, but
-    /// never enlarge, the service's capabilities.
+    /// Unlike `std::net::ToSocketAddrs`, this works in capability mode.  `host`
+    /// may be either a hostname or a numeric address.
     ///
     /// # Example
-    /// ```
-    /// use std::{
-    ///     os::fd::AsRawFd,
-    ///     str::FromStr
-    /// };
+    /// ```no_run
     /// use capsicum::casper::Casper;
-    /// use capsicum_net::{CasperExt, LimitFlags};
-    /// use nix::sys::socket::{SockaddrIn, SockaddrLike};
+    /// use capsicum_net::CasperExt;
     ///
+    /// // Safe because we are single-threaded
     /// let mut casper = unsafe { Casper::new().unwrap() };
     /// let mut cap_net = casper.net().unwrap();
-    /// let mut limit = cap_net.limit(LimitFlags::BIND);
-    /// let addr = SockaddrIn::from_str("127.0.0.1:8083").unwrap();
-    /// limit.bind(&addr);
-    /// limit.limit();
-    /// // Now the service will refuse attempts to bind to any other address or
-    /// // port.
+    ///
+    /// capsicum::enter();
+    ///
+    /// let addrs = cap_net.resolve("www.freebsd.org", 443).unwrap();
     /// ```
-    pub fn limit(&mut self, flags: LimitFlags) -> Limit {
-        let limit = unsafe {
-            ffi::cap_net_limit_init(self.0.as_mut_ptr(), flags.bits())
-        };
-        assert!(!limit.is_null());
-        Limit {
-            limit,
-            phantom: PhantomData,
-        }
+    pub fn resolve(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> io::Result<Vec<::std::net::SocketAddr>> {
+        // Without a socket type, each address would appear once per protocol.
+        let hints = AddrInfoHints::new().socktype(SockType::Stream);
+        self.resolve_with(host, port, &hints)
     }
-}
-
-/// Used to limit which operations will be allowed by the [`CapNetAgent`].
-#[repr(transparent)]
-pub struct Limit<'a> {
-    limit:   *mut ffi::cap_net_limit_t,
-    // Because cap_net_limit_t stores a pointer to cap_channel_t
-    phantom: PhantomData<&'a mut CapNetAgent>,
-}
 
-bitflags! {
-    /// Used by [`CapNetAgent::limit`] to restrict which functions are permitted.
-    pub struct LimitFlags: u64 {
-        /// Allow any of the `cap_bind` methods
-        const BIND = ffi::CAPNET_BIND as u64;
-        /// Allow any of the `cap_connect` methods
-        const CONNECT = ffi::CAPNET_CONNECT as u64;
+    /// Like [`resolve`](Self::resolve), but with control over which results
+    /// are returned.
+    ///
+    /// See [`addrinfo`] for an example.
+    pub fn resolve_with(
+        &mut self,
+        host: &str,
+        port: u16,
+        hints: &AddrInfoHints,
+    ) -> io::Result<Vec<::std::net::SocketAddr>> {
+        let list = self.getaddrinfo(host, port, hints)?;
+        Ok(list.filter_map(|ai| sockaddr::to_std(&ai.addr)).collect())
     }
-}
 
-impl Limit<'_> {
-    /// Limit the `cap_net` service to only allow binding to the given address.
+    /// Look up `host`, returning every result with its socket type and
+    /// protocol.
     ///
-    /// May be called multiple times to allow binding to multiple addresses.
-    pub fn bind(&mut self, sa: &dyn SockaddrLike) -> &mut Self {
-        let newlimit = unsafe {
-            ffi::cap_net_limit_bind(self.limit, sa.as_ptr(), sa.len())
-        };
-        assert_eq!(newlimit, self.limit);
-        self
+    /// This is a wrapper for
+    /// [getaddrinfo(3)](https://man.freebsd.org/cgi/man.cgi?query=getaddrinfo)
+    /// that works in capability mode.  Most callers only need
+    /// [`resolve`](Self::resolve).
+    ///
+    /// With the `idna` feature, `host` may be an internationalized domain
+    /// name.  It will be converted to ASCII before lookup, so
+    /// `"bücher.example"` resolves `"xn--bcher-kva.example"`.  Otherwise, such
+    /// names fail with `ErrorKind::NotFound`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, addrinfo::AddrInfoHints};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// let hints = AddrInfoHints::new();
+    /// for ai in cap_net.getaddrinfo("www.freebsd.org", 443, &hints).unwrap() {
+    ///     println!("{:?} {:?} {}", ai.socktype, ai.protocol, ai.addr);
+    /// }
+    /// ```
+    pub fn getaddrinfo(
+        &mut self,
+        host: &str,
+        port: u16,
+        hints: &AddrInfoHints,
+    ) -> io::Result<AddrInfoList> {
+        let serv = CString::new(port.to_string())?;
+        self.getaddrinfo_raw(host, &serv, hints.to_raw(libc::AI_NUMERICSERV))
     }
 
-    /// Limit the `cap_net` service to only allow connecting to the given
-    /// address.
+    /// Like [`getaddrinfo`](Self::getaddrinfo), but with a service name, like
+    /// `"https"` or `"domain"`, instead of a port number.
     ///
-    /// May be called multiple times to allow connecting to multiple addresses.
-    pub fn connect(&mut self, sa: &dyn SockaddrLike) -> &mut Self {
-        let newlimit = unsafe {
-            ffi::cap_net_limit_connect(self.limit, sa.as_ptr(), sa.len())
+    /// The name is looked up in
+    /// [services(5)](https://man.freebsd.org/cgi/man.cgi?query=services) by
+    /// the Casper service, so this works in capability mode.  A numeric string
+    /// works too.  An unknown service fails with `ErrorKind::NotFound`.
+    pub fn getaddrinfo_service(
+        &mut self,
+        host: &str,
+        service: &str,
+        hints: &AddrInfoHints,
+    ) -> io::Result<AddrInfoList> {
+        let serv = CString::new(service)?;
+        self.getaddrinfo_raw(host, &serv, hints.to_raw(0))
+    }
+
+    fn getaddrinfo_raw(
+        &mut self,
+        host: &str,
+        serv: &CStr,
+        hints: libc::addrinfo,
+    ) -> io::Result<AddrInfoList> {
+        #[cfg(feature = "idna")]
+        let host: &str = &to_ascii_host(host)?;
+        let host = CString::new(host)?;
+        let mut res: *mut libc::addrinfo = ::std::ptr::null_mut();
+        probes::getaddrinfo__start!(|| (
+            host.to_string_lossy(),
+            serv.to_string_lossy()
+        ));
+        let r = unsafe {
+            ffi::cap_getaddrinfo(
+                self.chan.as_mut_ptr(),
+                host.as_ptr(),
+                serv.as_ptr(),
+                &hints,
+                &mut res,
+            )
         };
-        assert_eq!(newlimit, self.limit);
-        self
+        probes::getaddrinfo__done!(|| r);
+        if r != 0 {
+            return Err(gai_error(r));
+        }
+        // Safe because cap_getaddrinfo returned a valid list, that we now own
+        Ok(unsafe { AddrInfoList::from_raw(res) })
+    }
+
+    /// Resolve `host` and open a TCP connection to it on port `port`.
+    ///
+    /// Each resolved address is tried in order, and the first connected socket
+    /// is returned.  The agent must permit both
+    /// [`LimitFlags::NAME2ADDR`] and [`LimitFlags::CONNECT`].  For a
+    /// `std::net::TcpStream`, see
+    /// [`TcpStreamExt::cap_connect_host`](crate::std::TcpStreamExt::cap_connect_host).
+    ///
+    /// # Example
+    /// ```no_run
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::CasperExt;
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// let fd = cap_net.connect_host("www.freebsd.org", 80).unwrap();
+    /// ```
+    pub fn connect_host(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> io::Result<OwnedFd> {
+        crate::std::TcpStreamBuilder::new()
+            .connect(self, (host, port))
+            .map(OwnedFd::from)
+    }
+
+    /// Resolve `host` with Casper, then connect to the first of its addresses
+    /// that accepts a TCP connection on `port`.
+    ///
+    /// This is the flow for an agent limited with [`LimitFlags::CONNECTDNS`],
+    /// which may only connect to addresses that it has itself resolved.
+    /// Unlike [`connect_host`](Self::connect_host), a numeric `host` is still
+    /// passed to Casper, so that its address is added to the permitted set.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, LimitFlags};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// cap_net
+    ///     .limit(LimitFlags::NAME2ADDR | LimitFlags::CONNECTDNS)
+    ///     .unwrap()
+    ///     .limit()
+    ///     .unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// // Connect only to what we resolved
+    /// let fd = cap_net.resolve_and_connect("www.freebsd.org", 80).unwrap();
+    /// ```
+    pub fn resolve_and_connect(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> io::Result<OwnedFd> {
+        let addrs = self.resolve(host, port)?;
+        crate::std::TcpStreamBuilder::new()
+            .connect(self, &addrs[..])
+            .map(OwnedFd::from)
+    }
+
+    /// Like [`connect_host`](Self::connect_host), but with a service name,
+    /// like `"http"`, instead of a port number.
+    ///
+    /// See [`getaddrinfo_service`](Self::getaddrinfo_service).
+    pub fn connect_service(
+        &mut self,
+        host: &str,
+        service: &str,
+    ) -> io::Result<OwnedFd> {
+        let hints = AddrInfoHints::new().socktype(SockType::Stream);
+        let addrs = self
+            .getaddrinfo_service(host, service, &hints)?
+            .filter_map(|ai| sockaddr::to_std(&ai.addr))
+            .collect::<Vec<_>>();
+        crate::std::TcpStreamBuilder::new()
+            .connect(self, &addrs[..])
+            .map(OwnedFd::from)
+    }
+
+    /// Like [`connect_host`](Self::connect_host), but resolve `host` with
+    /// `resolver` instead of with this agent.
+    ///
+    /// Then the agent needs only [`LimitFlags::CONNECT`].
+    pub fn connect_host_with<R: resolver::Resolve + ?Sized>(
+        &mut self,
+        resolver: &mut R,
+        host: &str,
+        port: u16,
+    ) -> io::Result<OwnedFd> {
+        let addrs = resolver::WithResolver::new(resolver, host, port);
+        crate::std::TcpStreamBuilder::new()
+            .connect(self, &addrs)
+            .map(OwnedFd::from)
+    }
+
+    /// Look up a host by name, like the legacy
+    /// [gethostbyname(3)](https://man.freebsd.org/cgi/man.cgi?query=gethostbyname),
+    /// in capability mode.
+    ///
+    /// Only IPv4 addresses are returned.  See [`hostent`] for an example.
+    #[cfg(feature = "deprecated-dns")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "deprecated-dns")))]
+    pub fn gethostbyname(
+        &mut self,
+        name: &str,
+    ) -> io::Result<hostent::HostEnt> {
+        let name = CString::new(name)?;
+        probes::gethostbyname__start!(|| (
+            name.to_string_lossy(),
+            libc::AF_INET
+        ));
+        let res = unsafe {
+            let he =
+                ffi::cap_gethostbyname(self.chan.as_mut_ptr(), name.as_ptr());
+            hostent::HostEnt::from_raw(he)
+        };
+        probes::gethostbyname__done!(|| probes::io_errno(&res));
+        res
+    }
+
+    /// Like [`gethostbyname`](Self::gethostbyname), but return addresses of
+    /// family `af`, which must be `Inet` or `Inet6`.
+    #[cfg(feature = "deprecated-dns")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "deprecated-dns")))]
+    pub fn gethostbyname2(
+        &mut self,
+        name: &str,
+        af: AddressFamily,
+    ) -> io::Result<hostent::HostEnt> {
+        let name = CString::new(name)?;
+        probes::gethostbyname__start!(|| (
+            name.to_string_lossy(),
+            af as libc::c_int
+        ));
+        let res = unsafe {
+            let he = ffi::cap_gethostbyname2(
+                self.chan.as_mut_ptr(),
+                name.as_ptr(),
+                af as libc::c_int,
+            );
+            hostent::HostEnt::from_raw(he)
+        };
+        probes::gethostbyname__done!(|| probes::io_errno(&res));
+        res
+    }
+
+    /// Resolve a socket address to a host name and service name, using the
+    /// `cap_net` service.
+    ///
+    /// This is a wrapper for
+    /// [getnameinfo(3)](https://man.freebsd.org/cgi/man.cgi?query=getnameinfo),
+    /// that works in capability mode.  It requires
+    /// [`LimitFlags::ADDR2NAME`] if the service has been limited.  Returns the
+    /// host and the service, in that order.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::SocketAddr;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, NameInfoFlags};
+    /// use nix::sys::socket::SockaddrStorage;
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// let addr: SocketAddr = "127.0.0.1:22".parse().unwrap();
+    /// let (host, serv) = cap_net
+    ///     .getnameinfo(&SockaddrStorage::from(addr), NameInfoFlags::NAMEREQD)
+    ///     .unwrap();
+    /// ```
+    pub fn getnameinfo(
+        &mut self,
+        sa: &dyn SockaddrLike,
+        flags: NameInfoFlags,
+    ) -> io::Result<(String, String)> {
+        let mut host = [0 as libc::c_char; libc::NI_MAXHOST];
+        // Not yet in libc: NI_MAXSERV from <netdb.h>
+        let mut serv = [0 as libc::c_char; 32];
+        probes::getnameinfo__start!(|| probes::fmt_sockaddr(
+            sa.as_ptr(),
+            sa.len()
+        ));
+        let r = unsafe {
+            ffi::cap_getnameinfo(
+                self.chan.as_mut_ptr(),
+                sa.as_ptr(),
+                sa.len(),
+                host.as_mut_ptr(),
+                host.len(),
+                serv.as_mut_ptr(),
+                serv.len(),
+                flags.bits(),
+            )
+        };
+        probes::getnameinfo__done!(|| r);
+        if r != 0 {
+            return Err(gai_error(r));
+        }
+        // Safe because getnameinfo always NUL-terminates its outputs
+        let (host, serv) = unsafe {
+            (CStr::from_ptr(host.as_ptr()), CStr::from_ptr(serv.as_ptr()))
+        };
+        Ok((
+            host.to_string_lossy().into_owned(),
+            serv.to_string_lossy().into_owned(),
+        ))
+    }
+
+    /// The limits most recently applied to this agent, or `None` if it has
+    /// never been limited.
+    ///
+    /// Since every limit must be narrower than the last, these are the
+    /// service's effective limits.  Long-running daemons may log them for
+    /// auditing.  Only limits applied through this crate are known.
+    pub fn applied_limits(&self) -> Option<&template::AppliedLimits> {
+        self.applied.as_ref()
+    }
+
+    /// The operations that this agent may still perform.
+    ///
+    /// An agent that has never been limited may do anything.  Library code can
+    /// check this to choose a degraded code path, rather than attempting an
+    /// operation and handling `ENOTCAPABLE`.  Like
+    /// [`CapNetAgent::applied_limits`], it only knows of limits applied
+    /// through this crate, and an operation it permits may still be limited to
+    /// certain addresses.
+    ///
+    /// # Example
+    /// ```
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, LimitFlags};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
+    /// if !cap_net.allowed_ops().contains(LimitFlags::NAME2ADDR) {
+    ///     // Fall back to a numeric address
+    /// }
+    /// ```
+    pub fn allowed_ops(&self) -> LimitFlags {
+        self.applied.as_ref().map_or(LimitFlags::ALL, |a| a.flags)
+    }
+
+    /// Return an opaque handle used to further limit the capabilities of the
+    /// `cap_net` service.
+    ///
+    /// Each time a [`Limit`] is constructed and applied it can reduce, but
+    /// never enlarge, the service's capabilities.
+    ///
+    /// Fails if libcasper can't allocate the limit, for example if it is out
+    /// of memory.
+    ///
+    /// # Example
+    /// ```
+    /// use std::{
+    ///     os::fd::AsRawFd,
+    ///     str::FromStr
+    /// };
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, LimitFlags};
+    /// use nix::sys::socket::{SockaddrIn, SockaddrLike};
+    ///
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+    /// let addr = SockaddrIn::from_str("127.0.0.1:8083").unwrap();
+    /// limit.bind(&addr);
+    /// limit.limit();
+    /// // Now the service will refuse attempts to bind to any other address or
+    /// // port.
+    /// ```
+    pub fn limit(&mut self, flags: LimitFlags) -> io::Result<Limit<'_>> {
+        let limit = unsafe {
+            ffi::cap_net_limit_init(self.chan.as_mut_ptr(), flags.bits())
+        };
+        if limit.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Limit {
+            limit:   RawLimit(limit),
+            applied: template::AppliedLimits::new(flags),
+            invalid: None,
+            agent:   self,
+        })
+    }
+
+    /// Open a new agent for each of `policies`, limited by that policy.
+    ///
+    /// This lets an application give each subsystem only the access that it
+    /// needs, such as a DNS-only agent for its resolver and a bind-only agent
+    /// for its listeners.  Each new agent is a separate connection to the
+    /// service, opened with `casper`, so it isn't bound by this agent's own
+    /// Casper limits.  But it does share this agent's
+    /// [access list](CapNetAgent::set_access_list), if any, and its
+    /// [connect rate limit](CapNetAgent::set_connect_rate_limit).  The rate
+    /// limit remains a single budget, shared by this agent and all of the new
+    /// ones.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, policy::NetPolicy};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let cap_net = casper.net().unwrap();
+    /// let policies = ["dns", "bind *:8080"]
+    ///     .map(|s| s.parse::<NetPolicy>().unwrap());
+    /// let mut agents = cap_net.split(&mut casper, &policies).unwrap();
+    /// let listener_agent = agents.pop().unwrap();
+    /// let resolver_agent = agents.pop().unwrap();
+    ///
+    /// capsicum::enter();
+    /// ```
+    pub fn split(
+        &self,
+        casper: &mut casper::Casper,
+        policies: &[policy::NetPolicy],
+    ) -> io::Result<Vec<CapNetAgent>> {
+        policies
+            .iter()
+            .map(|policy| {
+                let mut agent = casper.net()?;
+                agent.acl.clone_from(&self.acl);
+                agent.connect_limiter.clone_from(&self.connect_limiter);
+                policy.apply(&mut agent)?;
+                Ok(agent)
+            })
+            .collect()
+    }
+}
+
+/// Convert a Unicode hostname to the ASCII form used by DNS, as described by
+/// [UTS #46](https://www.unicode.org/reports/tr46/).
+#[cfg(feature = "idna")]
+fn to_ascii_host(host: &str) -> io::Result<::std::borrow::Cow<'_, str>> {
+    if host.is_ascii() {
+        return Ok(host.into());
+    }
+    ::idna::domain_to_ascii(host).map(Into::into).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid hostname {host:?}: {e}"),
+        )
+    })
+}
+
+/// Convert a getaddrinfo(3) error code into an `io::Error`.
+fn gai_error(code: libc::c_int) -> io::Error {
+    if code == libc::EAI_SYSTEM {
+        return io::Error::last_os_error();
+    }
+    // Safe because gai_strerror always returns a static string
+    let msg = unsafe { CStr::from_ptr(libc::gai_strerror(code)) }
+        .to_string_lossy()
+        .into_owned();
+    match code {
+        libc::EAI_NONAME | libc::EAI_SERVICE => {
+            io::Error::new(io::ErrorKind::NotFound, msg)
+        }
+        _ => io::Error::other(msg),
+    }
+}
+
+/// Owns a `cap_net_limit_t` until it is passed to `cap_net_limit`
+struct RawLimit(*mut ffi::cap_net_limit_t);
+
+impl Drop for RawLimit {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            // Safe because we own the limit, and it was never applied
+            unsafe { ffi::cap_net_free(self.0) };
+        }
+    }
+}
+
+/// Used to limit which operations will be allowed by the [`CapNetAgent`].
+///
+/// Dropping a `Limit` without calling [`Limit::limit`] discards it, leaving
+/// the agent's capabilities unchanged.  So does an entry that the limit's flags
+/// don't permit, like a bind address on a `CONNECT`-only limit, or an empty
+/// list of address families: `limit` will fail with `ErrorKind::InvalidInput`
+/// instead of applying anything.
+///
+/// A flag with no entries is not an error.  As in cap_net(3), it permits its
+/// operation for any address or name.  For example, a `BIND` limit without any
+/// [`bind`](Limit::bind) entries allows binding anywhere.
+pub struct Limit<'a> {
+    limit:   RawLimit,
+    // A record of every entry, for CapNetAgent::applied_limits
+    applied: template::AppliedLimits,
+    // The first invalid entry, reported by Limit::limit
+    invalid: Option<String>,
+    // Because cap_net_limit_t stores a pointer to cap_channel_t
+    agent:   &'a mut CapNetAgent,
+}
+
+bitflags! {
+    /// Used by [`CapNetAgent::limit`] to restrict which functions are permitted.
+    ///
+    /// Each flag gates the agent methods listed with it, and every method or
+    /// extension trait built on them.
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct LimitFlags: u64 {
+        /// Allow any of the `cap_bind` methods, like [`CapNetAgent::bind`]
+        /// and [`TcpListenerExt::cap_bind`](std::TcpListenerExt::cap_bind)
+        const BIND = ffi::CAPNET_BIND as u64;
+        /// Allow any of the `cap_connect` methods, like
+        /// [`CapNetAgent::connect`] and
+        /// [`TcpStreamExt::cap_connect`](std::TcpStreamExt::cap_connect)
+        const CONNECT = ffi::CAPNET_CONNECT as u64;
+        /// Allow resolving names to addresses, as with
+        /// [`resolve`](CapNetAgent::resolve),
+        /// [`resolve_with`](CapNetAgent::resolve_with), and
+        /// [`getaddrinfo`](CapNetAgent::getaddrinfo).  This includes any
+        /// hostname passed as a [`CapToSocketAddrs`].
+        const NAME2ADDR = ffi::CAPNET_NAME2ADDR as u64;
+        /// Allow resolving addresses to names, as with
+        /// [`getnameinfo`](CapNetAgent::getnameinfo)
+        const ADDR2NAME = ffi::CAPNET_ADDR2NAME as u64;
+        /// Allow the legacy `gethostbyaddr` reverse lookup
+        const DEPRECATED_ADDR2NAME = ffi::CAPNET_DEPRECATED_ADDR2NAME as u64;
+        /// Allow the legacy `gethostbyname` forward lookups, like
+        /// `CapNetAgent::gethostbyname` with the `deprecated-dns` feature
+        const DEPRECATED_NAME2ADDR = ffi::CAPNET_DEPRECATED_NAME2ADDR as u64;
+        /// Allow connecting to any address previously returned by a name
+        /// lookup, whether or not it's permitted by [`Limit::connect`].  See
+        /// [`resolve_and_connect`](CapNetAgent::resolve_and_connect).
+        const CONNECTDNS = ffi::CAPNET_CONNECTDNS as u64;
+        /// Allow name resolution in both directions: `NAME2ADDR` and
+        /// `ADDR2NAME`
+        const DNS = Self::NAME2ADDR.bits() | Self::ADDR2NAME.bits();
+        /// Allow every operation.  Useful to restrict only addresses, with
+        /// [`Limit::bind`], [`Limit::connect`], and friends.
+        const ALL = Self::BIND.bits()
+            | Self::CONNECT.bits()
+            | Self::NAME2ADDR.bits()
+            | Self::ADDR2NAME.bits()
+            | Self::DEPRECATED_ADDR2NAME.bits()
+            | Self::DEPRECATED_NAME2ADDR.bits()
+            | Self::CONNECTDNS.bits();
+    }
+}
+
+impl ::std::fmt::Display for LimitFlags {
+    /// Format the flags as lowercase names separated by `|`, like
+    /// `bind|connect`.  The empty set is the empty string.
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        for (i, (name, _)) in self.iter_names().enumerate() {
+            if i > 0 {
+                f.write_str("|")?;
+            }
+            f.write_str(&name.to_ascii_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+impl ::std::str::FromStr for LimitFlags {
+    type Err = io::Error;
+
+    /// Parse flag names separated by `|`, like `bind|connect|name2addr`.
+    ///
+    /// Names are case-insensitive, and the empty string is the empty set.
+    fn from_str(s: &str) -> io::Result<Self> {
+        let mut flags = LimitFlags::empty();
+        for name in s.split('|').map(str::trim) {
+            if name.is_empty() && !s.contains('|') {
+                continue;
+            }
+            let flag = LimitFlags::from_name(&name.to_ascii_uppercase())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown limit flag {name:?}"),
+                    )
+                })?;
+            flags |= flag;
+        }
+        Ok(flags)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl serde::Serialize for LimitFlags {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        s: S,
+    ) -> ::std::result::Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl<'de> serde::Deserialize<'de> for LimitFlags {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        d: D,
+    ) -> ::std::result::Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+bitflags! {
+    /// Modify the behavior of [`CapNetAgent::getnameinfo`].
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct NameInfoFlags: libc::c_int {
+        /// Return only the hostname part of the FQDN for local hosts
+        const NOFQDN = libc::NI_NOFQDN;
+        /// Return the numeric form of the host's address
+        const NUMERICHOST = libc::NI_NUMERICHOST;
+        /// Fail if the host's name can't be found
+        const NAMEREQD = libc::NI_NAMEREQD;
+        /// Return the numeric form of the port
+        const NUMERICSERV = libc::NI_NUMERICSERV;
+        /// Look up the service as a datagram service rather than a stream one
+        const DGRAM = libc::NI_DGRAM;
+        /// Return the numeric form of an IPv6 scope id
+        const NUMERICSCOPE = libc::NI_NUMERICSCOPE;
+    }
+}
+
+impl Limit<'_> {
+    /// Limit the `cap_net` service to only allow binding to the given address.
+    ///
+    /// May be called multiple times to allow binding to multiple addresses.
+    pub fn bind(&mut self, sa: &dyn SockaddrLike) -> &mut Self {
+        if !self.permits(LimitFlags::BIND, "a bind address") {
+            return self;
+        }
+        let newlimit = unsafe {
+            ffi::cap_net_limit_bind(self.limit.0, sa.as_ptr(), sa.len())
+        };
+        assert_eq!(newlimit, self.limit.0);
+        self.applied.bind.push(template::to_storage(sa));
+        self
+    }
+
+    /// Limit the `cap_net` service to only allow binding to the given port on
+    /// the wildcard address, for both IPv4 and IPv6.
+    ///
+    /// This is useful when a service knows its port but not which interface
+    /// addresses will exist at deploy time.  Note that cap_net matches bind
+    /// limits exactly, so binding to a specific local address on that port
+    /// will still be refused.
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::TcpListener;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, LimitFlags, std::TcpListenerExt};
+    ///
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+    /// limit.bind_port(8087);
+    /// limit.limit().unwrap();
+    /// let listener = TcpListener::cap_bind(&mut cap_net, "[::]:8087").unwrap();
+    /// ```
+    pub fn bind_port(&mut self, port: u16) -> &mut Self {
+        let v4 = SockaddrIn::new(0, 0, 0, 0, port);
+        let v6 = SockaddrIn6::from(::std::net::SocketAddrV6::new(
+            ::std::net::Ipv6Addr::UNSPECIFIED,
+            port,
+            0,
+            0,
+        ));
+        self.bind(&v4).bind(&v6)
+    }
+
+    /// Limit the `cap_net` service to only allow binding to an ephemeral port,
+    /// chosen by the kernel, on the given address.
+    ///
+    /// This allows binding to `ip` with port 0.  Because cap_net matches bind
+    /// limits exactly, it does not allow binding to any specific port on `ip`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::{Ipv4Addr, TcpListener};
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, LimitFlags, std::TcpListenerExt};
+    ///
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+    /// limit.bind_any_port(Ipv4Addr::LOCALHOST.into());
+    /// limit.limit().unwrap();
+    /// let listener = TcpListener::cap_bind(&mut cap_net, "127.0.0.1:0").unwrap();
+    /// ```
+    pub fn bind_any_port(&mut self, ip: IpAddr) -> &mut Self {
+        self.bind_std((ip, 0))
+    }
+
+    /// Limit the `cap_net` service to only allow connecting to the given
+    /// address.
+    ///
+    /// May be called multiple times to allow connecting to multiple addresses.
+    /// cap_net matches connect limits exactly, by address and port; it has no
+    /// wildcards.  To allow connecting to any address on a certain port, leave
+    /// the addresses unlimited and use an [`acl::CidrPolicy`] instead, with a
+    /// network like `0.0.0.0/0`.
+    pub fn connect(&mut self, sa: &dyn SockaddrLike) -> &mut Self {
+        if !self.permits(LimitFlags::CONNECT, "a connect address") {
+            return self;
+        }
+        let newlimit = unsafe {
+            ffi::cap_net_limit_connect(self.limit.0, sa.as_ptr(), sa.len())
+        };
+        assert_eq!(newlimit, self.limit.0);
+        self.applied.connect.push(template::to_storage(sa));
+        self
+    }
+
+    /// Like [`Limit::bind`], but for a std address.
+    ///
+    /// Accepts anything that converts into a `SocketAddr`, such as a
+    /// `SocketAddrV4` or an `(IpAddr, u16)` tuple.
+    pub fn bind_std<A: Into<SocketAddr>>(&mut self, addr: A) -> &mut Self {
+        self.bind(&sockaddr::from_std(addr.into()))
+    }
+
+    /// Like [`Limit::bind`], but for a unix-domain socket's path.
+    ///
+    /// # Panics
+    ///
+    /// If `path` is too long for a unix-domain socket address.
+    pub fn bind_unix<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        let sa = nix::sys::socket::UnixAddr::new(path.as_ref())
+            .expect("path is too long for a unix-domain socket");
+        self.bind(&sa)
+    }
+
+    /// Like [`Limit::connect`], but for a std address.
+    ///
+    /// Accepts anything that converts into a `SocketAddr`, such as a
+    /// `SocketAddrV4` or an `(IpAddr, u16)` tuple.
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::{IpAddr, Ipv4Addr};
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, LimitFlags};
+    ///
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+    /// limit.connect_std((IpAddr::V4(Ipv4Addr::LOCALHOST), 8080));
+    /// limit.limit().unwrap();
+    /// ```
+    pub fn connect_std<A: Into<SocketAddr>>(&mut self, addr: A) -> &mut Self {
+        self.connect(&sockaddr::from_std(addr.into()))
+    }
+
+    /// Like [`Limit::connect`], but for a unix-domain socket's path.
+    ///
+    /// # Panics
+    ///
+    /// If `path` is too long for a unix-domain socket address.
+    pub fn connect_unix<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        let sa = nix::sys::socket::UnixAddr::new(path.as_ref())
+            .expect("path is too long for a unix-domain socket");
+        self.connect(&sa)
+    }
+
+    /// Limit the `cap_net` service to only allow connecting to the addresses of
+    /// `host`, on `port`.
+    ///
+    /// The name is resolved once, now, with `resolver`.  Because the `Limit`
+    /// borrows this agent, the resolver must be something else, such as a
+    /// second [`CapNetAgent`] or a [`Resolver`](resolver::Resolver).  Every
+    /// returned address is added to the allow-list; later changes to the
+    /// host's DNS records won't be.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, LimitFlags};
+    ///
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// let mut resolver = casper.net().unwrap();
+    /// let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+    /// limit.connect_host(&mut resolver, "db.example.com", 5432).unwrap();
+    /// limit.limit().unwrap();
+    /// ```
+    pub fn connect_host<R: resolver::Resolve + ?Sized>(
+        &mut self,
+        resolver: &mut R,
+        host: &str,
+        port: u16,
+    ) -> io::Result<&mut Self> {
+        for addr in resolver.resolve(host, port)? {
+            self.connect_std(addr);
+        }
+        Ok(self)
+    }
+
+    /// Limit the `cap_net` service to only allow reverse lookups of the given
+    /// address, for [`LimitFlags::ADDR2NAME`].
+    ///
+    /// May be called multiple times to allow looking up multiple addresses.
+    pub fn addr2name(&mut self, sa: &dyn SockaddrLike) -> &mut Self {
+        if !self.permits(LimitFlags::ADDR2NAME, "an addr2name address") {
+            return self;
+        }
+        let newlimit = unsafe {
+            ffi::cap_net_limit_addr2name(self.limit.0, sa.as_ptr(), sa.len())
+        };
+        assert_eq!(newlimit, self.limit.0);
+        self.applied.addr2name.push(template::to_storage(sa));
+        self
+    }
+
+    /// Limit the `cap_net` service to only allow resolving the given name,
+    /// for [`LimitFlags::NAME2ADDR`].
+    ///
+    /// If `port` is `None`, the name may be resolved with any port.
+    /// Otherwise, only with `port`.  May be called multiple times to allow
+    /// resolving multiple names.  Names are matched exactly, so numeric
+    /// addresses must be listed too, if they're to be resolved.  A name that
+    /// contains a NUL byte makes [`Limit::limit`] fail.
+    ///
+    /// # Example
+    /// ```
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, LimitFlags};
+    ///
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// let mut limit = cap_net.limit(LimitFlags::NAME2ADDR).unwrap();
+    /// limit.name2addr("localhost", Some(80));
+    /// limit.limit().unwrap();
+    /// cap_net.resolve("localhost", 80).unwrap();
+    /// cap_net.resolve("localhost", 443).unwrap_err();
+    /// ```
+    pub fn name2addr(&mut self, name: &str, port: Option<u16>) -> &mut Self {
+        if !self.permits(LimitFlags::NAME2ADDR, "a name2addr name") {
+            return self;
+        }
+        #[cfg(feature = "idna")]
+        let ascii = match to_ascii_host(name) {
+            Ok(ascii) => ascii,
+            Err(e) => {
+                // getaddrinfo would reject the name too, so a limit on it
+                // could never match.
+                if self.invalid.is_none() {
+                    self.invalid = Some(e.to_string());
+                }
+                return self;
+            }
+        };
+        #[cfg(feature = "idna")]
+        let ascii_name: &str = &ascii;
+        #[cfg(not(feature = "idna"))]
+        let ascii_name = name;
+        let Ok(cname) = CString::new(ascii_name) else {
+            if self.invalid.is_none() {
+                self.invalid = Some(format!(
+                    "name2addr name {name:?} contains a NUL byte"
+                ));
+            }
+            return self;
+        };
+        self.applied.name2addr.push((name.to_owned(), port));
+        let serv = port.map(|p| CString::new(p.to_string()).unwrap());
+        let newlimit = unsafe {
+            ffi::cap_net_limit_name2addr(
+                self.limit.0,
+                cname.as_ptr(),
+                serv.as_ref().map_or(::std::ptr::null(), |s| s.as_ptr()),
+            )
+        };
+        assert_eq!(newlimit, self.limit.0);
+        self
+    }
+
+    /// Limit the `cap_net` service to only allow reverse lookups of addresses
+    /// in the given families, for [`LimitFlags::ADDR2NAME`].
+    ///
+    /// May be combined with [`Limit::addr2name`].
+    pub fn addr2name_family(
+        &mut self,
+        families: &[AddressFamily],
+    ) -> &mut Self {
+        if !self.permits_families(LimitFlags::ADDR2NAME, families) {
+            return self;
+        }
+        self.applied.addr2name_families.extend_from_slice(families);
+        let mut families = families
+            .iter()
+            .map(|f| *f as libc::c_int)
+            .collect::<Vec<_>>();
+        let newlimit = unsafe {
+            ffi::cap_net_limit_addr2name_family(
+                self.limit.0,
+                families.as_mut_ptr(),
+                families.len(),
+            )
+        };
+        assert_eq!(newlimit, self.limit.0);
+        self
+    }
+
+    /// Limit the `cap_net` service to only allow forward lookups of addresses
+    /// in the given families, for [`LimitFlags::NAME2ADDR`].
+    ///
+    /// May be combined with [`Limit::name2addr`].
+    ///
+    /// # Example
+    /// ```
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, LimitFlags};
+    /// use nix::sys::socket::AddressFamily;
+    ///
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// let mut limit = cap_net.limit(LimitFlags::NAME2ADDR).unwrap();
+    /// limit.name2addr_family(&[AddressFamily::Inet]);
+    /// limit.limit().unwrap();
+    /// ```
+    pub fn name2addr_family(
+        &mut self,
+        families: &[AddressFamily],
+    ) -> &mut Self {
+        if !self.permits_families(LimitFlags::NAME2ADDR, families) {
+            return self;
+        }
+        self.applied.name2addr_families.extend_from_slice(families);
+        let mut families = families
+            .iter()
+            .map(|f| *f as libc::c_int)
+            .collect::<Vec<_>>();
+        let newlimit = unsafe {
+            ffi::cap_net_limit_name2addr_family(
+                self.limit.0,
+                families.as_mut_ptr(),
+                families.len(),
+            )
+        };
+        assert_eq!(newlimit, self.limit.0);
+        self
     }
 
     /// Actually apply the limits
-    pub fn limit(self) -> io::Result<()> {
-        let res = unsafe { ffi::cap_net_limit(self.limit) };
+    ///
+    /// Fails with `ErrorKind::InvalidInput`, without consulting Casper, if any
+    /// entry was invalid.  Flags without any entries leave their operations
+    /// unrestricted.
+    pub fn limit(mut self) -> io::Result<()> {
+        self.apply()
+    }
+
+    /// Can an entry of kind `what` be added for operation `needed`?  If not,
+    /// remember why.
+    fn permits(&mut self, needed: LimitFlags, what: &str) -> bool {
+        if self.applied.flags.contains(needed) {
+            return true;
+        }
+        if self.invalid.is_none() {
+            self.invalid = Some(format!(
+                "{what} was given, but the limit lacks the {needed} flag"
+            ));
+        }
+        false
+    }
+
+    /// Like [`Limit::permits`], but for a list of address families, which
+    /// must not be empty.
+    fn permits_families(
+        &mut self,
+        needed: LimitFlags,
+        families: &[AddressFamily],
+    ) -> bool {
+        if families.is_empty() {
+            if self.invalid.is_none() {
+                self.invalid = Some(format!(
+                    "an empty list of {needed} families was given"
+                ));
+            }
+            return false;
+        }
+        self.permits(needed, &format!("a list of {needed} families"))
+    }
+
+    /// Discard the limit without applying it.
+    ///
+    /// This is the same as dropping it, but more explicit.
+    pub fn abandon(self) {}
+
+    fn apply(&mut self) -> io::Result<()> {
+        if let Some(msg) = self.invalid.take() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        let mode = self.applied.flags.bits();
+        // cap_net_limit takes ownership of the limit, even if it fails
+        let limit =
+            ::std::mem::replace(&mut self.limit.0, ::std::ptr::null_mut());
+        let res = unsafe { ffi::cap_net_limit(limit) };
+        probes::limit__done!(|| (mode, probes::errno(&Errno::result(res))));
         if res == 0 {
+            self.agent.applied = Some(self.applied.clone());
             Ok(())
         } else {
-            Err(io::Error::last_os_error())
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::ENOTCAPABLE) {
+                Err(LimitEnlarged {
+                    flags:    self.applied.flags,
+                    previous: self.agent.applied.as_ref().map(|a| a.flags),
+                }
+                .into())
+            } else {
+                Err(e)
+            }
         }
     }
 }
+
+/// The error returned when a [`Limit`] would enlarge the service's
+/// capabilities, rather than reduce them.
+///
+/// Every limit must be a subset of the one before it.  [`Limit::limit`]
+/// returns this wrapped in an `io::Error` of kind `PermissionDenied`, so it
+/// may be recovered with `io::Error::get_ref`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LimitEnlarged {
+    /// The operations that the rejected limit would have permitted
+    pub flags:    LimitFlags,
+    /// The operations permitted by the limit already in place, if it was
+    /// applied by this crate
+    pub previous: Option<LimitFlags>,
+}
+
+impl ::std::fmt::Display for LimitEnlarged {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        write!(f, "limit would enlarge the service's capabilities")?;
+        match self.previous {
+            Some(previous) if !previous.contains(self.flags) => write!(
+                f,
+                ": {} are not permitted by the existing limit",
+                self.flags.difference(previous)
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ::std::error::Error for LimitEnlarged {}
+
+impl From<LimitEnlarged> for io::Error {
+    fn from(e: LimitEnlarged) -> Self {
+        io::Error::new(io::ErrorKind::PermissionDenied, e)
+    }
+}
@@ -0,0 +1,189 @@
+// vim: tw=80
+//! Agents whose type reflects their limits
+//!
+//! Once a [`CapNetAgent`] has been limited to binding only, any attempt to
+//! connect with it will fail with `ENOTCAPABLE`.  [`Limit::apply_bind_only`]
+//! and [`Limit::apply_connect_only`] apply a limit and return a wrapper that
+//! exposes only the operations still permitted, so such misuse is caught at
+//! compile time instead.
+//!
+//! # Example
+//! ```no_run
+//! use std::net::{SocketAddr, TcpListener};
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, LimitFlags};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+//! let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+//! limit.bind_std(addr);
+//! let mut binder = limit.apply_bind_only().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let listener: TcpListener = binder.tcp_listener(addr).unwrap();
+//! // binder.tcp_stream(addr) would not compile
+//! ```
+use std::{
+    io,
+    net::{TcpListener, TcpStream, UdpSocket},
+    os::{
+        fd::AsFd,
+        unix::net::{UnixDatagram, UnixListener, UnixStream},
+    },
+    path::Path,
+};
+
+use nix::{sys::socket::SockaddrLike, Result};
+
+use crate::{
+    std::{
+        TcpListenerExt,
+        TcpStreamExt,
+        UdpSocketExt,
+        UnixDatagramExt,
+        UnixListenerExt,
+        UnixStreamExt,
+    },
+    CapNetAgent,
+    CapToSocketAddrs,
+    Limit,
+    LimitFlags,
+};
+
+/// A [`CapNetAgent`] that has been limited to binding sockets.
+///
+/// Created by [`Limit::apply_bind_only`].
+#[derive(Debug)]
+pub struct BindOnlyAgent<'a> {
+    agent: &'a mut CapNetAgent,
+}
+
+impl BindOnlyAgent<'_> {
+    /// Bind a socket to an address, like [`CapNetAgent::bind`].
+    pub fn bind<F: AsFd>(
+        &mut self,
+        sock: &F,
+        addr: &dyn SockaddrLike,
+    ) -> Result<()> {
+        self.agent.bind(sock, addr)
+    }
+
+    /// Bind a new TCP listener, like [`TcpListenerExt::cap_bind`].
+    pub fn tcp_listener<A>(&mut self, addrs: A) -> io::Result<TcpListener>
+    where
+        A: CapToSocketAddrs,
+    {
+        TcpListener::cap_bind(self.agent, addrs)
+    }
+
+    /// Bind a new UDP socket, like [`UdpSocketExt::cap_bind`].
+    pub fn udp_socket<A>(&mut self, addrs: A) -> io::Result<UdpSocket>
+    where
+        A: CapToSocketAddrs,
+    {
+        UdpSocket::cap_bind(self.agent, addrs)
+    }
+
+    /// Bind a new unix-domain datagram socket, like
+    /// [`UnixDatagramExt::cap_bind`].
+    pub fn unix_datagram<P>(&mut self, path: P) -> io::Result<UnixDatagram>
+    where
+        P: AsRef<Path>,
+    {
+        UnixDatagram::cap_bind(self.agent, path)
+    }
+
+    /// Bind a new unix-domain listener, like [`UnixListenerExt::cap_bind`].
+    pub fn unix_listener<P>(&mut self, path: P) -> io::Result<UnixListener>
+    where
+        P: AsRef<Path>,
+    {
+        UnixListener::cap_bind(self.agent, path)
+    }
+}
+
+/// A [`CapNetAgent`] that has been limited to connecting sockets.
+///
+/// Created by [`Limit::apply_connect_only`].
+#[derive(Debug)]
+pub struct ConnectOnlyAgent<'a> {
+    agent: &'a mut CapNetAgent,
+}
+
+impl ConnectOnlyAgent<'_> {
+    /// Connect a socket to an address, like [`CapNetAgent::connect`].
+    pub fn connect<F: AsFd>(
+        &mut self,
+        sock: &F,
+        addr: &dyn SockaddrLike,
+    ) -> Result<()> {
+        self.agent.connect(sock, addr)
+    }
+
+    /// Connect a new TCP stream, like [`TcpStreamExt::cap_connect`].
+    pub fn tcp_stream<A>(&mut self, addrs: A) -> io::Result<TcpStream>
+    where
+        A: CapToSocketAddrs,
+    {
+        TcpStream::cap_connect(self.agent, addrs)
+    }
+
+    /// Connect an existing UDP socket, like [`UdpSocketExt::cap_connect`].
+    pub fn connect_udp<A>(
+        &mut self,
+        sock: &UdpSocket,
+        addrs: A,
+    ) -> io::Result<()>
+    where
+        A: CapToSocketAddrs,
+    {
+        sock.cap_connect(self.agent, addrs)
+    }
+
+    /// Connect a new unix-domain stream, like [`UnixStreamExt::cap_connect`].
+    pub fn unix_stream<P>(&mut self, path: P) -> io::Result<UnixStream>
+    where
+        P: AsRef<Path>,
+    {
+        UnixStream::cap_connect(self.agent, path)
+    }
+}
+
+impl<'a> Limit<'a> {
+    /// Apply the limits, and return an agent that can only bind.
+    ///
+    /// Fails with `ErrorKind::InvalidInput`, without applying anything, unless
+    /// the limit was created with [`LimitFlags::BIND`].
+    pub fn apply_bind_only(self) -> io::Result<BindOnlyAgent<'a>> {
+        self.apply_as(LimitFlags::BIND)
+            .map(|agent| BindOnlyAgent { agent })
+    }
+
+    /// Apply the limits, and return an agent that can only connect.
+    ///
+    /// Fails with `ErrorKind::InvalidInput`, without applying anything, unless
+    /// the limit was created with [`LimitFlags::CONNECT`].
+    pub fn apply_connect_only(self) -> io::Result<ConnectOnlyAgent<'a>> {
+        self.apply_as(LimitFlags::CONNECT)
+            .map(|agent| ConnectOnlyAgent { agent })
+    }
+
+    fn apply_as(
+        mut self,
+        needed: LimitFlags,
+    ) -> io::Result<&'a mut CapNetAgent> {
+        if !self.applied.flags.contains(needed) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("limit does not permit {needed}"),
+            ));
+        }
+        self.apply()?;
+        Ok(self.agent)
+    }
+}
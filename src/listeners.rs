@@ -0,0 +1,133 @@
+// vim: tw=80
+//! A set of TCP listeners that can be reconfigured at runtime
+//!
+//! Daemons that reload their configuration, e.g. on `SIGHUP`, can't simply
+//! restart once sandboxed.  A [`ListenerSet`] instead reconciles its listeners
+//! with each new configuration: it binds newly required addresses through the
+//! agent, closes the ones that are no longer wanted, and leaves the rest
+//! untouched, so existing listeners keep their pending connections.
+//!
+//! Addresses that can't be bound, whether because they're in use or because
+//! the agent's limits forbid them, are reported as [`Conflict`]s rather than
+//! causing the whole update to fail.
+//!
+//! # Example
+//! ```no_run
+//! use std::net::SocketAddr;
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, listeners::ListenerSet};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let mut set = ListenerSet::new();
+//! let http: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+//! set.update(&mut cap_net, &[http]);
+//! // Later, after re-reading the configuration
+//! let https: SocketAddr = "0.0.0.0:8443".parse().unwrap();
+//! let report = set.update(&mut cap_net, &[http, https]);
+//! for conflict in report.conflicts {
+//!     eprintln!("Could not listen on {}: {}", conflict.addr, conflict.error);
+//! }
+//! ```
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{SocketAddr, TcpListener},
+};
+
+use crate::{std::TcpListenerExt, CapNetAgent};
+
+/// An address that [`ListenerSet::update`] could not bind
+#[derive(Debug)]
+pub struct Conflict {
+    /// The requested address
+    pub addr:  SocketAddr,
+    /// Why it couldn't be bound
+    pub error: io::Error,
+}
+
+/// The outcome of [`ListenerSet::update`]
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct Report {
+    /// Addresses that were newly bound
+    pub added:     Vec<SocketAddr>,
+    /// Addresses whose listeners were closed
+    pub removed:   Vec<SocketAddr>,
+    /// Addresses that were requested but could not be bound
+    pub conflicts: Vec<Conflict>,
+}
+
+/// TCP listeners, keyed by the address they were requested with
+#[derive(Debug, Default)]
+pub struct ListenerSet {
+    listeners: BTreeMap<SocketAddr, TcpListener>,
+}
+
+impl ListenerSet {
+    /// Create an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reconcile the set with `addrs`.
+    ///
+    /// Listeners for addresses not in `addrs` are closed first, so that an
+    /// address may move, e.g. from a wildcard to a specific interface, without
+    /// conflicting with itself.  Then each missing address is bound with
+    /// `cap_bind`.
+    pub fn update(
+        &mut self,
+        agent: &mut CapNetAgent,
+        addrs: &[SocketAddr],
+    ) -> Report {
+        let mut report = Report::default();
+        self.listeners.retain(|addr, _| {
+            let keep = addrs.contains(addr);
+            if !keep {
+                report.removed.push(*addr);
+            }
+            keep
+        });
+        for addr in addrs {
+            if self.listeners.contains_key(addr) {
+                continue;
+            }
+            match TcpListener::cap_bind(agent, addr) {
+                Ok(listener) => {
+                    self.listeners.insert(*addr, listener);
+                    report.added.push(*addr);
+                }
+                Err(error) => {
+                    report.conflicts.push(Conflict { addr: *addr, error })
+                }
+            }
+        }
+        report
+    }
+
+    /// Look up the listener that was requested for `addr`.
+    pub fn get(&self, addr: &SocketAddr) -> Option<&TcpListener> {
+        self.listeners.get(addr)
+    }
+
+    /// Iterate over the requested addresses and their listeners.
+    pub fn iter(&self) -> impl Iterator<Item = (&SocketAddr, &TcpListener)> {
+        self.listeners.iter()
+    }
+
+    /// The number of open listeners
+    pub fn len(&self) -> usize {
+        self.listeners.len()
+    }
+
+    /// Are there no open listeners?
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+}
@@ -0,0 +1,518 @@
+// vim: tw=80
+//! Netlink route sockets, for querying routes and interface state
+//!
+//! FreeBSD 14 and later support a Linux-compatible `AF_NETLINK` socket family.
+//! Creating a netlink socket and exchanging messages with the kernel are both
+//! permitted in capability mode, so a sandboxed process can query the routing
+//! table and interface state without any help.  Binding a netlink socket to a
+//! specific port ID or multicast group is not permitted, but may be done with
+//! [`NetlinkRoute::cap_bind`].
+//!
+//! # Example
+//! ```no_run
+//! use capsicum_net::netlink::NetlinkRoute;
+//!
+//! capsicum::enter();
+//!
+//! let mut nl = NetlinkRoute::new().unwrap();
+//! for link in nl.links().unwrap() {
+//!     println!("{}: {}", link.index, link.name);
+//! }
+//! ```
+use std::{
+    io,
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+};
+
+use super::CapNetAgent;
+
+const AF_NETLINK: libc::c_int = 38;
+const NETLINK_ROUTE: libc::c_int = 0;
+
+const NLMSG_HDRLEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const NLM_F_REQUEST: u16 = 0x01;
+const NLM_F_ACK: u16 = 0x04;
+const NLM_F_DUMP: u16 = 0x300;
+
+const RTM_GETLINK: u16 = 18;
+const RTM_NEWLINK: u16 = 16;
+const RTM_GETADDR: u16 = 22;
+const RTM_NEWADDR: u16 = 20;
+const RTM_GETROUTE: u16 = 26;
+const RTM_NEWROUTE: u16 = 24;
+
+const IFLA_ADDRESS: u16 = 1;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const RTA_PREFSRC: u16 = 7;
+const RTA_TABLE: u16 = 15;
+
+/// Size of `struct ifinfomsg`
+const IFINFOMSG_LEN: usize = 16;
+/// Size of `struct ifaddrmsg`
+const IFADDRMSG_LEN: usize = 8;
+/// Size of `struct rtmsg`
+const RTMSG_LEN: usize = 12;
+
+/// FreeBSD's `struct sockaddr_nl`
+#[repr(C)]
+struct SockaddrNl {
+    nl_len:    u8,
+    nl_family: u8,
+    nl_pad:    u16,
+    nl_pid:    u32,
+    nl_groups: u32,
+}
+
+/// Round a length up to netlink's 4-byte alignment
+fn nl_align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "truncated netlink message")
+}
+
+/// Interpret a netlink attribute payload as an IP address.
+fn ip_from_bytes(data: &[u8]) -> Option<IpAddr> {
+    match data.len() {
+        4 => Some(IpAddr::V4(Ipv4Addr::from(
+            <[u8; 4]>::try_from(data).unwrap(),
+        ))),
+        16 => Some(IpAddr::V6(Ipv6Addr::from(
+            <[u8; 16]>::try_from(data).unwrap(),
+        ))),
+        _ => None,
+    }
+}
+
+/// A single raw message received from a netlink socket.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Message {
+    /// Message type, such as `RTM_NEWLINK`
+    pub msg_type: u16,
+    /// `NLM_F_*` flags
+    pub flags:    u16,
+    /// Sequence number of the request that this message answers
+    pub seq:      u32,
+    /// Port ID of the sender.  Always 0 for messages from the kernel.
+    pub pid:      u32,
+    /// Everything following the `nlmsghdr`
+    pub payload:  Vec<u8>,
+}
+
+impl Message {
+    /// Iterate over the `nlattr` attributes that follow a fixed-size header
+    /// of `hdrlen` bytes within the payload.
+    pub fn attrs(&self, hdrlen: usize) -> Attrs<'_> {
+        let start = nl_align(hdrlen).min(self.payload.len());
+        Attrs {
+            buf: &self.payload[start..],
+        }
+    }
+}
+
+/// Iterator over the attributes of a netlink [`Message`]
+#[derive(Clone, Debug)]
+pub struct Attrs<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Iterator for Attrs<'a> {
+    /// Attribute type and payload
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.len() < 4 {
+            return None;
+        }
+        let len = usize::from(u16_at(self.buf, 0));
+        // The high bits of nla_type are the NLA_F_NESTED and
+        // NLA_F_NET_BYTEORDER flags.
+        let nla_type = u16_at(self.buf, 2) & 0x3fff;
+        if len < 4 || len > self.buf.len() {
+            return None;
+        }
+        let data = &self.buf[4..len];
+        self.buf = &self.buf[nl_align(len).min(self.buf.len())..];
+        Some((nla_type, data))
+    }
+}
+
+/// A network interface, as reported by `RTM_NEWLINK`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Link {
+    /// Interface index
+    pub index:  u32,
+    /// Interface name, like "lo0"
+    pub name:   String,
+    /// `IFF_*` interface flags
+    pub flags:  u32,
+    /// Maximum transmission unit
+    pub mtu:    Option<u32>,
+    /// Link-layer address, if any
+    pub hwaddr: Option<Vec<u8>>,
+}
+
+impl Link {
+    fn parse(msg: &Message) -> io::Result<Self> {
+        if msg.payload.len() < IFINFOMSG_LEN {
+            return Err(truncated());
+        }
+        let index = u32_at(&msg.payload, 4);
+        let flags = u32_at(&msg.payload, 8);
+        let mut name = String::new();
+        let mut mtu = None;
+        let mut hwaddr = None;
+        for (t, data) in msg.attrs(IFINFOMSG_LEN) {
+            match t {
+                IFLA_IFNAME => {
+                    let s = data.split(|b| *b == 0).next().unwrap_or(data);
+                    name = String::from_utf8_lossy(s).into_owned();
+                }
+                IFLA_MTU if data.len() >= 4 => mtu = Some(u32_at(data, 0)),
+                IFLA_ADDRESS => hwaddr = Some(data.to_vec()),
+                _ => (),
+            }
+        }
+        Ok(Link {
+            index,
+            name,
+            flags,
+            mtu,
+            hwaddr,
+        })
+    }
+}
+
+/// An address assigned to an interface, as reported by `RTM_NEWADDR`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Address {
+    /// Index of the interface that owns this address
+    pub index:      u32,
+    /// The address itself
+    pub addr:       IpAddr,
+    /// Length of the network prefix, in bits
+    pub prefix_len: u8,
+}
+
+impl Address {
+    fn parse(msg: &Message) -> io::Result<Option<Self>> {
+        if msg.payload.len() < IFADDRMSG_LEN {
+            return Err(truncated());
+        }
+        let prefix_len = msg.payload[1];
+        let index = u32_at(&msg.payload, 4);
+        let mut local = None;
+        let mut address = None;
+        for (t, data) in msg.attrs(IFADDRMSG_LEN) {
+            match t {
+                IFA_LOCAL => local = ip_from_bytes(data),
+                IFA_ADDRESS => address = ip_from_bytes(data),
+                _ => (),
+            }
+        }
+        // For point-to-point links, IFA_ADDRESS is the remote end and IFA_LOCAL
+        // is ours.
+        Ok(local.or(address).map(|addr| Address {
+            index,
+            addr,
+            prefix_len,
+        }))
+    }
+}
+
+/// A routing table entry, as reported by `RTM_NEWROUTE`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Route {
+    /// Destination network.  `None` for the default route.
+    pub dst:     Option<IpAddr>,
+    /// Length of the destination prefix, in bits
+    pub dst_len: u8,
+    /// Next hop, if the destination is not directly connected
+    pub gateway: Option<IpAddr>,
+    /// Index of the outgoing interface
+    pub oif:     Option<u32>,
+    /// Preferred source address for this route
+    pub prefsrc: Option<IpAddr>,
+    /// Routing table (FIB) number
+    pub table:   u32,
+}
+
+impl Route {
+    fn parse(msg: &Message) -> io::Result<Self> {
+        if msg.payload.len() < RTMSG_LEN {
+            return Err(truncated());
+        }
+        let dst_len = msg.payload[1];
+        let mut table = u32::from(msg.payload[4]);
+        let mut dst = None;
+        let mut gateway = None;
+        let mut oif = None;
+        let mut prefsrc = None;
+        for (t, data) in msg.attrs(RTMSG_LEN) {
+            match t {
+                RTA_DST => dst = ip_from_bytes(data),
+                RTA_GATEWAY => gateway = ip_from_bytes(data),
+                RTA_PREFSRC => prefsrc = ip_from_bytes(data),
+                RTA_OIF if data.len() >= 4 => oif = Some(u32_at(data, 0)),
+                RTA_TABLE if data.len() >= 4 => table = u32_at(data, 0),
+                _ => (),
+            }
+        }
+        Ok(Route {
+            dst,
+            dst_len,
+            gateway,
+            oif,
+            prefsrc,
+            table,
+        })
+    }
+}
+
+/// A `NETLINK_ROUTE` socket.
+#[derive(Debug)]
+pub struct NetlinkRoute {
+    fd:  OwnedFd,
+    seq: u32,
+}
+
+impl NetlinkRoute {
+    /// Open a new `NETLINK_ROUTE` socket.
+    ///
+    /// This is permitted in capability mode.  It will fail with
+    /// `EAFNOSUPPORT` on FreeBSD versions that lack netlink.
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safe because socket(2) just returned this fd to us.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(NetlinkRoute { fd, seq: 0 })
+    }
+
+    /// Bind the socket so that it will receive messages sent to the given
+    /// multicast `groups`, using a `cap_net` service.
+    ///
+    /// This is only necessary for receiving unsolicited notifications.
+    /// Ordinary requests work without binding.
+    pub fn cap_bind(
+        &self,
+        agent: &mut CapNetAgent,
+        groups: u32,
+    ) -> io::Result<()> {
+        let sa = SockaddrNl {
+            nl_len:    mem::size_of::<SockaddrNl>() as u8,
+            nl_family: AF_NETLINK as u8,
+            nl_pad:    0,
+            nl_pid:    0,
+            nl_groups: groups,
+        };
+        agent
+            .bind_raw(
+                self.fd.as_fd(),
+                &sa as *const SockaddrNl as *const libc::sockaddr,
+                mem::size_of::<SockaddrNl>() as libc::socklen_t,
+            )
+            .map_err(io::Error::from)
+    }
+
+    /// List all network interfaces.
+    pub fn links(&mut self) -> io::Result<Vec<Link>> {
+        let req = [0u8; IFINFOMSG_LEN];
+        self.request(RTM_GETLINK, NLM_F_DUMP, &req)?
+            .iter()
+            .filter(|m| m.msg_type == RTM_NEWLINK)
+            .map(Link::parse)
+            .collect()
+    }
+
+    /// List all addresses assigned to all interfaces.
+    pub fn addresses(&mut self) -> io::Result<Vec<Address>> {
+        let req = [0u8; IFADDRMSG_LEN];
+        let mut addrs = Vec::new();
+        for m in self.request(RTM_GETADDR, NLM_F_DUMP, &req)? {
+            if m.msg_type == RTM_NEWADDR {
+                addrs.extend(Address::parse(&m)?);
+            }
+        }
+        Ok(addrs)
+    }
+
+    /// Dump the routing table for the given address family, or for all
+    /// families if `family` is `None`.
+    pub fn routes(
+        &mut self,
+        family: Option<nix::sys::socket::AddressFamily>,
+    ) -> io::Result<Vec<Route>> {
+        let mut req = [0u8; RTMSG_LEN];
+        req[0] = family.map(|f| f as i32 as u8).unwrap_or(0);
+        self.request(RTM_GETROUTE, NLM_F_DUMP, &req)?
+            .iter()
+            .filter(|m| m.msg_type == RTM_NEWROUTE)
+            .map(Route::parse)
+            .collect()
+    }
+
+    /// Look up the route that the kernel would use to reach `dst`.
+    pub fn route_get(&mut self, dst: IpAddr) -> io::Result<Route> {
+        let (family, addr) = match dst {
+            IpAddr::V4(a) => (libc::AF_INET, a.octets().to_vec()),
+            IpAddr::V6(a) => (libc::AF_INET6, a.octets().to_vec()),
+        };
+        let mut req = vec![0u8; RTMSG_LEN];
+        req[0] = family as u8;
+        req[1] = (addr.len() * 8) as u8;
+        let attrlen = 4 + addr.len();
+        req.extend_from_slice(&(attrlen as u16).to_ne_bytes());
+        req.extend_from_slice(&RTA_DST.to_ne_bytes());
+        req.extend_from_slice(&addr);
+        req.resize(nl_align(req.len()), 0);
+        self.request(RTM_GETROUTE, 0, &req)?
+            .iter()
+            .find(|m| m.msg_type == RTM_NEWROUTE)
+            .map(Route::parse)
+            .unwrap_or_else(|| {
+                Err(io::Error::from_raw_os_error(libc::ENETUNREACH))
+            })
+    }
+
+    /// Send an arbitrary request and collect all of the kernel's responses.
+    ///
+    /// `payload` is everything following the `nlmsghdr`.  `NLM_F_REQUEST` is
+    /// always added to `flags`.  The returned messages exclude the final
+    /// `NLMSG_DONE` or acknowledgement, and an `NLMSG_ERROR` response is
+    /// converted to an `Err`.
+    pub fn request(
+        &mut self,
+        msg_type: u16,
+        flags: u16,
+        payload: &[u8],
+    ) -> io::Result<Vec<Message>> {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+        let dump = flags & NLM_F_DUMP == NLM_F_DUMP;
+        let flags = if dump {
+            flags | NLM_F_REQUEST
+        } else {
+            flags | NLM_F_REQUEST | NLM_F_ACK
+        };
+        let len = NLMSG_HDRLEN + payload.len();
+        let mut buf = Vec::with_capacity(nl_align(len));
+        buf.extend_from_slice(&(len as u32).to_ne_bytes());
+        buf.extend_from_slice(&msg_type.to_ne_bytes());
+        buf.extend_from_slice(&flags.to_ne_bytes());
+        buf.extend_from_slice(&seq.to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes());
+        buf.extend_from_slice(payload);
+        buf.resize(nl_align(len), 0);
+        let r = unsafe {
+            libc::send(self.fd.as_raw_fd(), buf.as_ptr().cast(), buf.len(), 0)
+        };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut msgs = Vec::new();
+        let mut rbuf = vec![0u8; 65536];
+        loop {
+            // Learn the size of the next datagram first, lest it be truncated.
+            let r = self.recv(&mut rbuf, libc::MSG_PEEK | libc::MSG_TRUNC)?;
+            if r == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "netlink socket closed",
+                ));
+            }
+            if r > rbuf.len() {
+                rbuf.resize(r, 0);
+            }
+            let r = self.recv(&mut rbuf, 0)?;
+            let mut data = &rbuf[..r];
+            while data.len() >= NLMSG_HDRLEN {
+                let mlen = u32_at(data, 0) as usize;
+                if mlen < NLMSG_HDRLEN || mlen > data.len() {
+                    return Err(truncated());
+                }
+                let msg = Message {
+                    msg_type: u16_at(data, 4),
+                    flags:    u16_at(data, 6),
+                    seq:      u32_at(data, 8),
+                    pid:      u32_at(data, 12),
+                    payload:  data[NLMSG_HDRLEN..mlen].to_vec(),
+                };
+                data = &data[nl_align(mlen).min(data.len())..];
+                if msg.seq != seq {
+                    // Stale response to an earlier, abandoned request
+                    continue;
+                }
+                match msg.msg_type {
+                    NLMSG_DONE => return Ok(msgs),
+                    NLMSG_ERROR => {
+                        if msg.payload.len() < 4 {
+                            return Err(truncated());
+                        }
+                        let errno = u32_at(&msg.payload, 0) as i32;
+                        if errno == 0 {
+                            // Acknowledgement
+                            return Ok(msgs);
+                        }
+                        return Err(io::Error::from_raw_os_error(-errno));
+                    }
+                    // Non-dump responses are followed by an ack
+                    _ => msgs.push(msg),
+                }
+            }
+        }
+    }
+
+    fn recv(&self, buf: &mut [u8], flags: libc::c_int) -> io::Result<usize> {
+        let r = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                flags,
+            )
+        };
+        if r < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(r as usize)
+        }
+    }
+}
+
+impl AsFd for NetlinkRoute {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
@@ -0,0 +1,244 @@
+// vim: tw=80
+//! ICMP echo ("ping") from inside a Capsicum sandbox
+//!
+//! In capability mode `sendto(2)` may not specify a destination address, so a
+//! [`Pinger`] connects its raw socket to the target host with `cap_connect`.
+//! After that, echo requests can be sent and replies received without further
+//! help from Casper.  Raw sockets require root privileges.
+//!
+//! # Example
+//! ```no_run
+//! use std::{net::Ipv4Addr, time::Duration};
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, ping::Pinger};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let mut pinger = Pinger::new(&mut cap_net, Ipv4Addr::LOCALHOST.into())
+//!     .unwrap();
+//! let reply = pinger.ping(Duration::from_secs(1)).unwrap();
+//! println!("seq={} time={:?}", reply.seq, reply.rtt);
+//! ```
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd},
+    sync::atomic::{AtomicU16, Ordering},
+    time::{Duration, Instant},
+};
+
+use nix::sys::socket::{AddressFamily, SockFlag, SockProtocol, SockType};
+
+use crate::CapNetAgent;
+
+const ICMP_ECHOREPLY: u8 = 0;
+const ICMP_ECHO: u8 = 8;
+const ICMP6_ECHO_REQUEST: u8 = 128;
+const ICMP6_ECHO_REPLY: u8 = 129;
+
+/// Length of an ICMP echo header
+const ECHO_HDRLEN: usize = 8;
+
+/// Used to give each `Pinger` a distinct ICMP identifier
+static NEXT_IDENT: AtomicU16 = AtomicU16::new(0);
+
+/// Compute the Internet checksum of `data`, as described in RFC 1071.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|c| u32::from(u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A reply to an ICMP echo request
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Reply {
+    /// Sequence number of the request that this reply answers
+    pub seq:  u16,
+    /// Round-trip time
+    pub rtt:  Duration,
+    /// Number of ICMP payload bytes received
+    pub size: usize,
+}
+
+/// Sends ICMP or ICMPv6 echo requests to a single host.
+#[derive(Debug)]
+pub struct Pinger {
+    sock:        OwnedFd,
+    dst:         IpAddr,
+    ident:       u16,
+    seq:         u16,
+    payload:     Vec<u8>,
+    outstanding: HashMap<u16, Instant>,
+}
+
+impl Pinger {
+    /// Create a new raw socket and connect it to `dst`, using a `cap_net`
+    /// service.
+    pub fn new(agent: &mut CapNetAgent, dst: IpAddr) -> io::Result<Self> {
+        let (family, proto) = match dst {
+            IpAddr::V4(_) => (AddressFamily::Inet, SockProtocol::Icmp),
+            IpAddr::V6(_) => (AddressFamily::Inet6, SockProtocol::IcmpV6),
+        };
+        let sock = nix::sys::socket::socket(
+            family,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC,
+            proto,
+        )?;
+        agent.connect_std_fd(sock.as_fd(), SocketAddr::new(dst, 0))?;
+        let ident = (std::process::id() as u16)
+            .wrapping_add(NEXT_IDENT.fetch_add(1, Ordering::Relaxed));
+        Ok(Pinger {
+            sock,
+            dst,
+            ident,
+            seq: 0,
+            payload: vec![0x55; 56],
+            outstanding: HashMap::new(),
+        })
+    }
+
+    /// The host being pinged
+    pub fn destination(&self) -> IpAddr {
+        self.dst
+    }
+
+    /// Set the number of data bytes to send with each request.  The default is
+    /// 56, like ping(8).
+    pub fn set_payload_size(&mut self, size: usize) {
+        self.payload = vec![0x55; size];
+    }
+
+    /// Send a single echo request, returning its sequence number.
+    pub fn send(&mut self) -> io::Result<u16> {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        let icmp_type = match self.dst {
+            IpAddr::V4(_) => ICMP_ECHO,
+            IpAddr::V6(_) => ICMP6_ECHO_REQUEST,
+        };
+        let mut pkt = Vec::with_capacity(ECHO_HDRLEN + self.payload.len());
+        pkt.extend_from_slice(&[icmp_type, 0, 0, 0]);
+        pkt.extend_from_slice(&self.ident.to_be_bytes());
+        pkt.extend_from_slice(&seq.to_be_bytes());
+        pkt.extend_from_slice(&self.payload);
+        if self.dst.is_ipv4() {
+            // The kernel computes ICMPv6 checksums, but not ICMP ones.
+            let cksum = checksum(&pkt);
+            pkt[2..4].copy_from_slice(&cksum.to_be_bytes());
+        }
+        let now = Instant::now();
+        let r = unsafe {
+            libc::send(self.sock.as_raw_fd(), pkt.as_ptr().cast(), pkt.len(), 0)
+        };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.outstanding.insert(seq, now);
+        Ok(seq)
+    }
+
+    /// Wait up to `timeout` for a reply to any outstanding request.
+    ///
+    /// Fails with `ErrorKind::TimedOut` if no reply arrives in time.
+    pub fn recv(&mut self, timeout: Duration) -> io::Result<Reply> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 65536];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut pfd = libc::pollfd {
+                fd:      self.sock.as_raw_fd(),
+                events:  libc::POLLIN,
+                revents: 0,
+            };
+            let ms = remaining.as_millis().min(i32::MAX as u128) as i32;
+            let r = unsafe { libc::poll(&mut pfd, 1, ms) };
+            if r < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            } else if r == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no ICMP echo reply received",
+                ));
+            }
+            let r = unsafe {
+                libc::recv(
+                    self.sock.as_raw_fd(),
+                    buf.as_mut_ptr().cast(),
+                    buf.len(),
+                    0,
+                )
+            };
+            if r < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let now = Instant::now();
+            let pkt = &buf[..r as usize];
+            // Raw ICMP sockets receive the IP header, but ICMPv6 ones don't.
+            let (icmp, reply_type) = match self.dst {
+                IpAddr::V4(_) => {
+                    let Some(b) = pkt.first() else { continue };
+                    let ihl = usize::from(b & 0x0f) * 4;
+                    (pkt.get(ihl..).unwrap_or_default(), ICMP_ECHOREPLY)
+                }
+                IpAddr::V6(_) => (pkt, ICMP6_ECHO_REPLY),
+            };
+            if icmp.len() < ECHO_HDRLEN || icmp[0] != reply_type {
+                continue;
+            }
+            let ident = u16::from_be_bytes([icmp[4], icmp[5]]);
+            let seq = u16::from_be_bytes([icmp[6], icmp[7]]);
+            if ident != self.ident {
+                // Somebody else's ping
+                continue;
+            }
+            if let Some(sent) = self.outstanding.remove(&seq) {
+                return Ok(Reply {
+                    seq,
+                    rtt: now - sent,
+                    size: icmp.len() - ECHO_HDRLEN,
+                });
+            }
+        }
+    }
+
+    /// Send a single echo request and wait up to `timeout` for its reply.
+    ///
+    /// Replies to earlier requests that arrive in the meantime are discarded.
+    pub fn ping(&mut self, timeout: Duration) -> io::Result<Reply> {
+        let deadline = Instant::now() + timeout;
+        let seq = self.send()?;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.recv(remaining) {
+                Ok(reply) if reply.seq == seq => return Ok(reply),
+                Ok(_) => continue,
+                Err(e) => {
+                    self.outstanding.remove(&seq);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+impl AsFd for Pinger {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.sock.as_fd()
+    }
+}
@@ -0,0 +1,566 @@
+// vim: tw=80
+//! A compact, one-line network policy format
+//!
+//! A [`NetPolicy`] describes everything a sandbox may do on the network, and
+//! can be parsed from a string short enough to pass as a single command-line
+//! flag.  The string is a list of statements separated by semicolons:
+//!
+//! * `bind ADDR:PORT` allows binding to that address.  `bind *:PORT` allows
+//!   binding to the wildcard address of either family on that port.
+//! * `connect HOST:PORT` allows connecting to that host.  `HOST` may be a
+//!   numeric address (IPv6 addresses in brackets), a hostname, a suffix
+//!   pattern like `*.internal`, or `*` for any host.  `PORT` may be `*` for any
+//!   port.
+//! * `dns` allows resolving any name to addresses.
+//! * `resolve NAME` allows resolving only that name, and any others listed the
+//!   same way.
+//! * `rdns` allows resolving addresses to names.
+//!
+//! Alternatively, a statement may be written compactly as `VERB=ARG,ARG,...`,
+//! which is short for one statement per argument.  In that form, `dns=NAME` is
+//! short for `resolve NAME`.  So this policy
+//!
+//! ```text
+//! bind=127.0.0.1:8080,[::1]:8080; connect=10.0.0.5:5432; dns=example.com
+//! ```
+//!
+//! is the same as
+//!
+//! ```text
+//! bind 127.0.0.1:8080; bind [::1]:8080; connect 10.0.0.5:5432;
+//! resolve example.com
+//! ```
+//!
+//! cap_net can only restrict connections to specific socket addresses.  So
+//! when applied, hostnames are resolved first and their addresses allowed.  A
+//! wildcard `connect` statement can't be enforced by Casper at all, so
+//! [`apply`](NetPolicy::apply) rejects any policy that has one.  To apply such
+//! a policy anyway, leaving connections unrestricted by Casper, use
+//! [`apply_with_wildcards`](NetPolicy::apply_with_wildcards), and check
+//! hostnames with [`permits_connect`](NetPolicy::permits_connect) before
+//! connecting to them.
+//!
+//! # Example
+//! ```no_run
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, policy::NetPolicy};
+//!
+//! let policy: NetPolicy = "bind 127.0.0.1:8080; connect db.example.com:5432"
+//!     .parse()
+//!     .unwrap();
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//! policy.apply(&mut cap_net).unwrap();
+//!
+//! capsicum::enter();
+//! ```
+//!
+//! With the `serde` feature, a `NetPolicy` can also be stored in a structured
+//! configuration file.  Each `bind` and `connect` entry is written just as in
+//! the string format:
+//!
+//! ```json
+//! {
+//!     "bind": ["127.0.0.1:8080", "*:53"],
+//!     "connect": ["db.example.com:5432"],
+//!     "names": ["db.example.com"]
+//! }
+//! ```
+//!
+//! The same feature lets operators keep a policy in its own TOML file, read
+//! with [`NetPolicy::from_path`].  Every key is optional: `bind`, `connect`,
+//! and `names` are arrays of strings, as above, and `dns` and `rdns` are
+//! booleans.  No tables or other keys are allowed.  For example:
+//!
+//! ```toml
+//! # /usr/local/etc/myd/net.toml
+//! bind = ["127.0.0.1:8080", "*:53"]
+//! connect = [
+//!     "db.example.com:5432",
+//!     "[2001:db8::1]:443",
+//! ]
+//! names = ["db.example.com"]
+//! rdns = false
+//! ```
+use std::{
+    fmt,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+use nix::sys::socket::SockaddrStorage;
+
+use crate::{CapNetAgent, LimitFlags};
+
+/// The host part of a `connect` statement
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Host {
+    /// Any host at all: `*`
+    Any,
+    /// A numeric address
+    Addr(IpAddr),
+    /// An exact hostname
+    Name(String),
+    /// Any hostname ending in this suffix, like `.internal` for `*.internal`
+    Suffix(String),
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Host::Any => f.write_str("*"),
+            Host::Addr(IpAddr::V4(a)) => write!(f, "{a}"),
+            Host::Addr(IpAddr::V6(a)) => write!(f, "[{a}]"),
+            Host::Name(n) => f.write_str(n),
+            Host::Suffix(s) => write!(f, "*{s}"),
+        }
+    }
+}
+
+/// A `connect` statement: a host, and a port or any port
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Endpoint {
+    /// The permitted host or hosts
+    pub host: Host,
+    /// The permitted port, or `None` for any
+    pub port: Option<u16>,
+}
+
+impl Endpoint {
+    /// Can this be enforced by Casper, after resolving any hostname?
+    fn is_exact(&self) -> bool {
+        self.port.is_some()
+            && matches!(self.host, Host::Addr(_) | Host::Name(_))
+    }
+
+    fn matches(&self, host: &str, port: u16) -> bool {
+        if self.port.is_some_and(|p| p != port) {
+            return false;
+        }
+        let host = host.trim_end_matches('.');
+        match &self.host {
+            Host::Any => true,
+            Host::Addr(a) => host.parse::<IpAddr>().is_ok_and(|h| h == *a),
+            Host::Name(n) => host.eq_ignore_ascii_case(n),
+            Host::Suffix(s) => {
+                host.len() > s.len()
+                    && host.is_char_boundary(host.len() - s.len())
+                    && host[host.len() - s.len()..].eq_ignore_ascii_case(s)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Some(p) => write!(f, "{}:{p}", self.host),
+            None => write!(f, "{}:*", self.host),
+        }
+    }
+}
+
+/// A `bind` statement
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BindTarget {
+    /// A specific socket address
+    Addr(SocketAddr),
+    /// The wildcard address of either family, on this port
+    Port(u16),
+}
+
+impl fmt::Display for BindTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindTarget::Addr(a) => write!(f, "{a}"),
+            BindTarget::Port(p) => write!(f, "*:{p}"),
+        }
+    }
+}
+
+/// A complete network sandbox policy.  See the [module docs](self) for the
+/// string format.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct NetPolicy {
+    /// Permitted `bind` targets
+    pub bind:    Vec<BindTarget>,
+    /// Permitted `connect` endpoints
+    pub connect: Vec<Endpoint>,
+    /// May any name be resolved to addresses?
+    pub dns:     bool,
+    /// Names that may be resolved to addresses, even if `dns` is false
+    pub names:   Vec<String>,
+    /// May addresses be resolved to names?
+    pub rdns:    bool,
+}
+
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl serde::Serialize for BindTarget {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl<'de> serde::Deserialize<'de> for BindTarget {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        d: D,
+    ) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        parse_bind(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl serde::Serialize for Endpoint {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl<'de> serde::Deserialize<'de> for Endpoint {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        d: D,
+    ) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        parse_connect(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Split `HOST:PORT`, where an IPv6 host must be bracketed.
+fn split_host_port(s: &str) -> io::Result<(&str, &str)> {
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| invalid(format!("unterminated '[' in {s:?}")))?;
+        let port = rest
+            .strip_prefix(':')
+            .ok_or_else(|| invalid(format!("missing port in {s:?}")))?;
+        (host, port)
+    } else {
+        s.rsplit_once(':')
+            .ok_or_else(|| invalid(format!("missing port in {s:?}")))?
+    };
+    if host.is_empty() {
+        return Err(invalid(format!("missing host in {s:?}")));
+    }
+    Ok((host, port))
+}
+
+fn parse_port(s: &str) -> io::Result<u16> {
+    s.parse()
+        .map_err(|_| invalid(format!("invalid port {s:?}")))
+}
+
+fn parse_bind(arg: &str) -> io::Result<BindTarget> {
+    let (host, port) = split_host_port(arg)?;
+    let port = parse_port(port)?;
+    if host == "*" {
+        return Ok(BindTarget::Port(port));
+    }
+    let ip = host
+        .parse::<IpAddr>()
+        .map_err(|_| invalid(format!("bind requires an address: {arg:?}")))?;
+    Ok(BindTarget::Addr(SocketAddr::new(ip, port)))
+}
+
+fn parse_host(host: &str) -> io::Result<Host> {
+    let host = if host.is_empty() {
+        return Err(invalid("missing host".to_owned()));
+    } else if host == "*" {
+        Host::Any
+    } else if let Some(suffix) = host.strip_prefix('*') {
+        if !suffix.starts_with('.') || suffix.len() < 2 {
+            return Err(invalid(format!("invalid host pattern {host:?}")));
+        }
+        Host::Suffix(suffix.to_owned())
+    } else if let Ok(ip) = host.parse::<IpAddr>() {
+        Host::Addr(ip)
+    } else if host.contains('*') {
+        return Err(invalid(format!("invalid host pattern {host:?}")));
+    } else {
+        Host::Name(host.to_owned())
+    };
+    Ok(host)
+}
+
+fn parse_connect(arg: &str) -> io::Result<Endpoint> {
+    let (host, port) = split_host_port(arg)?;
+    let port = if port == "*" {
+        None
+    } else {
+        Some(parse_port(port)?)
+    };
+    let host = parse_host(host)?;
+    Ok(Endpoint { host, port })
+}
+
+impl NetPolicy {
+    /// Add a single statement, in either form, to the policy.
+    fn push_stmt(&mut self, verb: &str, arg: Option<&str>) -> io::Result<()> {
+        match (verb, arg) {
+            ("bind", Some(arg)) => self.bind.push(parse_bind(arg)?),
+            ("connect", Some(arg)) => self.connect.push(parse_connect(arg)?),
+            ("dns", None) => self.dns = true,
+            ("resolve", Some(arg)) => self.names.push(arg.to_owned()),
+            ("rdns", None) => self.rdns = true,
+            ("bind" | "connect" | "resolve", None) => {
+                return Err(invalid(format!("{verb} needs an argument")))
+            }
+            ("dns" | "rdns", Some(_)) => {
+                return Err(invalid(format!("{verb} takes no argument")))
+            }
+            _ => return Err(invalid(format!("unknown statement {verb:?}"))),
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for NetPolicy {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let mut policy = NetPolicy::default();
+        for stmt in s.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            if let Some((verb, args)) = stmt.split_once('=') {
+                // The compact form: a verb and a list of arguments
+                let verb = match verb.trim() {
+                    "dns" => "resolve",
+                    verb => verb,
+                };
+                let mut args = args.split(',').map(str::trim).peekable();
+                if args.peek().map_or(true, |a| a.is_empty()) {
+                    return Err(invalid(format!("{verb} needs an argument")));
+                }
+                for arg in args {
+                    if arg.is_empty() {
+                        return Err(invalid(format!(
+                            "empty argument in {stmt:?}"
+                        )));
+                    }
+                    policy.push_stmt(verb, Some(arg))?;
+                }
+                continue;
+            }
+            let mut words = stmt.split_whitespace();
+            let verb = words.next().unwrap_or_default();
+            let arg = words.next();
+            if words.next().is_some() {
+                return Err(invalid(format!("trailing words in {stmt:?}")));
+            }
+            policy.push_stmt(verb, arg)?;
+        }
+        Ok(policy)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl NetPolicy {
+    /// Parse a policy from a TOML file.
+    ///
+    /// See the [module docs](self) for the schema.  Fails with
+    /// `ErrorKind::InvalidInput` if the file is malformed.  The policy is not
+    /// applied; call [`NetPolicy::apply`] for that.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, policy::NetPolicy};
+    ///
+    /// let policy = NetPolicy::from_path("/usr/local/etc/myd/net.toml")
+    ///     .unwrap();
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    /// policy.apply(&mut cap_net).unwrap();
+    /// ```
+    pub fn from_path<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)?;
+        Self::from_toml(&s)
+            .map_err(|e| invalid(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Parse a policy from a TOML document, as in a file read by
+    /// [`NetPolicy::from_path`].
+    pub fn from_toml(s: &str) -> io::Result<Self> {
+        toml::from_str(s).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+impl fmt::Display for NetPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut stmts = Vec::new();
+        stmts.extend(self.bind.iter().map(|b| format!("bind {b}")));
+        stmts.extend(self.connect.iter().map(|c| format!("connect {c}")));
+        if self.dns {
+            stmts.push("dns".to_owned());
+        }
+        stmts.extend(self.names.iter().map(|n| format!("resolve {n}")));
+        if self.rdns {
+            stmts.push("rdns".to_owned());
+        }
+        f.write_str(&stmts.join("; "))
+    }
+}
+
+impl NetPolicy {
+    /// A policy that permits no network access at all.
+    ///
+    /// Equivalent to the empty string.
+    pub fn no_network() -> Self {
+        Self::default()
+    }
+
+    /// A policy that permits binding and connecting to `ports` on the IPv4 and
+    /// IPv6 loopback addresses, and nothing else.
+    ///
+    /// Casper matches addresses exactly, so the ports must be listed.
+    pub fn loopback_only(ports: &[u16]) -> Self {
+        let mut policy = Self::default();
+        for ip in [Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()] {
+            for &port in ports {
+                policy
+                    .bind
+                    .push(BindTarget::Addr(SocketAddr::new(ip, port)));
+                policy.connect.push(Endpoint {
+                    host: Host::Addr(ip),
+                    port: Some(port),
+                });
+            }
+        }
+        policy
+    }
+
+    /// A policy that permits only connecting to port 443 of `hosts`.
+    ///
+    /// Each host may be a hostname, a numeric address, or a pattern, just as
+    /// in a `connect` statement.  Exact hostnames are resolved when the policy
+    /// is applied, so no name resolution is permitted afterwards.  Fails with
+    /// `ErrorKind::InvalidInput` if a host is malformed.
+    pub fn outbound_https_only<I, S>(hosts: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let connect = hosts
+            .into_iter()
+            .map(|h| {
+                let host = parse_host(h.as_ref())?;
+                Ok(Endpoint {
+                    host,
+                    port: Some(443),
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(NetPolicy {
+            connect,
+            ..Default::default()
+        })
+    }
+
+    /// The operations that this policy permits at all
+    pub fn flags(&self) -> LimitFlags {
+        let mut flags = LimitFlags::empty();
+        flags.set(LimitFlags::BIND, !self.bind.is_empty());
+        flags.set(LimitFlags::CONNECT, !self.connect.is_empty());
+        flags.set(LimitFlags::NAME2ADDR, self.dns || !self.names.is_empty());
+        flags.set(LimitFlags::ADDR2NAME, self.rdns);
+        flags
+    }
+
+    /// Would connecting to `host` on `port` be allowed?
+    ///
+    /// `host` may be a hostname or a numeric address.  This is the only way to
+    /// enforce wildcard `connect` statements.
+    pub fn permits_connect(&self, host: &str, port: u16) -> bool {
+        self.connect.iter().any(|e| e.matches(host, port))
+    }
+
+    /// Limit `agent` to this policy.
+    ///
+    /// Any hostnames in `connect` statements are resolved first, using the
+    /// agent itself, so this must be done before any other limit removes
+    /// name resolution.  As with any limit, this can only reduce the agent's
+    /// capabilities.
+    ///
+    /// Fails with `ErrorKind::InvalidInput`, without applying anything, if
+    /// any `connect` statement has a wildcard host or port, since Casper
+    /// can't enforce it.
+    pub fn apply(&self, agent: &mut CapNetAgent) -> io::Result<()> {
+        if let Some(e) = self.connect.iter().find(|e| !e.is_exact()) {
+            return Err(invalid(format!(
+                "Casper can't enforce connect {e}; see apply_with_wildcards"
+            )));
+        }
+        self.apply_with_wildcards(agent)
+    }
+
+    /// Like [`apply`](Self::apply), but accept wildcard `connect` statements.
+    ///
+    /// If there are any, Casper is told to permit connecting to any address,
+    /// and it's up to the caller to enforce the policy, with
+    /// [`permits_connect`](Self::permits_connect) or an
+    /// [access list](crate::acl::AccessList).  `bind` and name resolution are
+    /// still limited as usual.
+    pub fn apply_with_wildcards(
+        &self,
+        agent: &mut CapNetAgent,
+    ) -> io::Result<()> {
+        let mut connect = Vec::new();
+        if self.connect.iter().all(Endpoint::is_exact) {
+            for e in self.connect.iter() {
+                let port = e.port.unwrap();
+                match &e.host {
+                    Host::Addr(ip) => connect.push(SocketAddr::new(*ip, port)),
+                    Host::Name(name) => {
+                        connect.extend(agent.resolve(name, port)?)
+                    }
+                    Host::Any | Host::Suffix(_) => unreachable!(),
+                }
+            }
+        }
+        let mut limit = agent.limit(self.flags())?;
+        for b in self.bind.iter() {
+            match b {
+                BindTarget::Addr(addr) => {
+                    limit.bind(&SockaddrStorage::from(*addr));
+                }
+                BindTarget::Port(port) => {
+                    limit.bind_port(*port);
+                }
+            }
+        }
+        for addr in connect {
+            limit.connect(&SockaddrStorage::from(addr));
+        }
+        if !self.dns {
+            for name in self.names.iter() {
+                limit.name2addr(name, None);
+            }
+        }
+        limit.limit()
+    }
+}
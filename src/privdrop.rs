@@ -0,0 +1,152 @@
+// vim: tw=80
+//! Bind privileged ports, then drop privileges
+//!
+//! The canonical daemon startup sequence binds its well-known ports while still
+//! running as root, switches to an unprivileged user, and finally enters
+//! capability mode.  [`Startup`] packages that sequence.  Because the listeners
+//! are bound before privileges are dropped, no Casper service is needed to bind
+//! ports below 1024.
+//!
+//! Any Casper service opened before [`Startup::run`] keeps root's privileges.
+//! To open services as the unprivileged user instead, disable
+//! [`enter_capability_mode`](Startup::enter_capability_mode), open them after
+//! `run` returns, and then call `capsicum::enter` yourself.
+//!
+//! # Example
+//! ```no_run
+//! use std::net::SocketAddr;
+//!
+//! use capsicum_net::privdrop::Startup;
+//!
+//! let http: SocketAddr = "0.0.0.0:80".parse().unwrap();
+//! let dns: SocketAddr = "0.0.0.0:53".parse().unwrap();
+//! let listeners = Startup::new()
+//!     .tcp(http)
+//!     .udp(dns)
+//!     .user_name("www")
+//!     .unwrap()
+//!     .run()
+//!     .unwrap();
+//! let (stream, peer) = listeners.tcp[0].accept().unwrap();
+//! ```
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, UdpSocket},
+};
+
+use nix::unistd::{Gid, Uid, User};
+
+/// Sockets bound by [`Startup::run`], in the order they were requested
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Listeners {
+    /// Listening TCP sockets
+    pub tcp: Vec<TcpListener>,
+    /// Bound UDP sockets
+    pub udp: Vec<UdpSocket>,
+}
+
+/// Binds sockets, drops privileges, and enters capability mode.
+#[derive(Clone, Debug)]
+pub struct Startup {
+    tcp:   Vec<SocketAddr>,
+    udp:   Vec<SocketAddr>,
+    user:  Option<(Uid, Gid)>,
+    enter: bool,
+}
+
+impl Default for Startup {
+    fn default() -> Self {
+        Startup {
+            tcp:   Vec::new(),
+            udp:   Vec::new(),
+            user:  None,
+            enter: true,
+        }
+    }
+}
+
+impl Startup {
+    /// Create a new startup sequence, which by default binds nothing, keeps
+    /// the current user, and enters capability mode.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Listen for TCP connections on `addr`.
+    pub fn tcp(mut self, addr: SocketAddr) -> Self {
+        self.tcp.push(addr);
+        self
+    }
+
+    /// Bind a UDP socket to `addr`.
+    pub fn udp(mut self, addr: SocketAddr) -> Self {
+        self.udp.push(addr);
+        self
+    }
+
+    /// Switch to the given user and group after binding.
+    ///
+    /// All supplementary groups are dropped.
+    pub fn user(mut self, uid: Uid, gid: Gid) -> Self {
+        self.user = Some((uid, gid));
+        self
+    }
+
+    /// Switch to the named user, and that user's primary group, after
+    /// binding.
+    ///
+    /// The name is looked up immediately, because the password database won't
+    /// be accessible once in capability mode.
+    pub fn user_name(self, name: &str) -> io::Result<Self> {
+        let user = User::from_name(name)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such user: {name}"),
+            )
+        })?;
+        Ok(self.user(user.uid, user.gid))
+    }
+
+    /// Whether to enter capability mode after dropping privileges.  The
+    /// default is `true`.
+    pub fn enter_capability_mode(mut self, enter: bool) -> Self {
+        self.enter = enter;
+        self
+    }
+
+    /// Bind every requested socket, then drop privileges and enter capability
+    /// mode.
+    ///
+    /// If any step fails, the sockets bound so far are closed and the process
+    /// may be left partway through the sequence; daemons should treat an error
+    /// as fatal.
+    pub fn run(self) -> io::Result<Listeners> {
+        let tcp = self
+            .tcp
+            .iter()
+            .map(TcpListener::bind)
+            .collect::<io::Result<Vec<_>>>()?;
+        let udp = self
+            .udp
+            .iter()
+            .map(UdpSocket::bind)
+            .collect::<io::Result<Vec<_>>>()?;
+        if let Some((uid, gid)) = self.user {
+            // Order matters: once we setuid, we can no longer change groups.
+            nix::unistd::setgroups(&[gid])?;
+            nix::unistd::setgid(gid)?;
+            nix::unistd::setuid(uid)?;
+            if !uid.is_root() && nix::unistd::setuid(Uid::from_raw(0)).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "failed to permanently drop root privileges",
+                ));
+            }
+        }
+        if self.enter {
+            capsicum::enter()?;
+        }
+        Ok(Listeners { tcp, udp })
+    }
+}
@@ -0,0 +1,167 @@
+// vim: tw=80
+//! USDT probes for agent operations
+//!
+//! When built with the `usdt` feature, every bind, connect, limit, and name
+//! resolution performed through a [`CapNetAgent`](crate::CapNetAgent) fires a
+//! probe in the `capsicum_net` provider, so administrators can trace a
+//! sandbox's network activity with dtrace(1).  The resolver's `done` probes
+//! report the getaddrinfo(3) error code, or the errno for `gethostbyname`.
+//! For example:
+//!
+//! ```sh
+//! dtrace -n 'capsicum_net*:::connect-start { printf("%d %s", arg0, copyinstr(arg1)); }'
+//! ```
+//!
+//! The application must call [`register_probes`](crate::register_probes) once
+//! at startup for the probes to be visible.  Without the `usdt` feature, the
+//! probes compile to nothing.
+use nix::sys::socket::{SockaddrLike, SockaddrStorage};
+
+#[cfg(feature = "usdt")]
+#[usdt::provider(provider = "capsicum_net")]
+mod capsicum_net {
+    fn bind__start(fd: i32, addr: &str) {}
+    fn bind__done(fd: i32, errno: i32) {}
+    fn connect__start(fd: i32, addr: &str) {}
+    fn connect__done(fd: i32, errno: i32) {}
+    fn getaddrinfo__start(host: &str, serv: &str) {}
+    fn getaddrinfo__done(error: i32) {}
+    fn gethostbyname__start(name: &str, af: i32) {}
+    fn gethostbyname__done(errno: i32) {}
+    fn getnameinfo__start(addr: &str) {}
+    fn getnameinfo__done(error: i32) {}
+    fn limit__done(mode: u64, errno: i32) {}
+}
+
+// The gethostbyname probes are used only with the deprecated-dns feature
+#[cfg(feature = "usdt")]
+#[cfg_attr(not(feature = "deprecated-dns"), allow(unused_imports))]
+pub(crate) use self::capsicum_net::{
+    bind__done,
+    bind__start,
+    connect__done,
+    connect__start,
+    getaddrinfo__done,
+    getaddrinfo__start,
+    gethostbyname__done,
+    gethostbyname__start,
+    getnameinfo__done,
+    getnameinfo__start,
+    limit__done,
+};
+
+// Without the usdt feature, the probe macros type-check their argument
+// closures but never call them.
+#[cfg(not(feature = "usdt"))]
+macro_rules! bind__start {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+#[cfg(not(feature = "usdt"))]
+macro_rules! bind__done {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+#[cfg(not(feature = "usdt"))]
+macro_rules! connect__start {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+#[cfg(not(feature = "usdt"))]
+macro_rules! connect__done {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+#[cfg(not(feature = "usdt"))]
+macro_rules! getaddrinfo__start {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+#[cfg(not(feature = "usdt"))]
+macro_rules! getaddrinfo__done {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+#[cfg(not(feature = "usdt"))]
+#[cfg_attr(not(feature = "deprecated-dns"), allow(unused_macros))]
+macro_rules! gethostbyname__start {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+#[cfg(not(feature = "usdt"))]
+#[cfg_attr(not(feature = "deprecated-dns"), allow(unused_macros))]
+macro_rules! gethostbyname__done {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+#[cfg(not(feature = "usdt"))]
+macro_rules! getnameinfo__start {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+#[cfg(not(feature = "usdt"))]
+macro_rules! getnameinfo__done {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+#[cfg(not(feature = "usdt"))]
+macro_rules! limit__done {
+    ($args:expr) => {
+        let _ = $args;
+    };
+}
+// The gethostbyname probes are used only with the deprecated-dns feature
+#[cfg(not(feature = "usdt"))]
+#[cfg_attr(not(feature = "deprecated-dns"), allow(unused_imports))]
+pub(crate) use {
+    bind__done,
+    bind__start,
+    connect__done,
+    connect__start,
+    getaddrinfo__done,
+    getaddrinfo__start,
+    gethostbyname__done,
+    gethostbyname__start,
+    getnameinfo__done,
+    getnameinfo__start,
+    limit__done,
+};
+
+/// Format a raw sockaddr for a probe argument.
+pub(crate) fn fmt_sockaddr(
+    addr: *const libc::sockaddr,
+    len: libc::socklen_t,
+) -> String {
+    // Safe because our callers always pass a valid sockaddr and length.
+    match unsafe { SockaddrStorage::from_raw(addr, Some(len)) } {
+        Some(ss) if ss.family().is_some() => ss.to_string(),
+        _ => String::from("?"),
+    }
+}
+
+/// Convert an operation's result into an errno for a probe argument.
+pub(crate) fn errno<T>(res: &nix::Result<T>) -> i32 {
+    match res {
+        Ok(_) => 0,
+        Err(e) => *e as i32,
+    }
+}
+
+/// Like [`errno`], but for an `io::Result`.
+#[cfg_attr(not(feature = "deprecated-dns"), allow(dead_code))]
+pub(crate) fn io_errno<T>(res: &std::io::Result<T>) -> i32 {
+    match res {
+        Ok(_) => 0,
+        Err(e) => e.raw_os_error().unwrap_or(-1),
+    }
+}
@@ -0,0 +1,117 @@
+// vim: tw=80
+//! Rate limiting for outbound connections
+//!
+//! A [`RateLimiter`] installed with
+//! [`CapNetAgent::set_connect_rate_limit`](crate::CapNetAgent::set_connect_rate_limit)
+//! throttles every connect operation performed through that agent, so a
+//! compromised or buggy sandboxed worker cannot open thousands of connections
+//! per second, even to addresses that its Casper limits allow.
+//!
+//! # Example
+//! ```no_run
+//! use std::net::TcpStream;
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, ratelimit::RateLimiter, std::TcpStreamExt};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//! // Allow bursts of up to 10 connections, and 2 per second on average
+//! cap_net.set_connect_rate_limit(Some(RateLimiter::new(2, 10)));
+//!
+//! capsicum::enter();
+//!
+//! for _ in 0..100 {
+//!     TcpStream::cap_connect(&mut cap_net, "192.0.2.1:80").unwrap();
+//! }
+//! ```
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A token bucket rate limiter.
+///
+/// The bucket holds up to `burst` tokens and refills at `per_second` tokens
+/// per second.  Each operation consumes one token.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    tokens:   f64,
+    rate:     f64,
+    last:     Instant,
+}
+
+impl RateLimiter {
+    /// Create a full bucket that allows `per_second` operations per second on
+    /// average, in bursts of up to `burst`.
+    ///
+    /// # Panics
+    ///
+    /// If `per_second` is zero.
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        assert!(per_second > 0, "rate must be positive");
+        let capacity = f64::from(burst.max(1));
+        RateLimiter {
+            capacity,
+            tokens: capacity,
+            rate: f64::from(per_second),
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+
+    /// Consume a token, or return how long to wait for one.
+    pub(crate) fn try_acquire(&mut self) -> Result<(), Duration> {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+/// Permission to perform a single connect operation.
+///
+/// Acquired once per operation, before it starts, so that callers sharing a
+/// limiter can't race between checking for a token and consuming it.
+#[derive(Debug)]
+#[must_use]
+pub(crate) struct Permit(());
+
+impl Permit {
+    /// Consume a token from `limiter`, sleeping until one is available.
+    pub(crate) fn acquire(limiter: Option<&Arc<Mutex<RateLimiter>>>) -> Self {
+        // Don't hold the lock while sleeping; split agents share it.
+        while let Some(Err(delay)) = limiter
+            .map(|l| l.lock().unwrap_or_else(|e| e.into_inner()).try_acquire())
+        {
+            std::thread::sleep(delay);
+        }
+        Permit(())
+    }
+
+    /// Consume a token from `limiter`, waiting for one without blocking the
+    /// runtime.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn acquire_async(
+        limiter: Option<Arc<Mutex<RateLimiter>>>,
+    ) -> Self {
+        while let Some(Err(delay)) = limiter
+            .as_ref()
+            .map(|l| l.lock().unwrap_or_else(|e| e.into_inner()).try_acquire())
+        {
+            tokio::time::sleep(delay).await;
+        }
+        Permit(())
+    }
+}
@@ -0,0 +1,464 @@
+// vim: tw=80
+//! Client streams that transparently reconnect
+//!
+//! A [`ReconnectingStream`] owns a [`CapNetAgent`] and the address of a
+//! server.  It connects lazily, on first use.  Whenever the connection drops,
+//! it re-resolves the target and reconnects through the agent, waiting with
+//! exponential backoff between failed attempts.  Data in flight when the
+//! connection dropped is lost, so this is best suited to protocols that can
+//! tolerate that, like streaming telemetry.
+//!
+//! With the `tokio` feature, [`AsyncReconnectingStream`] does the same for
+//! asynchronous code.
+//!
+//! # Example
+//! ```no_run
+//! use std::io::Write;
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, reconnect::ReconnectingStream};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let cap_net = casper.net().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let mut stream = ReconnectingStream::new(cap_net, "192.0.2.1:2003");
+//! loop {
+//!     stream.write_all(b"sandbox.heartbeat 1 0\n").unwrap();
+//! }
+//! ```
+#[cfg(feature = "tokio")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    thread,
+    time::Duration,
+};
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{std::TcpStreamExt, CapNetAgent, CapToSocketAddrs};
+
+/// The server that a [`ReconnectingStream`] connects to
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Target {
+    /// A fixed list of addresses, tried in order
+    Addrs(Vec<SocketAddr>),
+    /// A "host:port" string, resolved anew before every connection attempt
+    Host(String),
+}
+
+impl CapToSocketAddrs for Target {
+    fn cap_to_socket_addrs(
+        &self,
+        agent: &mut CapNetAgent,
+    ) -> io::Result<Vec<SocketAddr>> {
+        match self {
+            Target::Addrs(addrs) => Ok(addrs.clone()),
+            Target::Host(host) => host.cap_to_socket_addrs(agent),
+        }
+    }
+}
+
+impl From<SocketAddr> for Target {
+    fn from(addr: SocketAddr) -> Self {
+        Target::Addrs(vec![addr])
+    }
+}
+
+impl From<Vec<SocketAddr>> for Target {
+    fn from(addrs: Vec<SocketAddr>) -> Self {
+        Target::Addrs(addrs)
+    }
+}
+
+impl From<&str> for Target {
+    fn from(host: &str) -> Self {
+        Target::Host(host.to_owned())
+    }
+}
+
+impl From<String> for Target {
+    fn from(host: String) -> Self {
+        Target::Host(host)
+    }
+}
+
+/// How long to wait between failed connection attempts
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Backoff {
+    /// Delay after the first failed attempt.  Each subsequent delay is twice
+    /// as long as the last.
+    pub initial:      Duration,
+    /// Upper limit for the delay
+    pub max:          Duration,
+    /// Give up after this many consecutive failed attempts.  `None` retries
+    /// forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Backoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+            .min(self.max)
+    }
+
+    fn exhausted(&self, attempts: u32) -> bool {
+        self.max_attempts.is_some_and(|max| attempts >= max)
+    }
+}
+
+impl Default for Backoff {
+    /// 100 ms, doubling up to 30 s, for at most 10 attempts
+    fn default() -> Self {
+        Backoff {
+            initial:      Duration::from_millis(100),
+            max:          Duration::from_secs(30),
+            max_attempts: Some(10),
+        }
+    }
+}
+
+/// Does this error mean that the peer went away?
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Make a single attempt to connect to `target`.
+fn connect_once(
+    agent: &mut CapNetAgent,
+    target: &Target,
+) -> io::Result<TcpStream> {
+    TcpStream::cap_connect(agent, target)
+}
+
+/// A TCP client stream that reconnects whenever its connection drops.
+///
+/// Reads and writes block while reconnecting.  If a freshly established
+/// connection immediately fails or reaches end-of-file, that result is
+/// returned to the caller rather than reconnecting again.
+#[derive(Debug)]
+pub struct ReconnectingStream {
+    agent:    CapNetAgent,
+    target:   Target,
+    backoff:  Backoff,
+    stream:   Option<TcpStream>,
+    /// Has the current connection not yet transferred any data?
+    fresh:    bool,
+    connects: u64,
+}
+
+impl ReconnectingStream {
+    /// Create a new stream that will connect to `target` using `agent`.
+    ///
+    /// No connection is made until the first read or write.
+    pub fn new<T: Into<Target>>(agent: CapNetAgent, target: T) -> Self {
+        ReconnectingStream {
+            agent,
+            target: target.into(),
+            backoff: Backoff::default(),
+            stream: None,
+            fresh: false,
+            connects: 0,
+        }
+    }
+
+    /// Change the delay between failed connection attempts.
+    pub fn set_backoff(&mut self, backoff: Backoff) {
+        self.backoff = backoff;
+    }
+
+    /// How many times the stream has reconnected after its first connection
+    pub fn reconnects(&self) -> u64 {
+        self.connects.saturating_sub(1)
+    }
+
+    /// The current connection, if any
+    pub fn get_ref(&self) -> Option<&TcpStream> {
+        self.stream.as_ref()
+    }
+
+    /// Connect, if not already connected, retrying with backoff.
+    pub fn connect(&mut self) -> io::Result<&mut TcpStream> {
+        if self.stream.is_none() {
+            let mut attempts = 0;
+            let stream = loop {
+                match connect_once(&mut self.agent, &self.target) {
+                    Ok(s) => break s,
+                    Err(e) => {
+                        attempts += 1;
+                        if self.backoff.exhausted(attempts) {
+                            return Err(e);
+                        }
+                        thread::sleep(self.backoff.delay(attempts - 1));
+                    }
+                }
+            };
+            self.stream = Some(stream);
+            self.fresh = true;
+            self.connects += 1;
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+}
+
+impl Read for ReconnectingStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let r = self.connect()?.read(buf);
+            match r {
+                Ok(0) if !buf.is_empty() && !self.fresh => self.stream = None,
+                Err(e) if is_disconnect(&e) && !self.fresh => {
+                    self.stream = None
+                }
+                Ok(n) => {
+                    self.fresh &= n == 0;
+                    return Ok(n);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Write for ReconnectingStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            let r = self.connect()?.write(buf);
+            match r {
+                Err(e) if is_disconnect(&e) && !self.fresh => {
+                    self.stream = None
+                }
+                Ok(n) => {
+                    self.fresh &= n == 0;
+                    return Ok(n);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stream.as_mut() {
+            Some(s) => s.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An asynchronous TCP client stream that reconnects whenever its connection
+/// drops.
+///
+/// This behaves like [`ReconnectingStream`], except that it never blocks the
+/// runtime: delays between connection attempts and each TCP handshake are
+/// awaited, as by [`cap_connect`](crate::tokio::cap_connect).  Resolving a
+/// [`Target::Host`] is still a synchronous request to the Casper service.
+/// Must be used within a Tokio runtime with the time driver enabled.
+#[cfg(feature = "tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+pub struct AsyncReconnectingStream {
+    /// `None` while a connection attempt owns it
+    agent:      Option<CapNetAgent>,
+    target:     Target,
+    backoff:    Backoff,
+    stream:     Option<tokio::net::TcpStream>,
+    fresh:      bool,
+    connects:   u64,
+    attempts:   u32,
+    sleep:      Option<Pin<Box<tokio::time::Sleep>>>,
+    connecting: Option<ConnectFuture>,
+}
+
+/// A connection attempt in progress, which returns the agent when done
+#[cfg(feature = "tokio")]
+type ConnectFuture = Pin<
+    Box<
+        dyn Future<Output = (CapNetAgent, io::Result<tokio::net::TcpStream>)>
+            + Send,
+    >,
+>;
+
+#[cfg(feature = "tokio")]
+impl std::fmt::Debug for AsyncReconnectingStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncReconnectingStream")
+            .field("agent", &self.agent)
+            .field("target", &self.target)
+            .field("backoff", &self.backoff)
+            .field("stream", &self.stream)
+            .field("fresh", &self.fresh)
+            .field("connects", &self.connects)
+            .field("attempts", &self.attempts)
+            .field("connecting", &self.connecting.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncReconnectingStream {
+    /// Create a new stream that will connect to `target` using `agent`.
+    ///
+    /// No connection is made until the first read or write.
+    pub fn new<T: Into<Target>>(agent: CapNetAgent, target: T) -> Self {
+        AsyncReconnectingStream {
+            agent:      Some(agent),
+            target:     target.into(),
+            backoff:    Backoff::default(),
+            stream:     None,
+            fresh:      false,
+            connects:   0,
+            attempts:   0,
+            sleep:      None,
+            connecting: None,
+        }
+    }
+
+    /// Change the delay between failed connection attempts.
+    pub fn set_backoff(&mut self, backoff: Backoff) {
+        self.backoff = backoff;
+    }
+
+    /// How many times the stream has reconnected after its first connection
+    pub fn reconnects(&self) -> u64 {
+        self.connects.saturating_sub(1)
+    }
+
+    /// The current connection, if any
+    pub fn get_ref(&self) -> Option<&tokio::net::TcpStream> {
+        self.stream.as_ref()
+    }
+
+    fn poll_connect(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<&mut tokio::net::TcpStream>> {
+        while self.stream.is_none() {
+            if let Some(sleep) = self.sleep.as_mut() {
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+            if self.connecting.is_none() {
+                // The future owns the agent, so that it can be stored here
+                // while it runs.  It waits for any rate limit too.
+                let mut agent = self.agent.take().unwrap();
+                let target = self.target.clone();
+                self.connecting = Some(Box::pin(async move {
+                    let r = crate::tokio::cap_connect(&mut agent, target).await;
+                    (agent, r)
+                }));
+            }
+            let connecting = self.connecting.as_mut().unwrap();
+            let (agent, r) = ready!(connecting.as_mut().poll(cx));
+            self.connecting = None;
+            self.agent = Some(agent);
+            match r {
+                Ok(s) => {
+                    self.stream = Some(s);
+                    self.fresh = true;
+                    self.connects += 1;
+                    self.attempts = 0;
+                }
+                Err(e) => {
+                    self.attempts += 1;
+                    if self.backoff.exhausted(self.attempts) {
+                        self.attempts = 0;
+                        return Poll::Ready(Err(e));
+                    }
+                    let delay = self.backoff.delay(self.attempts - 1);
+                    self.sleep = Some(Box::pin(tokio::time::sleep(delay)));
+                }
+            }
+        }
+        Poll::Ready(Ok(self.stream.as_mut().unwrap()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncRead for AsyncReconnectingStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let before = buf.filled().len();
+            let stream = ready!(this.poll_connect(cx))?;
+            let r = ready!(Pin::new(stream).poll_read(cx, buf));
+            let n = buf.filled().len() - before;
+            match r {
+                Ok(()) if n == 0 && buf.remaining() > 0 && !this.fresh => {
+                    this.stream = None
+                }
+                Err(e) if is_disconnect(&e) && !this.fresh => {
+                    this.stream = None
+                }
+                Ok(()) => {
+                    this.fresh &= n == 0;
+                    return Poll::Ready(Ok(()));
+                }
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncWrite for AsyncReconnectingStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let stream = ready!(this.poll_connect(cx))?;
+            match ready!(Pin::new(stream).poll_write(cx, buf)) {
+                Err(e) if is_disconnect(&e) && !this.fresh => {
+                    this.stream = None
+                }
+                Ok(n) => {
+                    this.fresh &= n == 0;
+                    return Poll::Ready(Ok(n));
+                }
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut().stream.as_mut() {
+            Some(s) => Pin::new(s).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut().stream.as_mut() {
+            Some(s) => Pin::new(s).poll_shutdown(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
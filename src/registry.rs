@@ -0,0 +1,125 @@
+// vim: tw=80
+//! Declare every socket up front, then revoke the ability to open more
+//!
+//! Many sandboxed services know, at startup, every port they will listen on and
+//! every peer they will ever contact.  A [`SocketRegistry`] collects those
+//! declarations, binds all of the listeners through the agent, and then limits
+//! the agent so that it can no longer bind anything, and can connect only to
+//! the declared targets.  If no targets were declared, connecting is revoked
+//! too.  Name resolution is left as it was.
+//!
+//! # Example
+//! ```no_run
+//! use std::net::SocketAddr;
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, registry::SocketRegistry, std::TcpStreamExt};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let listen: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+//! let backend: SocketAddr = "192.0.2.1:5432".parse().unwrap();
+//! let listeners = SocketRegistry::new()
+//!     .tcp_listener(listen)
+//!     .connect_target(backend)
+//!     .open(&mut cap_net)
+//!     .unwrap();
+//! // From now on, only connections to the backend are possible.
+//! let db = std::net::TcpStream::cap_connect(&mut cap_net, backend).unwrap();
+//! ```
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, UdpSocket},
+};
+
+use nix::sys::socket::SockaddrStorage;
+
+use crate::{
+    privdrop::Listeners,
+    std::{TcpListenerExt, UdpSocketExt},
+    CapNetAgent,
+    LimitFlags,
+};
+
+/// The complete set of sockets that an application will ever open
+#[derive(Clone, Debug, Default)]
+pub struct SocketRegistry {
+    tcp:     Vec<SocketAddr>,
+    udp:     Vec<SocketAddr>,
+    targets: Vec<SocketAddr>,
+}
+
+impl SocketRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a TCP listener on `addr`.
+    pub fn tcp_listener(mut self, addr: SocketAddr) -> Self {
+        self.tcp.push(addr);
+        self
+    }
+
+    /// Declare a UDP socket bound to `addr`.
+    pub fn udp_socket(mut self, addr: SocketAddr) -> Self {
+        self.udp.push(addr);
+        self
+    }
+
+    /// Declare a remote address that the application will connect to later.
+    pub fn connect_target(mut self, addr: SocketAddr) -> Self {
+        self.targets.push(addr);
+        self
+    }
+
+    /// Bind every declared socket, then limit `agent` so that it may no longer
+    /// bind, and may connect only to the declared targets.
+    ///
+    /// The agent keeps whatever name resolution it was allowed before,
+    /// including any limits on which names or addresses it may resolve.
+    /// Since limits can never be relaxed, this should be called only once per
+    /// agent.  If any bind fails, no limit is applied.
+    pub fn open(self, agent: &mut CapNetAgent) -> io::Result<Listeners> {
+        let tcp = self
+            .tcp
+            .iter()
+            .map(|addr| TcpListener::cap_bind(agent, addr))
+            .collect::<io::Result<Vec<_>>>()?;
+        let udp = self
+            .udp
+            .iter()
+            .map(|addr| UdpSocket::cap_bind(agent, addr))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut flags =
+            agent.allowed_ops() - (LimitFlags::BIND | LimitFlags::CONNECT);
+        flags.set(LimitFlags::CONNECT, !self.targets.is_empty());
+        // A new limit without entries would permit resolving anything, which
+        // Casper would reject as an attempt to enlarge the old one.
+        let prior = agent.applied_limits().cloned();
+        let mut limit = agent.limit(flags)?;
+        for addr in self.targets.iter() {
+            limit.connect(&SockaddrStorage::from(*addr));
+        }
+        if let Some(prior) = prior {
+            for sa in prior.addr2name.iter() {
+                limit.addr2name(sa);
+            }
+            if !prior.addr2name_families.is_empty() {
+                limit.addr2name_family(&prior.addr2name_families);
+            }
+            for (name, port) in prior.name2addr.iter() {
+                limit.name2addr(name, *port);
+            }
+            if !prior.name2addr_families.is_empty() {
+                limit.name2addr_family(&prior.name2addr_families);
+            }
+        }
+        limit.limit()?;
+        Ok(Listeners { tcp, udp })
+    }
+}
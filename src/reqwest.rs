@@ -0,0 +1,72 @@
+// vim: tw=80
+//! A [`reqwest`](https://docs.rs/reqwest) DNS resolver that works in
+//! capability mode
+//!
+//! reqwest normally resolves hostnames with the libc resolver, which can't
+//! work once the process has entered capability mode.  [`CapResolver`]
+//! resolves them through a `cap_net` service instead.  Note that this only
+//! handles name resolution.  reqwest's connections must still be permitted,
+//! for example by having connected them before entering capability mode, or
+//! through a connector that uses `cap_connect`.
+//!
+//! # Example
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, reqwest::CapResolver};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let resolver = CapResolver::new(casper.net().unwrap());
+//!
+//! let client = reqwest::Client::builder()
+//!     .dns_resolver(Arc::new(resolver))
+//!     .build()
+//!     .unwrap();
+//! ```
+#![cfg_attr(docsrs, doc(cfg(feature = "reqwest")))]
+use std::sync::{Arc, Mutex};
+
+use ::reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::{resolver::Resolver, CapNetAgent};
+
+/// Implements reqwest's `Resolve` with a [`Resolver`].
+///
+/// Each lookup is a synchronous Casper request, made while the future is
+/// polled.
+#[derive(Clone, Debug)]
+pub struct CapResolver {
+    resolver: Arc<Mutex<Resolver>>,
+}
+
+impl CapResolver {
+    /// Resolve names with `agent`, which must permit
+    /// [`LimitFlags::NAME2ADDR`](crate::LimitFlags::NAME2ADDR).
+    pub fn new(agent: CapNetAgent) -> Self {
+        Self::from(Resolver::new(agent))
+    }
+}
+
+impl From<Resolver> for CapResolver {
+    fn from(resolver: Resolver) -> Self {
+        CapResolver {
+            resolver: Arc::new(Mutex::new(resolver)),
+        }
+    }
+}
+
+impl Resolve for CapResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.resolver.clone();
+        Box::pin(async move {
+            // reqwest fills in the port itself
+            let addrs = resolver
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .lookup_host(name.as_str(), 0)?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
@@ -0,0 +1,634 @@
+// vim: tw=80
+//! Name resolution for applications that don't want to think about addrinfo
+//!
+//! [`CapNetAgent::resolve`] is a thin wrapper around `getaddrinfo`.  A
+//! [`Resolver`] takes the same kind of `"host:port"` strings that
+//! `std::net::ToSocketAddrs` does, skips the Casper round trip for numeric
+//! addresses, and removes duplicate results.
+//!
+//! # Example
+//! ```no_run
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, resolver::Resolver};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut resolver = Resolver::new(casper.net().unwrap());
+//!
+//! capsicum::enter();
+//!
+//! let addrs = resolver.lookup("www.freebsd.org:443").unwrap();
+//! let addrs = resolver.lookup("[2001:db8::1]:53").unwrap();
+//! ```
+//!
+//! Lookups may also be cached, to avoid a Casper round trip each time the same
+//! name is resolved.  See [`Resolver::set_cache`].  Or critical names may be
+//! pinned to fixed addresses with [`Resolver::add_host`].
+//!
+//! The connect helpers resolve names through the [`Resolve`] trait, so a
+//! cached or fake resolver may be substituted for the agent itself.  Pass one
+//! to them with [`WithResolver`], or to
+//! [`CapNetAgent::connect_host_with`].
+//!
+//! Third-party APIs that are generic over `std::net::ToSocketAddrs` can't use
+//! Casper, so they fail to resolve hostnames in capability mode.  Hand them a
+//! [`CapAddrs`] instead.
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+    vec,
+};
+
+use nix::sys::socket::SockType;
+
+use crate::{
+    addrinfo::{AddrInfoFlags, AddrInfoHints, FamilyPreference},
+    sockaddr,
+    CapNetAgent,
+    CapToSocketAddrs,
+};
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Split `HOST:PORT` into its parts.  An IPv6 host must be bracketed.
+fn split_host_port(s: &str) -> io::Result<(&str, u16)> {
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+        rest.split_once("]:")
+    } else {
+        s.rsplit_once(':').filter(|(host, _)| !host.contains(':'))
+    }
+    .ok_or_else(|| invalid(format!("invalid socket address {s:?}")))?;
+    if host.is_empty() {
+        return Err(invalid(format!("missing host in {s:?}")));
+    }
+    let port = port
+        .parse()
+        .map_err(|_| invalid(format!("invalid port in {s:?}")))?;
+    Ok((host, port))
+}
+
+/// Remove duplicates from `addrs`, keeping the first of each.
+fn dedup(addrs: &mut Vec<SocketAddr>) {
+    let mut seen = Vec::with_capacity(addrs.len());
+    addrs.retain(|a| {
+        let new = !seen.contains(a);
+        if new {
+            seen.push(*a);
+        }
+        new
+    });
+}
+
+/// Something that can resolve a hostname to socket addresses.
+///
+/// [`CapNetAgent`] implements this with [`CapNetAgent::resolve`], and
+/// [`Resolver`] with [`Resolver::lookup_host`].  Implement it to substitute
+/// another resolver, such as a fixed table for tests, wherever the connect
+/// helpers would otherwise ask Casper.
+pub trait Resolve {
+    /// Resolve `host` to a list of socket addresses with port `port`, in order
+    /// of preference.
+    fn resolve(&mut self, host: &str, port: u16)
+        -> io::Result<Vec<SocketAddr>>;
+}
+
+impl Resolve for CapNetAgent {
+    fn resolve(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> io::Result<Vec<SocketAddr>> {
+        CapNetAgent::resolve(self, host, port)
+    }
+}
+
+impl Resolve for Resolver {
+    fn resolve(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> io::Result<Vec<SocketAddr>> {
+        self.lookup_host(host, port)
+    }
+}
+
+impl<R: Resolve + ?Sized> Resolve for &mut R {
+    fn resolve(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> io::Result<Vec<SocketAddr>> {
+        (**self).resolve(host, port)
+    }
+}
+
+impl<R: Resolve + ?Sized> Resolve for Box<R> {
+    fn resolve(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> io::Result<Vec<SocketAddr>> {
+        (**self).resolve(host, port)
+    }
+}
+
+/// Recently resolved names, and when they were resolved.
+#[derive(Debug)]
+struct Cache {
+    max_age:  Duration,
+    capacity: usize,
+    entries:  HashMap<String, (Instant, Vec<IpAddr>)>,
+}
+
+impl Cache {
+    fn get(&mut self, host: &str) -> Option<&[IpAddr]> {
+        let expired = self
+            .entries
+            .get(host)
+            .is_some_and(|(added, _)| added.elapsed() >= self.max_age);
+        if expired {
+            self.entries.remove(host);
+        }
+        self.entries.get(host).map(|(_, ips)| &ips[..])
+    }
+
+    fn insert(&mut self, host: &str, ips: Vec<IpAddr>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let max_age = self.max_age;
+        self.entries
+            .retain(|_, (added, _)| added.elapsed() < max_age);
+        if self.entries.len() >= self.capacity
+            && !self.entries.contains_key(host)
+        {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (added, _))| *added)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(host.to_owned(), (Instant::now(), ips));
+    }
+}
+
+/// The result of [`Resolver::lookup_canonical`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Lookup {
+    /// The host's canonical name, if it has one.  Numeric addresses don't.
+    pub canonname: Option<String>,
+    /// The host's addresses, without duplicates
+    pub addrs:     Vec<SocketAddr>,
+}
+
+/// Resolves `"host:port"` strings through a `cap_net` service.
+#[derive(Debug)]
+pub struct Resolver {
+    agent:  CapNetAgent,
+    cache:  Option<Cache>,
+    family: FamilyPreference,
+    hosts:  HashMap<String, Vec<IpAddr>>,
+}
+
+impl Resolver {
+    /// Create a resolver that uses `agent`.
+    ///
+    /// `agent` must permit [`LimitFlags::NAME2ADDR`](crate::LimitFlags) to
+    /// resolve anything but numeric addresses.
+    pub fn new(agent: CapNetAgent) -> Self {
+        Resolver {
+            agent,
+            cache: None,
+            family: FamilyPreference::Any,
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Resolve `name` to `addrs`, without consulting Casper, like an entry in
+    /// [hosts(5)](https://man.freebsd.org/cgi/man.cgi?query=hosts).
+    ///
+    /// Names are matched case-insensitively.  Adding the same name again
+    /// appends to its addresses.  Static entries take precedence over both
+    /// the cache and Casper, and are never evicted.
+    pub fn add_host(&mut self, name: &str, addrs: &[IpAddr]) {
+        let entry = self.hosts.entry(name.to_ascii_lowercase()).or_default();
+        for addr in addrs {
+            if !entry.contains(addr) {
+                entry.push(*addr);
+            }
+        }
+    }
+
+    /// Remove the static entry for `name`, if any.
+    pub fn remove_host(&mut self, name: &str) {
+        self.hosts.remove(&name.to_ascii_lowercase());
+    }
+
+    /// Add a static entry for every line of `hosts`, which has the format of
+    /// [hosts(5)](https://man.freebsd.org/cgi/man.cgi?query=hosts): an
+    /// address followed by a name and any aliases.
+    ///
+    /// Blank lines and `#` comments are ignored.  If any line is malformed,
+    /// fails with `ErrorKind::InvalidInput` and adds nothing.
+    pub fn load_hosts(&mut self, hosts: &str) -> io::Result<()> {
+        let mut entries = Vec::new();
+        for (i, line) in hosts.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(addr) = fields.next() else {
+                continue;
+            };
+            let addr = addr.parse::<IpAddr>().map_err(|_| {
+                invalid(format!("invalid address on line {}", i + 1))
+            })?;
+            let names = fields.collect::<Vec<_>>();
+            if names.is_empty() {
+                return Err(invalid(format!("missing name on line {}", i + 1)));
+            }
+            entries.extend(names.into_iter().map(|name| (name, addr)));
+        }
+        for (name, addr) in entries {
+            self.add_host(name, &[addr]);
+        }
+        Ok(())
+    }
+
+    /// Reorder or filter every lookup's results by address family.
+    ///
+    /// This applies to numeric addresses, too.  A lookup whose every result
+    /// is filtered out fails with `ErrorKind::NotFound`.
+    pub fn set_family_preference(&mut self, family: FamilyPreference) {
+        self.family = family;
+    }
+
+    /// Cache up to `capacity` names, each for at most `max_age`.
+    ///
+    /// getaddrinfo doesn't report DNS TTLs, so every entry lives for the same
+    /// `max_age`, whatever its record's TTL.  Once the cache is full, the
+    /// oldest entry is evicted.  Numeric addresses are never cached, and
+    /// neither are failed lookups.  Replaces, and so empties, any previous
+    /// cache.
+    pub fn set_cache(&mut self, max_age: Duration, capacity: usize) {
+        self.cache = Some(Cache {
+            max_age,
+            capacity,
+            entries: HashMap::new(),
+        });
+    }
+
+    /// Forget every cached lookup.
+    pub fn flush(&mut self) {
+        if let Some(cache) = &mut self.cache {
+            cache.entries.clear();
+        }
+    }
+
+    /// Borrow the underlying agent.
+    pub fn agent_mut(&mut self) -> &mut CapNetAgent {
+        &mut self.agent
+    }
+
+    /// Return the underlying agent.
+    pub fn into_inner(self) -> CapNetAgent {
+        self.agent
+    }
+
+    /// Resolve `host:port` to a list of socket addresses.
+    ///
+    /// The host may be a name, an IPv4 address, or a bracketed IPv6 address.
+    /// Numeric addresses are returned as-is, without consulting Casper.  The
+    /// results are in the resolver's order, with duplicates removed, unless
+    /// reordered by [`set_family_preference`](Self::set_family_preference).
+    /// Fails with `ErrorKind::InvalidInput` if `s` is malformed, or
+    /// `ErrorKind::NotFound` if the name doesn't exist.
+    pub fn lookup(&mut self, s: &str) -> io::Result<Vec<SocketAddr>> {
+        let (host, port) = split_host_port(s)?;
+        self.lookup_host(host, port)
+    }
+
+    /// Like [`lookup`](Self::lookup), but with the host and port separate.
+    ///
+    /// `host` must not be bracketed.
+    pub fn lookup_host(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> io::Result<Vec<SocketAddr>> {
+        let mut addrs = self.lookup_any(host, port)?;
+        self.filter(host, &mut addrs)?;
+        Ok(addrs)
+    }
+
+    /// Apply the family preference to the results of looking up `host`.
+    fn filter(
+        &self,
+        host: &str,
+        addrs: &mut Vec<SocketAddr>,
+    ) -> io::Result<()> {
+        self.family.apply(addrs);
+        if addrs.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{host:?} has no addresses of the preferred family"),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Look up `host`, without regard to the family preference.
+    fn lookup_any(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> io::Result<Vec<SocketAddr>> {
+        if let Some(addrs) = self.lookup_local(host, port) {
+            return Ok(addrs);
+        }
+        let addrs = self.agent.resolve(host, port)?;
+        Ok(self.resolved(host, addrs))
+    }
+
+    /// Look up `host` without consulting Casper, if it's numeric, static, or
+    /// cached.
+    fn lookup_local(
+        &mut self,
+        host: &str,
+        port: u16,
+    ) -> Option<Vec<SocketAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Some(vec![SocketAddr::new(ip, port)]);
+        }
+        if let Some(ips) = self.hosts.get(&host.to_ascii_lowercase()) {
+            return Some(
+                ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect(),
+            );
+        }
+        let ips = self.cache.as_mut()?.get(host)?;
+        Some(ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect())
+    }
+
+    /// Clean up, and maybe cache, the results of a Casper lookup of `host`.
+    fn resolved(
+        &mut self,
+        host: &str,
+        mut addrs: Vec<SocketAddr>,
+    ) -> Vec<SocketAddr> {
+        dedup(&mut addrs);
+        if let Some(cache) = &mut self.cache {
+            cache.insert(host, addrs.iter().map(SocketAddr::ip).collect());
+        }
+        addrs
+    }
+
+    /// Resolve each of `names`, which are `host:port` strings as for
+    /// [`lookup`](Self::lookup), returning each name's result.
+    ///
+    /// This is meant for daemons that resolve a list of upstream servers at
+    /// startup, before limiting the agent.  Duplicate names are resolved only
+    /// once.
+    pub fn lookup_many<'n>(
+        &mut self,
+        names: &[&'n str],
+    ) -> HashMap<&'n str, io::Result<Vec<SocketAddr>>> {
+        self.lookup_many_parallel(names, &mut [])
+    }
+
+    /// Like [`lookup_many`](Self::lookup_many), but spread the lookups across
+    /// worker threads, one per agent.
+    ///
+    /// Since an agent can only make one request at a time, each worker needs
+    /// its own.  This resolver's agent is used too, so passing `n` `workers`
+    /// runs up to `n + 1` lookups at once.  Only the Casper lookups run in
+    /// parallel; numeric, static, and cached names are resolved in the calling
+    /// thread.
+    pub fn lookup_many_parallel<'n>(
+        &mut self,
+        names: &[&'n str],
+        workers: &mut [CapNetAgent],
+    ) -> HashMap<&'n str, io::Result<Vec<SocketAddr>>> {
+        let mut results = HashMap::with_capacity(names.len());
+        let mut pending: Vec<(&str, &str, u16)> = Vec::new();
+        for &name in names {
+            if results.contains_key(name)
+                || pending.iter().any(|(n, ..)| *n == name)
+            {
+                continue;
+            }
+            let (host, port) = match split_host_port(name) {
+                Ok(hp) => hp,
+                Err(e) => {
+                    results.insert(name, Err(e));
+                    continue;
+                }
+            };
+            match self.lookup_local(host, port) {
+                Some(mut addrs) => {
+                    let r = self.filter(host, &mut addrs).map(|()| addrs);
+                    results.insert(name, r);
+                }
+                None => pending.push((name, host, port)),
+            }
+        }
+        if pending.is_empty() {
+            return results;
+        }
+
+        let agents = ::std::iter::once(&mut self.agent)
+            .chain(workers.iter_mut())
+            .collect::<Vec<_>>();
+        let chunk_size = pending.len().div_ceil(agents.len());
+        let resolved = thread::scope(|s| {
+            let handles = agents
+                .into_iter()
+                .zip(pending.chunks(chunk_size))
+                .map(|(agent, chunk)| {
+                    s.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|&(name, host, port)| {
+                                (name, host, agent.resolve(host, port))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().expect("resolver thread panicked"))
+                .collect::<Vec<_>>()
+        });
+        for (name, host, r) in resolved {
+            let r = r.and_then(|addrs| {
+                let mut addrs = self.resolved(host, addrs);
+                self.filter(host, &mut addrs).map(|()| addrs)
+            });
+            results.insert(name, r);
+        }
+        results
+    }
+
+    /// Like [`lookup`](Self::lookup), but also return the host's canonical
+    /// name, as with `AI_CANONNAME`.
+    ///
+    /// This always consults Casper, even if the name is cached or has a static
+    /// entry.
+    pub fn lookup_canonical(&mut self, s: &str) -> io::Result<Lookup> {
+        let (host, port) = split_host_port(s)?;
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            let mut addrs = vec![SocketAddr::new(ip, port)];
+            self.filter(host, &mut addrs)?;
+            return Ok(Lookup {
+                canonname: None,
+                addrs,
+            });
+        }
+        let hints = AddrInfoHints::new()
+            .socktype(SockType::Stream)
+            .flags(AddrInfoFlags::CANONNAME);
+        let list = self.agent.getaddrinfo(host, port, &hints)?;
+        let canonname = list.canonname().map(str::to_owned);
+        let mut addrs = list
+            .filter_map(|ai| sockaddr::to_std(&ai.addr))
+            .collect::<Vec<_>>();
+        dedup(&mut addrs);
+        self.filter(host, &mut addrs)?;
+        Ok(Lookup { canonname, addrs })
+    }
+}
+
+/// A `"host:port"` string paired with an agent to resolve it, for APIs that
+/// take `std::net::ToSocketAddrs`.
+///
+/// Resolution happens each time `to_socket_addrs` is called, using
+/// [`CapToSocketAddrs`].
+///
+/// # Example
+/// ```no_run
+/// use std::net::TcpStream;
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, resolver::CapAddrs};
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+///
+/// capsicum::enter();
+///
+/// let addrs = CapAddrs::new(&mut cap_net, "www.freebsd.org:443");
+/// // Any function generic over ToSocketAddrs
+/// let _ = std::net::ToSocketAddrs::to_socket_addrs(&addrs).unwrap();
+/// ```
+pub struct CapAddrs<'a> {
+    agent: RefCell<&'a mut CapNetAgent>,
+    addrs: &'a str,
+}
+
+impl<'a> CapAddrs<'a> {
+    /// Pair `addrs` with `agent`.
+    pub fn new(agent: &'a mut CapNetAgent, addrs: &'a str) -> Self {
+        CapAddrs {
+            agent: RefCell::new(agent),
+            addrs,
+        }
+    }
+}
+
+impl fmt::Debug for CapAddrs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CapAddrs")
+            .field("addrs", &self.addrs)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ToSocketAddrs for CapAddrs<'_> {
+    type Iter = vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        let mut agent = self.agent.borrow_mut();
+        self.addrs
+            .cap_to_socket_addrs(&mut agent)
+            .map(Vec::into_iter)
+    }
+}
+
+/// A host and port paired with a [`Resolve`] implementation, for this crate's
+/// functions that take [`CapToSocketAddrs`].
+///
+/// The agent passed to those functions is then used only to bind or connect,
+/// never to resolve.
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{
+///     CasperExt,
+///     resolver::{Resolver, WithResolver},
+///     std::TcpStreamBuilder,
+/// };
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut resolver = Resolver::new(casper.net().unwrap());
+/// resolver.set_cache(Duration::from_secs(60), 64);
+/// let mut cap_net = casper.net().unwrap();
+///
+/// capsicum::enter();
+///
+/// let addrs = WithResolver::new(&mut resolver, "www.freebsd.org", 80);
+/// let stream = TcpStreamBuilder::new()
+///     .connect_happy_eyeballs(&mut cap_net, &addrs)
+///     .unwrap();
+/// ```
+pub struct WithResolver<'a, R: ?Sized> {
+    resolver: RefCell<&'a mut R>,
+    host:     &'a str,
+    port:     u16,
+}
+
+impl<'a, R: Resolve + ?Sized> WithResolver<'a, R> {
+    /// Resolve `host` with `resolver`, when needed.
+    pub fn new(resolver: &'a mut R, host: &'a str, port: u16) -> Self {
+        WithResolver {
+            resolver: RefCell::new(resolver),
+            host,
+            port,
+        }
+    }
+}
+
+impl<R: ?Sized> fmt::Debug for WithResolver<'_, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithResolver")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: Resolve + ?Sized> CapToSocketAddrs for WithResolver<'_, R> {
+    fn cap_to_socket_addrs(
+        &self,
+        _agent: &mut CapNetAgent,
+    ) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(ip) = self.host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, self.port)]);
+        }
+        self.resolver.borrow_mut().resolve(self.host, self.port)
+    }
+}
@@ -0,0 +1,376 @@
+// vim: tw=80
+//! `PF_ROUTE` routing sockets, for observing routing changes
+//!
+//! A routing socket receives a copy of every change to the kernel's routing
+//! table and interface state.  Opening one and reading from it are both
+//! permitted in capability mode, so sandboxed network monitors need no help
+//! from Casper.
+//!
+//! # Example
+//! ```no_run
+//! use capsicum_net::route::{RouteMessage, RouteSocket};
+//!
+//! capsicum::enter();
+//!
+//! let mut rs = RouteSocket::new(None).unwrap();
+//! loop {
+//!     match rs.recv().unwrap() {
+//!         RouteMessage::Route(r) => println!("{:?} {:?}", r.kind, r.dst),
+//!         RouteMessage::Interface(i) => println!("{} {:#x}", i.index, i.flags),
+//!         _ => (),
+//!     }
+//! }
+//! ```
+use std::{
+    io,
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+};
+
+use nix::sys::socket::AddressFamily;
+
+/// Size of `struct rt_msghdr`.  libc doesn't define it, but its layout is
+/// 32 bytes of header, `rtm_inits`, and a `struct rt_metrics` consisting of 14
+/// `u_long`s.
+const RT_MSGHDR_LEN: usize = 32 + 15 * mem::size_of::<libc::c_ulong>();
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn i32_at(buf: &[u8], offset: usize) -> i32 {
+    i32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "truncated routing message")
+}
+
+/// Equivalent of the `SA_SIZE` macro: the space occupied by a sockaddr in a
+/// routing message.
+fn sa_size(sa_len: usize) -> usize {
+    let align = mem::size_of::<libc::c_long>();
+    if sa_len == 0 {
+        align
+    } else {
+        1 + ((sa_len - 1) | (align - 1))
+    }
+}
+
+/// An address carried in a routing message
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RtAddr {
+    /// An IPv4 or IPv6 address
+    Ip(IpAddr),
+    /// A link-level address, used for directly connected routes
+    Link {
+        /// Interface index
+        index: u16,
+        /// Interface name, if supplied
+        name:  String,
+    },
+    /// An address of some other family
+    Other(u8),
+}
+
+/// Decode the sockaddr at the start of `sa`.
+///
+/// `family_hint` is used for netmasks, which the kernel often sends with a
+/// truncated length and no family.
+fn decode_sa(sa: &[u8], family_hint: Option<u8>) -> RtAddr {
+    let len = usize::from(sa[0]).min(sa.len()).min(128);
+    let mut family = sa.get(1).copied().unwrap_or(0);
+    if family == 0 {
+        family = family_hint.unwrap_or(0);
+    }
+    // Copy into a zero-filled buffer, to expand truncated netmasks
+    let mut buf = [0u8; 128];
+    buf[..len].copy_from_slice(&sa[..len]);
+    match i32::from(family) {
+        libc::AF_INET => {
+            let b: [u8; 4] = buf[4..8].try_into().unwrap();
+            RtAddr::Ip(Ipv4Addr::from(b).into())
+        }
+        libc::AF_INET6 => {
+            let b: [u8; 16] = buf[8..24].try_into().unwrap();
+            RtAddr::Ip(Ipv6Addr::from(b).into())
+        }
+        libc::AF_LINK => {
+            // struct sockaddr_dl
+            let index = u16_at(&buf, 2);
+            let nlen = usize::from(buf[5]).min(buf.len() - 8);
+            let name = String::from_utf8_lossy(&buf[8..8 + nlen]).into_owned();
+            RtAddr::Link { index, name }
+        }
+        _ => RtAddr::Other(family),
+    }
+}
+
+/// The addresses that may follow a routing message header, indexed by
+/// `RTAX_*`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Addrs([Option<RtAddr>; libc::RTAX_MAX as usize]);
+
+impl Addrs {
+    fn parse(bitmask: i32, mut buf: &[u8]) -> io::Result<Self> {
+        let mut addrs = Addrs::default();
+        let mut family = None;
+        for i in 0..libc::RTAX_MAX as usize {
+            if bitmask & (1 << i) == 0 {
+                continue;
+            }
+            if buf.is_empty() {
+                return Err(truncated());
+            }
+            let sa_len = usize::from(buf[0]);
+            let space = sa_size(sa_len);
+            if sa_len > buf.len() {
+                return Err(truncated());
+            }
+            if sa_len > 0 {
+                let hint = if i == libc::RTAX_NETMASK as usize {
+                    family
+                } else {
+                    None
+                };
+                if family.is_none() && sa_len > 1 {
+                    family = Some(buf[1]);
+                }
+                addrs.0[i] = Some(decode_sa(buf, hint));
+            }
+            buf = &buf[space.min(buf.len())..];
+        }
+        Ok(addrs)
+    }
+
+    fn take(&mut self, rtax: libc::c_int) -> Option<RtAddr> {
+        self.0[rtax as usize].take()
+    }
+}
+
+/// What happened to a route
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RouteEvent {
+    /// `RTM_ADD`
+    Add,
+    /// `RTM_DELETE`
+    Delete,
+    /// `RTM_CHANGE`
+    Change,
+    /// Any other route message type, like `RTM_GET` or `RTM_MISS`
+    Other(u8),
+}
+
+/// A change to a routing table entry
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RouteChange {
+    /// What happened
+    pub kind:    RouteEvent,
+    /// Index of the interface associated with the route
+    pub index:   u16,
+    /// `RTF_*` flags
+    pub flags:   i32,
+    /// Process ID of the sender, or 0 if originated by the kernel
+    pub pid:     libc::pid_t,
+    /// Error, if the request failed
+    pub errno:   i32,
+    /// Destination
+    pub dst:     Option<RtAddr>,
+    /// Gateway, or the link-level address for directly connected routes
+    pub gateway: Option<RtAddr>,
+    /// Destination netmask
+    pub netmask: Option<RtAddr>,
+}
+
+/// A change to an interface's status, as reported by `RTM_IFINFO`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InterfaceInfo {
+    /// Interface index
+    pub index:      u16,
+    /// `IFF_*` flags
+    pub flags:      i32,
+    /// `LINK_STATE_*` value
+    pub link_state: u8,
+    /// Maximum transmission unit
+    pub mtu:        u32,
+}
+
+/// An address being added to or removed from an interface, as reported by
+/// `RTM_NEWADDR` or `RTM_DELADDR`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AddressChange {
+    /// True for `RTM_NEWADDR`, false for `RTM_DELADDR`
+    pub added:   bool,
+    /// Interface index
+    pub index:   u16,
+    /// The interface address
+    pub addr:    Option<RtAddr>,
+    /// Its netmask
+    pub netmask: Option<RtAddr>,
+}
+
+/// A parsed message from a routing socket
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum RouteMessage {
+    /// A route was added, removed, or changed
+    Route(RouteChange),
+    /// An interface changed state
+    Interface(InterfaceInfo),
+    /// An interface gained or lost an address
+    Address(AddressChange),
+    /// Some other kind of message, identified by its `RTM_*` type
+    Other(u8),
+}
+
+impl RouteMessage {
+    /// Parse a single message, as read from a routing socket.
+    pub fn parse(buf: &[u8]) -> io::Result<Self> {
+        if buf.len() < 4 {
+            return Err(truncated());
+        }
+        let msglen = usize::from(u16_at(buf, 0));
+        if msglen > buf.len() {
+            return Err(truncated());
+        }
+        let buf = &buf[..msglen];
+        let version = i32::from(buf[2]);
+        if version != libc::RTM_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported routing message version",
+            ));
+        }
+        let rtm_type = buf[3];
+        match i32::from(rtm_type) {
+            libc::RTM_IFINFO => {
+                if buf.len() < mem::size_of::<libc::if_msghdr>() {
+                    return Err(truncated());
+                }
+                let mut ifm = mem::MaybeUninit::<libc::if_msghdr>::uninit();
+                // Safe because we checked the length, and if_msghdr is POD
+                let ifm = unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        buf.as_ptr(),
+                        ifm.as_mut_ptr().cast::<u8>(),
+                        mem::size_of::<libc::if_msghdr>(),
+                    );
+                    ifm.assume_init()
+                };
+                Ok(RouteMessage::Interface(InterfaceInfo {
+                    index:      ifm.ifm_index,
+                    flags:      ifm.ifm_flags,
+                    link_state: ifm.ifm_data.ifi_link_state,
+                    mtu:        ifm.ifm_data.ifi_mtu,
+                }))
+            }
+            libc::RTM_NEWADDR | libc::RTM_DELADDR => {
+                let hdrlen = mem::size_of::<libc::ifa_msghdr>();
+                if buf.len() < hdrlen {
+                    return Err(truncated());
+                }
+                let bitmask = i32_at(buf, 4);
+                let index = u16_at(buf, 12);
+                let mut addrs = Addrs::parse(bitmask, &buf[hdrlen..])?;
+                Ok(RouteMessage::Address(AddressChange {
+                    added: i32::from(rtm_type) == libc::RTM_NEWADDR,
+                    index,
+                    addr: addrs.take(libc::RTAX_IFA),
+                    netmask: addrs.take(libc::RTAX_NETMASK),
+                }))
+            }
+            libc::RTM_ADD
+            | libc::RTM_DELETE
+            | libc::RTM_CHANGE
+            | libc::RTM_GET
+            | libc::RTM_LOSING
+            | libc::RTM_REDIRECT
+            | libc::RTM_MISS => {
+                if buf.len() < RT_MSGHDR_LEN {
+                    return Err(truncated());
+                }
+                let kind = match i32::from(rtm_type) {
+                    libc::RTM_ADD => RouteEvent::Add,
+                    libc::RTM_DELETE => RouteEvent::Delete,
+                    libc::RTM_CHANGE => RouteEvent::Change,
+                    _ => RouteEvent::Other(rtm_type),
+                };
+                let index = u16_at(buf, 4);
+                let flags = i32_at(buf, 8);
+                let bitmask = i32_at(buf, 12);
+                let pid = i32_at(buf, 16);
+                let errno = i32_at(buf, 24);
+                let mut addrs = Addrs::parse(bitmask, &buf[RT_MSGHDR_LEN..])?;
+                Ok(RouteMessage::Route(RouteChange {
+                    kind,
+                    index,
+                    flags,
+                    pid,
+                    errno,
+                    dst: addrs.take(libc::RTAX_DST),
+                    gateway: addrs.take(libc::RTAX_GATEWAY),
+                    netmask: addrs.take(libc::RTAX_NETMASK),
+                }))
+            }
+            _ => Ok(RouteMessage::Other(rtm_type)),
+        }
+    }
+}
+
+/// A `PF_ROUTE` socket
+#[derive(Debug)]
+pub struct RouteSocket {
+    fd:  OwnedFd,
+    buf: Vec<u8>,
+}
+
+impl RouteSocket {
+    /// Open a new routing socket.
+    ///
+    /// If `family` is supplied, only messages concerning that address family
+    /// will be received.  This is permitted in capability mode.
+    pub fn new(family: Option<AddressFamily>) -> io::Result<Self> {
+        let af = family.map(|f| f as libc::c_int).unwrap_or(libc::AF_UNSPEC);
+        let fd = unsafe {
+            libc::socket(
+                libc::PF_ROUTE,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                af,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safe because socket(2) just returned this fd to us.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(RouteSocket {
+            fd,
+            buf: vec![0; 8192],
+        })
+    }
+
+    /// Read and parse the next message from the socket.
+    ///
+    /// Blocks unless the socket has been set to nonblocking mode.
+    pub fn recv(&mut self) -> io::Result<RouteMessage> {
+        let r = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                self.buf.as_mut_ptr().cast(),
+                self.buf.len(),
+                0,
+            )
+        };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        RouteMessage::parse(&self.buf[..r as usize])
+    }
+}
+
+impl AsFd for RouteSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
@@ -0,0 +1,257 @@
+// vim: tw=80
+//! Conversions between socket address types
+//!
+//! The standard library, nix, and socket2 each have their own socket address
+//! types.  This crate's low-level methods take nix's [`SockaddrLike`], while
+//! the extension traits take std's [`SocketAddr`].  These functions convert
+//! between them without any unsafe code on the caller's part.  The socket2
+//! conversions require the `socket2` feature.
+//!
+//! # Example
+//! ```
+//! use std::net::SocketAddr;
+//!
+//! use capsicum_net::sockaddr;
+//!
+//! let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+//! let ss = sockaddr::from_std(addr);
+//! assert_eq!(sockaddr::to_std(&ss), Some(addr));
+//! ```
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+use nix::sys::socket::{SockaddrLike, SockaddrStorage};
+
+/// Convert a raw sockaddr into a std `SocketAddr`, if it is IPv4 or IPv6.
+///
+/// `addr` must be null or point to a valid sockaddr of `len` bytes.
+pub(crate) fn from_raw(
+    addr: *const libc::sockaddr,
+    len: libc::socklen_t,
+) -> Option<SocketAddr> {
+    // Safe because our callers always pass a valid sockaddr and length.
+    let ss = unsafe { SockaddrStorage::from_raw(addr, Some(len)) }?;
+    if let Some(sin) = ss.as_sockaddr_in() {
+        Some(SocketAddr::V4((*sin).into()))
+    } else {
+        ss.as_sockaddr_in6()
+            .map(|sin6| SocketAddr::V6((*sin6).into()))
+    }
+}
+
+/// Convert any nix socket address into a std `SocketAddr`.
+///
+/// Returns `None` if `addr` is neither IPv4 nor IPv6.
+pub fn to_std(addr: &dyn SockaddrLike) -> Option<SocketAddr> {
+    from_raw(addr.as_ptr(), addr.len())
+}
+
+/// Convert a std `SocketAddr` into a nix socket address.
+pub fn from_std(addr: SocketAddr) -> SockaddrStorage {
+    SockaddrStorage::from(addr)
+}
+
+/// Parse a decimal number from `b[start..end]`, no greater than `max`.
+const fn parse_dec(b: &[u8], start: usize, end: usize, max: u32) -> u32 {
+    if start == end {
+        panic!("missing number in socket address");
+    }
+    let mut v = 0;
+    let mut i = start;
+    while i < end {
+        if !b[i].is_ascii_digit() {
+            panic!("invalid digit in socket address");
+        }
+        v = v * 10 + (b[i] - b'0') as u32;
+        if v > max {
+            panic!("number too large in socket address");
+        }
+        i += 1;
+    }
+    v
+}
+
+const fn parse_v4(b: &[u8], start: usize, end: usize) -> Ipv4Addr {
+    let mut octets = [0u8; 4];
+    let mut n = 0;
+    let mut i = start;
+    while n < 4 {
+        let mut j = i;
+        while j < end && b[j] != b'.' {
+            j += 1;
+        }
+        if j - i > 1 && b[i] == b'0' {
+            panic!("leading zero in IPv4 address");
+        }
+        octets[n] = parse_dec(b, i, j, 255) as u8;
+        n += 1;
+        if n < 4 && j == end {
+            panic!("too few octets in IPv4 address");
+        }
+        i = j + 1;
+    }
+    if i <= end {
+        panic!("too many octets in IPv4 address");
+    }
+    Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3])
+}
+
+const fn hex_digit(c: u8) -> u32 {
+    match c {
+        b'0'..=b'9' => (c - b'0') as u32,
+        b'a'..=b'f' => (c - b'a' + 10) as u32,
+        b'A'..=b'F' => (c - b'A' + 10) as u32,
+        _ => panic!("invalid digit in IPv6 address"),
+    }
+}
+
+const fn parse_v6(b: &[u8], start: usize, end: usize) -> Ipv6Addr {
+    let mut head = [0u16; 8];
+    let mut nhead = 0;
+    let mut tail = [0u16; 8];
+    let mut ntail = 0;
+    let mut compressed = false;
+    let mut i = start;
+    if end - start >= 2 && b[i] == b':' && b[i + 1] == b':' {
+        compressed = true;
+        i += 2;
+    }
+    while i < end {
+        let mut group = 0;
+        let mut digits = 0;
+        while i < end && b[i] != b':' {
+            group = group * 16 + hex_digit(b[i]);
+            digits += 1;
+            if digits > 4 {
+                panic!("group too long in IPv6 address");
+            }
+            i += 1;
+        }
+        if digits == 0 {
+            panic!("empty group in IPv6 address");
+        }
+        if nhead + ntail == 8 {
+            panic!("too many groups in IPv6 address");
+        }
+        if compressed {
+            tail[ntail] = group as u16;
+            ntail += 1;
+        } else {
+            head[nhead] = group as u16;
+            nhead += 1;
+        }
+        if i < end {
+            i += 1;
+            if i < end && b[i] == b':' {
+                if compressed {
+                    panic!("more than one '::' in IPv6 address");
+                }
+                compressed = true;
+                i += 1;
+            } else if i == end {
+                panic!("trailing ':' in IPv6 address");
+            }
+        }
+    }
+    if compressed && nhead + ntail == 8 || !compressed && nhead < 8 {
+        panic!("wrong number of groups in IPv6 address");
+    }
+    let mut groups = [0u16; 8];
+    let mut j = 0;
+    while j < nhead {
+        groups[j] = head[j];
+        j += 1;
+    }
+    j = 0;
+    while j < ntail {
+        groups[8 - ntail + j] = tail[j];
+        j += 1;
+    }
+    let g = groups;
+    Ipv6Addr::new(g[0], g[1], g[2], g[3], g[4], g[5], g[6], g[7])
+}
+
+/// Parse a numeric socket address, like `"127.0.0.1:80"` or `"[::1]:80"`, in
+/// a const context.
+///
+/// This accepts the same plain addresses as `SocketAddr`'s `FromStr`,
+/// except for IPv6 scope IDs and IPv4-embedded IPv6 notation.  It's what
+/// lets [`limits!`](crate::limits) reject malformed addresses at compile time.
+///
+/// # Panics
+///
+/// If `s` isn't a valid socket address.  In a const context, that's a
+/// compile error.
+///
+/// # Example
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use capsicum_net::sockaddr;
+///
+/// const DB: SocketAddr = sockaddr::parse_const("[2001:db8::1]:5432");
+/// assert_eq!(DB, "[2001:db8::1]:5432".parse().unwrap());
+/// ```
+pub const fn parse_const(s: &str) -> SocketAddr {
+    let b = s.as_bytes();
+    let len = b.len();
+    if len > 0 && b[0] == b'[' {
+        let mut end = 1;
+        while end < len && b[end] != b']' {
+            end += 1;
+        }
+        if end + 1 >= len || b[end + 1] != b':' {
+            panic!("missing port in socket address");
+        }
+        let ip = parse_v6(b, 1, end);
+        let port = parse_dec(b, end + 2, len, u16::MAX as u32) as u16;
+        SocketAddr::V6(SocketAddrV6::new(ip, port, 0, 0))
+    } else {
+        let mut colon = len;
+        let mut i = 0;
+        while i < len {
+            if b[i] == b':' {
+                colon = i;
+            }
+            i += 1;
+        }
+        if colon == len {
+            panic!("missing port in socket address");
+        }
+        let ip = parse_v4(b, 0, colon);
+        let port = parse_dec(b, colon + 1, len, u16::MAX as u32) as u16;
+        SocketAddr::V4(SocketAddrV4::new(ip, port))
+    }
+}
+
+/// Convert any nix socket address into a socket2 `SockAddr`.
+#[cfg(feature = "socket2")]
+#[cfg_attr(docsrs, doc(cfg(feature = "socket2")))]
+pub fn to_socket2(addr: &dyn SockaddrLike) -> socket2::SockAddr {
+    let len = addr.len();
+    // Safe because nix's sockaddrs are never longer than sockaddr_storage, and
+    // try_init's storage is zeroed first.
+    let (_, sa) = unsafe {
+        socket2::SockAddr::try_init(|storage, storage_len| {
+            assert!(len <= *storage_len);
+            std::ptr::copy_nonoverlapping(
+                addr.as_ptr().cast::<u8>(),
+                storage.cast::<u8>(),
+                len as usize,
+            );
+            *storage_len = len;
+            Ok(())
+        })
+    }
+    .unwrap();
+    sa
+}
+
+/// Convert a socket2 `SockAddr` into a nix socket address.
+///
+/// Returns `None` if nix doesn't recognize the address family.
+#[cfg(feature = "socket2")]
+#[cfg_attr(docsrs, doc(cfg(feature = "socket2")))]
+pub fn from_socket2(addr: &socket2::SockAddr) -> Option<SockaddrStorage> {
+    // Safe because SockAddr always holds a valid sockaddr of its length
+    unsafe { SockaddrStorage::from_raw(addr.as_ptr().cast(), Some(addr.len())) }
+}
@@ -1,17 +1,143 @@
 // vim: tw=80
 //! Extension traits for socket types from the standard library
 use ::std::{
-    io,
-    net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+    collections::HashMap,
+    ffi::CString,
+    io::{self, Write},
+    net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
     os::{
-        fd::AsFd,
-        unix::net::{UnixDatagram, UnixListener},
+        fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+        unix::net::{UnixDatagram, UnixListener, UnixStream},
     },
     path::Path,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+use capsicum::{CapRights, FileRights, Right};
+use nix::sys::socket::{
+    getsockopt,
+    listen,
+    sockopt::SocketError,
+    AddressFamily,
+    Backlog as NixBacklog,
+    SockFlag,
+    SockType,
+    SockaddrLike,
+};
+
+use super::{CapNetAgent, CapToSocketAddrs};
+use crate::{
+    addrinfo::{AddrInfoFlags, AddrInfoHints, FamilyPreference},
+    kqueue::{wait_any_writable, wait_connect},
 };
-use nix::sys::socket::{listen, AddressFamily, Backlog, SockFlag, SockType};
 
-use super::CapNetAgent;
+/// From FreeBSD's netinet6/in6.h
+const IPV6_PREFER_TEMPADDR: libc::c_int = 63;
+
+/// Set an integer-valued socket option.
+fn setsockopt_int(
+    fd: BorrowedFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    val: libc::c_int,
+) -> io::Result<()> {
+    let r = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            level,
+            name,
+            (&val as *const libc::c_int).cast(),
+            ::std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if r < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Socket options that the builders apply before the Casper operation.
+#[derive(Clone, Debug, Default)]
+struct SocketOptions {
+    congestion:      Option<String>,
+    fast_open:       Option<bool>,
+    prefer_tempaddr: Option<bool>,
+    reuse_addr:      Option<bool>,
+    reuse_port:      Option<bool>,
+    reuse_port_lb:   Option<bool>,
+    v6only:          Option<bool>,
+}
+
+impl SocketOptions {
+    fn apply(&self, fd: BorrowedFd, family: AddressFamily) -> io::Result<()> {
+        if let Some(cc) = &self.congestion {
+            let r = unsafe {
+                libc::setsockopt(
+                    fd.as_raw_fd(),
+                    libc::IPPROTO_TCP,
+                    libc::TCP_CONGESTION,
+                    cc.as_ptr().cast(),
+                    cc.len() as libc::socklen_t,
+                )
+            };
+            if r < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(tfo) = self.fast_open {
+            setsockopt_int(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_FASTOPEN,
+                libc::c_int::from(tfo),
+            )?;
+        }
+        if let Some(reuse) = self.reuse_addr {
+            setsockopt_int(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_REUSEADDR,
+                libc::c_int::from(reuse),
+            )?;
+        }
+        if let Some(reuse) = self.reuse_port {
+            setsockopt_int(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_REUSEPORT,
+                libc::c_int::from(reuse),
+            )?;
+        }
+        if let Some(reuse) = self.reuse_port_lb {
+            setsockopt_int(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_REUSEPORT_LB,
+                libc::c_int::from(reuse),
+            )?;
+        }
+        if family == AddressFamily::Inet6 {
+            if let Some(prefer) = self.prefer_tempaddr {
+                setsockopt_int(
+                    fd,
+                    libc::IPPROTO_IPV6,
+                    IPV6_PREFER_TEMPADDR,
+                    libc::c_int::from(prefer),
+                )?;
+            }
+            if let Some(v6only) = self.v6only {
+                setsockopt_int(
+                    fd,
+                    libc::IPPROTO_IPV6,
+                    libc::IPV6_V6ONLY,
+                    libc::c_int::from(v6only),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
 
 /// Adds extra features to `std::net::TcpListener` that require Casper.
 pub trait TcpListenerExt {
@@ -36,17 +162,598 @@ pub trait TcpListenerExt {
         addrs: A,
     ) -> io::Result<TcpListener>
     where
-        A: ToSocketAddrs;
+        A: CapToSocketAddrs;
+
+    /// Like [`cap_bind`](Self::cap_bind), but also return the address that
+    /// was used, if `addrs` resolved to more than one.
+    fn cap_bind_with_addr<A>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<(TcpListener, SocketAddr)>
+    where
+        A: CapToSocketAddrs;
+
+    /// Create a single `TcpListener` on `[::]:port` that accepts both IPv6
+    /// and IPv4 connections, regardless of the `net.inet6.ip6.v6only` sysctl.
+    ///
+    /// IPv4 peers appear as v4-mapped IPv6 addresses.  The agent must be
+    /// allowed to bind `[::]:port`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::net::TcpListener;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::TcpListenerExt};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// let socket = TcpListener::cap_bind_dual_stack(&mut cap_net, 8080)
+    ///     .unwrap();
+    /// ```
+    fn cap_bind_dual_stack(
+        agent: &mut CapNetAgent,
+        port: u16,
+    ) -> io::Result<TcpListener>;
+
+    /// Create a new `TcpListener` bound to an ephemeral port on `ip`, and
+    /// return it with the address that the kernel chose.
+    ///
+    /// If the agent is limited, it must be allowed to bind port 0, as with
+    /// [`Limit::bind_any_port`](crate::Limit::bind_any_port).
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::net::{Ipv4Addr, TcpListener};
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::TcpListenerExt};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// let (socket, addr) =
+    ///     TcpListener::cap_bind_ephemeral(&mut cap_net, Ipv4Addr::LOCALHOST)
+    ///         .unwrap();
+    /// println!("Listening on port {}", addr.port());
+    /// ```
+    fn cap_bind_ephemeral<I: Into<IpAddr>>(
+        agent: &mut CapNetAgent,
+        ip: I,
+    ) -> io::Result<(TcpListener, SocketAddr)>;
+
+    /// Create a `TcpListener` on every address that `addrs` resolves to,
+    /// rather than just the first that succeeds.
+    ///
+    /// This is what a dual-stack server usually needs.  If any address fails,
+    /// the listeners already bound are closed and the error returned.  See
+    /// [`TcpListenerBuilder::bind_all`].
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::TcpListenerExt};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// let addrs = [
+    ///     SocketAddr::from((Ipv4Addr::LOCALHOST, 8080)),
+    ///     SocketAddr::from((Ipv6Addr::LOCALHOST, 8080)),
+    /// ];
+    /// let listeners = TcpListener::cap_bind_all(&mut cap_net, &addrs[..])
+    ///     .unwrap();
+    /// assert_eq!(listeners.len(), 2);
+    /// ```
+    fn cap_bind_all<A>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<Vec<TcpListener>>
+    where
+        A: CapToSocketAddrs;
 }
 
 impl TcpListenerExt for TcpListener {
     fn cap_bind<A>(agent: &mut CapNetAgent, addrs: A) -> io::Result<TcpListener>
     where
-        A: ToSocketAddrs,
+        A: CapToSocketAddrs,
     {
-        let s: TcpListener = agent.bind_std_to_addrs(addrs)?;
-        listen(&s, Backlog::MAXALLOWABLE)?;
-        Ok(s)
+        TcpListenerBuilder::new().bind(agent, addrs)
+    }
+
+    fn cap_bind_with_addr<A>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<(TcpListener, SocketAddr)>
+    where
+        A: CapToSocketAddrs,
+    {
+        TcpListenerBuilder::new().bind_with_addr(agent, addrs)
+    }
+
+    fn cap_bind_dual_stack(
+        agent: &mut CapNetAgent,
+        port: u16,
+    ) -> io::Result<TcpListener> {
+        TcpListenerBuilder::new()
+            .v6only(false)
+            .bind(agent, (Ipv6Addr::UNSPECIFIED, port))
+    }
+
+    fn cap_bind_ephemeral<I: Into<IpAddr>>(
+        agent: &mut CapNetAgent,
+        ip: I,
+    ) -> io::Result<(TcpListener, SocketAddr)> {
+        let listener = TcpListener::cap_bind(agent, (ip.into(), 0))?;
+        let addr = listener.local_addr()?;
+        Ok((listener, addr))
+    }
+
+    fn cap_bind_all<A>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<Vec<TcpListener>>
+    where
+        A: CapToSocketAddrs,
+    {
+        TcpListenerBuilder::new().bind_all(agent, addrs)
+    }
+}
+
+/// The kernel's maximum listen queue length, cached after the first successful
+/// call
+static SOMAXCONN: OnceLock<i32> = OnceLock::new();
+
+/// Return the kernel's maximum listen queue length, `kern.ipc.somaxconn`.
+///
+/// The sysctl can't be read in capability mode, so call this once before
+/// entering it; afterwards the cached value is returned.
+pub fn somaxconn() -> io::Result<i32> {
+    if let Some(v) = SOMAXCONN.get() {
+        return Ok(*v);
+    }
+    let mut val: libc::c_int = 0;
+    let mut len = ::std::mem::size_of::<libc::c_int>();
+    let r = unsafe {
+        libc::sysctlbyname(
+            c"kern.ipc.somaxconn".as_ptr(),
+            (&mut val as *mut libc::c_int).cast(),
+            &mut len,
+            ::std::ptr::null(),
+            0,
+        )
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(*SOMAXCONN.get_or_init(|| val))
+}
+
+/// A listen queue length for [`TcpListenerBuilder::backlog`] and
+/// [`UnixListenerExt::cap_bind_with_backlog`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backlog {
+    /// The kernel's maximum, as reported by [`somaxconn`].  If that hasn't
+    /// been cached and can't be read, the kernel clamps the request instead.
+    Max,
+    /// The traditional default, `SOMAXCONN` from `<sys/socket.h>`
+    SystemDefault,
+    /// An explicit length.  The kernel silently clamps it to [`somaxconn`].
+    Explicit(i32),
+}
+
+impl Backlog {
+    /// The value that will be passed to
+    /// [listen(2)](https://man.freebsd.org/cgi/man.cgi?query=listen).
+    pub fn value(self) -> i32 {
+        match self {
+            Backlog::Max => somaxconn().unwrap_or(-1),
+            Backlog::SystemDefault => libc::SOMAXCONN,
+            Backlog::Explicit(n) => n,
+        }
+    }
+}
+
+/// Creates TCP listeners with socket options that must be set before binding.
+///
+/// # Examples
+/// ```
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, std::TcpListenerBuilder};
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+///
+/// let socket = TcpListenerBuilder::new()
+///     .congestion("newreno")
+///     .bind(&mut cap_net, "127.0.0.1:8092")
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TcpListenerBuilder {
+    opts:    SocketOptions,
+    backlog: Option<Backlog>,
+}
+
+impl TcpListenerBuilder {
+    /// Create a builder with the system's default socket options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Select a congestion control algorithm for accepted connections, like
+    /// "cubic" or "bbr".
+    ///
+    /// The algorithm's kernel module must already be loaded; see
+    /// [mod_cc(4)](https://man.freebsd.org/cgi/man.cgi?query=mod_cc).
+    pub fn congestion(&mut self, algorithm: &str) -> &mut Self {
+        self.opts.congestion = Some(algorithm.to_owned());
+        self
+    }
+
+    /// Choose the listen queue length.  By default, the kernel's maximum is
+    /// used.
+    pub fn backlog(&mut self, backlog: Backlog) -> &mut Self {
+        self.backlog = Some(backlog);
+        self
+    }
+
+    /// Set `SO_REUSEADDR`, so a restarted server can bind its port while
+    /// connections from its previous instance are still in `TIME_WAIT`.
+    pub fn reuse_addr(&mut self, reuse: bool) -> &mut Self {
+        self.opts.reuse_addr = Some(reuse);
+        self
+    }
+
+    /// Set `SO_REUSEPORT`, so that several sockets may bind the same address
+    /// and port.  Every one of them must set it.
+    pub fn reuse_port(&mut self, reuse: bool) -> &mut Self {
+        self.opts.reuse_port = Some(reuse);
+        self
+    }
+
+    /// Set `SO_REUSEPORT_LB`, so that several listeners may bind the same
+    /// address and port, and the kernel balances new connections among them.
+    /// Every one of them must set it.  See also
+    /// [`bind_sharded`](Self::bind_sharded).
+    pub fn reuse_port_lb(&mut self, reuse: bool) -> &mut Self {
+        self.opts.reuse_port_lb = Some(reuse);
+        self
+    }
+
+    /// Set `IPV6_V6ONLY`.  If false, an IPv6 socket bound to `[::]` also
+    /// accepts IPv4 traffic, as v4-mapped addresses.
+    ///
+    /// If unset, the `net.inet6.ip6.v6only` sysctl decides, which is true by
+    /// default on FreeBSD.  Has no effect on IPv4 sockets.
+    pub fn v6only(&mut self, v6only: bool) -> &mut Self {
+        self.opts.v6only = Some(v6only);
+        self
+    }
+
+    /// Create a new `TcpListener` bound to the specified address.
+    ///
+    /// Each address is tried in turn until one succeeds.
+    pub fn bind<A: CapToSocketAddrs>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<TcpListener> {
+        self.bind_with_addr(agent, addrs).map(|(s, _)| s)
+    }
+
+    /// Like [`bind`](Self::bind), but also return the address that was used.
+    pub fn bind_with_addr<A: CapToSocketAddrs>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<(TcpListener, SocketAddr)> {
+        let mut last_err = None;
+        for addr in addrs.cap_to_socket_addrs(agent)? {
+            match self.bind_one(agent, addr) {
+                Ok(listener) => return Ok((listener, addr)),
+                Err(e) => {
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            )
+        }))
+    }
+
+    /// Resolve a local hostname with `AI_PASSIVE`, and bind a listener to
+    /// every address that it returns.
+    ///
+    /// This lets servers be configured with a hostname instead of an IP
+    /// address, even in capability mode.  Unlike [`bind`](Self::bind), every
+    /// address must succeed; if any fails, the listeners already bound are
+    /// closed and the error returned.  The returned listeners are in the
+    /// resolver's order, paired with their addresses.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::TcpListenerBuilder};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// let listeners = TcpListenerBuilder::new()
+    ///     .bind_host(&mut cap_net, "localhost", 8080)
+    ///     .unwrap();
+    /// ```
+    pub fn bind_host(
+        &self,
+        agent: &mut CapNetAgent,
+        host: &str,
+        port: u16,
+    ) -> io::Result<Vec<(TcpListener, SocketAddr)>> {
+        let hints = AddrInfoHints::new()
+            .socktype(SockType::Stream)
+            .flags(AddrInfoFlags::PASSIVE);
+        let addrs = agent.resolve_with(host, port, &hints)?;
+        self.bind_each(agent, addrs)
+    }
+
+    /// Bind a listener to every address that `addrs` resolves to, such as
+    /// both `127.0.0.1` and `::1`.
+    ///
+    /// Unlike [`bind`](Self::bind), every address must succeed; if any fails,
+    /// the listeners already bound are closed and the error returned.  The
+    /// listeners are returned in the order of `addrs`.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if `addrs` is empty.
+    pub fn bind_all<A: CapToSocketAddrs>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<Vec<TcpListener>> {
+        let addrs = addrs.cap_to_socket_addrs(agent)?;
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            ));
+        }
+        self.bind_each(agent, addrs)
+            .map(|v| v.into_iter().map(|(l, _)| l).collect())
+    }
+
+    /// Bind a listener to each distinct address in `addrs`.
+    fn bind_each(
+        &self,
+        agent: &mut CapNetAgent,
+        mut addrs: Vec<SocketAddr>,
+    ) -> io::Result<Vec<(TcpListener, SocketAddr)>> {
+        // A host may list the same address more than once, but it can only be
+        // bound once.
+        let mut seen = Vec::with_capacity(addrs.len());
+        addrs.retain(|a| {
+            let new = !seen.contains(a);
+            if new {
+                seen.push(*a);
+            }
+            new
+        });
+        addrs
+            .into_iter()
+            .map(|addr| self.bind_one(agent, addr).map(|l| (l, addr)))
+            .collect()
+    }
+
+    /// Create `n` listeners that all share `addr`, using `SO_REUSEPORT_LB`.
+    ///
+    /// The kernel distributes incoming connections among them, so each can be
+    /// handed to a separate worker thread to accept from.  If `addr`'s port is
+    /// 0, the port chosen for the first listener is used for the rest, which
+    /// requires the agent to be allowed to bind it.  If any listener fails,
+    /// those already bound are closed and the error returned.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if `n` is 0.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::thread;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::TcpListenerBuilder};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// let addr = "0.0.0.0:8080".parse().unwrap();
+    /// let listeners = TcpListenerBuilder::new()
+    ///     .bind_sharded(&mut cap_net, addr, 4)
+    ///     .unwrap();
+    /// for listener in listeners {
+    ///     thread::spawn(move || {
+    ///         for stream in listener.incoming() {
+    ///             // ...
+    ///         }
+    ///     });
+    /// }
+    /// ```
+    pub fn bind_sharded(
+        &self,
+        agent: &mut CapNetAgent,
+        addr: SocketAddr,
+        n: usize,
+    ) -> io::Result<Vec<TcpListener>> {
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "at least one listener is required",
+            ));
+        }
+        let mut builder = self.clone();
+        builder.reuse_port_lb(true);
+        let first = builder.bind_one(agent, addr)?;
+        let addr = first.local_addr()?;
+        let mut listeners = Vec::with_capacity(n);
+        listeners.push(first);
+        for _ in 1..n {
+            listeners.push(builder.bind_one(agent, addr)?);
+        }
+        Ok(listeners)
+    }
+
+    /// Create a listener bound to `addr`.
+    fn bind_one(
+        &self,
+        agent: &mut CapNetAgent,
+        addr: SocketAddr,
+    ) -> io::Result<TcpListener> {
+        let family = if addr.is_ipv4() {
+            AddressFamily::Inet
+        } else {
+            AddressFamily::Inet6
+        };
+        let sock = nix::sys::socket::socket(
+            family,
+            SockType::Stream,
+            SockFlag::empty(),
+            None,
+        )
+        .map_err(io::Error::from)?;
+        self.opts.apply(sock.as_fd(), family)?;
+        agent.bind_std_fd(sock.as_fd(), addr)?;
+        let backlog = self.backlog.map_or(-1, Backlog::value);
+        // Not nix::sys::socket::listen, which rejects backlogs larger than
+        // SOMAXCONN.
+        let r = unsafe { libc::listen(sock.as_raw_fd(), backlog) };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(TcpListener::from(sock))
+    }
+}
+
+/// The Capsicum rights that [`LimitedIncoming`] grants to each accepted
+/// connection by default: read, write, shutdown, and event.
+pub fn default_stream_rights() -> FileRights {
+    *FileRights::new()
+        .allow(Right::Read)
+        .allow(Right::Write)
+        .allow(Right::Shutdown)
+        .allow(Right::Event)
+}
+
+/// Create a connected pair of unix-domain sockets, and restrict both ends to
+/// [`default_stream_rights`].
+///
+/// This is the usual way to set up a control channel between a parent and a
+/// sandboxed child.  `ty` is typically `SockType::Stream` or
+/// `SockType::SeqPacket`.  Both ends are close-on-exec.  The restricted rights
+/// still allow passing file descriptors with `SCM_RIGHTS`.
+///
+/// # Examples
+/// ```
+/// use std::os::unix::net::UnixStream;
+///
+/// use capsicum_net::std::cap_socketpair;
+/// use nix::sys::socket::SockType;
+///
+/// let (parent, child) = cap_socketpair(SockType::Stream).unwrap();
+/// let parent = UnixStream::from(parent);
+/// let child = UnixStream::from(child);
+/// ```
+pub fn cap_socketpair(ty: SockType) -> io::Result<(OwnedFd, OwnedFd)> {
+    cap_socketpair_with_rights(ty, &default_stream_rights())
+}
+
+/// Like [`cap_socketpair`], but restrict both ends to `rights` instead.
+pub fn cap_socketpair_with_rights(
+    ty: SockType,
+    rights: &FileRights,
+) -> io::Result<(OwnedFd, OwnedFd)> {
+    let (a, b) = nix::sys::socket::socketpair(
+        AddressFamily::Unix,
+        ty,
+        None,
+        SockFlag::SOCK_CLOEXEC,
+    )?;
+    rights.limit(&a)?;
+    rights.limit(&b)?;
+    Ok((a, b))
+}
+
+/// Accepts connections from a `TcpListener`, restricting each with
+/// [cap_rights_limit(2)](https://man.freebsd.org/cgi/man.cgi?query=cap_rights_limit)
+/// before returning it.
+///
+/// This is defense in depth for worker code: a compromised worker can't, for
+/// example, pass its connection's file descriptor to `fchmod` or `getpeername`.
+/// As an iterator, it behaves like `TcpListener::incoming`.
+///
+/// # Examples
+/// ```no_run
+/// use std::{io::Write, net::TcpListener};
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, std::{LimitedIncoming, TcpListenerExt}};
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+///
+/// capsicum::enter();
+///
+/// let listener = TcpListener::cap_bind(&mut cap_net, "127.0.0.1:8093")
+///     .unwrap();
+/// for stream in LimitedIncoming::new(&listener) {
+///     stream.unwrap().write_all(b"Hello, World!\n").unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct LimitedIncoming<'a> {
+    listener: &'a TcpListener,
+    rights:   FileRights,
+}
+
+impl<'a> LimitedIncoming<'a> {
+    /// Accept connections from `listener`, granting them
+    /// [`default_stream_rights`].
+    pub fn new(listener: &'a TcpListener) -> Self {
+        Self::with_rights(listener, default_stream_rights())
+    }
+
+    /// Accept connections from `listener`, granting them only `rights`.
+    pub fn with_rights(listener: &'a TcpListener, rights: FileRights) -> Self {
+        LimitedIncoming { listener, rights }
+    }
+
+    /// Accept a single connection, returning it along with the peer's address.
+    ///
+    /// The connection is closed if its rights cannot be limited.
+    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (stream, addr) = self.listener.accept()?;
+        self.rights.limit(&stream)?;
+        Ok((stream, addr))
+    }
+}
+
+impl Iterator for LimitedIncoming<'_> {
+    type Item = io::Result<TcpStream>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.accept().map(|(s, _)| s))
     }
 }
 
@@ -68,19 +775,250 @@ pub trait TcpStreamExt {
     ///
     /// let sock = TcpStream::cap_connect(&mut cap_net, "8.8.8.8:53").unwrap();
     /// ```
-    fn cap_connect<A: ToSocketAddrs>(
+    fn cap_connect<A: CapToSocketAddrs>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<TcpStream>;
+
+    /// Like [`cap_connect`](Self::cap_connect), but also return the address
+    /// that was used, if `addrs` resolved to more than one.
+    fn cap_connect_with_addr<A: CapToSocketAddrs>(
         agent: &mut CapNetAgent,
         addrs: A,
+    ) -> io::Result<(TcpStream, SocketAddr)>;
+
+    /// Resolve `host` through the `cap_net` service, and connect to the first
+    /// of its addresses that accepts a connection on `port`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::net::TcpStream;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::TcpStreamExt};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// let sock =
+    ///     TcpStream::cap_connect_host(&mut cap_net, "www.freebsd.org", 80)
+    ///         .unwrap();
+    /// ```
+    fn cap_connect_host(
+        agent: &mut CapNetAgent,
+        host: &str,
+        port: u16,
     ) -> io::Result<TcpStream>;
 }
 
 impl TcpStreamExt for TcpStream {
-    fn cap_connect<A: ToSocketAddrs>(
+    fn cap_connect<A: CapToSocketAddrs>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<TcpStream> {
+        TcpStreamBuilder::new().connect(agent, addrs)
+    }
+
+    fn cap_connect_with_addr<A: CapToSocketAddrs>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
+        TcpStreamBuilder::new().connect_with_addr(agent, addrs)
+    }
+
+    fn cap_connect_host(
+        agent: &mut CapNetAgent,
+        host: &str,
+        port: u16,
+    ) -> io::Result<TcpStream> {
+        agent.connect_host(host, port).map(TcpStream::from)
+    }
+}
+
+/// Opens TCP connections with socket options that must be set before
+/// connecting.
+///
+/// # Examples
+/// ```no_run
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, std::TcpStreamBuilder};
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+///
+/// let sock = TcpStreamBuilder::new()
+///     .prefer_tempaddr(true)
+///     .connect(&mut cap_net, "[2001:4860:4860::8888]:53")
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TcpStreamBuilder {
+    opts:          SocketOptions,
+    attempt_delay: Option<Duration>,
+    family:        FamilyPreference,
+}
+
+impl TcpStreamBuilder {
+    /// Create a builder with the system's default socket options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Choose whether IPv6 connections should prefer temporary (RFC 8981
+    /// "privacy") source addresses over public ones.
+    ///
+    /// If unset, the system-wide `net.inet6.ip6.prefer_tempaddr` sysctl
+    /// decides.  Has no effect on IPv4 connections.
+    pub fn prefer_tempaddr(&mut self, prefer: bool) -> &mut Self {
+        self.opts.prefer_tempaddr = Some(prefer);
+        self
+    }
+
+    /// Select a congestion control algorithm, like "cubic" or "bbr".
+    ///
+    /// The algorithm's kernel module must already be loaded; see
+    /// [mod_cc(4)](https://man.freebsd.org/cgi/man.cgi?query=mod_cc).
+    pub fn congestion(&mut self, algorithm: &str) -> &mut Self {
+        self.opts.congestion = Some(algorithm.to_owned());
+        self
+    }
+
+    /// Set how long [`connect_happy_eyeballs`](Self::connect_happy_eyeballs)
+    /// waits for one connection attempt before starting the next.
+    ///
+    /// The default is 250 ms, as recommended by RFC 8305.
+    pub fn attempt_delay(&mut self, delay: Duration) -> &mut Self {
+        self.attempt_delay = Some(delay);
+        self
+    }
+
+    /// Reorder or filter the resolved addresses by family before trying them.
+    pub fn family_preference(&mut self, family: FamilyPreference) -> &mut Self {
+        self.family = family;
+        self
+    }
+
+    /// Request TCP Fast Open for the connection.
+    ///
+    /// Use [`connect_and_send`](Self::connect_and_send) to actually carry data
+    /// in the SYN.  Client-side TFO must be enabled with the
+    /// `net.inet.tcp.fastopen.client_enable` sysctl.
+    pub fn fast_open(&mut self, tfo: bool) -> &mut Self {
+        self.opts.fast_open = Some(tfo);
+        self
+    }
+
+    /// Resolve `addrs`, applying the family preference.
+    fn resolve<A: CapToSocketAddrs>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<Vec<SocketAddr>> {
+        let mut addrs = addrs.cap_to_socket_addrs(agent)?;
+        self.family.apply(&mut addrs);
+        Ok(addrs)
+    }
+
+    /// Open a TCP connection using TCP Fast Open, and send `data` as the
+    /// connection's first write.
+    ///
+    /// If the server has previously issued this host a TFO cookie, `data`
+    /// travels in the SYN, saving a round trip.  Otherwise the connection
+    /// falls back to a normal handshake.  Returns once all of `data` has been
+    /// written.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::io::Read;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::TcpStreamBuilder};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// let mut stream = TcpStreamBuilder::new()
+    ///     .connect_and_send(&mut cap_net, "192.0.2.1:7", b"hello")
+    ///     .unwrap();
+    /// let mut buf = [0u8; 5];
+    /// stream.read_exact(&mut buf).unwrap();
+    /// ```
+    pub fn connect_and_send<A: CapToSocketAddrs>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+        data: &[u8],
+    ) -> io::Result<TcpStream> {
+        let mut opts = self.opts.clone();
+        opts.fast_open = Some(true);
+        let mut last_err = None;
+        for addr in self.resolve(agent, addrs)? {
+            let family = if addr.is_ipv4() {
+                AddressFamily::Inet
+            } else {
+                AddressFamily::Inet6
+            };
+            // Nonblocking, because with TFO the kernel may defer the SYN until
+            // the first write.
+            let sock = nix::sys::socket::socket(
+                family,
+                SockType::Stream,
+                SockFlag::SOCK_NONBLOCK,
+                None,
+            )
+            .map_err(io::Error::from)?;
+            opts.apply(sock.as_fd(), family)?;
+            match agent.connect_std_fd(sock.as_fd(), addr) {
+                Err(e) if e.raw_os_error() != Some(libc::EINPROGRESS) => {
+                    last_err = Some(e);
+                    continue;
+                }
+                _ => (),
+            }
+            let mut stream = TcpStream::from(sock);
+            match send_first(&mut stream, data) {
+                Ok(()) => return Ok(stream),
+                Err(e) => {
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            )
+        }))
+    }
+
+    /// Open a TCP connection to a remote host, connecting via a `cap_net`
+    /// service.
+    ///
+    /// Each address is tried in turn until one succeeds.
+    pub fn connect<A: CapToSocketAddrs>(
+        &self,
         agent: &mut CapNetAgent,
         addrs: A,
     ) -> io::Result<TcpStream> {
+        self.connect_with_addr(agent, addrs).map(|(s, _)| s)
+    }
+
+    /// Like [`connect`](Self::connect), but also return the address that was
+    /// used.
+    pub fn connect_with_addr<A: CapToSocketAddrs>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
         let mut last_err = None;
-        for addr in addrs.to_socket_addrs()? {
+        for addr in self.resolve(agent, addrs)? {
             let family = if addr.is_ipv4() {
                 AddressFamily::Inet
             } else {
@@ -93,8 +1031,9 @@ impl TcpStreamExt for TcpStream {
                 None,
             )
             .map_err(io::Error::from)?;
+            self.opts.apply(sock.as_fd(), family)?;
             match agent.connect_std_fd(sock.as_fd(), addr) {
-                Ok(()) => return Ok(TcpStream::from(sock)),
+                Ok(()) => return Ok((TcpStream::from(sock), addr)),
                 Err(e) => {
                     last_err = Some(e);
                 }
@@ -107,6 +1046,150 @@ impl TcpStreamExt for TcpStream {
             )
         }))
     }
+
+    /// Open a TCP connection by racing attempts to each address, as in RFC
+    /// 8305 "Happy Eyeballs".
+    ///
+    /// The addresses are reordered to alternate between IPv6 and IPv4,
+    /// starting with whichever family comes first after applying the
+    /// [`family_preference`](Self::family_preference).  A new attempt starts
+    /// each [`attempt_delay`](Self::attempt_delay), or as soon as the previous
+    /// one fails, without abandoning those still in progress.
+    /// The first to connect wins, and the rest are closed.  This avoids long
+    /// stalls on hosts where one family is configured but broken.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::TcpStreamBuilder};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// let stream = TcpStreamBuilder::new()
+    ///     .connect_happy_eyeballs(&mut cap_net, "www.freebsd.org:80")
+    ///     .unwrap();
+    /// ```
+    pub fn connect_happy_eyeballs<A: CapToSocketAddrs>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<TcpStream> {
+        let delay = self.attempt_delay.unwrap_or(Duration::from_millis(250));
+        let mut addrs = interleave(self.resolve(agent, addrs)?).into_iter();
+        let mut pending: Vec<OwnedFd> = Vec::new();
+        let mut last_err = None;
+        let mut next_attempt = Instant::now();
+        loop {
+            if Instant::now() >= next_attempt {
+                if let Some(addr) = addrs.next() {
+                    let family = if addr.is_ipv4() {
+                        AddressFamily::Inet
+                    } else {
+                        AddressFamily::Inet6
+                    };
+                    let sock = nix::sys::socket::socket(
+                        family,
+                        SockType::Stream,
+                        SockFlag::SOCK_NONBLOCK,
+                        None,
+                    )
+                    .map_err(io::Error::from)?;
+                    self.opts.apply(sock.as_fd(), family)?;
+                    match agent.connect_std_fd(sock.as_fd(), addr) {
+                        Ok(()) => return connected(sock),
+                        Err(e)
+                            if e.raw_os_error() == Some(libc::EINPROGRESS) =>
+                        {
+                            pending.push(sock);
+                            next_attempt = Instant::now() + delay;
+                        }
+                        Err(e) => {
+                            // Move on to the next address right away
+                            last_err = Some(e);
+                            continue;
+                        }
+                    }
+                }
+            }
+            if pending.is_empty() {
+                return Err(last_err.unwrap_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "could not resolve to any addresses",
+                    )
+                }));
+            }
+            let timeout = if addrs.len() > 0 {
+                Some(next_attempt.saturating_duration_since(Instant::now()))
+            } else {
+                None
+            };
+            let fds = pending.iter().map(AsFd::as_fd).collect::<Vec<_>>();
+            let Some(i) = wait_any_writable(&fds, timeout)? else {
+                continue;
+            };
+            let sock = pending.swap_remove(i);
+            match getsockopt(&sock, SocketError)? {
+                0 => return connected(sock),
+                e => {
+                    last_err = Some(io::Error::from_raw_os_error(e));
+                    next_attempt = Instant::now();
+                }
+            }
+        }
+    }
+}
+
+/// Reorder `addrs` to alternate between address families, starting with the
+/// family of the first.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_v4 = first.is_ipv4();
+    let (a, b): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv4() == first_v4);
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let (mut a, mut b) = (a.into_iter(), b.into_iter());
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return out,
+            (x, y) => out.extend(x.into_iter().chain(y)),
+        }
+    }
+}
+
+/// Finish a nonblocking connection, making the socket blocking again.
+fn connected(sock: OwnedFd) -> io::Result<TcpStream> {
+    let stream = TcpStream::from(sock);
+    stream.set_nonblocking(false)?;
+    Ok(stream)
+}
+
+/// Write `data` to a nonblocking, possibly still-connecting stream, then make
+/// the stream blocking.
+fn send_first(stream: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+    let mut sent = 0;
+    while sent < data.len() {
+        match stream.write(&data[sent..]) {
+            Ok(n) => sent += n,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock
+                    || e.raw_os_error() == Some(libc::ENOTCONN) =>
+            {
+                wait_connect(stream, None)?
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if data.is_empty() {
+        wait_connect(stream, None)?;
+    }
+    stream.set_nonblocking(false)
 }
 
 /// Adds extra features to `std::net::UdpSocket` that require Casper.
@@ -129,7 +1212,7 @@ pub trait UdpSocketExt {
     /// ```
     fn cap_bind<A>(agent: &mut CapNetAgent, addr: A) -> io::Result<UdpSocket>
     where
-        A: ToSocketAddrs;
+        A: CapToSocketAddrs;
 
     /// Connects this UDP socket to a remote address, using a `cap_net` service.
     ///
@@ -153,15 +1236,52 @@ pub trait UdpSocketExt {
         addrs: A,
     ) -> io::Result<()>
     where
-        A: ToSocketAddrs;
+        A: CapToSocketAddrs;
+
+    /// Like [`cap_bind`](Self::cap_bind), but also return the address that
+    /// was used, if `addrs` resolved to more than one.
+    fn cap_bind_with_addr<A>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<(UdpSocket, SocketAddr)>
+    where
+        A: CapToSocketAddrs;
+
+    /// Like [`cap_connect`](Self::cap_connect), but return the address that
+    /// was used, if `addrs` resolved to more than one.
+    fn cap_connect_with_addr<A>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<SocketAddr>
+    where
+        A: CapToSocketAddrs;
+
+    /// Create a single `UdpSocket` on `[::]:port` that handles both IPv6 and
+    /// IPv4 traffic, regardless of the `net.inet6.ip6.v6only` sysctl.
+    ///
+    /// IPv4 peers appear as v4-mapped IPv6 addresses.  The agent must be
+    /// allowed to bind `[::]:port`.
+    fn cap_bind_dual_stack(
+        agent: &mut CapNetAgent,
+        port: u16,
+    ) -> io::Result<UdpSocket>;
+
+    /// Create a new `UdpSocket` bound to an ephemeral port on `ip`, and
+    /// return it with the address that the kernel chose.  See
+    /// [`TcpListenerExt::cap_bind_ephemeral`].
+    fn cap_bind_ephemeral<I: Into<IpAddr>>(
+        agent: &mut CapNetAgent,
+        ip: I,
+    ) -> io::Result<(UdpSocket, SocketAddr)>;
 }
 
 impl UdpSocketExt for UdpSocket {
     fn cap_bind<A>(agent: &mut CapNetAgent, addrs: A) -> io::Result<UdpSocket>
     where
-        A: ToSocketAddrs,
+        A: CapToSocketAddrs,
     {
-        agent.bind_std_to_addrs(addrs)
+        UdpSocketBuilder::new().bind(agent, addrs)
     }
 
     fn cap_connect<A>(
@@ -170,10 +1290,126 @@ impl UdpSocketExt for UdpSocket {
         addrs: A,
     ) -> io::Result<()>
     where
-        A: ToSocketAddrs,
+        A: CapToSocketAddrs,
+    {
+        agent.connect_std_to_addrs(self.as_fd(), addrs).map(drop)
+    }
+
+    fn cap_bind_with_addr<A>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<(UdpSocket, SocketAddr)>
+    where
+        A: CapToSocketAddrs,
+    {
+        UdpSocketBuilder::new().bind_with_addr(agent, addrs)
+    }
+
+    fn cap_connect_with_addr<A>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<SocketAddr>
+    where
+        A: CapToSocketAddrs,
     {
         agent.connect_std_to_addrs(self.as_fd(), addrs)
     }
+
+    fn cap_bind_dual_stack(
+        agent: &mut CapNetAgent,
+        port: u16,
+    ) -> io::Result<UdpSocket> {
+        UdpSocketBuilder::new()
+            .v6only(false)
+            .bind(agent, (Ipv6Addr::UNSPECIFIED, port))
+    }
+
+    fn cap_bind_ephemeral<I: Into<IpAddr>>(
+        agent: &mut CapNetAgent,
+        ip: I,
+    ) -> io::Result<(UdpSocket, SocketAddr)> {
+        let socket = UdpSocket::cap_bind(agent, (ip.into(), 0))?;
+        let addr = socket.local_addr()?;
+        Ok((socket, addr))
+    }
+}
+
+/// Creates UDP sockets with socket options that must be set before binding.
+///
+/// # Examples
+/// ```
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, std::UdpSocketBuilder};
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+///
+/// let socket = UdpSocketBuilder::new()
+///     .reuse_addr(true)
+///     .bind(&mut cap_net, "127.0.0.1:8096")
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct UdpSocketBuilder {
+    opts: SocketOptions,
+}
+
+impl UdpSocketBuilder {
+    /// Create a builder with the system's default socket options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `SO_REUSEADDR`.  For UDP, this allows binding a wildcard address
+    /// and a specific one on the same port.
+    pub fn reuse_addr(&mut self, reuse: bool) -> &mut Self {
+        self.opts.reuse_addr = Some(reuse);
+        self
+    }
+
+    /// Set `SO_REUSEPORT`, so that several sockets may bind the same address
+    /// and port.  Every one of them must set it.
+    pub fn reuse_port(&mut self, reuse: bool) -> &mut Self {
+        self.opts.reuse_port = Some(reuse);
+        self
+    }
+
+    /// Set `IPV6_V6ONLY`.  If false, an IPv6 socket bound to `[::]` also
+    /// accepts IPv4 traffic, as v4-mapped addresses.
+    ///
+    /// If unset, the `net.inet6.ip6.v6only` sysctl decides, which is true by
+    /// default on FreeBSD.  Has no effect on IPv4 sockets.
+    pub fn v6only(&mut self, v6only: bool) -> &mut Self {
+        self.opts.v6only = Some(v6only);
+        self
+    }
+
+    /// Create a new `UdpSocket` bound to the specified address.
+    ///
+    /// Each address is tried in turn until one succeeds.
+    pub fn bind<A: CapToSocketAddrs>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<UdpSocket> {
+        self.bind_with_addr(agent, addrs).map(|(s, _)| s)
+    }
+
+    /// Like [`bind`](Self::bind), but also return the address that was used.
+    pub fn bind_with_addr<A: CapToSocketAddrs>(
+        &self,
+        agent: &mut CapNetAgent,
+        addrs: A,
+    ) -> io::Result<(UdpSocket, SocketAddr)> {
+        agent.bind_std_to_addrs(
+            addrs,
+            SockType::Datagram,
+            None,
+            |fd, family| self.opts.apply(fd, family),
+        )
+    }
 }
 
 /// Adds extra features to `std::os::unix::net::UnixDatagram` that require
@@ -201,6 +1437,50 @@ pub trait UnixDatagramExt {
     ) -> io::Result<UnixDatagram>
     where
         P: AsRef<Path>;
+
+    /// Like [`cap_bind`](Self::cap_bind), but create the socket file with
+    /// permissions `mode`, like `0o660`.
+    ///
+    /// The mode is set on the socket before binding, so there's no window
+    /// when the file is more widely accessible.  The Casper service's umask
+    /// may restrict it further.  Requires a kernel that supports
+    /// [fchmod(2)](https://man.freebsd.org/cgi/man.cgi?query=fchmod) on
+    /// unbound unix-domain sockets.
+    fn cap_bind_with_mode<P>(
+        agent: &mut CapNetAgent,
+        path: P,
+        mode: libc::mode_t,
+    ) -> io::Result<UnixDatagram>
+    where
+        P: AsRef<Path>;
+
+    /// Connect an existing `std::os::unix::net::UnixDatagram` to the socket
+    /// at `path`, so that it may use `send` and `recv`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::os::unix::net::UnixDatagram;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::UnixDatagramExt};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// let socket = UnixDatagram::unbound().unwrap();
+    /// socket.cap_connect(&mut cap_net, "/var/run/log").unwrap();
+    /// socket.send(b"<14>myd: started").unwrap();
+    /// ```
+    fn cap_connect<P>(
+        &self,
+        agent: &mut CapNetAgent,
+        path: P,
+    ) -> io::Result<()>
+    where
+        P: AsRef<Path>;
 }
 
 impl UnixDatagramExt for UnixDatagram {
@@ -208,9 +1488,30 @@ impl UnixDatagramExt for UnixDatagram {
     where
         P: AsRef<Path>,
     {
-        let s = agent.bind_std_unix(SockType::Datagram, path)?;
+        let s = agent.bind_std_unix(SockType::Datagram, path, None)?;
+        Ok(UnixDatagram::from(s))
+    }
+
+    fn cap_bind_with_mode<P>(
+        agent: &mut CapNetAgent,
+        path: P,
+        mode: libc::mode_t,
+    ) -> io::Result<UnixDatagram>
+    where
+        P: AsRef<Path>,
+    {
+        let s = agent.bind_std_unix(SockType::Datagram, path, Some(mode))?;
         Ok(UnixDatagram::from(s))
     }
+
+    fn cap_connect<P>(&self, agent: &mut CapNetAgent, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let want = nix::sys::socket::UnixAddr::new(path.as_ref())?;
+        agent.connect(self, &want)?;
+        Ok(())
+    }
 }
 
 /// Adds extra features to `std::os::unix::net::UnixListener` that require
@@ -238,6 +1539,72 @@ pub trait UnixListenerExt {
     ) -> io::Result<UnixListener>
     where
         P: AsRef<Path>;
+
+    /// Like [`cap_bind`](Self::cap_bind), but with an explicit listen queue
+    /// length.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::os::unix::net::UnixListener;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{
+    ///     CasperExt,
+    ///     std::{Backlog, UnixListenerExt},
+    /// };
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// let path = "/var/run/foo.sock";
+    /// let socket = UnixListener::cap_bind_with_backlog(
+    ///     &mut cap_net,
+    ///     &path,
+    ///     Backlog::Explicit(16),
+    /// )
+    /// .unwrap();
+    /// ```
+    fn cap_bind_with_backlog<P>(
+        agent: &mut CapNetAgent,
+        path: P,
+        backlog: Backlog,
+    ) -> io::Result<UnixListener>
+    where
+        P: AsRef<Path>;
+
+    /// Like [`cap_bind`](Self::cap_bind), but create the socket file with
+    /// permissions `mode`, like `0o660`.
+    ///
+    /// This is how a service can restrict who may connect to its control
+    /// socket.  The mode is set on the socket before binding, so there's no
+    /// window when the file is more widely accessible.  The Casper service's
+    /// umask may restrict it further.  Requires a kernel that supports
+    /// [fchmod(2)](https://man.freebsd.org/cgi/man.cgi?query=fchmod) on
+    /// unbound unix-domain sockets.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::os::unix::net::UnixListener;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::UnixListenerExt};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// let path = "/var/run/foo.sock";
+    /// let socket =
+    ///     UnixListener::cap_bind_with_mode(&mut cap_net, &path, 0o600).unwrap();
+    /// ```
+    fn cap_bind_with_mode<P>(
+        agent: &mut CapNetAgent,
+        path: P,
+        mode: libc::mode_t,
+    ) -> io::Result<UnixListener>
+    where
+        P: AsRef<Path>;
 }
 
 impl UnixListenerExt for UnixListener {
@@ -245,8 +1612,325 @@ impl UnixListenerExt for UnixListener {
     where
         P: AsRef<Path>,
     {
-        let s = agent.bind_std_unix(SockType::Stream, path)?;
-        listen(&s, Backlog::MAXALLOWABLE)?;
+        let s = agent.bind_std_unix(SockType::Stream, path, None)?;
+        listen(&s, NixBacklog::MAXALLOWABLE)?;
+        Ok(UnixListener::from(s))
+    }
+
+    fn cap_bind_with_backlog<P>(
+        agent: &mut CapNetAgent,
+        path: P,
+        backlog: Backlog,
+    ) -> io::Result<UnixListener>
+    where
+        P: AsRef<Path>,
+    {
+        let s = agent.bind_std_unix(SockType::Stream, path, None)?;
+        // Not nix::sys::socket::listen, which rejects backlogs larger than
+        // SOMAXCONN.
+        let r = unsafe { libc::listen(s.as_raw_fd(), backlog.value()) };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(UnixListener::from(s))
+    }
+
+    fn cap_bind_with_mode<P>(
+        agent: &mut CapNetAgent,
+        path: P,
+        mode: libc::mode_t,
+    ) -> io::Result<UnixListener>
+    where
+        P: AsRef<Path>,
+    {
+        let s = agent.bind_std_unix(SockType::Stream, path, Some(mode))?;
+        listen(&s, NixBacklog::MAXALLOWABLE)?;
         Ok(UnixListener::from(s))
     }
 }
+
+/// Adds extra features to `std::os::unix::net::UnixStream` that require
+/// Casper.
+pub trait UnixStreamExt {
+    /// Connect a `std::os::unix::net::UnixStream` to the socket at `path`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::os::unix::net::UnixStream;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{CasperExt, std::UnixStreamExt};
+    ///
+    /// // Safe because we are single-threaded
+    /// let mut casper = unsafe { Casper::new().unwrap() };
+    /// let mut cap_net = casper.net().unwrap();
+    ///
+    /// capsicum::enter();
+    ///
+    /// let path = "/var/run/foo.sock";
+    /// let stream = UnixStream::cap_connect(&mut cap_net, &path).unwrap();
+    /// ```
+    fn cap_connect<P>(
+        agent: &mut CapNetAgent,
+        path: P,
+    ) -> io::Result<UnixStream>
+    where
+        P: AsRef<Path>;
+}
+
+impl UnixStreamExt for UnixStream {
+    fn cap_connect<P>(
+        agent: &mut CapNetAgent,
+        path: P,
+    ) -> io::Result<UnixStream>
+    where
+        P: AsRef<Path>,
+    {
+        let s = agent.connect_std_unix(SockType::Stream, path)?;
+        Ok(UnixStream::from(s))
+    }
+}
+
+extern "C" {
+    // Not yet in the libc crate
+    fn bindat(
+        fd: libc::c_int,
+        s: libc::c_int,
+        addr: *const libc::sockaddr,
+        addrlen: libc::socklen_t,
+    ) -> libc::c_int;
+    fn connectat(
+        fd: libc::c_int,
+        s: libc::c_int,
+        addr: *const libc::sockaddr,
+        addrlen: libc::socklen_t,
+    ) -> libc::c_int;
+}
+
+/// Create and bind several unix-domain listening sockets in one directory.
+///
+/// Each of `names` is bound relative to `dir`, with permissions `mode`.  This
+/// uses [bindat(2)](https://man.freebsd.org/cgi/man.cgi?query=bindat)
+/// directly, bypassing the `cap_net` service on purpose: the service can only
+/// bind by absolute path, and a directory descriptor has none that it could
+/// use.  `bindat` is permitted in capability mode as long as `dir` has the
+/// `CAP_BINDAT` right.  If any socket can't be created, those already created
+/// are removed again, which also requires `CAP_UNLINKAT`.
+///
+/// Returns a map from each name to its listener.
+///
+/// # Examples
+/// ```no_run
+/// use std::fs::File;
+///
+/// use capsicum_net::std::bind_many_unix;
+///
+/// let dir = File::open("/var/run/mydaemon").unwrap();
+/// capsicum::enter();
+///
+/// let listeners =
+///     bind_many_unix(&dir, &["control.sock", "status.sock"], 0o660).unwrap();
+/// let (stream, _) = listeners["control.sock"].accept().unwrap();
+/// ```
+pub fn bind_many_unix<D: AsFd>(
+    dir: &D,
+    names: &[&str],
+    mode: libc::mode_t,
+) -> io::Result<HashMap<String, UnixListener>> {
+    let dirfd = dir.as_fd().as_raw_fd();
+    let mut listeners = HashMap::with_capacity(names.len());
+    for name in names {
+        match bind_one_unix_at(dirfd, name, mode) {
+            Ok(listener) => {
+                listeners.insert((*name).to_owned(), listener);
+            }
+            Err(e) => {
+                for bound in listeners.keys() {
+                    if let Ok(cname) = CString::new(bound.as_str()) {
+                        unsafe { libc::unlinkat(dirfd, cname.as_ptr(), 0) };
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(listeners)
+}
+
+fn bind_one_unix_at(
+    dirfd: RawFd,
+    name: &str,
+    mode: libc::mode_t,
+) -> io::Result<UnixListener> {
+    let cname = CString::new(name)?;
+    let addr = nix::sys::socket::UnixAddr::new(name)?;
+    let s = nix::sys::socket::socket(
+        AddressFamily::Unix,
+        SockType::Stream,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    // FreeBSD applies an unbound socket's own mode to the file that bind
+    // creates.  Chmodding the file afterwards would follow a symlink, if
+    // somebody swapped one in.
+    let r = unsafe { libc::fchmod(s.as_raw_fd(), mode) };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let r = unsafe {
+        bindat(
+            dirfd,
+            s.as_raw_fd(),
+            SockaddrLike::as_ptr(&addr),
+            addr.len(),
+        )
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if let Err(e) = listen(&s, NixBacklog::MAXALLOWABLE) {
+        unsafe { libc::unlinkat(dirfd, cname.as_ptr(), 0) };
+        return Err(e.into());
+    }
+    Ok(UnixListener::from(s))
+}
+
+/// How many times to try connecting to a unix-domain socket before judging it
+/// stale
+const STALE_UNIX_ATTEMPTS: u32 = 5;
+
+/// How long to wait between those attempts
+const STALE_UNIX_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Try once to connect to the unix-domain socket `addr` within `dirfd`.
+/// Returns `false` if the connection was refused.
+fn probe_unix_at(
+    dirfd: RawFd,
+    addr: &nix::sys::socket::UnixAddr,
+) -> io::Result<bool> {
+    for ty in [SockType::Stream, SockType::Datagram] {
+        let s = nix::sys::socket::socket(
+            AddressFamily::Unix,
+            ty,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        let r = unsafe {
+            connectat(
+                dirfd,
+                s.as_raw_fd(),
+                SockaddrLike::as_ptr(addr),
+                addr.len(),
+            )
+        };
+        if r == 0 {
+            return Ok(true);
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::ECONNREFUSED) => return Ok(false),
+            // The socket is of the other type
+            Some(libc::EPROTOTYPE) => continue,
+            _ => return Err(e),
+        }
+    }
+    Ok(false)
+}
+
+/// Is anything accepting connections on the unix-domain socket `name` within
+/// `dirfd`?
+///
+/// A stale socket refuses connections with `ECONNREFUSED`, but so does a live
+/// listener whose accept backlog is full.  So the socket is only judged stale
+/// if it refuses every one of several attempts, giving a busy server time to
+/// drain its backlog.
+fn unix_socket_is_live(dirfd: RawFd, name: &str) -> io::Result<bool> {
+    let addr = nix::sys::socket::UnixAddr::new(name)?;
+    for attempt in 0..STALE_UNIX_ATTEMPTS {
+        if attempt > 0 {
+            ::std::thread::sleep(STALE_UNIX_INTERVAL);
+        }
+        if probe_unix_at(dirfd, &addr)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Remove a stale unix-domain socket left behind by a previous instance, so
+/// that its path may be bound again.
+///
+/// A socket file outlives the process that bound it, and binding to an
+/// existing file fails with `EADDRINUSE`.  But unlink(2) isn't permitted in
+/// capability mode.  Instead, open the socket's directory before entering
+/// capability mode, with at least the `CAP_CONNECTAT`, `CAP_FSTATAT`, and
+/// `CAP_UNLINKAT` rights, and pass it here before binding `name` again.
+///
+/// `name` is only removed if it is a socket and nothing is accepting
+/// connections on it.  Returns `true` if it was removed, and `false` if it
+/// didn't exist.  Fails with `EADDRINUSE` if the socket is still in use, and
+/// `ENOTSOCK` if `name` is some other kind of file.
+///
+/// A listener whose accept backlog is full refuses connections just like a
+/// stale socket does, so `name` is only judged stale if it refuses several
+/// attempts over about 100 ms.  A server that stays overloaded for that long
+/// could still be mistaken for a stale one.  The check and the removal are
+/// also not atomic: if another process binds `name` in between, its new
+/// socket will be removed.  So only use this in a directory where no other
+/// program binds sockets concurrently.
+///
+/// # Examples
+/// ```no_run
+/// use std::{fs::File, os::unix::net::UnixListener};
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{
+///     CasperExt,
+///     std::{remove_stale_unix_at, UnixListenerExt},
+/// };
+///
+/// let dir = File::open("/var/run/mydaemon").unwrap();
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+///
+/// capsicum::enter();
+///
+/// remove_stale_unix_at(&dir, "control.sock").unwrap();
+/// let listener =
+///     UnixListener::cap_bind(&mut cap_net, "/var/run/mydaemon/control.sock")
+///         .unwrap();
+/// ```
+pub fn remove_stale_unix_at<D: AsFd>(dir: &D, name: &str) -> io::Result<bool> {
+    let dirfd = dir.as_fd().as_raw_fd();
+    let cname = CString::new(name)?;
+    let mut sb = ::std::mem::MaybeUninit::<libc::stat>::uninit();
+    let r = unsafe {
+        libc::fstatat(
+            dirfd,
+            cname.as_ptr(),
+            sb.as_mut_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if r < 0 {
+        let e = io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(libc::ENOENT) => Ok(false),
+            _ => Err(e),
+        };
+    }
+    // Safe because fstatat succeeded
+    let sb = unsafe { sb.assume_init() };
+    if sb.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return Err(io::Error::from_raw_os_error(libc::ENOTSOCK));
+    }
+    if unix_socket_is_live(dirfd, name)? {
+        return Err(io::Error::from_raw_os_error(libc::EADDRINUSE));
+    }
+    let r = unsafe { libc::unlinkat(dirfd, cname.as_ptr(), 0) };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(true)
+}
@@ -0,0 +1,192 @@
+// vim: tw=80
+//! `Stream`s of accepted connections
+//!
+//! Server frameworks built on `futures` usually consume connections as a
+//! `Stream`.  [`Incoming`] turns any listener that implements [`Accept`] into
+//! one, restricting each accepted connection's capability rights before
+//! yielding it, just like [`std::LimitedIncoming`](crate::std::LimitedIncoming)
+//! does for blocking code.
+//!
+//! [`Incoming`] itself depends only on `futures-core`, so it works with any
+//! runtime whose listeners implement [`Accept`].  With the `tokio` feature,
+//! Tokio's `TcpListener` and `UnixListener` already do, and
+//! [`Incoming::cap_bind_tcp`] and [`Incoming::cap_bind_unix`] bind them via
+//! Casper.
+//!
+//! # Example
+//! ```no_run
+//! use std::io;
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, stream::Incoming};
+//! use futures::StreamExt;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> io::Result<()> {
+//!     // Safe because we are single-threaded
+//!     let mut casper = unsafe { Casper::new().unwrap() };
+//!     let mut cap_net = casper.net().unwrap();
+//!
+//!     capsicum::enter();
+//!
+//!     let mut incoming = Incoming::cap_bind_tcp(&mut cap_net, "0.0.0.0:8080")?;
+//!     while let Some(stream) = incoming.next().await {
+//!         let stream = stream?;
+//!     }
+//!     Ok(())
+//! }
+//! ```
+#![cfg_attr(docsrs, doc(cfg(feature = "stream")))]
+use std::{
+    io,
+    os::fd::AsFd,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use capsicum::{CapRights, FileRights};
+use futures_core::Stream;
+
+use crate::std::default_stream_rights;
+
+/// A listener that can accept connections asynchronously.
+///
+/// Implement this to use [`Incoming`] with a runtime other than Tokio.
+pub trait Accept {
+    /// The type of an accepted connection
+    type Conn: AsFd;
+    /// The type of the peer's address
+    type Addr;
+
+    /// Poll for a single incoming connection.
+    ///
+    /// Like `Future::poll`, this must arrange for `cx` to be woken when it
+    /// returns `Poll::Pending`.
+    fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<(Self::Conn, Self::Addr)>>;
+}
+
+#[cfg(feature = "tokio")]
+impl Accept for tokio::net::TcpListener {
+    type Addr = std::net::SocketAddr;
+    type Conn = tokio::net::TcpStream;
+
+    fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<(Self::Conn, Self::Addr)>> {
+        tokio::net::TcpListener::poll_accept(self, cx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Accept for tokio::net::UnixListener {
+    type Addr = tokio::net::unix::SocketAddr;
+    type Conn = tokio::net::UnixStream;
+
+    fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<(Self::Conn, Self::Addr)>> {
+        tokio::net::UnixListener::poll_accept(self, cx)
+    }
+}
+
+/// A `Stream` of connections accepted from a listener, each restricted to a
+/// set of capability rights.
+///
+/// The stream never ends on its own.  A connection whose rights cannot be
+/// limited is closed, and the error yielded in its place.
+#[derive(Debug)]
+pub struct Incoming<L> {
+    listener: L,
+    rights:   FileRights,
+}
+
+impl<L: Accept> Incoming<L> {
+    /// Accept connections from `listener`, granting them
+    /// [`default_stream_rights`].
+    pub fn new(listener: L) -> Self {
+        Self::with_rights(listener, default_stream_rights())
+    }
+
+    /// Accept connections from `listener`, granting them only `rights`.
+    ///
+    /// For Tokio, `rights` must include `Right::Event`.
+    pub fn with_rights(listener: L, rights: FileRights) -> Self {
+        Incoming { listener, rights }
+    }
+
+    /// Poll for a single connection, returning it along with the peer's
+    /// address.
+    pub fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<(L::Conn, L::Addr)>> {
+        let (conn, addr) = ready!(self.listener.poll_accept(cx))?;
+        self.rights.limit(&conn)?;
+        Poll::Ready(Ok((conn, addr)))
+    }
+
+    /// Borrow the underlying listener.
+    pub fn get_ref(&self) -> &L {
+        &self.listener
+    }
+
+    /// Consume the stream, returning the underlying listener.
+    pub fn into_inner(self) -> L {
+        self.listener
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Incoming<tokio::net::TcpListener> {
+    /// Bind a TCP listener with
+    /// [`std::TcpListenerExt::cap_bind`](crate::std::TcpListenerExt::cap_bind),
+    /// and accept connections from it.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn cap_bind_tcp<A: crate::CapToSocketAddrs>(
+        agent: &mut crate::CapNetAgent,
+        addrs: A,
+    ) -> io::Result<Self> {
+        let std_sock =
+            <std::net::TcpListener as crate::std::TcpListenerExt>::cap_bind(
+                agent, addrs,
+            )?;
+        std_sock.set_nonblocking(true)?;
+        tokio::net::TcpListener::from_std(std_sock).map(Self::new)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Incoming<tokio::net::UnixListener> {
+    /// Bind a unix-domain listener with
+    /// [`tokio::UnixListenerExt::cap_bind`](crate::tokio::UnixListenerExt::cap_bind),
+    /// and accept connections from it.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn cap_bind_unix<P: AsRef<std::path::Path>>(
+        agent: &mut crate::CapNetAgent,
+        path: P,
+    ) -> io::Result<Self> {
+        <tokio::net::UnixListener as crate::tokio::UnixListenerExt>::cap_bind(
+            agent, path,
+        )
+        .map(Self::new)
+    }
+}
+
+impl<L: Accept + Unpin> Stream for Incoming<L> {
+    type Item = io::Result<L::Conn>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let r = ready!(self.poll_accept(cx));
+        Poll::Ready(Some(r.map(|(conn, _)| conn)))
+    }
+}
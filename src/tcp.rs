@@ -0,0 +1,299 @@
+// vim: tw=80
+//! TCP-specific helpers for Casper-connected sockets
+use std::{
+    io,
+    mem,
+    os::fd::{AsFd, AsRawFd},
+    ptr,
+    time::Duration,
+};
+
+/// Connection-quality statistics for a TCP socket, from FreeBSD's
+/// `struct tcp_info`.
+///
+/// See [tcp(4)](https://man.freebsd.org/cgi/man.cgi?query=tcp) for details.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct TcpInfo {
+    /// Connection state, one of the `TCPS_*` values from netinet/tcp_fsm.h
+    pub state:          u8,
+    /// TCP options negotiated on this connection, as `TCPI_OPT_*` bits
+    pub options:        u8,
+    /// Send window scale factor
+    pub snd_wscale:     u8,
+    /// Receive window scale factor
+    pub rcv_wscale:     u8,
+    /// Retransmission timeout
+    pub rto:            Duration,
+    /// Smoothed round-trip time
+    pub rtt:            Duration,
+    /// Round-trip time variance
+    pub rttvar:         Duration,
+    /// Time since data was last received
+    pub last_data_recv: Duration,
+    /// Maximum segment size for sending
+    pub snd_mss:        u32,
+    /// Maximum segment size for receiving
+    pub rcv_mss:        u32,
+    /// Slow start threshold, in bytes
+    pub snd_ssthresh:   u32,
+    /// Congestion window, in bytes
+    pub snd_cwnd:       u32,
+    /// Peer's advertised receive window, in bytes
+    pub snd_wnd:        u32,
+    /// Advertised receive window, in bytes
+    pub rcv_space:      u32,
+    /// Number of retransmitted packets
+    pub snd_rexmitpack: u32,
+    /// Number of out-of-order packets received
+    pub rcv_ooopack:    u32,
+    /// Number of zero-sized send windows received
+    pub snd_zerowin:    u32,
+}
+
+// FreeBSD's tcp.h packs both window scales into a single byte, as
+// `u_int8_t tcpi_snd_wscale:4, tcpi_rcv_wscale:4`, but libc models them as
+// two separate bytes.  So both live in what libc calls `tcp_snd_wscale`, and
+// `tcp_rcv_wscale` is padding.  The C ABI allocates bitfields starting from
+// the least significant bit on little-endian platforms, and from the most
+// significant bit on big-endian ones, so the first-declared field,
+// tcpi_snd_wscale, is the low nibble only on little-endian.
+#[cfg(target_endian = "little")]
+fn snd_wscale(packed: u8) -> u8 {
+    packed & 0x0f
+}
+
+#[cfg(target_endian = "little")]
+fn rcv_wscale(packed: u8) -> u8 {
+    packed >> 4
+}
+
+#[cfg(target_endian = "big")]
+fn snd_wscale(packed: u8) -> u8 {
+    packed >> 4
+}
+
+#[cfg(target_endian = "big")]
+fn rcv_wscale(packed: u8) -> u8 {
+    packed & 0x0f
+}
+
+impl From<libc::tcp_info> for TcpInfo {
+    fn from(ti: libc::tcp_info) -> Self {
+        TcpInfo {
+            state:          ti.tcpi_state,
+            options:        ti.tcpi_options,
+            snd_wscale:     snd_wscale(ti.tcp_snd_wscale),
+            rcv_wscale:     rcv_wscale(ti.tcp_snd_wscale),
+            rto:            Duration::from_micros(ti.tcpi_rto.into()),
+            rtt:            Duration::from_micros(ti.tcpi_rtt.into()),
+            rttvar:         Duration::from_micros(ti.tcpi_rttvar.into()),
+            last_data_recv: Duration::from_micros(
+                ti.tcpi_last_data_recv.into(),
+            ),
+            snd_mss:        ti.tcpi_snd_mss,
+            rcv_mss:        ti.tcpi_rcv_mss,
+            snd_ssthresh:   ti.tcpi_snd_ssthresh,
+            snd_cwnd:       ti.tcpi_snd_cwnd,
+            snd_wnd:        ti.tcpi_snd_wnd,
+            rcv_space:      ti.tcpi_rcv_space,
+            snd_rexmitpack: ti.tcpi_snd_rexmitpack,
+            rcv_ooopack:    ti.tcpi_rcv_ooopack,
+            snd_zerowin:    ti.tcpi_snd_zerowin,
+        }
+    }
+}
+
+/// Retrieve `TCP_INFO` statistics for a TCP socket.
+///
+/// This works in capability mode, so sandboxed services can use it to export
+/// connection-quality metrics.
+///
+/// # Examples
+/// ```no_run
+/// use std::net::TcpStream;
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, std::TcpStreamExt, tcp::tcp_info};
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+///
+/// let sock = TcpStream::cap_connect(&mut cap_net, "8.8.8.8:53").unwrap();
+/// let info = tcp_info(&sock).unwrap();
+/// println!("rtt={:?} cwnd={}", info.rtt, info.snd_cwnd);
+/// ```
+pub fn tcp_info<F: AsFd>(fd: &F) -> io::Result<TcpInfo> {
+    // Safe because tcp_info is a plain C struct
+    let mut ti: libc::tcp_info = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let r = unsafe {
+        libc::getsockopt(
+            fd.as_fd().as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            (&mut ti as *mut libc::tcp_info).cast(),
+            &mut len,
+        )
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(TcpInfo::from(ti))
+}
+
+/// Copy a file to a socket with `pread(2)`, or `read(2)`, and `write(2)`.
+fn send_file_fallback<S: AsFd, F: AsFd>(
+    sock: &S,
+    file: &F,
+    mut offset: u64,
+    len: Option<u64>,
+) -> io::Result<u64> {
+    let mut buf = vec![0u8; 65536];
+    let mut total = 0;
+    let mut seekable = true;
+    loop {
+        let want = len
+            .map_or(buf.len() as u64, |l| (l - total).min(buf.len() as u64))
+            as usize;
+        if want == 0 {
+            break;
+        }
+        let fd = file.as_fd().as_raw_fd();
+        let r = if seekable {
+            unsafe {
+                libc::pread(
+                    fd,
+                    buf.as_mut_ptr().cast(),
+                    want,
+                    offset as libc::off_t,
+                )
+            }
+        } else {
+            unsafe { libc::read(fd, buf.as_mut_ptr().cast(), want) }
+        };
+        if r < 0 {
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                // Pipes and the like can only be read sequentially
+                Some(libc::ESPIPE) if seekable && offset == 0 => {
+                    seekable = false;
+                    continue;
+                }
+                _ => return Err(e),
+            }
+        } else if r == 0 {
+            break;
+        }
+        let mut chunk = &buf[..r as usize];
+        while !chunk.is_empty() {
+            let w = unsafe {
+                libc::write(
+                    sock.as_fd().as_raw_fd(),
+                    chunk.as_ptr().cast(),
+                    chunk.len(),
+                )
+            };
+            if w < 0 {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    // A nonblocking socket's buffer is full
+                    io::ErrorKind::WouldBlock if total > 0 => return Ok(total),
+                    _ => return Err(e),
+                }
+            }
+            chunk = &chunk[w as usize..];
+            total += w as u64;
+            offset += w as u64;
+        }
+    }
+    Ok(total)
+}
+
+/// Send the contents of `file` over a connected stream socket, using
+/// [sendfile(2)](https://man.freebsd.org/cgi/man.cgi?query=sendfile) for
+/// zero-copy transmission.
+///
+/// Sends `len` bytes starting at `offset`, or everything up to end-of-file if
+/// `len` is `None`.  If `file` isn't something that sendfile can handle, like a
+/// pipe, it falls back to reading and writing.  Returns the number of bytes
+/// sent.
+///
+/// On a nonblocking socket this returns as soon as the socket buffer is full,
+/// so the count may be short.  If nothing at all could be sent, it fails with
+/// `ErrorKind::WouldBlock`.  When falling back on a source that can only be
+/// read sequentially, like a pipe, any bytes that were read but not sent are
+/// lost.
+///
+/// # Example
+/// ```no_run
+/// use std::{fs::File, net::TcpListener};
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, std::TcpListenerExt, tcp::send_file};
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+/// let file = File::open("/usr/share/doc/index.html").unwrap();
+///
+/// capsicum::enter();
+///
+/// let listener = TcpListener::cap_bind(&mut cap_net, "0.0.0.0:8080").unwrap();
+/// let (stream, _) = listener.accept().unwrap();
+/// send_file(&stream, &file, 0, None).unwrap();
+/// ```
+pub fn send_file<S: AsFd, F: AsFd>(
+    sock: &S,
+    file: &F,
+    offset: u64,
+    len: Option<u64>,
+) -> io::Result<u64> {
+    let mut total: u64 = 0;
+    loop {
+        let nbytes = match len {
+            Some(l) if l == total => return Ok(total),
+            Some(l) => (l - total) as usize,
+            // Zero means "until end of file"
+            None => 0,
+        };
+        let mut sbytes: libc::off_t = 0;
+        let r = unsafe {
+            libc::sendfile(
+                file.as_fd().as_raw_fd(),
+                sock.as_fd().as_raw_fd(),
+                (offset + total) as libc::off_t,
+                nbytes,
+                ptr::null_mut(),
+                &mut sbytes,
+                0,
+            )
+        };
+        total += sbytes as u64;
+        if r == 0 {
+            if len.is_none() || sbytes == 0 {
+                // Reached end of file
+                return Ok(total);
+            }
+            continue;
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::EAGAIN) | Some(libc::EBUSY) if total > 0 => {
+                return Ok(total)
+            }
+            Some(libc::ENOTSOCK)
+            | Some(libc::EOPNOTSUPP)
+            | Some(libc::EINVAL)
+                if total == 0 =>
+            {
+                return send_file_fallback(sock, file, offset, len);
+            }
+            _ => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+// vim: tw=80
+//! Limits that can be applied to many agents
+//!
+//! A [`Limit`](crate::Limit) is tied to the one agent that created it.  A
+//! [`LimitTemplate`] records the same kinds of entries without any agent, and
+//! can then be applied to as many agents as needed, such as one per thread or
+//! one per subsystem.
+//!
+//! # Example
+//! ```no_run
+//! use std::net::SocketAddr;
+//!
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, LimitFlags, template::LimitTemplate};
+//!
+//! let db: SocketAddr = "192.0.2.1:5432".parse().unwrap();
+//! let mut template = LimitTemplate::new(LimitFlags::CONNECT);
+//! template.connect_std(db);
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut agents = Vec::new();
+//! for _ in 0..4 {
+//!     let mut cap_net = casper.net().unwrap();
+//!     template.apply(&mut cap_net).unwrap();
+//!     agents.push(cap_net);
+//! }
+//!
+//! capsicum::enter();
+//! ```
+//!
+//! For a static policy, the [`limits!`](crate::limits) macro builds a
+//! template with less boilerplate, and checks its addresses at compile time.
+use std::{io, net::SocketAddr, path::Path};
+
+use nix::sys::socket::{AddressFamily, SockaddrLike, SockaddrStorage};
+
+use crate::{CapNetAgent, LimitFlags};
+
+/// The entries of a Casper limit
+///
+/// This is what a [`LimitTemplate`] records, and what
+/// [`CapNetAgent::applied_limits`] reports.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AppliedLimits {
+    /// The operations permitted at all
+    pub flags:              LimitFlags,
+    /// Addresses that may be bound to, or empty for any
+    pub bind:               Vec<SockaddrStorage>,
+    /// Addresses that may be connected to, or empty for any
+    pub connect:            Vec<SockaddrStorage>,
+    /// Addresses that may be resolved to names, or empty for any
+    pub addr2name:          Vec<SockaddrStorage>,
+    /// Families of addresses that may be resolved to names, or empty for any
+    pub addr2name_families: Vec<AddressFamily>,
+    /// Names, and optionally ports, that may be resolved, or empty for any
+    pub name2addr:          Vec<(String, Option<u16>)>,
+    /// Families of addresses that names may be resolved to, or empty for any
+    pub name2addr_families: Vec<AddressFamily>,
+}
+
+impl AppliedLimits {
+    pub(crate) fn new(flags: LimitFlags) -> Self {
+        AppliedLimits {
+            flags,
+            bind: Vec::new(),
+            connect: Vec::new(),
+            addr2name: Vec::new(),
+            addr2name_families: Vec::new(),
+            name2addr: Vec::new(),
+            name2addr_families: Vec::new(),
+        }
+    }
+}
+
+/// A reusable set of limits, independent of any agent
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LimitTemplate {
+    limits: AppliedLimits,
+}
+
+/// Copy any socket address into owned storage.
+pub(crate) fn to_storage(sa: &dyn SockaddrLike) -> SockaddrStorage {
+    // Safe because sa is valid for sa.len() bytes
+    unsafe { SockaddrStorage::from_raw(sa.as_ptr(), Some(sa.len())) }
+        .expect("socket address is too large")
+}
+
+impl LimitTemplate {
+    /// A template that permits only the operations in `flags`, with no other
+    /// restrictions.
+    pub fn new(flags: LimitFlags) -> Self {
+        LimitTemplate {
+            limits: AppliedLimits::new(flags),
+        }
+    }
+
+    /// The operations that this template permits at all
+    pub fn flags(&self) -> LimitFlags {
+        self.limits.flags
+    }
+
+    /// Allow binding to the given address.  See
+    /// [`Limit::bind`](crate::Limit::bind).
+    pub fn bind(&mut self, sa: &dyn SockaddrLike) -> &mut Self {
+        self.limits.bind.push(to_storage(sa));
+        self
+    }
+
+    /// Like [`LimitTemplate::bind`], but for a std address.
+    pub fn bind_std<A: Into<SocketAddr>>(&mut self, addr: A) -> &mut Self {
+        self.limits.bind.push(SockaddrStorage::from(addr.into()));
+        self
+    }
+
+    /// Like [`LimitTemplate::bind`], but for a unix-domain socket's path.
+    ///
+    /// # Panics
+    ///
+    /// If `path` is too long for a unix-domain socket address.
+    pub fn bind_unix<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        let sa = nix::sys::socket::UnixAddr::new(path.as_ref())
+            .expect("path is too long for a unix-domain socket");
+        self.bind(&sa)
+    }
+
+    /// Allow connecting to the given address.  See
+    /// [`Limit::connect`](crate::Limit::connect).
+    pub fn connect(&mut self, sa: &dyn SockaddrLike) -> &mut Self {
+        self.limits.connect.push(to_storage(sa));
+        self
+    }
+
+    /// Like [`LimitTemplate::connect`], but for a std address.
+    pub fn connect_std<A: Into<SocketAddr>>(&mut self, addr: A) -> &mut Self {
+        self.limits.connect.push(SockaddrStorage::from(addr.into()));
+        self
+    }
+
+    /// Like [`LimitTemplate::connect`], but for a unix-domain socket's path.
+    ///
+    /// # Panics
+    ///
+    /// If `path` is too long for a unix-domain socket address.
+    pub fn connect_unix<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        let sa = nix::sys::socket::UnixAddr::new(path.as_ref())
+            .expect("path is too long for a unix-domain socket");
+        self.connect(&sa)
+    }
+
+    /// Allow reverse lookups of the given address.  See
+    /// [`Limit::addr2name`](crate::Limit::addr2name).
+    pub fn addr2name(&mut self, sa: &dyn SockaddrLike) -> &mut Self {
+        self.limits.addr2name.push(to_storage(sa));
+        self
+    }
+
+    /// Allow reverse lookups only of addresses in the given families.  See
+    /// [`Limit::addr2name_family`](crate::Limit::addr2name_family).
+    pub fn addr2name_family(
+        &mut self,
+        families: &[AddressFamily],
+    ) -> &mut Self {
+        self.limits.addr2name_families.extend_from_slice(families);
+        self
+    }
+
+    /// Allow resolving the given name.  See
+    /// [`Limit::name2addr`](crate::Limit::name2addr).  A name that contains a
+    /// NUL byte makes [`apply`](Self::apply) fail.
+    pub fn name2addr(&mut self, name: &str, port: Option<u16>) -> &mut Self {
+        self.limits.name2addr.push((name.to_owned(), port));
+        self
+    }
+
+    /// Allow forward lookups only of addresses in the given families.  See
+    /// [`Limit::name2addr_family`](crate::Limit::name2addr_family).
+    pub fn name2addr_family(
+        &mut self,
+        families: &[AddressFamily],
+    ) -> &mut Self {
+        self.limits.name2addr_families.extend_from_slice(families);
+        self
+    }
+
+    /// The entries recorded so far
+    pub fn limits(&self) -> &AppliedLimits {
+        &self.limits
+    }
+
+    /// Limit `agent` according to this template.
+    ///
+    /// As with any limit, this can only reduce the agent's capabilities.
+    pub fn apply(&self, agent: &mut CapNetAgent) -> io::Result<()> {
+        let mut limit = agent.limit(self.limits.flags)?;
+        for sa in self.limits.bind.iter() {
+            limit.bind(sa);
+        }
+        for sa in self.limits.connect.iter() {
+            limit.connect(sa);
+        }
+        for sa in self.limits.addr2name.iter() {
+            limit.addr2name(sa);
+        }
+        if !self.limits.addr2name_families.is_empty() {
+            limit.addr2name_family(&self.limits.addr2name_families);
+        }
+        for (name, port) in self.limits.name2addr.iter() {
+            limit.name2addr(name, *port);
+        }
+        if !self.limits.name2addr_families.is_empty() {
+            limit.name2addr_family(&self.limits.name2addr_families);
+        }
+        limit.limit()
+    }
+}
+
+impl From<AppliedLimits> for LimitTemplate {
+    /// A template that reproduces `limits`, such as those of another agent
+    fn from(limits: AppliedLimits) -> Self {
+        LimitTemplate { limits }
+    }
+}
+
+/// Build a [`LimitTemplate`] from lists of numeric socket addresses.
+///
+/// Each key, `bind` or `connect`, permits that operation, limited to the
+/// listed addresses.  An empty list permits the operation on any address.
+/// Operations without a key aren't permitted at all.  Every address is parsed
+/// by [`sockaddr::parse_const`](crate::sockaddr::parse_const) at compile time,
+/// so a typo is a build failure rather than a runtime panic.
+///
+/// # Example
+/// ```no_run
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{limits, CasperExt};
+///
+/// let template = limits! {
+///     bind: ["127.0.0.1:80", "[::1]:80"],
+///     connect: ["10.0.0.1:5432"],
+/// };
+///
+/// // Safe because we are single-threaded
+/// let mut casper = unsafe { Casper::new().unwrap() };
+/// let mut cap_net = casper.net().unwrap();
+/// template.apply(&mut cap_net).unwrap();
+/// ```
+///
+/// An invalid address won't compile:
+/// ```compile_fail
+/// let template = capsicum_net::limits! { bind: ["127.0.0.256:80"] };
+/// ```
+#[macro_export]
+macro_rules! limits {
+    (@flag bind) => { $crate::LimitFlags::BIND };
+    (@flag connect) => { $crate::LimitFlags::CONNECT };
+    ($($op:ident: [$($addr:literal),* $(,)?]),* $(,)?) => {{
+        let flags = $crate::LimitFlags::empty() $(| $crate::limits!(@flag $op))*;
+        #[allow(unused_mut)]
+        let mut template = $crate::template::LimitTemplate::new(flags);
+        $($(
+            {
+                const ADDR: ::std::net::SocketAddr =
+                    $crate::sockaddr::parse_const($addr);
+                $crate::limits!(@add template, $op, ADDR);
+            }
+        )*)*
+        template
+    }};
+    (@add $template:ident, bind, $addr:ident) => {
+        $template.bind_std($addr);
+    };
+    (@add $template:ident, connect, $addr:ident) => {
+        $template.connect_std($addr);
+    };
+}
@@ -2,16 +2,472 @@
 //! Extension traits for use with Tokio's socket types
 
 #![cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
-use std::{io, net::ToSocketAddrs, os::fd::AsFd, path::Path};
+use std::{
+    borrow::BorrowMut,
+    future::{poll_fn, Future},
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    os::fd::AsFd,
+    path::Path,
+    pin::pin,
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::Duration,
+};
 
-use tokio::net::{TcpSocket, UdpSocket, UnixDatagram, UnixListener};
+use capsicum::{CapRights, FileRights};
+use nix::sys::socket::{AddressFamily, SockFlag, SockType, SockaddrStorage};
+use tokio::{
+    net::{
+        TcpListener,
+        TcpSocket,
+        TcpStream,
+        UdpSocket,
+        UnixDatagram,
+        UnixListener,
+    },
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
 
-use super::CapNetAgent;
+use super::{CapNetAgent, CapToSocketAddrs, NameInfoFlags};
+
+/// Ways to abandon an asynchronous operation early.
+///
+/// Casper requests themselves are synchronous and can't be interrupted, but
+/// every wait between or after them can be, and so can a request running on
+/// the blocking pool.  An operation abandoned this way fails with
+/// `ErrorKind::Interrupted` if its token was cancelled, or
+/// `ErrorKind::TimedOut` if its deadline passed.
+///
+/// # Examples
+/// ```no_run
+/// use std::{
+///     io,
+///     sync::{Arc, Mutex},
+///     time::Duration,
+/// };
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, tokio::{cap_connect_with, Cancellation}};
+/// use tokio_util::sync::CancellationToken;
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() -> io::Result<()> {
+///     // Safe because we are single-threaded
+///     let mut casper = unsafe { Casper::new().unwrap() };
+///     let cap_net = Arc::new(Mutex::new(casper.net().unwrap()));
+///
+///     let token = CancellationToken::new();
+///     let cancel = Cancellation::new()
+///         .token(token.clone())
+///         .timeout(Duration::from_secs(5));
+///     let stream = cap_connect_with(&cap_net, "8.8.8.8:53", &cancel).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    token:    Option<CancellationToken>,
+    deadline: Option<Instant>,
+}
+
+impl Cancellation {
+    /// Never abandon the operation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Abandon the operation when `token` is cancelled.
+    pub fn token(mut self, token: CancellationToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Abandon the operation if it hasn't completed by `deadline`.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Abandon the operation if it hasn't completed within `timeout` from
+    /// now.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.deadline(Instant::now() + timeout)
+    }
+
+    fn cancelled() -> io::Error {
+        io::Error::new(io::ErrorKind::Interrupted, "operation cancelled")
+    }
+
+    fn timed_out() -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, "operation timed out")
+    }
+
+    /// Fail if the operation should already have been abandoned.
+    fn check(&self) -> io::Result<()> {
+        if self.token.as_ref().is_some_and(|t| t.is_cancelled()) {
+            Err(Self::cancelled())
+        } else if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            Err(Self::timed_out())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Run `fut` to completion, unless abandoned first.
+    async fn run<T, F>(&self, fut: F) -> io::Result<T>
+    where
+        F: Future<Output = io::Result<T>>,
+    {
+        let mut fut = pin!(fut);
+        let mut cancelled =
+            self.token.as_ref().map(|t| Box::pin(t.cancelled()));
+        let mut sleep =
+            self.deadline.map(|d| Box::pin(tokio::time::sleep_until(d)));
+        poll_fn(|cx| {
+            if let Some(c) = cancelled.as_mut() {
+                if c.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Err(Self::cancelled()));
+                }
+            }
+            if let Some(s) = sleep.as_mut() {
+                if s.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Err(Self::timed_out()));
+                }
+            }
+            fut.as_mut().poll(cx)
+        })
+        .await
+    }
+}
+
+/// Run `f` with a shared agent locked, even if another thread panicked while
+/// holding it.
+fn with_agent<A, T, F>(agent: &Mutex<A>, f: F) -> T
+where
+    A: BorrowMut<CapNetAgent>,
+    F: FnOnce(&mut CapNetAgent) -> T,
+{
+    let mut guard = agent.lock().unwrap_or_else(|e| e.into_inner());
+    f(<A as BorrowMut<CapNetAgent>>::borrow_mut(&mut guard))
+}
+
+/// Connect a nonblocking socket to a single address, waiting asynchronously
+/// for the handshake to complete.  The agent is only locked while talking to
+/// Casper.
+async fn connect_one<A: BorrowMut<CapNetAgent>>(
+    agent: &Mutex<A>,
+    addr: SocketAddr,
+    cancel: &Cancellation,
+) -> io::Result<TcpStream> {
+    let wait = with_agent(agent, |a| a.connect_permit_async());
+    let permit = cancel.run(async { Ok(wait.await) }).await?;
+    let family = if addr.is_ipv4() {
+        AddressFamily::Inet
+    } else {
+        AddressFamily::Inet6
+    };
+    let sock = nix::sys::socket::socket(
+        family,
+        SockType::Stream,
+        SockFlag::SOCK_NONBLOCK,
+        None,
+    )?;
+    let res = with_agent(agent, |a| {
+        a.connect_std_fd_with(sock.as_fd(), addr, permit)
+    });
+    match res {
+        Err(e) if e.raw_os_error() != Some(libc::EINPROGRESS) => return Err(e),
+        _ => (),
+    }
+    let stream = TcpStream::from_std(std::net::TcpStream::from(sock))?;
+    cancel
+        .run(async {
+            stream.writable().await?;
+            match stream.take_error()? {
+                Some(e) => Err(e),
+                None => Ok(()),
+            }
+        })
+        .await?;
+    Ok(stream)
+}
+
+/// Try each of `addrs` in turn, until one connects.
+async fn connect_addrs<A: BorrowMut<CapNetAgent>>(
+    agent: &Mutex<A>,
+    addrs: Vec<SocketAddr>,
+    cancel: &Cancellation,
+) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for addr in addrs {
+        cancel.check()?;
+        match connect_one(agent, addr, cancel).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                // Don't try the next address if we were abandoned
+                cancel.check()?;
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+/// Open a TCP connection to a remote host, connecting via a `cap_net` service.
+///
+/// If the agent has a [connect rate
+/// limit](crate::CapNetAgent::set_connect_rate_limit), this waits for it
+/// without blocking the runtime.  The TCP handshake is likewise awaited
+/// asynchronously.  But a hostname in `addrs` is resolved synchronously; use
+/// [`cap_connect_with`] to resolve it on the blocking pool instead.
+///
+/// # Examples
+/// ```no_run
+/// use std::io;
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, tokio::cap_connect};
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() -> io::Result<()> {
+///     // Safe because we are single-threaded
+///     let mut casper = unsafe { Casper::new().unwrap() };
+///     let mut cap_net = casper.net().unwrap();
+///
+///     let stream = cap_connect(&mut cap_net, "8.8.8.8:53").await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn cap_connect<A: CapToSocketAddrs>(
+    agent: &mut CapNetAgent,
+    addrs: A,
+) -> io::Result<TcpStream> {
+    let addrs = addrs.cap_to_socket_addrs(agent)?;
+    connect_addrs(&Mutex::new(agent), addrs, &Cancellation::new()).await
+}
+
+/// Like [`cap_connect`], but may be abandoned early.
+///
+/// Any hostname in `addrs` is resolved on Tokio's blocking pool, so that
+/// resolution may be abandoned too.  An abandoned lookup still finishes in
+/// the background, holding the agent's lock until it does.  That's why the
+/// agent must be shared.
+pub async fn cap_connect_with<A>(
+    agent: &Arc<Mutex<CapNetAgent>>,
+    addrs: A,
+    cancel: &Cancellation,
+) -> io::Result<TcpStream>
+where
+    A: CapToSocketAddrs + Send + 'static,
+{
+    cancel.check()?;
+    let resolver = Arc::clone(agent);
+    let addrs = cancel
+        .run(async move {
+            tokio::task::spawn_blocking(move || {
+                with_agent(&resolver, |a| addrs.cap_to_socket_addrs(a))
+            })
+            .await?
+        })
+        .await?;
+    connect_addrs(agent, addrs, cancel).await
+}
+
+/// Like [`accept_limited`], but may be abandoned early.
+pub async fn accept_limited_with(
+    listener: &TcpListener,
+    rights: &FileRights,
+    cancel: &Cancellation,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    cancel.run(accept_limited(listener, rights)).await
+}
+
+/// Accept a connection from `listener`, then restrict it to `rights` with
+/// [cap_rights_limit(2)](https://man.freebsd.org/cgi/man.cgi?query=cap_rights_limit)
+/// before returning it.
+///
+/// See [`std::LimitedIncoming`](crate::std::LimitedIncoming) for the rationale.
+/// `rights` must include `Right::Event` for the stream to work with tokio;
+/// [`default_stream_rights`](crate::std::default_stream_rights) does.
+///
+/// # Examples
+/// ```no_run
+/// use std::io;
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, std::default_stream_rights, tokio::{accept_limited, TcpSocketExt}};
+/// use tokio::net::TcpSocket;
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() -> io::Result<()> {
+///     // Safe because we are single-threaded
+///     let mut casper = unsafe { Casper::new().unwrap() };
+///     let mut cap_net = casper.net().unwrap();
+///
+///     let socket = TcpSocket::new_v4()?;
+///     socket.cap_bind(&mut cap_net, "127.0.0.1:8094".parse().unwrap())?;
+///     let listener = socket.listen(1024)?;
+///     let rights = default_stream_rights();
+///     loop {
+///         let (stream, peer) = accept_limited(&listener, &rights).await?;
+///     }
+/// }
+/// ```
+pub async fn accept_limited(
+    listener: &TcpListener,
+    rights: &FileRights,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    let (stream, addr) = listener.accept().await?;
+    rights.limit(&stream)?;
+    Ok((stream, addr))
+}
+
+/// Look up the name of `addr`, via its PTR record, from asynchronous code.
+///
+/// Like [`CapNetAgent::getnameinfo`], this asks Casper to resolve the address,
+/// which may take a while.  The request runs on Tokio's blocking pool, so it
+/// never stalls the runtime's other tasks.  The agent is locked for the
+/// duration of the lookup.  Fails with `ErrorKind::NotFound` if `addr` has no
+/// name.
+///
+/// # Examples
+/// ```no_run
+/// use std::{io, net::{IpAddr, Ipv4Addr}, sync::{Arc, Mutex}};
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, tokio::lookup_addr};
+///
+/// fn main() -> io::Result<()> {
+///     // Safe because we are single-threaded, until the runtime starts
+///     let mut casper = unsafe { Casper::new().unwrap() };
+///     let cap_net = Arc::new(Mutex::new(casper.net().unwrap()));
+///
+///     let rt = tokio::runtime::Builder::new_current_thread()
+///         .enable_all()
+///         .build()?;
+///     rt.block_on(async {
+///         let ip = IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8));
+///         let name = lookup_addr(&cap_net, ip).await?;
+///         Ok(())
+///     })
+/// }
+/// ```
+pub async fn lookup_addr(
+    agent: &Arc<Mutex<CapNetAgent>>,
+    addr: IpAddr,
+) -> io::Result<String> {
+    let agent = Arc::clone(agent);
+    let sa = SockaddrStorage::from(SocketAddr::new(addr, 0));
+    tokio::task::spawn_blocking(move || {
+        with_agent(&agent, |a| a.getnameinfo(&sa, NameInfoFlags::NAMEREQD))
+            .map(|(host, _)| host)
+    })
+    .await?
+}
+
+/// Socket addresses resolved via a `cap_net` service, in a form that Tokio's
+/// APIs accept.
+///
+/// Tokio's own `ToSocketAddrs` is sealed, and its implementation for strings
+/// can't resolve hostnames in capability mode.  Instead, resolve the name with
+/// [`ResolvedAddrs::resolve`], then pass [`as_slice`](Self::as_slice) to any
+/// Tokio function that takes `ToSocketAddrs`, or [`first`](Self::first) to
+/// functions like `TcpSocket::connect` that take a single `SocketAddr`.  It also
+/// implements the standard `ToSocketAddrs`, so it can be used with this
+/// crate's own functions.
+///
+/// # Examples
+/// ```no_run
+/// use std::io;
+///
+/// use capsicum::casper::Casper;
+/// use capsicum_net::{CasperExt, tokio::ResolvedAddrs};
+/// use tokio::net::{TcpSocket, UdpSocket};
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() -> io::Result<()> {
+///     // Safe because we are single-threaded
+///     let mut casper = unsafe { Casper::new().unwrap() };
+///     let mut cap_net = casper.net().unwrap();
+///
+///     let addrs = ResolvedAddrs::resolve(&mut cap_net, "localhost", 53)?;
+///     let socket = UdpSocket::bind("127.0.0.1:0").await?;
+///     socket.connect(addrs.as_slice()).await?;
+///     let stream = TcpSocket::new_v4()?.connect(addrs.first()).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ResolvedAddrs(Vec<SocketAddr>);
+
+impl ResolvedAddrs {
+    /// Resolve `host` with [`CapNetAgent::resolve`].
+    ///
+    /// Fails if the name resolves to no addresses.
+    pub fn resolve(
+        agent: &mut CapNetAgent,
+        host: &str,
+        port: u16,
+    ) -> io::Result<Self> {
+        let addrs = agent.resolve(host, port)?;
+        if addrs.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            ))
+        } else {
+            Ok(ResolvedAddrs(addrs))
+        }
+    }
+
+    /// All of the resolved addresses, in the resolver's order of preference.
+    pub fn as_slice(&self) -> &[SocketAddr] {
+        &self.0
+    }
+
+    /// The most preferred address
+    pub fn first(&self) -> SocketAddr {
+        self.0[0]
+    }
+}
+
+impl ToSocketAddrs for ResolvedAddrs {
+    type Iter = std::vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        Ok(self.0.clone().into_iter())
+    }
+}
+
+impl CapToSocketAddrs for ResolvedAddrs {
+    fn cap_to_socket_addrs(
+        &self,
+        _agent: &mut CapNetAgent,
+    ) -> io::Result<Vec<SocketAddr>> {
+        Ok(self.0.clone())
+    }
+}
+
+impl From<ResolvedAddrs> for Vec<SocketAddr> {
+    fn from(addrs: ResolvedAddrs) -> Self {
+        addrs.0
+    }
+}
 
 /// Adds extra features to `tokio::net::TcpSocket` that require Casper.
 pub trait TcpSocketExt {
     /// Bind a `tokio::net::TcpSocket` to a port.
     ///
+    /// Options like `SO_REUSEADDR` can be set with the socket's own methods,
+    /// such as [`TcpSocket::set_reuseaddr`], before calling this.
+    ///
     /// # Examples
     /// ```
     /// use std::{io, str::FromStr };
@@ -40,6 +496,17 @@ pub trait TcpSocketExt {
         agent: &mut CapNetAgent,
         addr: std::net::SocketAddr,
     ) -> io::Result<()>;
+
+    /// Bind a `tokio::net::TcpSocket` to an ephemeral port on `ip`, and
+    /// return the address that the kernel chose.
+    ///
+    /// If the agent is limited, it must be allowed to bind port 0, as with
+    /// [`Limit::bind_any_port`](crate::Limit::bind_any_port).
+    fn cap_bind_ephemeral<I: Into<IpAddr>>(
+        &self,
+        agent: &mut CapNetAgent,
+        ip: I,
+    ) -> io::Result<SocketAddr>;
 }
 
 impl TcpSocketExt for TcpSocket {
@@ -51,6 +518,15 @@ impl TcpSocketExt for TcpSocket {
         let sock = self.as_fd();
         agent.bind_std_fd(sock, addr)
     }
+
+    fn cap_bind_ephemeral<I: Into<IpAddr>>(
+        &self,
+        agent: &mut CapNetAgent,
+        ip: I,
+    ) -> io::Result<SocketAddr> {
+        self.cap_bind(agent, SocketAddr::new(ip.into(), 0))?;
+        self.local_addr()
+    }
 }
 
 /// Adds extra features to `tokio::net::UdpSocket` that require Casper.
@@ -77,17 +553,67 @@ pub trait UdpSocketExt {
     ///     Ok(())
     /// }
     /// ```
-    // This function takes std::net::ToSocketAddrs instead of
+    // This function takes CapToSocketAddrs instead of
     // tokio::net::ToSocketAddrs because the latter has no publicly available
-    // methods.
-    fn cap_bind<A: ToSocketAddrs>(
+    // methods, and couldn't resolve hostnames in capability mode anyway.
+    fn cap_bind<A: CapToSocketAddrs>(
         agent: &mut CapNetAgent,
         addrs: A,
     ) -> io::Result<UdpSocket>;
+
+    /// Like [`cap_bind`](Self::cap_bind), but first set the socket options
+    /// chosen in `builder`, such as `SO_REUSEADDR`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::io;
+    ///
+    /// use capsicum::casper::Casper;
+    /// use capsicum_net::{
+    ///     std::UdpSocketBuilder,
+    ///     tokio::UdpSocketExt,
+    ///     CasperExt,
+    /// };
+    /// use tokio::net::UdpSocket;
+    ///
+    /// #[tokio::main(flavor = "current_thread")]
+    /// async fn main() -> io::Result<()> {
+    ///     // Safe because we are single-threaded
+    ///     let mut casper = unsafe { Casper::new().unwrap() };
+    ///     let mut cap_net = casper.net().unwrap();
+    ///
+    ///     let mut builder = UdpSocketBuilder::new();
+    ///     builder.reuse_port(true);
+    ///     let addr = "127.0.0.1:8082";
+    ///     let sock = UdpSocket::cap_bind_with(&mut cap_net, addr, &builder)?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    fn cap_bind_with<A: CapToSocketAddrs>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+        builder: &crate::std::UdpSocketBuilder,
+    ) -> io::Result<UdpSocket>;
+
+    /// Bind a single `tokio::net::UdpSocket` to `[::]:port` that handles both
+    /// IPv6 and IPv4 traffic.  See
+    /// [`std::UdpSocketExt::cap_bind_dual_stack`](crate::std::UdpSocketExt::cap_bind_dual_stack).
+    fn cap_bind_dual_stack(
+        agent: &mut CapNetAgent,
+        port: u16,
+    ) -> io::Result<UdpSocket>;
+
+    /// Bind a `tokio::net::UdpSocket` to an ephemeral port on `ip`, and
+    /// return it with the address that the kernel chose.
+    fn cap_bind_ephemeral<I: Into<IpAddr>>(
+        agent: &mut CapNetAgent,
+        ip: I,
+    ) -> io::Result<(UdpSocket, SocketAddr)>;
 }
 
 impl UdpSocketExt for UdpSocket {
-    fn cap_bind<A: ToSocketAddrs>(
+    fn cap_bind<A: CapToSocketAddrs>(
         agent: &mut CapNetAgent,
         addrs: A,
     ) -> io::Result<UdpSocket> {
@@ -98,6 +624,34 @@ impl UdpSocketExt for UdpSocket {
         std_sock.set_nonblocking(true)?;
         UdpSocket::from_std(std_sock)
     }
+
+    fn cap_bind_with<A: CapToSocketAddrs>(
+        agent: &mut CapNetAgent,
+        addrs: A,
+        builder: &crate::std::UdpSocketBuilder,
+    ) -> io::Result<UdpSocket> {
+        let std_sock = builder.bind(agent, addrs)?;
+        std_sock.set_nonblocking(true)?;
+        UdpSocket::from_std(std_sock)
+    }
+
+    fn cap_bind_dual_stack(
+        agent: &mut CapNetAgent,
+        port: u16,
+    ) -> io::Result<UdpSocket> {
+        let mut builder = crate::std::UdpSocketBuilder::new();
+        builder.v6only(false);
+        Self::cap_bind_with(agent, (Ipv6Addr::UNSPECIFIED, port), &builder)
+    }
+
+    fn cap_bind_ephemeral<I: Into<IpAddr>>(
+        agent: &mut CapNetAgent,
+        ip: I,
+    ) -> io::Result<(UdpSocket, SocketAddr)> {
+        let socket = Self::cap_bind(agent, (ip.into(), 0))?;
+        let addr = socket.local_addr()?;
+        Ok((socket, addr))
+    }
 }
 
 /// Adds extra features to `tokio::net::UnixDatagram` that require Casper.
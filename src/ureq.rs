@@ -0,0 +1,60 @@
+// vim: tw=80
+//! A [`ureq`](https://docs.rs/ureq) resolver that works in capability mode
+//!
+//! ureq normally resolves hostnames with `std::net::ToSocketAddrs`, which
+//! can't work once the process has entered capability mode.  [`CapResolver`]
+//! resolves them through a `cap_net` service instead.  As with
+//! [`reqwest`](crate::reqwest), this only handles name resolution; ureq's
+//! connections must still be permitted.
+//!
+//! # Example
+//! ```no_run
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, ureq::CapResolver};
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let resolver = CapResolver::new(casper.net().unwrap());
+//!
+//! let agent = ureq::AgentBuilder::new().resolver(resolver).build();
+//! ```
+#![cfg_attr(docsrs, doc(cfg(feature = "ureq")))]
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::{resolver::Resolver, CapNetAgent};
+
+/// Implements ureq's `Resolver` with a [`Resolver`].
+#[derive(Clone, Debug)]
+pub struct CapResolver {
+    resolver: Arc<Mutex<Resolver>>,
+}
+
+impl CapResolver {
+    /// Resolve names with `agent`, which must permit
+    /// [`LimitFlags::NAME2ADDR`](crate::LimitFlags::NAME2ADDR).
+    pub fn new(agent: CapNetAgent) -> Self {
+        Self::from(Resolver::new(agent))
+    }
+}
+
+impl From<Resolver> for CapResolver {
+    fn from(resolver: Resolver) -> Self {
+        CapResolver {
+            resolver: Arc::new(Mutex::new(resolver)),
+        }
+    }
+}
+
+impl ::ureq::Resolver for CapResolver {
+    fn resolve(&self, netloc: &str) -> io::Result<Vec<SocketAddr>> {
+        // ureq passes "host:port", with IPv6 hosts bracketed
+        self.resolver
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .lookup(netloc)
+    }
+}
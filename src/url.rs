@@ -0,0 +1,85 @@
+// vim: tw=80
+//! Connect to the server named by a [`Url`]
+//!
+//! Sandboxed clients are often configured with a URL rather than a host and
+//! port.  [`cap_connect_url`] extracts both, resolves the host through Casper,
+//! and returns a connected `TcpStream`.  It doesn't speak the URL's protocol;
+//! that's up to the caller.
+//!
+//! # Example
+//! ```no_run
+//! use capsicum::casper::Casper;
+//! use capsicum_net::{CasperExt, url::cap_connect_url};
+//! use url::Url;
+//!
+//! // Safe because we are single-threaded
+//! let mut casper = unsafe { Casper::new().unwrap() };
+//! let mut cap_net = casper.net().unwrap();
+//!
+//! capsicum::enter();
+//!
+//! let url = Url::parse("https://www.freebsd.org/").unwrap();
+//! let stream = cap_connect_url(&mut cap_net, &url).unwrap();
+//! ```
+#![cfg_attr(docsrs, doc(cfg(feature = "url")))]
+use std::{
+    io,
+    net::{SocketAddr, TcpStream},
+};
+
+use ::url::{Host, Url};
+
+use crate::{std::TcpStreamExt, CapNetAgent};
+
+/// The default port for `scheme`, including some that the `url` crate
+/// doesn't know.
+pub fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "ftp" => Some(21),
+        "ssh" => Some(22),
+        "smtp" => Some(25),
+        "gopher" => Some(70),
+        "http" | "ws" => Some(80),
+        "pop3" => Some(110),
+        "imap" => Some(143),
+        "ldap" => Some(389),
+        "https" | "wss" => Some(443),
+        "smtps" => Some(465),
+        "submission" => Some(587),
+        "ldaps" => Some(636),
+        "imaps" => Some(993),
+        "pop3s" => Some(995),
+        _ => None,
+    }
+}
+
+/// Open a TCP connection to the host and port named by `url`.
+///
+/// If `url` has no explicit port, the scheme's [`default_port`] is used.  A
+/// hostname is resolved via Casper, and each of its addresses tried in turn,
+/// as with [`CapNetAgent::connect_host`].  Fails with
+/// `ErrorKind::InvalidInput` if `url` has no host, or no port can be
+/// determined.
+pub fn cap_connect_url(
+    agent: &mut CapNetAgent,
+    url: &Url,
+) -> io::Result<TcpStream> {
+    let invalid = |msg: &str| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{msg} in {url}"))
+    };
+    let port = url
+        .port()
+        .or_else(|| default_port(url.scheme()))
+        .ok_or_else(|| invalid("unknown port"))?;
+    match url.host().ok_or_else(|| invalid("missing host"))? {
+        Host::Domain(domain) => {
+            TcpStream::cap_connect_host(agent, domain, port)
+        }
+        Host::Ipv4(ip) => {
+            TcpStream::cap_connect(agent, SocketAddr::from((ip, port)))
+        }
+        Host::Ipv6(ip) => {
+            TcpStream::cap_connect(agent, SocketAddr::from((ip, port)))
+        }
+    }
+}
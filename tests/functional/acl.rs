@@ -0,0 +1,362 @@
+// vim: tw=80
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+};
+
+use capsicum_net::{
+    acl::{
+        AccessDenied,
+        AccessList,
+        Action,
+        CidrPolicy,
+        DenyList,
+        IpNet,
+        Rule,
+    },
+    std::{TcpListenerExt, TcpStreamExt},
+    CasperExt,
+    LimitFlags,
+};
+use nix::{
+    errno::Errno,
+    sys::socket::{socket, AddressFamily, SockFlag, SockType, SockaddrIn},
+};
+
+use crate::{std::get_local_in, CASPER};
+
+#[test]
+fn connect_denied() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+    cap_net.set_access_list(Some(AccessList::new(Action::Deny)));
+
+    let want = get_local_in();
+    let _listener = TcpListener::bind(want).unwrap();
+    let err = TcpStream::cap_connect(&mut cap_net, want).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    let denied = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<AccessDenied>()
+        .unwrap();
+    assert_eq!(denied.op, LimitFlags::CONNECT);
+    assert_eq!(denied.addr, want);
+}
+
+#[test]
+fn bind_allowed() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+    let acl = AccessList::new(Action::Deny);
+    acl.push(Rule::allow(
+        LimitFlags::BIND,
+        "127.0.0.0/8".parse().unwrap(),
+    ));
+    cap_net.set_access_list(Some(acl));
+
+    let want = get_local_in();
+    TcpListener::cap_bind(&mut cap_net, want).unwrap();
+}
+
+#[test]
+fn first_match_wins() {
+    let acl = AccessList::new(Action::Allow);
+    let lo = Ipv4Addr::LOCALHOST;
+    acl.push(
+        Rule::deny(LimitFlags::CONNECT, IpNet::from(IpAddr::V4(lo)))
+            .ports(1..=1023),
+    );
+    acl.push(Rule::allow(
+        LimitFlags::CONNECT,
+        "127.0.0.0/8".parse().unwrap(),
+    ));
+    let low = SocketAddr::new(lo.into(), 80);
+    let high = SocketAddr::new(lo.into(), 8080);
+    assert_eq!(acl.check(LimitFlags::CONNECT, &low), Action::Deny);
+    assert_eq!(acl.check(LimitFlags::CONNECT, &high), Action::Allow);
+    assert_eq!(acl.check(LimitFlags::BIND, &low), Action::Allow);
+}
+
+#[test]
+fn ipnet() {
+    let net: IpNet = "192.0.2.0/24".parse().unwrap();
+    assert!(net.contains(&"192.0.2.77".parse().unwrap()));
+    assert!(!net.contains(&"192.0.3.1".parse().unwrap()));
+    assert!(!net.contains(&"::1".parse().unwrap()));
+    let net: IpNet = "2001:db8::/32".parse().unwrap();
+    assert!(net.contains(&"2001:db8::1".parse().unwrap()));
+    let any: IpNet = "0.0.0.0/0".parse().unwrap();
+    assert!(any.contains(&"203.0.113.9".parse().unwrap()));
+    "192.0.2.0/33".parse::<IpNet>().unwrap_err();
+}
+
+/// An IPv4-mapped IPv6 address matches IPv4 networks, and IPv6 ones too
+#[test]
+fn ipnet_v4_mapped() {
+    let mapped: IpAddr = "::ffff:169.254.169.254".parse().unwrap();
+    let net: IpNet = "169.254.0.0/16".parse().unwrap();
+    assert!(net.contains(&mapped));
+    let net: IpNet = "::ffff:0:0/96".parse().unwrap();
+    assert!(net.contains(&mapped));
+    let net: IpNet = "192.0.2.0/24".parse().unwrap();
+    assert!(!net.contains(&mapped));
+}
+
+#[test]
+fn v4_mapped_denied() {
+    let acl = AccessList::new(Action::Allow);
+    acl.push(Rule::deny(
+        LimitFlags::CONNECT,
+        "169.254.0.0/16".parse().unwrap(),
+    ));
+    let mapped: SocketAddr = "[::ffff:169.254.169.254]:80".parse().unwrap();
+    assert_eq!(acl.check(LimitFlags::CONNECT, &mapped), Action::Deny);
+}
+
+/// The nix-style methods report EACCES
+#[test]
+fn raw_eacces() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+    cap_net.set_access_list(Some(AccessList::new(Action::Deny)));
+
+    let s = socket(
+        AddressFamily::Inet,
+        SockType::Stream,
+        SockFlag::empty(),
+        None,
+    )
+    .unwrap();
+    let SocketAddr::V4(want) = get_local_in() else {
+        unreachable!()
+    };
+    let err = cap_net.bind(&s, &SockaddrIn::from(want)).unwrap_err();
+    assert_eq!(err, Errno::EACCES);
+}
+
+/// Updates through one handle are visible to the agent
+#[test]
+fn runtime_update() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+    let acl = AccessList::new(Action::Deny);
+    cap_net.set_access_list(Some(acl.clone()));
+
+    let want = get_local_in();
+    let _listener = TcpListener::bind(want).unwrap();
+    TcpStream::cap_connect(&mut cap_net, want).unwrap_err();
+    acl.set_default(Action::Allow);
+    TcpStream::cap_connect(&mut cap_net, want).unwrap();
+}
+
+mod cidr_policy {
+    use super::*;
+
+    #[test]
+    fn connect() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let mut policy = CidrPolicy::new();
+        policy.allow_connect("127.0.0.0/8".parse().unwrap());
+        policy.apply(&mut cap_net);
+
+        let want = get_local_in();
+        let _listener = TcpListener::bind(want).unwrap();
+        TcpStream::cap_connect(&mut cap_net, want).unwrap();
+        let outside = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 80);
+        let err = TcpStream::cap_connect(&mut cap_net, outside).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        // Connecting doesn't allow binding
+        let err =
+            TcpListener::cap_bind(&mut cap_net, get_local_in()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn to_access_list() {
+        let mut policy = CidrPolicy::new();
+        policy
+            .allow_bind("::1".parse().unwrap())
+            .allow_connect("192.0.2.0/24".parse().unwrap());
+        let acl = policy.to_access_list();
+        assert_eq!(acl.rules().len(), 2);
+        let inside = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 7).into(), 443);
+        let outside = SocketAddr::new(Ipv4Addr::new(192, 0, 3, 7).into(), 443);
+        assert_eq!(acl.check(LimitFlags::CONNECT, &inside), Action::Allow);
+        assert_eq!(acl.check(LimitFlags::BIND, &inside), Action::Deny);
+        assert_eq!(acl.check(LimitFlags::CONNECT, &outside), Action::Deny);
+        let lo6 =
+            SocketAddr::new(IpAddr::V6(std::net::Ipv6Addr::LOCALHOST), 80);
+        assert_eq!(acl.check(LimitFlags::BIND, &lo6), Action::Allow);
+    }
+
+    #[test]
+    fn port_range() {
+        let mut policy = CidrPolicy::new();
+        policy
+            .allow_connect_ports("192.0.2.0/24".parse().unwrap(), 8000..=8100);
+        let acl = policy.to_access_list();
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 9));
+        let inside = SocketAddr::new(ip, 8050);
+        let outside = SocketAddr::new(ip, 8101);
+        assert_eq!(acl.check(LimitFlags::CONNECT, &inside), Action::Allow);
+        assert_eq!(acl.check(LimitFlags::CONNECT, &outside), Action::Deny);
+    }
+
+    /// A single address with a short port range is enforced by Casper too
+    #[test]
+    fn apply_and_limit_expanded() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let first = get_local_in();
+        let second = get_local_in();
+        let mut policy = CidrPolicy::new();
+        policy
+            .allow_bind_ports(first.ip().into(), first.port()..=second.port());
+        policy
+            .apply_and_limit(&mut cap_net, LimitFlags::BIND)
+            .unwrap();
+
+        TcpListener::cap_bind(&mut cap_net, second).unwrap();
+        // With the access list removed, Casper still refuses other ports
+        cap_net.set_access_list(None);
+        let err =
+            TcpListener::cap_bind(&mut cap_net, get_local_in()).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTCAPABLE));
+    }
+
+    /// A subnet can't be expanded, so only the access list enforces it
+    #[test]
+    fn apply_and_limit_subnet() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let mut policy = CidrPolicy::new();
+        policy.allow_bind_ports("127.0.0.0/8".parse().unwrap(), 1024..=65535);
+        policy
+            .apply_and_limit(&mut cap_net, LimitFlags::BIND)
+            .unwrap();
+
+        TcpListener::cap_bind(&mut cap_net, get_local_in()).unwrap();
+        let err =
+            TcpStream::cap_connect(&mut cap_net, get_local_in()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
+
+mod deny_list {
+    use super::*;
+
+    #[test]
+    fn connect() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let mut deny = DenyList::new();
+        deny.deny_connect("192.0.2.0/24".parse().unwrap());
+        deny.apply(&mut cap_net);
+
+        let want = get_local_in();
+        let _listener = TcpListener::bind(want).unwrap();
+        TcpStream::cap_connect(&mut cap_net, want).unwrap();
+        let denied = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 80);
+        let err = TcpStream::cap_connect(&mut cap_net, denied).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        let inner = err.get_ref().unwrap().downcast_ref::<AccessDenied>();
+        assert_eq!(inner.unwrap().addr, denied);
+    }
+
+    #[test]
+    fn to_access_list() {
+        let mut deny = DenyList::new();
+        deny.deny_bind("0.0.0.0".parse().unwrap())
+            .deny_connect_ports("169.254.0.0/16".parse().unwrap(), 80..=80);
+        let acl = deny.to_access_list();
+        assert_eq!(acl.rules().len(), 2);
+        let ip = IpAddr::V4(Ipv4Addr::new(169, 254, 169, 254));
+        let metadata = SocketAddr::new(ip, 80);
+        let other_port = SocketAddr::new(ip, 443);
+        let any = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 80);
+        assert_eq!(acl.check(LimitFlags::CONNECT, &metadata), Action::Deny);
+        assert_eq!(acl.check(LimitFlags::CONNECT, &other_port), Action::Allow);
+        assert_eq!(acl.check(LimitFlags::BIND, &metadata), Action::Allow);
+        assert_eq!(acl.check(LimitFlags::BIND, &any), Action::Deny);
+    }
+}
+
+mod unix_prefix {
+    use std::{os::unix::net::UnixListener, path::Path};
+
+    use capsicum_net::std::UnixListenerExt;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn bind() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let allowed = TempDir::new().unwrap();
+        let other = TempDir::new().unwrap();
+        let acl = AccessList::new(Action::Allow);
+        acl.allow_unix_prefix(LimitFlags::BIND, allowed.path());
+        cap_net.set_access_list(Some(acl));
+
+        UnixListener::cap_bind(&mut cap_net, allowed.path().join("sock"))
+            .unwrap();
+        let err =
+            UnixListener::cap_bind(&mut cap_net, other.path().join("sock"))
+                .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn check_path() {
+        let acl = AccessList::new(Action::Deny);
+        assert_eq!(
+            acl.check_path(LimitFlags::BIND, Path::new("/tmp/sock")),
+            Action::Allow
+        );
+        acl.allow_unix_prefix(LimitFlags::BIND, "/var/run/app");
+        for (path, want) in [
+            ("/var/run/app/sock", Action::Allow),
+            ("/var/run/app/sub/sock", Action::Allow),
+            ("/var/run/application", Action::Deny),
+            ("/var/run/app/../sock", Action::Deny),
+            ("/tmp/sock", Action::Deny),
+        ] {
+            assert_eq!(
+                acl.check_path(LimitFlags::BIND, Path::new(path)),
+                want,
+                "{path}"
+            );
+        }
+        // Connects weren't restricted
+        assert_eq!(
+            acl.check_path(LimitFlags::CONNECT, Path::new("/tmp/sock")),
+            Action::Allow
+        );
+        acl.clear_unix_prefixes();
+        assert_eq!(
+            acl.check_path(LimitFlags::BIND, Path::new("/tmp/sock")),
+            Action::Allow
+        );
+    }
+}
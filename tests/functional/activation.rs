@@ -0,0 +1,141 @@
+// vim: tw=80
+use std::{
+    env,
+    io,
+    net::{SocketAddr, TcpListener, UdpSocket},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::CommandExt,
+    },
+    process::Command,
+};
+
+use capsicum_net::{activation::Inherited, CasperExt};
+
+use crate::{std::get_local_in, CASPER};
+
+/// Tells the `child` test what to check
+const CHILD_ENV: &str = "CAPSICUM_NET_TEST_ACTIVATION";
+
+/// Run the `child` test in a new process, with `fds` inherited as descriptors
+/// 3, 4, ... and every higher descriptor closed.
+///
+/// `Inherited::from_env` modifies the environment, which isn't safe once other
+/// threads have started, and it may only find sockets once per process.  So
+/// it can't be exercised within the multithreaded test harness itself.
+fn run_child(mode: &str, fds: &[RawFd], envs: &[(&str, String)]) {
+    // Move the sources out of the way first, lest one of them already occupy
+    // a target.  These copies are close-on-exec.
+    let high = fds
+        .iter()
+        .map(|fd| {
+            let r = unsafe { libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, 100) };
+            assert!(r >= 0, "{}", io::Error::last_os_error());
+            // Safe because fcntl just created it
+            unsafe { OwnedFd::from_raw_fd(r) }
+        })
+        .collect::<Vec<_>>();
+    let raw = high.iter().map(AsRawFd::as_raw_fd).collect::<Vec<_>>();
+    let mut cmd = Command::new(env::current_exe().unwrap());
+    cmd.args(["--exact", "activation::child", "--test-threads=1"])
+        .env(CHILD_ENV, mode)
+        .envs(envs.iter().cloned());
+    // Safe because the closure only calls async-signal-safe functions, and
+    // doesn't allocate.
+    unsafe {
+        cmd.pre_exec(move || {
+            for (i, fd) in raw.iter().enumerate() {
+                if libc::dup2(*fd, 3 + i as RawFd) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            libc::closefrom(3 + raw.len() as RawFd);
+            Ok(())
+        });
+    }
+    let status = cmd.status().unwrap();
+    drop(high);
+    assert!(status.success(), "child failed: {status}");
+}
+
+/// The body of the subprocess tests.  It does nothing unless run by
+/// `run_child`.
+#[test]
+fn child() {
+    let Ok(mode) = env::var(CHILD_ENV) else {
+        return;
+    };
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+    match mode.as_str() {
+        "fallback" => {
+            let want: SocketAddr = env::var("WANT").unwrap().parse().unwrap();
+            let mut inherited = Inherited::from_env().unwrap();
+            assert!(inherited.is_empty());
+            assert!(inherited.take_named("http").is_none());
+            let listener = inherited.tcp_listener(&mut cap_net, want).unwrap();
+            assert_eq!(listener.local_addr().unwrap(), want);
+        }
+        "inherited" => {
+            let tcp_addr: SocketAddr =
+                env::var("TCP_ADDR").unwrap().parse().unwrap();
+            let mut inherited = Inherited::from_env().unwrap();
+            assert!(env::var_os("LISTEN_FDS").is_none());
+            assert!(env::var_os("LISTEN_FDNAMES").is_none());
+            assert_eq!(inherited.len(), 2);
+            // The parent still holds the address, so binding a new socket
+            // would fail.
+            let listener =
+                inherited.tcp_listener(&mut cap_net, tcp_addr).unwrap();
+            assert_eq!(listener.local_addr().unwrap(), tcp_addr);
+            assert!(inherited.take_named("dns").is_some());
+            assert!(inherited.is_empty());
+        }
+        "ebadf" => {
+            let e = Inherited::from_env().unwrap_err();
+            assert_eq!(e.raw_os_error(), Some(libc::EBADF));
+            // The descriptor that does exist was not taken, and so not closed
+            assert!(unsafe { libc::fcntl(3, libc::F_GETFD) } >= 0);
+        }
+        _ => panic!("unknown mode {mode}"),
+    }
+}
+
+/// Without any inherited sockets, the getters fall back to cap_bind.
+#[test]
+fn fallback() {
+    run_child("fallback", &[], &[("WANT", get_local_in().to_string())]);
+}
+
+/// Sockets passed with `LISTEN_FDS` are found by address and by name.
+#[test]
+fn inherited() {
+    let tcp_addr = get_local_in();
+    let tcp = TcpListener::bind(tcp_addr).unwrap();
+    let udp = UdpSocket::bind(get_local_in()).unwrap();
+    run_child(
+        "inherited",
+        &[tcp.as_raw_fd(), udp.as_raw_fd()],
+        &[
+            ("LISTEN_FDS", "2".to_owned()),
+            ("LISTEN_FDNAMES", "web:dns".to_owned()),
+            ("TCP_ADDR", tcp_addr.to_string()),
+        ],
+    );
+}
+
+/// A `LISTEN_FDS` count larger than the number of open descriptors is an
+/// error, and none of the descriptors are taken.
+#[test]
+fn too_many() {
+    let tcp = TcpListener::bind(get_local_in()).unwrap();
+    // Casper may open a few descriptors of its own in the child, right after
+    // the inherited one, so claim far more than that.
+    run_child(
+        "ebadf",
+        &[tcp.as_raw_fd()],
+        &[("LISTEN_FDS", "1000".to_owned())],
+    );
+}
@@ -0,0 +1,53 @@
+// vim: tw=80
+use std::net::UdpSocket;
+
+use capsicum_net::{batch, std::UdpSocketExt, CasperExt};
+
+use crate::{std::get_local_in, CASPER};
+
+#[test]
+fn send_and_recv() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let server_addr = get_local_in();
+    let server = UdpSocket::bind(server_addr).unwrap();
+    let client_addr = get_local_in();
+    let client = UdpSocket::bind(client_addr).unwrap();
+    client.cap_connect(&mut cap_net, server_addr).unwrap();
+
+    let sent = batch::send(&client, &[&b"one"[..], b"two", b"three"]).unwrap();
+    assert_eq!(sent, 3);
+
+    let mut bufs = [[0u8; 16]; 4];
+    let mut slices = bufs.iter_mut().map(|b| &mut b[..]).collect::<Vec<_>>();
+    let mut got = Vec::new();
+    while got.len() < 3 {
+        let start = got.len();
+        let r = batch::recv(&server, &mut slices[start..]).unwrap();
+        got.extend(r);
+    }
+    assert_eq!(got.len(), 3);
+    assert!(got
+        .iter()
+        .all(|r| r.addr == Some(client_addr) && !r.truncated));
+    assert_eq!(&bufs[0][..got[0].len], b"one");
+    assert_eq!(&bufs[1][..got[1].len], b"two");
+    assert_eq!(&bufs[2][..got[2].len], b"three");
+}
+
+#[test]
+fn truncated() {
+    let server_addr = get_local_in();
+    let server = UdpSocket::bind(server_addr).unwrap();
+    let client = UdpSocket::bind(get_local_in()).unwrap();
+    client.send_to(b"too long", server_addr).unwrap();
+
+    let mut buf = [0u8; 3];
+    let r = batch::recv(&server, &mut [&mut buf[..]]).unwrap();
+    assert_eq!(r.len(), 1);
+    assert!(r[0].truncated);
+    assert_eq!(&buf, b"too");
+}
@@ -0,0 +1,66 @@
+// vim: tw=80
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    os::unix::net::UnixStream,
+    thread,
+};
+
+use capsicum::{FileRights, Right};
+use capsicum_net::broker::{Broker, Worker};
+
+use crate::std::{get_local_in, get_local_in6};
+
+fn forward(addr: std::net::SocketAddr) {
+    let listener = TcpListener::bind(addr).unwrap();
+    let (broker_end, worker_end) = UnixStream::pair().unwrap();
+    let mut broker = Broker::new(listener);
+    broker.add_worker(broker_end);
+    let jh = thread::spawn(move || broker.forward_one().unwrap());
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    let worker = Worker::new(worker_end);
+    let (mut stream, peer) = worker.recv().unwrap().unwrap();
+    assert_eq!(peer, client.local_addr().unwrap());
+    assert_eq!(jh.join().unwrap(), peer);
+
+    let rights = FileRights::from_file(&stream).unwrap();
+    assert!(rights.is_set(Right::Read));
+    assert!(!rights.is_set(Right::Getpeername));
+
+    client.write_all(b"hello").unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn forward_ipv4() {
+    forward(get_local_in());
+}
+
+#[test]
+fn forward_ipv6() {
+    forward(get_local_in6());
+}
+
+#[test]
+fn broker_gone() {
+    let (broker_end, worker_end) = UnixStream::pair().unwrap();
+    drop(broker_end);
+    assert!(Worker::new(worker_end).recv().unwrap().is_none());
+}
+
+#[test]
+fn no_workers() {
+    let addr = get_local_in();
+    let listener = TcpListener::bind(addr).unwrap();
+    let (broker_end, worker_end) = UnixStream::pair().unwrap();
+    drop(worker_end);
+    let mut broker = Broker::new(listener);
+    broker.add_worker(broker_end);
+    let _client = TcpStream::connect(addr).unwrap();
+    let e = broker.forward_one().unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotConnected);
+    assert_eq!(broker.workers(), 0);
+}
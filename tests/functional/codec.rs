@@ -0,0 +1,41 @@
+// vim: tw=80
+use bytes::Bytes;
+use capsicum_net::{codec::ConnectedUdpFramed, CasperExt};
+use futures::{SinkExt, StreamExt};
+use tokio_util::codec::BytesCodec;
+
+use crate::{std::get_local_in, CASPER};
+
+#[tokio::test]
+async fn send_and_recv() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let peer_addr = get_local_in();
+    let peer = std::net::UdpSocket::bind(peer_addr).unwrap();
+    let local_addr = get_local_in();
+    let std_sock = std::net::UdpSocket::bind(local_addr).unwrap();
+    std_sock.set_nonblocking(true).unwrap();
+    let socket = tokio::net::UdpSocket::from_std(std_sock).unwrap();
+    let mut framed = ConnectedUdpFramed::connect(
+        &mut cap_net,
+        socket,
+        peer_addr,
+        BytesCodec::new(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(framed.peer_addr().unwrap(), peer_addr);
+
+    framed.send(Bytes::from_static(b"ping")).await.unwrap();
+    let mut buf = [0u8; 16];
+    let (n, from) = peer.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"ping");
+    assert_eq!(from, local_addr);
+
+    peer.send_to(b"pong", local_addr).unwrap();
+    let frame = framed.next().await.unwrap().unwrap();
+    assert_eq!(&frame[..], b"pong");
+}
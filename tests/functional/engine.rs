@@ -0,0 +1,102 @@
+// vim: tw=80
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+};
+
+use capsicum_net::{
+    acl::{AccessDenied, Action},
+    engine::{PolicyAgent, PolicyRule},
+    sockaddr,
+    CasperExt,
+    LimitFlags,
+};
+use nix::sys::socket::{AddressFamily, UnixAddr};
+
+use crate::{std::get_local_in, CASPER};
+
+fn policy_agent(default: Action) -> PolicyAgent {
+    let cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+    PolicyAgent::new(cap_net, default)
+}
+
+#[test]
+fn first_match_wins() {
+    let mut agent = policy_agent(Action::Allow);
+    agent
+        .push(
+            PolicyRule::allow(LimitFlags::CONNECT)
+                .net("192.0.2.7".parse().unwrap()),
+        )
+        .push(
+            PolicyRule::deny(LimitFlags::CONNECT)
+                .net("192.0.2.0/24".parse().unwrap()),
+        );
+    let allowed = sockaddr::from_std("192.0.2.7:80".parse().unwrap());
+    let denied = sockaddr::from_std("192.0.2.8:80".parse().unwrap());
+    assert_eq!(agent.check(LimitFlags::CONNECT, &allowed), Action::Allow);
+    assert_eq!(agent.check(LimitFlags::CONNECT, &denied), Action::Deny);
+    assert_eq!(agent.check(LimitFlags::BIND, &denied), Action::Allow);
+}
+
+#[test]
+fn family_and_ports() {
+    let mut agent = policy_agent(Action::Deny);
+    agent.push(
+        PolicyRule::allow(LimitFlags::CONNECT)
+            .family(AddressFamily::Inet6)
+            .ports(443..=443),
+    );
+    let v6 = sockaddr::from_std("[2001:db8::1]:443".parse().unwrap());
+    let v6_other_port = sockaddr::from_std("[2001:db8::1]:80".parse().unwrap());
+    let v4 = sockaddr::from_std("192.0.2.1:443".parse().unwrap());
+    assert_eq!(agent.check(LimitFlags::CONNECT, &v6), Action::Allow);
+    assert_eq!(
+        agent.check(LimitFlags::CONNECT, &v6_other_port),
+        Action::Deny
+    );
+    assert_eq!(agent.check(LimitFlags::CONNECT, &v4), Action::Deny);
+}
+
+#[test]
+fn unix_path() {
+    let mut agent = policy_agent(Action::Deny);
+    agent.push(PolicyRule::allow(LimitFlags::BIND).unix_path("/var/run/app"));
+    let inside = UnixAddr::new("/var/run/app/sock").unwrap();
+    let sibling = UnixAddr::new("/var/run/application").unwrap();
+    let escape = UnixAddr::new("/var/run/app/../other").unwrap();
+    assert_eq!(agent.check(LimitFlags::BIND, &inside), Action::Allow);
+    assert_eq!(agent.check(LimitFlags::BIND, &sibling), Action::Deny);
+    assert_eq!(agent.check(LimitFlags::BIND, &escape), Action::Deny);
+}
+
+#[test]
+fn tcp_stream() {
+    let mut agent = policy_agent(Action::Deny);
+    agent.push(
+        PolicyRule::allow(LimitFlags::CONNECT)
+            .net("127.0.0.0/8".parse().unwrap()),
+    );
+
+    let want = get_local_in();
+    let _listener = TcpListener::bind(want).unwrap();
+    let stream: TcpStream = agent.tcp_stream(want).unwrap();
+    assert_eq!(want, stream.peer_addr().unwrap());
+
+    let outside = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 80);
+    let err = agent.tcp_stream(outside).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    let inner = err.get_ref().unwrap().downcast_ref::<AccessDenied>();
+    assert_eq!(inner.unwrap().addr, outside);
+}
+
+#[test]
+fn tcp_listener_denied() {
+    let mut agent = policy_agent(Action::Allow);
+    agent.push(PolicyRule::deny(LimitFlags::BIND).family(AddressFamily::Inet));
+    let err = agent.tcp_listener(get_local_in()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+}
@@ -0,0 +1,47 @@
+// vim: tw=80
+use std::net::{TcpListener, UdpSocket};
+
+use capsicum_net::{hickory::CapRuntimeProvider, CasperExt};
+use hickory_resolver::proto::{runtime::RuntimeProvider, udp::DnsUdpSocket};
+
+use crate::{std::get_local_in, CASPER};
+
+#[tokio::test]
+async fn bind_udp() {
+    let cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let server_addr = get_local_in();
+    let server = UdpSocket::bind(server_addr).unwrap();
+    let provider = CapRuntimeProvider::new(cap_net);
+    let socket = provider
+        .bind_udp("0.0.0.0:0".parse().unwrap(), server_addr)
+        .await
+        .unwrap();
+    socket.send_to(b"query", server_addr).await.unwrap();
+    let mut buf = [0u8; 16];
+    let (n, client) = server.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"query");
+    server.send_to(b"answer", client).unwrap();
+    let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"answer");
+    assert_eq!(from, server_addr);
+    // Other destinations are refused
+    socket.send_to(b"query", get_local_in()).await.unwrap_err();
+}
+
+#[tokio::test]
+async fn connect_tcp() {
+    let cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let server_addr = get_local_in();
+    let _listener = TcpListener::bind(server_addr).unwrap();
+    let provider = CapRuntimeProvider::new(cap_net);
+    let stream = provider.connect_tcp(server_addr, None, None).await.unwrap();
+    assert_eq!(stream.0.peer_addr().unwrap(), server_addr);
+}
@@ -0,0 +1,35 @@
+// vim: tw=80
+use std::str::FromStr;
+
+use capsicum_net::{hyper_util::CapGaiResolver, CasperExt, LimitFlags};
+use hyper_util::client::legacy::connect::dns::Name;
+use tower_service::Service;
+
+use crate::CASPER;
+
+#[tokio::test]
+async fn localhost() {
+    let cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let mut resolver = CapGaiResolver::new(cap_net);
+    let name = Name::from_str("localhost").unwrap();
+    let addrs = resolver.call(name).await.unwrap().collect::<Vec<_>>();
+    assert!(!addrs.is_empty());
+    assert!(addrs.iter().all(|a| a.ip().is_loopback() && a.port() == 0));
+}
+
+#[tokio::test]
+async fn limited() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
+    let mut resolver = CapGaiResolver::new(cap_net);
+    let name = Name::from_str("localhost").unwrap();
+    assert!(resolver.call(name).await.is_err());
+}
@@ -0,0 +1,56 @@
+// vim: tw=80
+use std::net::{IpAddr, Ipv4Addr};
+
+use capsicum_net::{
+    ifaces::{interfaces, route_lookup, InterfaceAddr, InterfaceFlags},
+    netlink::NetlinkRoute,
+    CasperExt,
+};
+
+use crate::CASPER;
+
+#[test]
+fn loopback() {
+    let ifaces = interfaces().unwrap();
+    let lo0 = ifaces.iter().find(|i| i.name == "lo0").unwrap();
+    assert!(lo0.index > 0);
+    assert!(lo0.flags.contains(InterfaceFlags::IFF_LOOPBACK));
+    let want = InterfaceAddr {
+        addr:       IpAddr::V4(Ipv4Addr::LOCALHOST),
+        prefix_len: 8,
+    };
+    assert!(lo0.addrs.contains(&want));
+}
+
+mod route_lookup {
+    use super::*;
+
+    #[test]
+    fn agent() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let dst = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let route = route_lookup(&mut cap_net, dst).unwrap();
+        assert_eq!(route.name, "lo0");
+        assert_eq!(route.source, dst);
+    }
+
+    #[test]
+    fn netlink() {
+        let mut nl = match NetlinkRoute::new() {
+            Ok(nl) => nl,
+            Err(e) if e.raw_os_error() == Some(libc::EAFNOSUPPORT) => {
+                eprintln!("Skipping test: netlink is not supported");
+                return;
+            }
+            Err(e) => panic!("{e}"),
+        };
+        let dst = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let route = route_lookup(&mut nl, dst).unwrap();
+        assert_eq!(route.name, "lo0");
+        assert_eq!(route.source, dst);
+    }
+}
@@ -0,0 +1,104 @@
+// vim: tw=80
+use std::{
+    io,
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use capsicum_net::{
+    kqueue::{wait_accept, wait_connect},
+    std::TcpListenerExt,
+    CasperExt,
+};
+use nix::{
+    errno::Errno,
+    sys::socket::{socket, AddressFamily, SockFlag, SockType, SockaddrIn},
+};
+
+use crate::{std::get_local_in, CASPER};
+
+#[test]
+fn accept() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let want = get_local_in();
+    let listener = TcpListener::cap_bind(&mut cap_net, want).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let _client = TcpStream::connect(want).unwrap();
+    wait_accept(&listener, Some(Duration::from_secs(5))).unwrap();
+    listener.accept().unwrap();
+}
+
+#[test]
+fn accept_timeout() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let want = get_local_in();
+    let listener = TcpListener::cap_bind(&mut cap_net, want).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let e =
+        wait_accept(&listener, Some(Duration::from_millis(10))).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn connect() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let want = get_local_in();
+    let _listener = TcpListener::bind(want).unwrap();
+    let sock = socket(
+        AddressFamily::Inet,
+        SockType::Stream,
+        SockFlag::SOCK_NONBLOCK,
+        None,
+    )
+    .unwrap();
+    let std::net::SocketAddr::V4(want4) = want else {
+        unreachable!()
+    };
+    match cap_net.connect(&sock, &SockaddrIn::from(want4)) {
+        Ok(()) | Err(Errno::EINPROGRESS) => (),
+        Err(e) => panic!("connect: {e}"),
+    }
+    wait_connect(&sock, Some(Duration::from_secs(5))).unwrap();
+}
+
+#[test]
+fn connect_refused() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    // Nobody is listening on this port
+    let want = get_local_in();
+    let sock = socket(
+        AddressFamily::Inet,
+        SockType::Stream,
+        SockFlag::SOCK_NONBLOCK,
+        None,
+    )
+    .unwrap();
+    let std::net::SocketAddr::V4(want4) = want else {
+        unreachable!()
+    };
+    match cap_net.connect(&sock, &SockaddrIn::from(want4)) {
+        Err(Errno::EINPROGRESS) => {
+            let e =
+                wait_connect(&sock, Some(Duration::from_secs(5))).unwrap_err();
+            assert_eq!(e.raw_os_error(), Some(libc::ECONNREFUSED));
+        }
+        Err(Errno::ECONNREFUSED) => (),
+        r => panic!("unexpected result {r:?}"),
+    }
+}
@@ -0,0 +1,104 @@
+// vim: tw=80
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+use capsicum_net::{ktls, std::TcpStreamExt};
+use rustls::{
+    crypto::ring,
+    pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+    ClientConfig,
+    ClientConnection,
+    RootCertStore,
+    ServerConfig,
+    ServerConnection,
+};
+
+use crate::{std::get_local_in, CASPER};
+
+/// Is this how the kernel says that ktls is unavailable?
+fn ktls_off(e: &io::Error) -> bool {
+    // ENOPROTOOPT if the kernel lacks "options KERN_TLS", or ENOTSUP if the
+    // kern.ipc.tls.enable sysctl is off.
+    matches!(e.raw_os_error(), Some(libc::ENOPROTOOPT | libc::ENOTSUP))
+}
+
+/// Client and server configs that trust a fresh self-signed certificate for
+/// "localhost"
+fn configs() -> (ClientConfig, ServerConfig) {
+    let rcgen::CertifiedKey { cert, key_pair } =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_owned()])
+            .unwrap();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+        key_pair.serialize_der(),
+    ));
+    let provider = Arc::new(ring::default_provider());
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.der().clone()).unwrap();
+
+    let mut client = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client.enable_secret_extraction = true;
+
+    let mut server = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.der().clone()], key)
+        .unwrap();
+    server.enable_secret_extraction = true;
+    // A TLS 1.3 session ticket would reach the client after its handshake
+    // finished, as a record that ktls would have to pass up to userland.
+    server.send_tls13_tickets = 0;
+    (client, server)
+}
+
+/// Complete a handshake over loopback and offload both ends.  Either ktls
+/// carries data between them, or the kernel reports that ktls is off.
+#[test]
+fn loopback() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+    let (client_config, server_config) = configs();
+    let addr = get_local_in();
+    let listener = TcpListener::bind(addr).unwrap();
+
+    let server = thread::spawn(move || {
+        let (mut sock, _) = listener.accept().unwrap();
+        let mut conn = ServerConnection::new(Arc::new(server_config)).unwrap();
+        while conn.is_handshaking() {
+            conn.complete_io(&mut sock).unwrap();
+        }
+        let r = ktls::enable(&sock, conn);
+        (sock, r)
+    });
+
+    let mut sock = TcpStream::cap_connect(&mut cap_net, addr).unwrap();
+    let name = "localhost".try_into().unwrap();
+    let mut conn =
+        ClientConnection::new(Arc::new(client_config), name).unwrap();
+    while conn.is_handshaking() {
+        conn.complete_io(&mut sock).unwrap();
+    }
+    let r = ktls::enable(&sock, conn);
+    let (mut server_sock, server_r) = server.join().unwrap();
+
+    match r.and(server_r) {
+        Ok(()) => {
+            // The kernel now encrypts and decrypts the records by itself.
+            sock.write_all(b"hello").unwrap();
+            let mut buf = [0u8; 5];
+            server_sock.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"hello");
+        }
+        Err(e) => assert!(ktls_off(&e), "{e}"),
+    }
+}
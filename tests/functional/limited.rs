@@ -0,0 +1,114 @@
+// vim: tw=80
+use std::{
+    io,
+    net::{TcpListener, UdpSocket},
+};
+
+use capsicum_net::{CasperExt, LimitFlags};
+
+use crate::{std::get_local_in, CASPER};
+
+mod bind_only {
+    use super::*;
+
+    #[test]
+    fn ok() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+        limit.bind_std(want);
+        let mut binder = limit.apply_bind_only().unwrap();
+
+        let listener = binder.tcp_listener(want).unwrap();
+        assert_eq!(want, listener.local_addr().unwrap());
+        let e = binder.udp_socket(get_local_in()).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+    }
+
+    #[test]
+    fn wrong_flags() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+        let e = limit.apply_bind_only().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+mod connect_only {
+    use super::*;
+
+    #[test]
+    fn ok() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let other = get_local_in();
+        let _server = TcpListener::bind(want).unwrap();
+        let _other_server = TcpListener::bind(other).unwrap();
+        let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+        limit.connect_std(want);
+        let mut connector = limit.apply_connect_only().unwrap();
+
+        let stream = connector.tcp_stream(want).unwrap();
+        assert_eq!(want, stream.peer_addr().unwrap());
+        let e = connector.tcp_stream(other).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+    }
+
+    #[test]
+    fn udp() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+        limit.connect_std(want);
+        let mut connector = limit.apply_connect_only().unwrap();
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        connector.connect_udp(&sock, want).unwrap();
+        assert_eq!(want, sock.peer_addr().unwrap());
+    }
+
+    #[test]
+    fn unix_stream() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sock");
+        let _server = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+        limit.connect_unix(&path);
+        let mut connector = limit.apply_connect_only().unwrap();
+
+        connector.unix_stream(&path).unwrap();
+    }
+
+    #[test]
+    fn wrong_flags() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let limit = cap_net.limit(LimitFlags::BIND).unwrap();
+        let e = limit.apply_connect_only().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,79 @@
+// vim: tw=80
+use std::net::TcpListener;
+
+use capsicum_net::{listeners::ListenerSet, CasperExt, LimitFlags};
+use nix::sys::socket::SockaddrStorage;
+
+use crate::{std::get_local_in, CASPER};
+
+#[test]
+fn add_and_remove() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let a = get_local_in();
+    let b = get_local_in();
+    let mut set = ListenerSet::new();
+    let report = set.update(&mut cap_net, &[a]);
+    assert_eq!(report.added, vec![a]);
+    assert!(report.removed.is_empty());
+    assert!(report.conflicts.is_empty());
+
+    let report = set.update(&mut cap_net, &[b]);
+    assert_eq!(report.added, vec![b]);
+    assert_eq!(report.removed, vec![a]);
+    assert_eq!(set.len(), 1);
+    assert_eq!(set.get(&b).unwrap().local_addr().unwrap(), b);
+    // The removed listener was closed, so its address may be reused.
+    TcpListener::bind(a).unwrap();
+}
+
+#[test]
+fn unchanged() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let a = get_local_in();
+    let mut set = ListenerSet::new();
+    set.update(&mut cap_net, &[a]);
+    let report = set.update(&mut cap_net, &[a]);
+    assert!(report.added.is_empty());
+    assert!(report.removed.is_empty());
+    assert_eq!(set.len(), 1);
+}
+
+#[test]
+fn conflicts() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let in_use = get_local_in();
+    let _other = TcpListener::bind(in_use).unwrap();
+    let allowed = get_local_in();
+    let forbidden = get_local_in();
+    let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+    limit.bind(&SockaddrStorage::from(in_use));
+    limit.bind(&SockaddrStorage::from(allowed));
+    limit.limit().unwrap();
+
+    let mut set = ListenerSet::new();
+    let report = set.update(&mut cap_net, &[in_use, allowed, forbidden]);
+    assert_eq!(report.added, vec![allowed]);
+    assert_eq!(report.conflicts.len(), 2);
+    assert_eq!(report.conflicts[0].addr, in_use);
+    assert_eq!(
+        report.conflicts[0].error.raw_os_error(),
+        Some(libc::EADDRINUSE)
+    );
+    assert_eq!(report.conflicts[1].addr, forbidden);
+    assert_eq!(
+        report.conflicts[1].error.raw_os_error(),
+        Some(libc::ENOTCAPABLE)
+    );
+}
@@ -8,10 +8,47 @@ use capsicum::casper::Casper;
 use capsicum_net::CasperExt;
 use ctor::ctor;
 
+mod acl;
+mod activation;
+mod batch;
+mod broker;
+#[cfg(feature = "codec")]
+mod codec;
+mod engine;
+#[cfg(feature = "hickory")]
+mod hickory;
+#[cfg(feature = "hyper-util")]
+mod hyper_dns;
+mod ifaces;
+mod kqueue;
+#[cfg(feature = "ktls")]
+mod ktls;
+mod limited;
+mod listeners;
+mod netlink;
 mod nix;
+mod ping;
+mod policy;
+mod privdrop;
+mod ratelimit;
+mod reconnect;
+mod registry;
+#[cfg(feature = "reqwest")]
+mod reqwest_dns;
+mod resolver;
+mod route;
+mod sockaddr;
 mod std;
+#[cfg(all(feature = "stream", feature = "tokio"))]
+mod stream;
+mod tcp;
+mod template;
 #[cfg(feature = "tokio")]
 mod tokio;
+#[cfg(feature = "ureq")]
+mod ureq_dns;
+#[cfg(feature = "url")]
+mod url;
 
 // CASPER must be static because it cannot be created after the program becomes
 // multithreaded.
@@ -0,0 +1,48 @@
+// vim: tw=80
+use std::net::{IpAddr, Ipv4Addr};
+
+use capsicum_net::netlink::NetlinkRoute;
+
+/// Open a netlink socket, or return None if the OS doesn't support netlink.
+fn netlink() -> Option<NetlinkRoute> {
+    match NetlinkRoute::new() {
+        Ok(nl) => Some(nl),
+        Err(e) if e.raw_os_error() == Some(libc::EAFNOSUPPORT) => {
+            eprintln!("Skipping test: netlink is not supported");
+            None
+        }
+        Err(e) => panic!("{e}"),
+    }
+}
+
+#[test]
+fn addresses() {
+    let Some(mut nl) = netlink() else { return };
+    let addrs = nl.addresses().unwrap();
+    assert!(addrs
+        .iter()
+        .any(|a| a.addr == IpAddr::V4(Ipv4Addr::LOCALHOST)));
+}
+
+#[test]
+fn links() {
+    let Some(mut nl) = netlink() else { return };
+    let links = nl.links().unwrap();
+    let lo0 = links.iter().find(|l| l.name == "lo0").unwrap();
+    assert!(lo0.index > 0);
+    assert_eq!(
+        lo0.flags & libc::IFF_LOOPBACK as u32,
+        libc::IFF_LOOPBACK as u32
+    );
+}
+
+#[test]
+fn routes() {
+    let Some(mut nl) = netlink() else { return };
+    let routes = nl
+        .routes(Some(nix::sys::socket::AddressFamily::Inet))
+        .unwrap();
+    assert!(routes
+        .iter()
+        .any(|r| r.dst == Some(IpAddr::V4(Ipv4Addr::LOCALHOST))));
+}
@@ -121,9 +121,146 @@ mod bind {
     }
 }
 
+mod bind_new {
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4},
+        os::fd::OwnedFd,
+    };
+
+    use nix::sys::socket::{getsockopt, sockopt, SockProtocol};
+
+    use super::*;
+
+    #[test]
+    fn datagram() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = SocketAddrV4::new(Ipv4Addr::LOCALHOST, crate::next_port());
+        let (s, addr): (OwnedFd, SocketAddr) =
+            cap_net.bind_new(want, SockType::Datagram, None).unwrap();
+        assert_eq!(SocketAddr::from(want), addr);
+        let bound: SockaddrIn = getsockname(s.as_raw_fd()).unwrap();
+        assert_eq!(SockaddrIn::from(want), bound);
+        let ty = getsockopt(&s, sockopt::SockType).unwrap();
+        assert_eq!(ty, SockType::Datagram);
+    }
+
+    #[test]
+    fn protocol() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = SocketAddr::from((Ipv6Addr::LOCALHOST, crate::next_port()));
+        let (s, _): (OwnedFd, SocketAddr) = cap_net
+            .bind_new(want, SockType::Stream, Some(SockProtocol::Tcp))
+            .unwrap();
+        let bound: SockaddrIn6 = getsockname(s.as_raw_fd()).unwrap();
+        assert_eq!(bound.port(), want.port());
+        let ty = getsockopt(&s, sockopt::SockType).unwrap();
+        assert_eq!(ty, SockType::Stream);
+    }
+
+    #[test]
+    fn no_addresses() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let addrs: Vec<SocketAddr> = Vec::new();
+        let err = cap_net
+            .bind_new::<_, OwnedFd>(&addrs[..], SockType::Datagram, None)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
+
 mod limit {
     use super::*;
 
+    mod abandon {
+        use super::*;
+
+        #[test]
+        fn abandon() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::empty()).unwrap();
+            limit.connect(&get_local_in());
+            limit.abandon();
+
+            // The agent is still unlimited
+            cap_net.resolve("localhost", 80).unwrap();
+            assert!(cap_net.applied_limits().is_none());
+        }
+
+        #[test]
+        fn drop() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            for _ in 0..100 {
+                let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+                limit.bind(&get_local_in());
+            }
+            cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
+        }
+    }
+
+    mod enlarge {
+        use capsicum_net::LimitEnlarged;
+
+        use super::*;
+
+        #[test]
+        fn flags() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
+
+            let e = cap_net
+                .limit(LimitFlags::CONNECT | LimitFlags::BIND)
+                .unwrap()
+                .limit()
+                .unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+            let enlarged = e
+                .get_ref()
+                .unwrap()
+                .downcast_ref::<LimitEnlarged>()
+                .unwrap();
+            assert_eq!(enlarged.flags, LimitFlags::CONNECT | LimitFlags::BIND);
+            assert_eq!(enlarged.previous, Some(LimitFlags::CONNECT));
+            assert!(e.to_string().contains("BIND"), "{e}");
+        }
+
+        #[test]
+        fn addresses() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+            limit.connect(&get_local_in());
+            limit.limit().unwrap();
+
+            let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+            limit.connect(&get_local_in());
+            let e = limit.limit().unwrap_err();
+            assert!(e.get_ref().unwrap().is::<LimitEnlarged>());
+        }
+    }
+
     mod bind {
         use super::*;
 
@@ -134,10 +271,14 @@ mod limit {
                 casper.net().unwrap()
             };
             let want = get_local_in();
-            let mut limit = cap_net.limit(LimitFlags::CONNECT);
+            let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
             limit.bind(&want);
-            limit.limit().unwrap();
+            let e = limit.limit().unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(e.to_string().contains("bind"), "{e}");
 
+            // Nothing was applied
+            assert!(cap_net.applied_limits().is_none());
             let s = socket(
                 AddressFamily::Inet,
                 SockType::Stream,
@@ -145,8 +286,26 @@ mod limit {
                 None,
             )
             .unwrap();
-            let e = cap_net.bind(&s, &want).unwrap_err();
-            assert_eq!(Error::ENOTCAPABLE, e);
+            cap_net.bind(&s, &want).unwrap();
+        }
+
+        // A BIND limit without any addresses allows binding anywhere
+        #[test]
+        fn no_entries() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            cap_net.limit(LimitFlags::BIND).unwrap().limit().unwrap();
+
+            let s = socket(
+                AddressFamily::Inet,
+                SockType::Stream,
+                SockFlag::empty(),
+                None,
+            )
+            .unwrap();
+            cap_net.bind(&s, &get_local_in()).unwrap();
         }
 
         #[test]
@@ -157,7 +316,7 @@ mod limit {
             };
             let limit_to = get_local_in();
             let want = get_local_in();
-            let mut limit = cap_net.limit(LimitFlags::BIND);
+            let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
             limit.bind(&limit_to);
             limit.limit().unwrap();
 
@@ -179,7 +338,7 @@ mod limit {
                 casper.net().unwrap()
             };
             let want = get_local_in();
-            let mut limit = cap_net.limit(LimitFlags::BIND);
+            let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
             limit.bind(&want);
             limit.limit().unwrap();
 
@@ -196,6 +355,123 @@ mod limit {
         }
     }
 
+    mod bind_port {
+        use super::*;
+
+        #[test]
+        fn wildcard_included() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let port = crate::next_port();
+            let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+            limit.bind_port(port);
+            limit.limit().unwrap();
+
+            let s4 = socket(
+                AddressFamily::Inet,
+                SockType::Stream,
+                SockFlag::empty(),
+                None,
+            )
+            .unwrap();
+            let want4 = SockaddrIn::new(0, 0, 0, 0, port);
+            cap_net.bind(&s4, &want4).unwrap();
+
+            let s6 = socket(
+                AddressFamily::Inet6,
+                SockType::Stream,
+                SockFlag::empty(),
+                None,
+            )
+            .unwrap();
+            nix::sys::socket::setsockopt(
+                &s6,
+                nix::sys::socket::sockopt::Ipv6V6Only,
+                &true,
+            )
+            .unwrap();
+            let want6 = SockaddrIn6::from(std::net::SocketAddrV6::new(
+                std::net::Ipv6Addr::UNSPECIFIED,
+                port,
+                0,
+                0,
+            ));
+            cap_net.bind(&s6, &want6).unwrap();
+        }
+
+        #[test]
+        fn other_port_excluded() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let port = crate::next_port();
+            let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+            limit.bind_port(port);
+            limit.limit().unwrap();
+
+            let s = socket(
+                AddressFamily::Inet,
+                SockType::Stream,
+                SockFlag::empty(),
+                None,
+            )
+            .unwrap();
+            let want = SockaddrIn::new(0, 0, 0, 0, crate::next_port());
+            let e = cap_net.bind(&s, &want).unwrap_err();
+            assert_eq!(Error::ENOTCAPABLE, e);
+        }
+    }
+
+    mod bind_any_port {
+        use super::*;
+
+        #[test]
+        fn ephemeral() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+            limit.bind_any_port(std::net::Ipv4Addr::LOCALHOST.into());
+            limit.limit().unwrap();
+
+            let s = socket(
+                AddressFamily::Inet,
+                SockType::Stream,
+                SockFlag::empty(),
+                None,
+            )
+            .unwrap();
+            let want = SockaddrIn::new(127, 0, 0, 1, 0);
+            cap_net.bind(&s, &want).unwrap();
+        }
+
+        #[test]
+        fn fixed_port_excluded() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+            limit.bind_any_port(std::net::Ipv4Addr::LOCALHOST.into());
+            limit.limit().unwrap();
+
+            let s = socket(
+                AddressFamily::Inet,
+                SockType::Stream,
+                SockFlag::empty(),
+                None,
+            )
+            .unwrap();
+            let want = SockaddrIn::new(127, 0, 0, 1, crate::next_port());
+            let e = cap_net.bind(&s, &want).unwrap_err();
+            assert_eq!(Error::ENOTCAPABLE, e);
+        }
+    }
+
     mod connect {
         use super::*;
 
@@ -206,10 +482,24 @@ mod limit {
                 casper.net().unwrap()
             };
             let want = get_local_in();
-            let mut limit = cap_net.limit(LimitFlags::BIND);
+            let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
             limit.connect(&want);
-            limit.limit().unwrap();
+            let e = limit.limit().unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(e.to_string().contains("connect"), "{e}");
+            assert!(cap_net.applied_limits().is_none());
+        }
+
+        // A CONNECT limit without any addresses allows connecting anywhere
+        #[test]
+        fn no_entries() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
 
+            let want = get_local_in();
             let _server_sock =
                 std::net::TcpListener::bind(std::net::SocketAddrV4::from(want));
 
@@ -220,8 +510,7 @@ mod limit {
                 None,
             )
             .unwrap();
-            let e = cap_net.connect(&client_sock, &want).unwrap_err();
-            assert_eq!(Error::ENOTCAPABLE, e);
+            cap_net.connect(&client_sock, &want).unwrap();
         }
 
         #[test]
@@ -236,7 +525,7 @@ mod limit {
             let _server_sock =
                 std::net::TcpListener::bind(std::net::SocketAddrV4::from(want));
 
-            let mut limit = cap_net.limit(LimitFlags::CONNECT);
+            let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
             limit.connect(&limit_to);
             limit.limit().unwrap();
 
@@ -273,6 +562,399 @@ mod limit {
             let peer = getpeername(client_sock.as_raw_fd()).unwrap();
             assert_eq!(want, peer);
         }
+
+        #[test]
+        fn host() {
+            let (mut cap_net, mut resolver) = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                (casper.net().unwrap(), casper.net().unwrap())
+            };
+
+            let want = get_local_in();
+            let _server_sock =
+                std::net::TcpListener::bind(std::net::SocketAddrV4::from(want));
+
+            let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+            limit
+                .connect_host(&mut resolver, "127.0.0.1", want.port())
+                .unwrap();
+            limit.limit().unwrap();
+
+            let client_sock = socket(
+                AddressFamily::Inet,
+                SockType::Stream,
+                SockFlag::empty(),
+                None,
+            )
+            .unwrap();
+            cap_net.connect(&client_sock, &want).unwrap();
+
+            let other_sock = socket(
+                AddressFamily::Inet,
+                SockType::Stream,
+                SockFlag::empty(),
+                None,
+            )
+            .unwrap();
+            let other = get_local_in();
+            let e = cap_net.connect(&other_sock, &other).unwrap_err();
+            assert_eq!(Error::ENOTCAPABLE, e);
+        }
+    }
+
+    mod addr2name {
+        use capsicum_net::NameInfoFlags;
+
+        use super::*;
+
+        #[test]
+        fn allowed() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let allowed = SockaddrIn::new(127, 0, 0, 1, 80);
+            let mut limit = cap_net.limit(LimitFlags::ADDR2NAME).unwrap();
+            limit.addr2name(&allowed);
+            limit.limit().unwrap();
+
+            let flags = NameInfoFlags::NUMERICHOST | NameInfoFlags::NUMERICSERV;
+            cap_net.getnameinfo(&allowed, flags).unwrap();
+            let other = SockaddrIn::new(127, 0, 0, 2, 80);
+            cap_net.getnameinfo(&other, flags).unwrap_err();
+        }
+
+        #[test]
+        fn family() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::ADDR2NAME).unwrap();
+            limit.addr2name_family(&[AddressFamily::Inet]);
+            limit.limit().unwrap();
+
+            let flags = NameInfoFlags::NUMERICHOST | NameInfoFlags::NUMERICSERV;
+            cap_net.getnameinfo(&get_local_in(), flags).unwrap();
+            cap_net.getnameinfo(&get_local_in6(), flags).unwrap_err();
+        }
+    }
+
+    mod name2addr {
+        use capsicum_net::addrinfo::AddrInfoHints;
+
+        use super::*;
+
+        #[test]
+        fn empty_families() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::NAME2ADDR).unwrap();
+            limit.name2addr_family(&[]);
+            let e = limit.limit().unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+            cap_net.resolve("localhost", 80).unwrap();
+        }
+
+        #[test]
+        fn family() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::NAME2ADDR).unwrap();
+            limit.name2addr_family(&[AddressFamily::Inet]);
+            limit.limit().unwrap();
+
+            let v4 = AddrInfoHints::new().family(AddressFamily::Inet);
+            let v6 = AddrInfoHints::new().family(AddressFamily::Inet6);
+            cap_net.resolve_with("localhost", 80, &v4).unwrap();
+            cap_net.resolve_with("localhost", 80, &v6).unwrap_err();
+        }
+
+        #[test]
+        fn any_port() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::NAME2ADDR).unwrap();
+            limit.name2addr("localhost", None);
+            limit.limit().unwrap();
+
+            cap_net.resolve("localhost", 22).unwrap();
+            cap_net.resolve("localhost", 80).unwrap();
+            // Even numeric hosts must be listed
+            cap_net.resolve("127.0.0.1", 80).unwrap_err();
+        }
+
+        #[test]
+        fn port() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::NAME2ADDR).unwrap();
+            limit.name2addr("localhost", Some(80));
+            limit.name2addr("127.0.0.1", Some(80));
+            limit.limit().unwrap();
+
+            cap_net.resolve("localhost", 80).unwrap();
+            cap_net.resolve("127.0.0.1", 80).unwrap();
+            cap_net.resolve("localhost", 22).unwrap_err();
+        }
+
+        #[test]
+        fn nul() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::NAME2ADDR).unwrap();
+            limit.name2addr("local\0host", None);
+            let e = limit.limit().unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        }
+
+        /// A name that can't be converted to ASCII could never match
+        #[cfg(feature = "idna")]
+        #[test]
+        fn idna_disallowed() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            let mut limit = cap_net.limit(LimitFlags::NAME2ADDR).unwrap();
+            limit.name2addr("\u{fffd}.invalid", None);
+            let e = limit.limit().unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    mod connectdns {
+        use std::net::{SocketAddr, SocketAddrV4, TcpListener, TcpStream};
+
+        use super::*;
+
+        /// Only addresses that were resolved may be connected to
+        #[test]
+        fn resolve_and_connect() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+            cap_net
+                .limit(LimitFlags::NAME2ADDR | LimitFlags::CONNECTDNS)
+                .unwrap()
+                .limit()
+                .unwrap();
+
+            let unresolved = get_local_in();
+            let _unresolved_listener =
+                TcpListener::bind(SocketAddrV4::from(unresolved)).unwrap();
+            let client_sock = socket(
+                AddressFamily::Inet,
+                SockType::Stream,
+                SockFlag::empty(),
+                None,
+            )
+            .unwrap();
+            cap_net.connect(&client_sock, &unresolved).unwrap_err();
+
+            let want = SocketAddr::from(SocketAddrV4::from(get_local_in()));
+            let _listener = TcpListener::bind(want).unwrap();
+            let fd = cap_net
+                .resolve_and_connect("127.0.0.1", want.port())
+                .unwrap();
+            assert_eq!(TcpStream::from(fd).peer_addr().unwrap(), want);
+        }
+    }
+}
+
+mod limit_flags {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(LimitFlags::empty().to_string(), "");
+        assert_eq!(
+            (LimitFlags::BIND | LimitFlags::NAME2ADDR).to_string(),
+            "bind|name2addr"
+        );
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!("".parse::<LimitFlags>().unwrap(), LimitFlags::empty());
+        assert_eq!(
+            "bind|connect| Name2Addr".parse::<LimitFlags>().unwrap(),
+            LimitFlags::BIND | LimitFlags::CONNECT | LimitFlags::NAME2ADDR
+        );
+        let all = LimitFlags::all();
+        assert_eq!(all.to_string().parse::<LimitFlags>().unwrap(), all);
+        for bad in ["listen", "bind|", "bind,connect"] {
+            let e = bad.parse::<LimitFlags>().unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput, "{bad}");
+        }
+    }
+
+    #[test]
+    fn combinations() {
+        assert_eq!(
+            LimitFlags::DNS,
+            LimitFlags::NAME2ADDR | LimitFlags::ADDR2NAME
+        );
+        assert_eq!(LimitFlags::ALL, LimitFlags::all());
+        assert_eq!(LimitFlags::DNS.to_string(), "name2addr|addr2name");
+        assert_eq!(
+            "dns|bind".parse::<LimitFlags>().unwrap(),
+            LimitFlags::DNS | LimitFlags::BIND
+        );
+    }
+
+    #[test]
+    fn dns_limit() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        cap_net.limit(LimitFlags::DNS).unwrap().limit().unwrap();
+        cap_net.resolve("localhost", 80).unwrap();
+        let s = socket(
+            AddressFamily::Inet,
+            SockType::Stream,
+            SockFlag::empty(),
+            None,
+        )
+        .unwrap();
+        let e = cap_net.bind(&s, &get_local_in()).unwrap_err();
+        assert_eq!(Error::ENOTCAPABLE, e);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let flags = LimitFlags::CONNECT | LimitFlags::CONNECTDNS;
+        let json = serde_json::to_string(&flags).unwrap();
+        assert_eq!(json, r#""connect|connectdns""#);
+        assert_eq!(serde_json::from_str::<LimitFlags>(&json).unwrap(), flags);
+        serde_json::from_str::<LimitFlags>(r#""bogus""#).unwrap_err();
+    }
+}
+
+mod on_denied {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn bind() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen2 = seen.clone();
+        cap_net.on_denied(Some(Box::new(move |op, addr, errno| {
+            seen2.lock().unwrap().push((op, *addr, errno));
+        })));
+        let allowed = get_local_in();
+        let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+        limit.bind(&allowed);
+        limit.limit().unwrap();
+
+        let s = socket(
+            AddressFamily::Inet,
+            SockType::Stream,
+            SockFlag::empty(),
+            None,
+        )
+        .unwrap();
+        cap_net.bind(&s, &allowed).unwrap();
+        assert!(seen.lock().unwrap().is_empty());
+
+        let s = socket(
+            AddressFamily::Inet,
+            SockType::Stream,
+            SockFlag::empty(),
+            None,
+        )
+        .unwrap();
+        let denied = get_local_in();
+        let e = cap_net.bind(&s, &denied).unwrap_err();
+        assert_eq!(Error::ENOTCAPABLE, e);
+        let want = (
+            LimitFlags::BIND,
+            std::net::SocketAddr::V4(denied.into()),
+            Error::ENOTCAPABLE,
+        );
+        assert_eq!(&seen.lock().unwrap()[..], &[want]);
+    }
+
+    #[test]
+    fn removed() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let seen = Arc::new(Mutex::new(0));
+        let seen2 = seen.clone();
+        cap_net.on_denied(Some(Box::new(move |_, _, _| {
+            *seen2.lock().unwrap() += 1;
+        })));
+        cap_net.on_denied(None);
+        cap_net.limit(LimitFlags::BIND).unwrap().limit().unwrap();
+
+        let s = socket(
+            AddressFamily::Inet,
+            SockType::Stream,
+            SockFlag::empty(),
+            None,
+        )
+        .unwrap();
+        let e = cap_net.connect(&s, &get_local_in()).unwrap_err();
+        assert_eq!(Error::ENOTCAPABLE, e);
+        assert_eq!(*seen.lock().unwrap(), 0);
+    }
+}
+
+mod allowed_ops {
+    use super::*;
+
+    #[test]
+    fn unlimited() {
+        let cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        assert_eq!(cap_net.allowed_ops(), LimitFlags::ALL);
+    }
+
+    #[test]
+    fn narrowed() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let flags = LimitFlags::BIND | LimitFlags::CONNECT;
+        cap_net.limit(flags).unwrap().limit().unwrap();
+        assert_eq!(cap_net.allowed_ops(), flags);
+        cap_net.limit(LimitFlags::BIND).unwrap().limit().unwrap();
+        assert_eq!(cap_net.allowed_ops(), LimitFlags::BIND);
+    }
+
+    /// A limit that fails to apply doesn't change the allowed operations
+    #[test]
+    fn failed() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        cap_net.limit(LimitFlags::BIND).unwrap().limit().unwrap();
+        cap_net.limit(LimitFlags::ALL).unwrap().limit().unwrap_err();
+        assert_eq!(cap_net.allowed_ops(), LimitFlags::BIND);
     }
 }
 
@@ -370,3 +1052,232 @@ mod connect {
         assert_eq!(want, peer);
     }
 }
+
+mod resolve {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use super::*;
+
+    #[test]
+    fn numeric() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let addrs = cap_net.resolve("127.0.0.1", 80).unwrap();
+        assert_eq!(addrs, vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 80))]);
+        let addrs = cap_net.resolve("::1", 443).unwrap();
+        assert_eq!(addrs, vec![SocketAddr::from((Ipv6Addr::LOCALHOST, 443))]);
+    }
+
+    #[test]
+    fn localhost() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let addrs = cap_net.resolve("localhost", 22).unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|a| a.ip().is_loopback() && a.port() == 22));
+    }
+
+    #[test]
+    fn with_hints() {
+        use capsicum_net::addrinfo::{AddrInfoFlags, AddrInfoHints};
+
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let v4 = AddrInfoHints::new()
+            .family(AddressFamily::Inet)
+            .socktype(SockType::Datagram);
+        let addrs = cap_net.resolve_with("localhost", 53, &v4).unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|a| a.is_ipv4()));
+        cap_net.resolve_with("::1", 53, &v4).unwrap_err();
+
+        let numeric = AddrInfoHints::new().flags(AddrInfoFlags::NUMERICHOST);
+        cap_net.resolve_with("localhost", 53, &numeric).unwrap_err();
+        let addrs = cap_net.resolve_with("::1", 53, &numeric).unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|a| *a == (Ipv6Addr::LOCALHOST, 53).into()));
+    }
+
+    #[test]
+    fn getaddrinfo() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let hints = capsicum_net::addrinfo::AddrInfoHints::new();
+        let results = cap_net
+            .getaddrinfo("127.0.0.1", 80, &hints)
+            .unwrap()
+            .collect::<Vec<_>>();
+        // Without a socket type hint, each protocol gets its own result
+        assert!(results.len() > 1);
+        for ai in results.iter() {
+            assert_eq!(ai.family, AddressFamily::Inet);
+            assert_eq!(
+                ai.addr.as_sockaddr_in(),
+                Some(&SockaddrIn::new(127, 0, 0, 1, 80))
+            );
+        }
+        assert!(results
+            .iter()
+            .any(|ai| ai.socktype == Some(SockType::Stream)
+                && ai.protocol == libc::IPPROTO_TCP));
+        assert!(results
+            .iter()
+            .any(|ai| ai.socktype == Some(SockType::Datagram)
+                && ai.protocol == libc::IPPROTO_UDP));
+    }
+
+    #[test]
+    fn getaddrinfo_service() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let hints = capsicum_net::addrinfo::AddrInfoHints::new()
+            .socktype(SockType::Datagram);
+        for (service, port) in [("domain", 53), ("http", 80), ("8080", 8080)] {
+            let results = cap_net
+                .getaddrinfo_service("127.0.0.1", service, &hints)
+                .unwrap()
+                .collect::<Vec<_>>();
+            assert!(!results.is_empty());
+            for ai in results.iter() {
+                assert_eq!(
+                    ai.addr.as_sockaddr_in(),
+                    Some(&SockaddrIn::new(127, 0, 0, 1, port)),
+                    "{service}"
+                );
+            }
+        }
+        let e = cap_net
+            .getaddrinfo_service("127.0.0.1", "nonesuch", &hints)
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    }
+}
+
+mod getnameinfo {
+    use capsicum_net::NameInfoFlags;
+
+    use super::*;
+
+    #[test]
+    fn numeric() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let sin = SockaddrIn::new(127, 0, 0, 1, 8080);
+        let flags = NameInfoFlags::NUMERICHOST | NameInfoFlags::NUMERICSERV;
+        let (host, serv) = cap_net.getnameinfo(&sin, flags).unwrap();
+        assert_eq!(host, "127.0.0.1");
+        assert_eq!(serv, "8080");
+    }
+
+    #[test]
+    fn localhost() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let sin = SockaddrIn::new(127, 0, 0, 1, 22);
+        let (host, serv) =
+            cap_net.getnameinfo(&sin, NameInfoFlags::empty()).unwrap();
+        assert!(!host.is_empty());
+        assert_eq!(serv, "ssh");
+    }
+
+    #[test]
+    fn not_permitted() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        cap_net
+            .limit(LimitFlags::NAME2ADDR)
+            .unwrap()
+            .limit()
+            .unwrap();
+        let sin = SockaddrIn::new(127, 0, 0, 1, 22);
+        cap_net
+            .getnameinfo(&sin, NameInfoFlags::NUMERICHOST)
+            .unwrap_err();
+    }
+}
+
+#[cfg(feature = "deprecated-dns")]
+mod gethostbyname {
+    use std::{
+        io,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    };
+
+    use super::*;
+
+    #[test]
+    fn numeric() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let he = cap_net.gethostbyname("127.0.0.1").unwrap();
+        assert_eq!(he.addrs, vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+    }
+
+    #[test]
+    fn inet6() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let he = cap_net.gethostbyname2("::1", AddressFamily::Inet6).unwrap();
+        assert_eq!(he.addrs, vec![IpAddr::V6(Ipv6Addr::LOCALHOST)]);
+    }
+
+    #[test]
+    fn not_found() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let e = cap_net.gethostbyname("nonexistent.invalid").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+    /// The legacy functions need their own limit flag
+    #[test]
+    fn limited() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        cap_net
+            .limit(LimitFlags::DEPRECATED_NAME2ADDR)
+            .unwrap()
+            .limit()
+            .unwrap();
+        cap_net.gethostbyname("127.0.0.1").unwrap();
+        cap_net.resolve("127.0.0.1", 80).unwrap_err();
+    }
+
+    #[test]
+    fn not_permitted() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        cap_net
+            .limit(LimitFlags::NAME2ADDR)
+            .unwrap()
+            .limit()
+            .unwrap();
+        cap_net.gethostbyname("127.0.0.1").unwrap_err();
+        cap_net.resolve("127.0.0.1", 80).unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+// vim: tw=80
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use capsicum_net::{ping::Pinger, CasperExt};
+
+use crate::CASPER;
+
+fn ping(dst: IpAddr) {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Skipping test: must be root");
+        return;
+    }
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let mut pinger = Pinger::new(&mut cap_net, dst).unwrap();
+    let r0 = pinger.ping(Duration::from_secs(5)).unwrap();
+    let r1 = pinger.ping(Duration::from_secs(5)).unwrap();
+    assert_eq!(r0.seq, 0);
+    assert_eq!(r1.seq, 1);
+    assert_eq!(r0.size, 56);
+}
+
+#[test]
+fn ipv4() {
+    ping(IpAddr::V4(Ipv4Addr::LOCALHOST));
+}
+
+#[test]
+fn ipv6() {
+    ping(IpAddr::V6(Ipv6Addr::LOCALHOST));
+}
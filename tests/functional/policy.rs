@@ -0,0 +1,372 @@
+// vim: tw=80
+use std::net::{TcpListener, TcpStream};
+
+use capsicum_net::{
+    policy::{BindTarget, Endpoint, Host, NetPolicy},
+    std::{TcpListenerExt, TcpStreamExt},
+    CasperExt,
+    LimitFlags,
+};
+
+use crate::{std::get_local_in, CASPER};
+
+#[test]
+fn parse() {
+    let policy: NetPolicy = "bind 127.0.0.1:8080; bind *:53; connect \
+                             *.internal:443; connect [::1]:*; dns"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        policy.bind,
+        vec![
+            BindTarget::Addr("127.0.0.1:8080".parse().unwrap()),
+            BindTarget::Port(53),
+        ]
+    );
+    assert_eq!(
+        policy.connect,
+        vec![
+            Endpoint {
+                host: Host::Suffix(".internal".to_owned()),
+                port: Some(443),
+            },
+            Endpoint {
+                host: Host::Addr("::1".parse().unwrap()),
+                port: None,
+            },
+        ]
+    );
+    assert!(policy.dns);
+    assert!(!policy.rdns);
+    assert_eq!(
+        policy.flags(),
+        LimitFlags::BIND | LimitFlags::CONNECT | LimitFlags::NAME2ADDR
+    );
+}
+
+#[test]
+fn display_roundtrip() {
+    let s = "bind 127.0.0.1:8080; bind *:53; connect db.example.com:5432; \
+             connect [::1]:*; dns; resolve example.org; rdns";
+    let policy: NetPolicy = s.parse().unwrap();
+    assert_eq!(policy.to_string(), s);
+    assert_eq!(policy.to_string().parse::<NetPolicy>().unwrap(), policy);
+}
+
+#[test]
+fn compact() {
+    let policy: NetPolicy = "bind=127.0.0.1:8080,[::1]:8080; \
+                             connect=10.0.0.5:5432; dns=example.com"
+        .parse()
+        .unwrap();
+    let long: NetPolicy = "bind 127.0.0.1:8080; bind [::1]:8080; connect \
+                           10.0.0.5:5432; resolve example.com"
+        .parse()
+        .unwrap();
+    assert_eq!(policy, long);
+    assert!(!policy.dns);
+}
+
+#[test]
+fn compact_mixed() {
+    let policy: NetPolicy = "connect = a.example:80 , b.example:80; dns; rdns"
+        .parse()
+        .unwrap();
+    assert_eq!(policy.connect.len(), 2);
+    assert!(policy.dns);
+    assert!(policy.rdns);
+}
+
+#[test]
+fn names() {
+    let policy: NetPolicy = "resolve db.example.com; resolve cache.example.com"
+        .parse()
+        .unwrap();
+    assert!(!policy.dns);
+    assert_eq!(policy.names, vec!["db.example.com", "cache.example.com"]);
+    assert_eq!(policy.flags(), LimitFlags::NAME2ADDR);
+}
+
+#[cfg(feature = "serde")]
+mod serde {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let policy: NetPolicy = "bind 127.0.0.1:8080; bind *:53; connect \
+                                 *.internal:443; resolve db.internal; rdns"
+            .parse()
+            .unwrap();
+        let json = serde_json::to_string(&policy).unwrap();
+        assert_eq!(
+            json,
+            r#"{"bind":["127.0.0.1:8080","*:53"],"connect":["*.internal:443"],"dns":false,"names":["db.internal"],"rdns":true}"#
+        );
+        assert_eq!(serde_json::from_str::<NetPolicy>(&json).unwrap(), policy);
+    }
+
+    #[test]
+    fn defaults() {
+        let policy: NetPolicy =
+            serde_json::from_str(r#"{"connect": ["[::1]:*"]}"#).unwrap();
+        assert_eq!(policy, "connect [::1]:*".parse().unwrap());
+    }
+
+    #[test]
+    fn errors() {
+        for bad in [
+            r#"{"bind": ["localhost:80"]}"#,
+            r#"{"connect": ["foo*:80"]}"#,
+            r#"{"listen": ["127.0.0.1:80"]}"#,
+        ] {
+            serde_json::from_str::<NetPolicy>(bad).unwrap_err();
+        }
+    }
+}
+
+#[test]
+fn empty() {
+    let policy: NetPolicy = " ; ".parse().unwrap();
+    assert_eq!(policy, NetPolicy::default());
+    assert_eq!(policy.flags(), LimitFlags::empty());
+}
+
+#[test]
+fn no_network() {
+    assert_eq!(NetPolicy::no_network().flags(), LimitFlags::empty());
+}
+
+#[test]
+fn loopback_only() {
+    let policy = NetPolicy::loopback_only(&[80, 8080]);
+    assert_eq!(
+        policy.to_string(),
+        "bind 127.0.0.1:80; bind 127.0.0.1:8080; bind [::1]:80; bind \
+         [::1]:8080; connect 127.0.0.1:80; connect 127.0.0.1:8080; connect \
+         [::1]:80; connect [::1]:8080"
+    );
+    assert!(policy.permits_connect("::1", 8080));
+    assert!(!policy.permits_connect("192.0.2.1", 80));
+}
+
+#[test]
+fn outbound_https_only() {
+    let policy =
+        NetPolicy::outbound_https_only(["example.com", "*.internal", "::1"])
+            .unwrap();
+    assert_eq!(
+        policy.to_string(),
+        "connect example.com:443; connect *.internal:443; connect [::1]:443"
+    );
+    assert_eq!(policy.flags(), LimitFlags::CONNECT);
+    let e = NetPolicy::outbound_https_only(["foo*"]).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    let e = NetPolicy::outbound_https_only([""]).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn errors() {
+    for bad in [
+        "listen 127.0.0.1:80",
+        "bind",
+        "bind localhost:80",
+        "bind 127.0.0.1",
+        "bind 127.0.0.1:99999",
+        "connect foo*.example.com:80",
+        "connect *example.com:80",
+        "connect [::1:80",
+        "dns please",
+        "resolve",
+        "bind=",
+        "bind=127.0.0.1:80,",
+        "rdns=example.com",
+        "listen=127.0.0.1:80",
+        "bind 127.0.0.1:80 127.0.0.1:81",
+    ] {
+        let e = bad.parse::<NetPolicy>().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput, "{bad}");
+    }
+}
+
+#[cfg(feature = "serde")]
+mod toml {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn from_toml() {
+        let s = r##"
+            # A comment
+            bind = ["127.0.0.1:8080", '*:53'] # trailing comment
+            connect = [
+                "db.example.com:5432",  # a "#" inside a comment
+                "[::1]:*",
+            ]
+            names = ["db.example.com"]
+            rdns = true
+        "##;
+        let policy = NetPolicy::from_toml(s).unwrap();
+        let want: NetPolicy = "bind 127.0.0.1:8080; bind *:53; connect \
+                               db.example.com:5432; connect [::1]:*; resolve \
+                               db.example.com; rdns"
+            .parse()
+            .unwrap();
+        assert_eq!(policy, want);
+    }
+
+    /// Escapes, literal strings, '#' within strings, and nested whitespace
+    #[test]
+    fn strings() {
+        let s = r##"
+            names = [
+                "\u0064b.example.com",
+                'C:\not-an-escape',
+                "has#hash",
+            ]
+            dns = false
+        "##;
+        let policy = NetPolicy::from_toml(s).unwrap();
+        assert_eq!(
+            policy.names,
+            ["db.example.com", "C:\\not-an-escape", "has#hash"]
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let policy = NetPolicy::from_toml("# nothing\n\n").unwrap();
+        assert_eq!(policy, NetPolicy::no_network());
+    }
+
+    #[test]
+    fn errors() {
+        for bad in [
+            "listen = []",
+            "bind",
+            "bind = \"127.0.0.1:80\"",
+            "bind = [\"localhost:80\"]",
+            "bind = [\"127.0.0.1:80\" \"127.0.0.1:81\"]",
+            "bind = [\"127.0.0.1:80\"",
+            "dns = yes",
+            "dns = true\ndns = false",
+            "[net]",
+        ] {
+            let e = NetPolicy::from_toml(bad).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput, "{bad}");
+        }
+    }
+
+    #[test]
+    fn from_path() {
+        let mut f = tempfile::NamedTempFile::new().unwrap();
+        writeln!(f, "connect = [\"192.0.2.1:443\"]\ndns = true").unwrap();
+        let policy = NetPolicy::from_path(f.path()).unwrap();
+        assert_eq!(policy, "connect 192.0.2.1:443; dns".parse().unwrap());
+
+        writeln!(f, "rdns = 1").unwrap();
+        let e = NetPolicy::from_path(f.path()).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(e.to_string().contains("line 3"), "{e}");
+    }
+}
+
+#[test]
+fn permits_connect() {
+    let policy: NetPolicy = "connect *.internal:443; connect 192.0.2.1:*; \
+                             connect exact.org:80"
+        .parse()
+        .unwrap();
+    assert!(policy.permits_connect("db.internal", 443));
+    assert!(policy.permits_connect("a.b.INTERNAL.", 443));
+    assert!(!policy.permits_connect("internal", 443));
+    assert!(!policy.permits_connect("db.internal", 80));
+    assert!(policy.permits_connect("192.0.2.1", 22));
+    assert!(policy.permits_connect("exact.org", 80));
+    assert!(!policy.permits_connect("www.exact.org", 80));
+}
+
+#[test]
+fn apply() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let bind_ok = get_local_in();
+    let target = get_local_in();
+    let other = get_local_in();
+    let _target_listener = TcpListener::bind(target).unwrap();
+    let _other_listener = TcpListener::bind(other).unwrap();
+    let policy: NetPolicy =
+        format!("bind {bind_ok}; connect {target}").parse().unwrap();
+    policy.apply(&mut cap_net).unwrap();
+
+    TcpListener::cap_bind(&mut cap_net, bind_ok).unwrap();
+    let e = TcpListener::cap_bind(&mut cap_net, get_local_in()).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+    TcpStream::cap_connect(&mut cap_net, target).unwrap();
+    let e = TcpStream::cap_connect(&mut cap_net, other).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+    // Name resolution wasn't allowed
+    cap_net.resolve("localhost", 80).unwrap_err();
+}
+
+#[test]
+fn apply_wildcard() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let target = get_local_in();
+    let _listener = TcpListener::bind(target).unwrap();
+    let policy: NetPolicy = "connect *.internal:443".parse().unwrap();
+    let e = policy.apply(&mut cap_net).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    // Nothing was applied
+    TcpListener::cap_bind(&mut cap_net, get_local_in()).unwrap();
+
+    policy.apply_with_wildcards(&mut cap_net).unwrap();
+    TcpStream::cap_connect(&mut cap_net, target).unwrap();
+    let e = TcpListener::cap_bind(&mut cap_net, get_local_in()).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+}
+
+#[test]
+fn apply_names() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let policy: NetPolicy = "resolve localhost".parse().unwrap();
+    policy.apply(&mut cap_net).unwrap();
+
+    cap_net.resolve("localhost", 80).unwrap();
+    cap_net.resolve("localhost.", 80).unwrap_err();
+}
+
+#[test]
+fn split() {
+    let mut casper = CASPER.get().unwrap().lock().unwrap();
+    let cap_net = casper.net().unwrap();
+
+    let bind_ok = get_local_in();
+    let policies = [
+        "dns".parse().unwrap(),
+        format!("bind {bind_ok}").parse().unwrap(),
+    ];
+    let mut agents = cap_net.split(&mut casper, &policies).unwrap();
+    drop(casper);
+    assert_eq!(agents.len(), 2);
+    let mut binder = agents.pop().unwrap();
+    let mut resolver = agents.pop().unwrap();
+
+    resolver.resolve("localhost", 80).unwrap();
+    let e = TcpListener::cap_bind(&mut resolver, get_local_in()).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+    TcpListener::cap_bind(&mut binder, bind_ok).unwrap();
+    binder.resolve("localhost", 80).unwrap_err();
+}
@@ -0,0 +1,30 @@
+// vim: tw=80
+use capsicum_net::privdrop::Startup;
+
+use crate::std::{get_local_in, get_local_in6};
+
+/// Binding without dropping privileges or entering capability mode is
+/// harmless to the rest of the test suite.
+#[test]
+fn bind_only() {
+    let tcp = get_local_in();
+    let udp = get_local_in6();
+    let listeners = Startup::new()
+        .tcp(tcp)
+        .udp(udp)
+        .enter_capability_mode(false)
+        .run()
+        .unwrap();
+    assert_eq!(listeners.tcp.len(), 1);
+    assert_eq!(listeners.tcp[0].local_addr().unwrap(), tcp);
+    assert_eq!(listeners.udp.len(), 1);
+    assert_eq!(listeners.udp[0].local_addr().unwrap(), udp);
+}
+
+#[test]
+fn no_such_user() {
+    let err = Startup::new()
+        .user_name("no-such-user-capsicum-net")
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
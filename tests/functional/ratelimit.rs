@@ -0,0 +1,127 @@
+// vim: tw=80
+use std::{
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use capsicum_net::{
+    policy::NetPolicy,
+    ratelimit::RateLimiter,
+    std::TcpStreamExt,
+    CasperExt,
+};
+
+use crate::{std::get_local_in, CASPER};
+
+#[test]
+fn connect() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+    cap_net.set_connect_rate_limit(Some(RateLimiter::new(10, 1)));
+
+    let want = get_local_in();
+    let _listener = TcpListener::bind(want).unwrap();
+    let start = Instant::now();
+    let _streams = (0..3)
+        .map(|_| TcpStream::cap_connect(&mut cap_net, want).unwrap())
+        .collect::<Vec<_>>();
+    // The first connection is free, but the next two must wait 100 ms each.
+    assert!(start.elapsed() >= Duration::from_millis(190));
+}
+
+#[test]
+fn unlimited() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+    cap_net.set_connect_rate_limit(Some(RateLimiter::new(1, 1)));
+    cap_net.set_connect_rate_limit(None);
+
+    let want = get_local_in();
+    let _listener = TcpListener::bind(want).unwrap();
+    let start = Instant::now();
+    let _streams = (0..3)
+        .map(|_| TcpStream::cap_connect(&mut cap_net, want).unwrap())
+        .collect::<Vec<_>>();
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+// Split agents share one budget, rather than each getting its own.
+#[test]
+fn split() {
+    let want = get_local_in();
+    let _listener = TcpListener::bind(want).unwrap();
+    let mut agents = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        let mut cap_net = casper.net().unwrap();
+        cap_net.set_connect_rate_limit(Some(RateLimiter::new(10, 1)));
+        let policy: NetPolicy = format!("connect {want}").parse().unwrap();
+        cap_net
+            .split(&mut casper, &[policy.clone(), policy])
+            .unwrap()
+    };
+
+    let start = Instant::now();
+    let _streams = agents
+        .iter_mut()
+        .map(|agent| TcpStream::cap_connect(agent, want).unwrap())
+        .collect::<Vec<_>>();
+    assert!(start.elapsed() >= Duration::from_millis(90));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn connect_async() {
+    use capsicum_net::tokio::cap_connect;
+
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+    cap_net.set_connect_rate_limit(Some(RateLimiter::new(10, 1)));
+
+    let want = get_local_in();
+    let _listener = TcpListener::bind(want).unwrap();
+    let start = Instant::now();
+    let _s0 = cap_connect(&mut cap_net, want).await.unwrap();
+    let _s1 = cap_connect(&mut cap_net, want).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(90));
+}
+
+// Agents that share a limiter wait for it without blocking the runtime's other
+// tasks.
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn split_async() {
+    use capsicum_net::tokio::cap_connect;
+
+    let want = get_local_in();
+    let _listener = TcpListener::bind(want).unwrap();
+    let mut agents = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        let mut cap_net = casper.net().unwrap();
+        cap_net.set_connect_rate_limit(Some(RateLimiter::new(10, 1)));
+        let policy: NetPolicy = format!("connect {want}").parse().unwrap();
+        cap_net
+            .split(&mut casper, &[policy.clone(), policy])
+            .unwrap()
+    };
+    let (first, second) = agents.split_at_mut(1);
+
+    let start = Instant::now();
+    let (s0, s1, ticked) = tokio::join!(
+        cap_connect(&mut first[0], want),
+        cap_connect(&mut second[0], want),
+        async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            start.elapsed()
+        }
+    );
+    s0.unwrap();
+    s1.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(90));
+    assert!(ticked < Duration::from_millis(90), "{ticked:?}");
+}
@@ -0,0 +1,119 @@
+// vim: tw=80
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    thread,
+    time::Duration,
+};
+
+use capsicum_net::{
+    reconnect::{Backoff, ReconnectingStream},
+    CasperExt,
+};
+
+use crate::{std::get_local_in, CASPER};
+
+/// The server drops the first connection, and the client reconnects.
+#[test]
+fn reconnect() {
+    let cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let want = get_local_in();
+    let listener = TcpListener::bind(want).unwrap();
+    let server = thread::spawn(move || {
+        let (mut c1, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 1];
+        c1.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"a");
+        drop(c1);
+        let (mut c2, _) = listener.accept().unwrap();
+        c2.write_all(b"b").unwrap();
+    });
+
+    let mut stream = ReconnectingStream::new(cap_net, want);
+    stream.write_all(b"a").unwrap();
+    let mut buf = [0u8; 1];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"b");
+    assert_eq!(stream.reconnects(), 1);
+    server.join().unwrap();
+}
+
+/// Give up after the configured number of attempts
+#[test]
+fn refused() {
+    let cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    // Nobody is listening
+    let want = get_local_in();
+    let mut stream = ReconnectingStream::new(cap_net, want);
+    stream.set_backoff(Backoff {
+        initial:      Duration::from_millis(1),
+        max:          Duration::from_millis(10),
+        max_attempts: Some(3),
+    });
+    let err = stream.write(b"a").unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ECONNREFUSED));
+}
+
+#[cfg(feature = "tokio")]
+mod async_stream {
+    use capsicum_net::reconnect::AsyncReconnectingStream;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reconnect() {
+        let cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let listener = TcpListener::bind(want).unwrap();
+        let server = thread::spawn(move || {
+            let (mut c1, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1];
+            c1.read_exact(&mut buf).unwrap();
+            drop(c1);
+            let (mut c2, _) = listener.accept().unwrap();
+            c2.write_all(b"b").unwrap();
+        });
+
+        let mut stream = AsyncReconnectingStream::new(cap_net, want);
+        stream.write_all(b"a").await.unwrap();
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"b");
+        assert_eq!(stream.reconnects(), 1);
+        server.join().unwrap();
+    }
+
+    /// Give up after the configured number of attempts
+    #[tokio::test]
+    async fn refused() {
+        let cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let mut stream = AsyncReconnectingStream::new(cap_net, want);
+        stream.set_backoff(Backoff {
+            initial:      Duration::from_millis(1),
+            max:          Duration::from_millis(10),
+            max_attempts: Some(3),
+        });
+        let err = stream.write(b"a").await.unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ECONNREFUSED));
+        // The stream can be spawned onto a multi-threaded runtime
+        tokio::spawn(async move { drop(stream) }).await.unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+// vim: tw=80
+use std::net::{TcpListener, TcpStream};
+
+use capsicum_net::{
+    registry::SocketRegistry,
+    std::{TcpListenerExt, TcpStreamExt},
+    CasperExt,
+    LimitFlags,
+};
+
+use crate::{std::get_local_in, CASPER};
+
+#[test]
+fn bind_revoked() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let addr = get_local_in();
+    let listeners = SocketRegistry::new()
+        .tcp_listener(addr)
+        .open(&mut cap_net)
+        .unwrap();
+    assert_eq!(listeners.tcp[0].local_addr().unwrap(), addr);
+
+    let e = TcpListener::cap_bind(&mut cap_net, get_local_in()).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+    let e = TcpStream::cap_connect(&mut cap_net, addr).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+}
+
+#[test]
+fn connect_targets() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let target = get_local_in();
+    let other = get_local_in();
+    let _target_listener = TcpListener::bind(target).unwrap();
+    let _other_listener = TcpListener::bind(other).unwrap();
+    SocketRegistry::new()
+        .connect_target(target)
+        .open(&mut cap_net)
+        .unwrap();
+
+    TcpStream::cap_connect(&mut cap_net, target).unwrap();
+    let e = TcpStream::cap_connect(&mut cap_net, other).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+}
+
+/// Name resolution is left alone
+#[test]
+fn dns_kept() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    SocketRegistry::new()
+        .tcp_listener(get_local_in())
+        .open(&mut cap_net)
+        .unwrap();
+    let ops = cap_net.allowed_ops();
+    assert!(ops.contains(LimitFlags::DNS));
+    assert!(!ops.intersects(LimitFlags::BIND | LimitFlags::CONNECT));
+    cap_net.resolve("localhost", 80).unwrap();
+}
+
+/// Earlier limits on name resolution are kept too
+#[test]
+fn dns_names_kept() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let mut limit = cap_net.limit(LimitFlags::ALL).unwrap();
+    limit.name2addr("localhost", None);
+    limit.limit().unwrap();
+
+    let target = get_local_in();
+    SocketRegistry::new()
+        .connect_target(target)
+        .open(&mut cap_net)
+        .unwrap();
+    cap_net.resolve("localhost", 80).unwrap();
+    cap_net.resolve("localhost.", 80).unwrap_err();
+}
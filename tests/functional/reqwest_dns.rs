@@ -0,0 +1,37 @@
+// vim: tw=80
+use capsicum_net::{reqwest::CapResolver, CasperExt, LimitFlags};
+use reqwest::dns::Resolve;
+
+use crate::CASPER;
+
+#[tokio::test]
+async fn localhost() {
+    let cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let resolver = CapResolver::new(cap_net);
+    let addrs = resolver
+        .resolve("localhost".parse().unwrap())
+        .await
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert!(!addrs.is_empty());
+    assert!(addrs.iter().all(|a| a.ip().is_loopback() && a.port() == 0));
+}
+
+#[tokio::test]
+async fn limited() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
+    let resolver = CapResolver::new(cap_net);
+    assert!(resolver
+        .resolve("localhost".parse().unwrap())
+        .await
+        .is_err());
+}
@@ -0,0 +1,410 @@
+// vim: tw=80
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+use capsicum_net::{
+    resolver::{CapAddrs, Resolver},
+    CasperExt,
+    LimitFlags,
+};
+
+use crate::{std::get_local_in, CASPER};
+
+fn resolver() -> Resolver {
+    let mut casper = CASPER.get().unwrap().lock().unwrap();
+    Resolver::new(casper.net().unwrap())
+}
+
+#[test]
+fn numeric() {
+    let mut resolver = resolver();
+    // Numeric addresses don't need Casper at all
+    resolver
+        .agent_mut()
+        .limit(LimitFlags::empty())
+        .unwrap()
+        .limit()
+        .unwrap();
+    assert_eq!(
+        resolver.lookup("127.0.0.1:80").unwrap(),
+        vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 80))]
+    );
+    assert_eq!(
+        resolver.lookup("[::1]:443").unwrap(),
+        vec![SocketAddr::from((Ipv6Addr::LOCALHOST, 443))]
+    );
+}
+
+#[test]
+fn localhost() {
+    let mut resolver = resolver();
+    let addrs = resolver.lookup("localhost:22").unwrap();
+    assert!(!addrs.is_empty());
+    assert!(addrs.iter().all(|a| a.ip().is_loopback() && a.port() == 22));
+    for (i, a) in addrs.iter().enumerate() {
+        assert!(!addrs[i + 1..].contains(a), "duplicate {a}");
+    }
+}
+
+#[test]
+fn invalid() {
+    let mut resolver = resolver();
+    for bad in [
+        "localhost",
+        ":80",
+        "::1:80",
+        "[::1]",
+        "localhost:http",
+        "[]:80",
+    ] {
+        let e = resolver.lookup(bad).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{bad}");
+    }
+}
+
+#[test]
+fn not_found() {
+    let mut resolver = resolver();
+    let e = resolver.lookup("nonexistent.invalid:80").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn canonical() {
+    let mut resolver = resolver();
+    let lookup = resolver.lookup_canonical("localhost:80").unwrap();
+    assert!(!lookup.canonname.unwrap().is_empty());
+    assert!(!lookup.addrs.is_empty());
+    let lookup = resolver.lookup_canonical("127.0.0.1:80").unwrap();
+    assert_eq!(lookup.canonname, None);
+    assert_eq!(
+        lookup.addrs,
+        vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 80))]
+    );
+}
+
+mod hosts {
+    use std::net::IpAddr;
+
+    use super::*;
+
+    #[test]
+    fn add_host() {
+        let mut resolver = resolver();
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        resolver.add_host("Pinned.Example", &[ip]);
+        // Static entries don't need Casper
+        resolver
+            .agent_mut()
+            .limit(LimitFlags::empty())
+            .unwrap()
+            .limit()
+            .unwrap();
+        assert_eq!(
+            resolver.lookup("pinned.example:443").unwrap(),
+            vec![SocketAddr::new(ip, 443)]
+        );
+        resolver.remove_host("PINNED.example");
+        resolver.lookup("pinned.example:443").unwrap_err();
+    }
+
+    #[test]
+    fn load_hosts() {
+        let mut resolver = resolver();
+        resolver
+            .load_hosts(
+                "# upstreams\n\n192.0.2.1 a.example b.example # \
+                 both\n2001:db8::1\ta.example\n",
+            )
+            .unwrap();
+        assert_eq!(
+            resolver.lookup("a.example:80").unwrap(),
+            vec![
+                SocketAddr::from(([192, 0, 2, 1], 80)),
+                "[2001:db8::1]:80".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            resolver.lookup("b.example:80").unwrap(),
+            vec![SocketAddr::from(([192, 0, 2, 1], 80))]
+        );
+    }
+
+    #[test]
+    fn load_hosts_invalid() {
+        let mut resolver = resolver();
+        for bad in ["192.0.2.1 a.example\nbogus b.example", "192.0.2.1"] {
+            let e = resolver.load_hosts(bad).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{bad}");
+        }
+        // Nothing was added from the partially valid file
+        resolver
+            .agent_mut()
+            .limit(LimitFlags::empty())
+            .unwrap()
+            .limit()
+            .unwrap();
+        resolver.lookup("a.example:80").unwrap_err();
+    }
+}
+
+mod lookup_many {
+    use super::*;
+
+    const NAMES: [&str; 5] = [
+        "localhost:22",
+        "127.0.0.1:80",
+        "nonexistent.invalid:80",
+        "bogus",
+        "localhost:22",
+    ];
+
+    fn check(
+        results: &std::collections::HashMap<&str, io::Result<Vec<SocketAddr>>>,
+    ) {
+        assert_eq!(results.len(), 4);
+        let localhost = results["localhost:22"].as_ref().unwrap();
+        assert!(!localhost.is_empty());
+        assert!(localhost.iter().all(|a| a.ip().is_loopback()));
+        assert_eq!(
+            results["127.0.0.1:80"].as_ref().unwrap(),
+            &vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 80))]
+        );
+        assert_eq!(
+            results["nonexistent.invalid:80"]
+                .as_ref()
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(
+            results["bogus"].as_ref().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn serial() {
+        let mut resolver = resolver();
+        check(&resolver.lookup_many(&NAMES));
+    }
+
+    #[test]
+    fn parallel() {
+        let mut resolver = resolver();
+        let mut workers = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            vec![casper.net().unwrap(), casper.net().unwrap()]
+        };
+        check(&resolver.lookup_many_parallel(&NAMES, &mut workers));
+    }
+}
+
+#[cfg(feature = "idna")]
+mod idna {
+    use super::*;
+
+    /// UTS #46 maps fullwidth letters to their ASCII equivalents
+    #[test]
+    fn fullwidth() {
+        let mut resolver = resolver();
+        let addrs = resolver.lookup("ｌｏｃａｌｈｏｓｔ:22").unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|a| a.ip().is_loopback()));
+    }
+
+    #[test]
+    fn disallowed() {
+        let mut resolver = resolver();
+        let e = resolver.lookup("\u{fffd}.invalid:22").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+mod cache {
+    use super::*;
+
+    /// Prevent any further lookups from reaching Casper
+    fn forbid_lookups(resolver: &mut Resolver) {
+        resolver
+            .agent_mut()
+            .limit(LimitFlags::CONNECT)
+            .unwrap()
+            .limit()
+            .unwrap();
+    }
+
+    #[test]
+    fn hit() {
+        let mut resolver = resolver();
+        resolver.set_cache(Duration::from_secs(3600), 8);
+        let addrs = resolver.lookup("localhost:22").unwrap();
+        forbid_lookups(&mut resolver);
+        // The port isn't part of the cache key
+        let cached = resolver.lookup("localhost:80").unwrap();
+        assert_eq!(cached.len(), addrs.len());
+        for (a, c) in addrs.iter().zip(cached.iter()) {
+            assert_eq!(a.ip(), c.ip());
+            assert_eq!(c.port(), 80);
+        }
+    }
+
+    #[test]
+    fn expired() {
+        let mut resolver = resolver();
+        resolver.set_cache(Duration::ZERO, 8);
+        resolver.lookup("localhost:22").unwrap();
+        forbid_lookups(&mut resolver);
+        resolver.lookup("localhost:22").unwrap_err();
+    }
+
+    #[test]
+    fn flush() {
+        let mut resolver = resolver();
+        resolver.set_cache(Duration::from_secs(3600), 8);
+        resolver.lookup("localhost:22").unwrap();
+        resolver.flush();
+        forbid_lookups(&mut resolver);
+        resolver.lookup("localhost:22").unwrap_err();
+    }
+
+    #[test]
+    fn uncached() {
+        let mut resolver = resolver();
+        resolver.lookup("localhost:22").unwrap();
+        forbid_lookups(&mut resolver);
+        resolver.lookup("localhost:22").unwrap_err();
+    }
+}
+
+mod family_preference {
+    use capsicum_net::addrinfo::FamilyPreference;
+
+    use super::*;
+
+    #[test]
+    fn ipv4_only() {
+        let mut resolver = resolver();
+        resolver.set_family_preference(FamilyPreference::Ipv4Only);
+        let addrs = resolver.lookup("localhost:22").unwrap();
+        assert!(addrs.iter().all(SocketAddr::is_ipv4));
+        let e = resolver.lookup("[::1]:22").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn prefer_ipv4() {
+        let mut resolver = resolver();
+        let all = resolver.lookup("localhost:22").unwrap();
+        resolver.set_family_preference(FamilyPreference::PreferIpv4);
+        let addrs = resolver.lookup("localhost:22").unwrap();
+        assert_eq!(addrs.len(), all.len());
+        let first_v6 = addrs.iter().position(SocketAddr::is_ipv6);
+        let last_v4 = addrs.iter().rposition(SocketAddr::is_ipv4);
+        if let (Some(first_v6), Some(last_v4)) = (first_v6, last_v4) {
+            assert!(last_v4 < first_v6);
+        }
+    }
+}
+
+mod resolve {
+    use std::net::IpAddr;
+
+    use capsicum_net::{
+        resolver::{Resolve, WithResolver},
+        std::TcpStreamBuilder,
+    };
+
+    use super::*;
+
+    /// A resolver that knows a single name
+    struct Fixed(IpAddr);
+
+    impl Resolve for Fixed {
+        fn resolve(
+            &mut self,
+            host: &str,
+            port: u16,
+        ) -> io::Result<Vec<SocketAddr>> {
+            if host == "fixed.test" {
+                Ok(vec![SocketAddr::new(self.0, port)])
+            } else {
+                Err(io::Error::from(io::ErrorKind::NotFound))
+            }
+        }
+    }
+
+    #[test]
+    fn connect_host_with() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let _listener = TcpListener::bind(want).unwrap();
+        // The agent itself may not resolve anything
+        cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
+        let mut fixed = Fixed(want.ip());
+        let fd = cap_net
+            .connect_host_with(&mut fixed, "fixed.test", want.port())
+            .unwrap();
+        assert_eq!(TcpStream::from(fd).peer_addr().unwrap(), want);
+        let e = cap_net
+            .connect_host_with(&mut fixed, "other.test", want.port())
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn happy_eyeballs() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let _listener = TcpListener::bind(want).unwrap();
+        let mut resolver = resolver();
+        let addrs = WithResolver::new(&mut resolver, "localhost", want.port());
+        let stream = TcpStreamBuilder::new()
+            .connect_happy_eyeballs(&mut cap_net, &addrs)
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), want);
+    }
+}
+
+mod cap_addrs {
+    use super::*;
+
+    #[test]
+    fn std_connect() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let _listener = TcpListener::bind(want).unwrap();
+        let host = format!("localhost:{}", want.port());
+        // Plain std connect, but resolving through Casper
+        let stream =
+            TcpStream::connect(CapAddrs::new(&mut cap_net, &host)).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), want);
+    }
+
+    #[test]
+    fn limited() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
+        let addrs = CapAddrs::new(&mut cap_net, "localhost:80");
+        std::net::ToSocketAddrs::to_socket_addrs(&addrs).unwrap_err();
+    }
+}
@@ -0,0 +1,41 @@
+// vim: tw=80
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    process::Command,
+};
+
+use capsicum_net::route::{RouteEvent, RouteMessage, RouteSocket, RtAddr};
+use nix::sys::socket::AddressFamily;
+
+/// Adding and deleting a route should generate RTM_ADD and RTM_DELETE messages
+#[test]
+fn add_and_delete() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Skipping test: must be root");
+        return;
+    }
+    // 198.51.100.0/24 is reserved for documentation
+    let dst = Ipv4Addr::new(198, 51, 100, 1);
+    let mut rs = RouteSocket::new(Some(AddressFamily::Inet)).unwrap();
+
+    let route = |verb: &str| {
+        let status = Command::new("route")
+            .args(["-q", verb, "-host", &dst.to_string(), "-iface", "lo0"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    let wait_for = |rs: &mut RouteSocket, kind| loop {
+        if let RouteMessage::Route(r) = rs.recv().unwrap() {
+            if r.kind == kind && r.dst == Some(RtAddr::Ip(IpAddr::V4(dst))) {
+                break r;
+            }
+        }
+    };
+
+    route("add");
+    let added = wait_for(&mut rs, RouteEvent::Add);
+    assert_eq!(added.errno, 0);
+    route("delete");
+    wait_for(&mut rs, RouteEvent::Delete);
+}
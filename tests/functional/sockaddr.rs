@@ -0,0 +1,109 @@
+// vim: tw=80
+use std::net::SocketAddr;
+
+use capsicum_net::sockaddr;
+use nix::sys::socket::{SockaddrIn6, UnixAddr};
+
+use crate::std::{get_local_in, get_local_in6};
+
+#[test]
+fn std_roundtrip_ipv4() {
+    let addr = get_local_in();
+    let ss = sockaddr::from_std(addr);
+    assert_eq!(ss.as_sockaddr_in().map(|sin| sin.port()), Some(addr.port()));
+    assert_eq!(sockaddr::to_std(&ss), Some(addr));
+}
+
+#[test]
+fn std_roundtrip_ipv6() {
+    let addr: SocketAddr = "[fe80::1%2]:8080".parse().unwrap();
+    let ss = sockaddr::from_std(addr);
+    assert_eq!(sockaddr::to_std(&ss), Some(addr));
+}
+
+#[test]
+fn nix_to_std() {
+    let SocketAddr::V6(addr) = get_local_in6() else {
+        unreachable!()
+    };
+    let sin6 = SockaddrIn6::from(addr);
+    assert_eq!(sockaddr::to_std(&sin6), Some(SocketAddr::V6(addr)));
+}
+
+#[test]
+fn unix_is_not_inet() {
+    let sun = UnixAddr::new("/tmp/sock").unwrap();
+    assert_eq!(sockaddr::to_std(&sun), None);
+}
+
+#[cfg(feature = "socket2")]
+mod socket2 {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        for addr in [get_local_in(), get_local_in6()] {
+            let ss = sockaddr::from_std(addr);
+            let sa = sockaddr::to_socket2(&ss);
+            assert_eq!(sa.as_socket(), Some(addr));
+            let back = sockaddr::from_socket2(&sa).unwrap();
+            assert_eq!(sockaddr::to_std(&back), Some(addr));
+        }
+    }
+
+    #[test]
+    fn unix() {
+        let sun = UnixAddr::new("/tmp/sock").unwrap();
+        let sa = sockaddr::to_socket2(&sun);
+        assert_eq!(sa.as_pathname(), Some(std::path::Path::new("/tmp/sock")));
+        let back = sockaddr::from_socket2(&sa).unwrap();
+        assert_eq!(
+            back.as_unix_addr().and_then(|u| u.path()),
+            Some(std::path::Path::new("/tmp/sock"))
+        );
+    }
+}
+
+#[test]
+fn parse_const() {
+    for s in [
+        "0.0.0.0:0",
+        "127.0.0.1:80",
+        "255.255.255.255:65535",
+        "[::]:1",
+        "[::1]:80",
+        "[1::]:80",
+        "[2001:db8::8:800:200c:417a]:443",
+        "[2001:DB8:0:0:8:800:200C:417A]:443",
+        "[fe80:1:2:3:4:5:6:7]:8",
+    ] {
+        let want: SocketAddr = s.parse().unwrap();
+        assert_eq!(sockaddr::parse_const(s), want, "{s}");
+    }
+}
+
+#[test]
+fn parse_const_invalid() {
+    for s in [
+        "",
+        "127.0.0.1",
+        "127.0.0.1:",
+        "127.0.0.1:65536",
+        "127.0.0.256:80",
+        "127.0.0:80",
+        "127.0.0.1.1:80",
+        "127.0.0.01:80",
+        "localhost:80",
+        "[::1]",
+        "[::1:80",
+        "[1:2:3:4:5:6:7:8:9]:80",
+        "[1:2:3:4:5:6:7]:80",
+        "[1::2::3]:80",
+        "[1:]:80",
+        "[12345::]:80",
+        "[g::]:80",
+    ] {
+        let r = std::panic::catch_unwind(|| sockaddr::parse_const(s));
+        assert!(r.is_err(), "{s}");
+    }
+}
@@ -5,8 +5,11 @@ use std::{
     os::fd::AsRawFd,
 };
 
-use capsicum_net::CasperExt;
-use nix::sys::socket::{getsockopt, sockopt::ListenQLimit};
+use capsicum_net::{CasperExt, LimitFlags};
+use nix::sys::socket::{
+    getsockopt,
+    sockopt::{self, ListenQLimit, ReuseAddr, ReusePort},
+};
 use tempfile::TempDir;
 
 use crate::CASPER;
@@ -21,6 +24,49 @@ pub fn get_local_in6() -> SocketAddr {
     SocketAddrV6::new(Ipv6Addr::LOCALHOST, crate::next_port(), 0, 0).into()
 }
 
+/// Read back an integer-valued socket option.
+fn getsockopt_int<F: AsRawFd>(
+    f: &F,
+    level: libc::c_int,
+    name: libc::c_int,
+) -> libc::c_int {
+    let mut val: libc::c_int = -1;
+    let mut len = ::std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let r = unsafe {
+        libc::getsockopt(
+            f.as_raw_fd(),
+            level,
+            name,
+            (&mut val as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    assert_eq!(r, 0);
+    val
+}
+
+/// Read back a string-valued socket option.
+fn getsockopt_str<F: AsRawFd>(
+    f: &F,
+    level: libc::c_int,
+    name: libc::c_int,
+) -> String {
+    let mut buf = [0u8; 16];
+    let mut len = buf.len() as libc::socklen_t;
+    let r = unsafe {
+        libc::getsockopt(
+            f.as_raw_fd(),
+            level,
+            name,
+            buf.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+    assert_eq!(r, 0);
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
 mod tcp_listener {
     use std::net::TcpListener;
 
@@ -44,6 +90,43 @@ mod tcp_listener {
             assert_eq!(err.raw_os_error(), Some(libc::EADDRINUSE));
         }
 
+        #[test]
+        fn with_addr() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let taken = get_local_in();
+            let _socket1 = TcpListener::cap_bind(&mut cap_net, taken).unwrap();
+            let want = get_local_in();
+            let (socket, used) = TcpListener::cap_bind_with_addr(
+                &mut cap_net,
+                &[taken, want][..],
+            )
+            .unwrap();
+            assert_eq!(want, used);
+            assert_eq!(want, socket.local_addr().unwrap());
+        }
+
+        #[test]
+        fn limited() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+            limit.bind_std(want);
+            limit.limit().unwrap();
+
+            TcpListener::cap_bind(&mut cap_net, want).unwrap();
+            let err = TcpListener::cap_bind(&mut cap_net, get_local_in())
+                .unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::ENOTCAPABLE));
+        }
+
         #[test]
         fn no_addresses() {
             let mut cap_net = {
@@ -85,34 +168,111 @@ mod tcp_listener {
             assert!(getsockopt(&socket, ListenQLimit).unwrap() > 0);
         }
     }
-}
 
-mod tcp_stream {
-    use std::net::{TcpListener, TcpStream};
+    mod bind_all {
+        use super::*;
 
-    use capsicum_net::std::TcpStreamExt;
+        #[test]
+        fn dual_stack() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
 
-    use super::*;
+            let port = crate::next_port();
+            let addrs = [
+                SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+                SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+            ];
+            let listeners =
+                TcpListener::cap_bind_all(&mut cap_net, &addrs[..]).unwrap();
+            let bound: Vec<SocketAddr> =
+                listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+            assert_eq!(bound, addrs);
+        }
+
+        #[test]
+        fn duplicates() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let listeners =
+                TcpListener::cap_bind_all(&mut cap_net, &[want, want][..])
+                    .unwrap();
+            assert_eq!(listeners.len(), 1);
+        }
+
+        #[test]
+        fn eaddrinuse() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let free = get_local_in();
+            let taken = get_local_in();
+            let _socket = TcpListener::cap_bind(&mut cap_net, taken).unwrap();
+            let err =
+                TcpListener::cap_bind_all(&mut cap_net, &[free, taken][..])
+                    .unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EADDRINUSE));
+            // The listener on the free address was closed
+            TcpListener::cap_bind(&mut cap_net, free).unwrap();
+        }
+
+        #[test]
+        fn no_addresses() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let addrs: Vec<SocketAddr> = Vec::new();
+            let err = TcpListener::cap_bind_all(&mut cap_net, &addrs[..])
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    mod bind_dual_stack {
+        use std::net::{IpAddr, TcpStream};
 
-    mod connect {
         use super::*;
 
         #[test]
-        fn eaddrnotavail() {
+        fn accepts_ipv4() {
             let mut cap_net = {
                 let mut casper = CASPER.get().unwrap().lock().unwrap();
                 casper.net().unwrap()
             };
 
-            // 127.100.0.1 is reserved for local use, but most likely not assigned
-            let want: SocketAddr = SocketAddrV4::new(
-                Ipv4Addr::new(127, 100, 0, 1),
-                crate::next_port(),
-            )
-            .into();
-            let err = TcpStream::cap_connect(&mut cap_net, want).unwrap_err();
-            assert_eq!(err.raw_os_error(), Some(libc::EADDRNOTAVAIL));
+            let port = crate::next_port();
+            let listener =
+                TcpListener::cap_bind_dual_stack(&mut cap_net, port).unwrap();
+            let local = listener.local_addr().unwrap();
+            assert_eq!(local, SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)));
+            let v6only = getsockopt_int(
+                &listener,
+                libc::IPPROTO_IPV6,
+                libc::IPV6_V6ONLY,
+            );
+            assert_eq!(v6only, 0);
+
+            let _client =
+                TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+            let (_server, peer) = listener.accept().unwrap();
+            let IpAddr::V6(ip) = peer.ip() else {
+                panic!("peer address should be IPv6");
+            };
+            assert_eq!(ip.to_ipv4_mapped(), Some(Ipv4Addr::LOCALHOST));
         }
+    }
+
+    mod bind_ephemeral {
+        use super::*;
 
         #[test]
         fn ipv4() {
@@ -121,12 +281,14 @@ mod tcp_stream {
                 casper.net().unwrap()
             };
 
-            let want = get_local_in();
-            let _server_socket = TcpListener::bind(want).unwrap();
-            let client_socket =
-                TcpStream::cap_connect(&mut cap_net, want).unwrap();
-            let connected = client_socket.peer_addr().unwrap();
-            assert_eq!(want, connected);
+            let (socket, addr) = TcpListener::cap_bind_ephemeral(
+                &mut cap_net,
+                Ipv4Addr::LOCALHOST,
+            )
+            .unwrap();
+            assert_eq!(addr.ip(), Ipv4Addr::LOCALHOST);
+            assert_ne!(addr.port(), 0);
+            assert_eq!(addr, socket.local_addr().unwrap());
         }
 
         #[test]
@@ -136,171 +298,1341 @@ mod tcp_stream {
                 casper.net().unwrap()
             };
 
-            let want = get_local_in6();
-            let _server_socket = TcpListener::bind(want).unwrap();
-            let client_socket =
-                TcpStream::cap_connect(&mut cap_net, want).unwrap();
-            let connected = client_socket.peer_addr().unwrap();
-            assert_eq!(want, connected);
+            let (socket, addr) = TcpListener::cap_bind_ephemeral(
+                &mut cap_net,
+                Ipv6Addr::LOCALHOST,
+            )
+            .unwrap();
+            assert_eq!(addr.ip(), Ipv6Addr::LOCALHOST);
+            assert_ne!(addr.port(), 0);
+            assert_eq!(addr, socket.local_addr().unwrap());
         }
     }
-}
-
-mod udp_socket {
-    use std::net::UdpSocket;
-
-    use capsicum_net::std::UdpSocketExt;
 
-    use super::*;
+    mod builder {
+        use capsicum_net::std::{somaxconn, Backlog, TcpListenerBuilder};
 
-    mod bind {
         use super::*;
 
         #[test]
-        fn eaddrinuse() {
+        fn congestion() {
             let mut cap_net = {
                 let mut casper = CASPER.get().unwrap().lock().unwrap();
                 casper.net().unwrap()
             };
 
             let want = get_local_in();
-            let _socket1 = UdpSocket::cap_bind(&mut cap_net, want).unwrap();
-            let err = UdpSocket::cap_bind(&mut cap_net, want).unwrap_err();
-            assert_eq!(err.raw_os_error(), Some(libc::EADDRINUSE));
+            let socket = TcpListenerBuilder::new()
+                .congestion("newreno")
+                .bind(&mut cap_net, want)
+                .unwrap();
+            assert_eq!(want, socket.local_addr().unwrap());
+            assert!(getsockopt(&socket, ListenQLimit).unwrap() > 0);
+            let cc = getsockopt_str(
+                &socket,
+                libc::IPPROTO_TCP,
+                libc::TCP_CONGESTION,
+            );
+            assert_eq!(cc, "newreno");
         }
 
         #[test]
-        fn no_addresses() {
+        fn backlog_explicit() {
             let mut cap_net = {
                 let mut casper = CASPER.get().unwrap().lock().unwrap();
                 casper.net().unwrap()
             };
 
-            let addrs: Vec<SocketAddr> = Vec::new();
-            let err =
-                UdpSocket::cap_bind(&mut cap_net, &addrs[..]).unwrap_err();
-            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let socket = TcpListenerBuilder::new()
+                .backlog(Backlog::Explicit(5))
+                .bind(&mut cap_net, get_local_in())
+                .unwrap();
+            assert_eq!(getsockopt(&socket, ListenQLimit).unwrap(), 5);
         }
 
         #[test]
-        fn ipv4() {
+        fn backlog_max() {
             let mut cap_net = {
                 let mut casper = CASPER.get().unwrap().lock().unwrap();
                 casper.net().unwrap()
             };
 
-            let want = get_local_in();
-            let socket = UdpSocket::cap_bind(&mut cap_net, want).unwrap();
-            let bound = socket.local_addr().unwrap();
-            assert_eq!(want, bound);
+            let max = somaxconn().unwrap();
+            assert!(max > 0);
+            assert_eq!(Backlog::Max.value(), max);
+            let socket = TcpListenerBuilder::new()
+                .backlog(Backlog::Max)
+                .bind(&mut cap_net, get_local_in())
+                .unwrap();
+            assert_eq!(getsockopt(&socket, ListenQLimit).unwrap(), max as u32);
         }
 
         #[test]
-        fn ipv6() {
+        fn reuse_addr() {
             let mut cap_net = {
                 let mut casper = CASPER.get().unwrap().lock().unwrap();
                 casper.net().unwrap()
             };
 
-            let want = get_local_in6();
-            let socket = UdpSocket::cap_bind(&mut cap_net, want).unwrap();
-            let bound = socket.local_addr().unwrap();
-            assert_eq!(want, bound);
+            let socket = TcpListenerBuilder::new()
+                .reuse_addr(true)
+                .bind(&mut cap_net, get_local_in())
+                .unwrap();
+            assert!(getsockopt(&socket, ReuseAddr).unwrap());
+            assert!(!getsockopt(&socket, ReusePort).unwrap());
         }
-    }
 
-    mod connect {
-        use super::*;
+        #[test]
+        fn reuse_port() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let mut builder = TcpListenerBuilder::new();
+            builder.reuse_port(true);
+            let socket1 = builder.bind(&mut cap_net, want).unwrap();
+            let socket2 = builder.bind(&mut cap_net, want).unwrap();
+            assert!(getsockopt(&socket1, ReusePort).unwrap());
+            assert_eq!(socket2.local_addr().unwrap(), want);
+        }
 
         #[test]
-        fn ipv4() {
+        fn v6only() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            for v6only in [false, true] {
+                let socket = TcpListenerBuilder::new()
+                    .v6only(v6only)
+                    .bind(&mut cap_net, get_local_in6())
+                    .unwrap();
+                let val = getsockopt_int(
+                    &socket,
+                    libc::IPPROTO_IPV6,
+                    libc::IPV6_V6ONLY,
+                );
+                assert_eq!(val != 0, v6only);
+            }
+        }
+
+        #[test]
+        fn bind_sharded() {
             let mut cap_net = {
                 let mut casper = CASPER.get().unwrap().lock().unwrap();
                 casper.net().unwrap()
             };
 
             let want = get_local_in();
-            let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-            socket.cap_connect(&mut cap_net, want).unwrap();
-            let connected = socket.peer_addr().unwrap();
-            assert_eq!(want, connected);
+            let listeners = TcpListenerBuilder::new()
+                .bind_sharded(&mut cap_net, want, 3)
+                .unwrap();
+            assert_eq!(listeners.len(), 3);
+            for listener in listeners.iter() {
+                assert_eq!(listener.local_addr().unwrap(), want);
+                let lb = getsockopt_int(
+                    listener,
+                    libc::SOL_SOCKET,
+                    libc::SO_REUSEPORT_LB,
+                );
+                assert_ne!(lb, 0);
+            }
         }
 
         #[test]
-        fn ipv6() {
+        fn bind_sharded_ephemeral() {
             let mut cap_net = {
                 let mut casper = CASPER.get().unwrap().lock().unwrap();
                 casper.net().unwrap()
             };
 
-            let want = get_local_in6();
-            let socket = UdpSocket::bind("[::0]:0").unwrap();
-            socket.cap_connect(&mut cap_net, want).unwrap();
-            let connected = socket.peer_addr().unwrap();
-            assert_eq!(want, connected);
+            let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+            let listeners = TcpListenerBuilder::new()
+                .bind_sharded(&mut cap_net, addr, 2)
+                .unwrap();
+            let bound = listeners[0].local_addr().unwrap();
+            assert_ne!(bound.port(), 0);
+            assert_eq!(listeners[1].local_addr().unwrap(), bound);
         }
-    }
-}
 
-mod unix_datagram {
-    use std::os::unix::net::UnixDatagram;
+        #[test]
+        fn bind_sharded_zero() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
 
-    use capsicum_net::std::UnixDatagramExt;
+            let e = TcpListenerBuilder::new()
+                .bind_sharded(&mut cap_net, get_local_in(), 0)
+                .unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
 
-    use super::*;
+        #[test]
+        fn bind_host() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
 
-    mod bind {
-        use super::*;
+            let port = crate::next_port();
+            let listeners = TcpListenerBuilder::new()
+                .bind_host(&mut cap_net, "localhost", port)
+                .unwrap();
+            assert!(!listeners.is_empty());
+            for (listener, addr) in listeners.iter() {
+                assert!(addr.ip().is_loopback());
+                assert_eq!(addr.port(), port);
+                assert_eq!(listener.local_addr().unwrap(), *addr);
+            }
+        }
 
         #[test]
-        fn ok() {
+        fn bind_host_not_found() {
             let mut cap_net = {
                 let mut casper = CASPER.get().unwrap().lock().unwrap();
                 casper.net().unwrap()
             };
 
-            let dir = TempDir::new().unwrap();
-            let path = dir.path().join("sock");
-            let socket = UnixDatagram::cap_bind(&mut cap_net, &path).unwrap();
+            let e = TcpListenerBuilder::new()
+                .bind_host(&mut cap_net, "nonexistent.invalid", 80)
+                .unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        }
+    }
+}
 
-            // We can't use UnixDatagram::local_addr due to
-            // https://github.com/rust-lang/rust/issues/118925 , so use nix's
-            // gethostname instead.
-            let bound: nix::sys::socket::UnixAddr =
-                nix::sys::socket::getsockname(socket.as_raw_fd()).unwrap();
-            assert_eq!(path, bound.path().unwrap());
+mod socketpair {
+    use std::{
+        io::{Read, Write},
+        os::unix::net::UnixStream,
+    };
+
+    use capsicum::{FileRights, Right};
+    use capsicum_net::std::cap_socketpair;
+    use nix::sys::socket::SockType;
+
+    #[test]
+    fn stream() {
+        let (a, b) = cap_socketpair(SockType::Stream).unwrap();
+        for fd in [&a, &b] {
+            let rights = FileRights::from_file(fd).unwrap();
+            assert!(rights.is_set(Right::Read));
+            assert!(rights.is_set(Right::Write));
+            assert!(!rights.is_set(Right::Getpeername));
         }
+        let mut a = UnixStream::from(a);
+        let mut b = UnixStream::from(b);
+        a.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
     }
 }
 
-mod unix_listener {
-    use std::os::unix::net::UnixListener;
+mod limited_incoming {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+    };
 
-    use capsicum_net::std::UnixListenerExt;
+    use capsicum::{FileRights, Right};
+    use capsicum_net::std::{LimitedIncoming, TcpListenerExt};
 
     use super::*;
 
-    mod bind {
+    #[test]
+    fn default_rights() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let listener = TcpListener::cap_bind(&mut cap_net, want).unwrap();
+        let mut client = TcpStream::connect(want).unwrap();
+        let (mut stream, peer) =
+            LimitedIncoming::new(&listener).accept().unwrap();
+        assert_eq!(peer, client.local_addr().unwrap());
+        let rights = FileRights::from_file(&stream).unwrap();
+        assert!(rights.is_set(Right::Read));
+        assert!(rights.is_set(Right::Write));
+        assert!(!rights.is_set(Right::Getpeername));
+        stream.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn iterator() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let listener = TcpListener::cap_bind(&mut cap_net, want).unwrap();
+        let _client = TcpStream::connect(want).unwrap();
+        let mut rights = FileRights::new();
+        rights.allow(Right::Read);
+        let stream = LimitedIncoming::with_rights(&listener, rights)
+            .next()
+            .unwrap()
+            .unwrap();
+        let got = FileRights::from_file(&stream).unwrap();
+        assert!(got.is_set(Right::Read));
+        assert!(!got.is_set(Right::Write));
+    }
+}
+
+mod tcp_stream {
+    use std::net::{TcpListener, TcpStream};
+
+    use capsicum_net::std::TcpStreamExt;
+
+    use super::*;
+
+    mod connect {
         use super::*;
 
         #[test]
-        fn ok() {
+        fn eaddrnotavail() {
             let mut cap_net = {
                 let mut casper = CASPER.get().unwrap().lock().unwrap();
                 casper.net().unwrap()
             };
 
-            let dir = TempDir::new().unwrap();
-            let path = dir.path().join("sock");
-            let socket = UnixListener::cap_bind(&mut cap_net, &path).unwrap();
+            // 127.100.0.1 is reserved for local use, but most likely not assigned
+            let want: SocketAddr = SocketAddrV4::new(
+                Ipv4Addr::new(127, 100, 0, 1),
+                crate::next_port(),
+            )
+            .into();
+            let err = TcpStream::cap_connect(&mut cap_net, want).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EADDRNOTAVAIL));
+        }
 
-            // We can't use UnixListener::local_addr due to
-            // https://github.com/rust-lang/rust/issues/118925 , so use nix's
-            // gethostname instead.
-            let bound: nix::sys::socket::UnixAddr =
-                nix::sys::socket::getsockname(socket.as_raw_fd()).unwrap();
-            assert_eq!(path, bound.path().unwrap());
-            assert!(getsockopt(&socket, ListenQLimit).unwrap() > 0);
+        #[test]
+        fn with_addr() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            // The first address will fail with EADDRNOTAVAIL
+            let bad: SocketAddr = SocketAddrV4::new(
+                Ipv4Addr::new(127, 100, 0, 1),
+                crate::next_port(),
+            )
+            .into();
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let (client_socket, used) = TcpStream::cap_connect_with_addr(
+                &mut cap_net,
+                &[bad, want][..],
+            )
+            .unwrap();
+            assert_eq!(want, used);
+            assert_eq!(want, client_socket.peer_addr().unwrap());
         }
+
+        #[test]
+        fn ipv4() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let client_socket =
+                TcpStream::cap_connect(&mut cap_net, want).unwrap();
+            let connected = client_socket.peer_addr().unwrap();
+            assert_eq!(want, connected);
+        }
+
+        #[test]
+        fn ipv6() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in6();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let client_socket =
+                TcpStream::cap_connect(&mut cap_net, want).unwrap();
+            let connected = client_socket.peer_addr().unwrap();
+            assert_eq!(want, connected);
+        }
+
+        #[test]
+        fn hostname() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let host = format!("localhost:{}", want.port());
+            let client_socket =
+                TcpStream::cap_connect(&mut cap_net, host.as_str()).unwrap();
+            assert_eq!(want, client_socket.peer_addr().unwrap());
+        }
+
+        /// Hostnames must be resolved by Casper, not by the libc resolver
+        #[test]
+        fn hostname_limited() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
+            let host = format!("localhost:{}", want.port());
+            TcpStream::cap_connect(&mut cap_net, host.as_str()).unwrap_err();
+            TcpStream::cap_connect(&mut cap_net, want).unwrap();
+        }
+    }
+
+    mod connect_host {
+        use super::*;
+
+        /// localhost may resolve to ::1 first, which isn't listening
+        #[test]
+        fn localhost() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let client_socket = TcpStream::cap_connect_host(
+                &mut cap_net,
+                "localhost",
+                want.port(),
+            )
+            .unwrap();
+            assert_eq!(want, client_socket.peer_addr().unwrap());
+        }
+
+        #[test]
+        fn fd() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let fd = cap_net.connect_host("localhost", want.port()).unwrap();
+            let client_socket = TcpStream::from(fd);
+            assert_eq!(want, client_socket.peer_addr().unwrap());
+        }
+
+        #[test]
+        fn service() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let service = want.port().to_string();
+            let fd = cap_net.connect_service("localhost", &service).unwrap();
+            let client_socket = TcpStream::from(fd);
+            assert_eq!(want, client_socket.peer_addr().unwrap());
+            let e = cap_net
+                .connect_service("localhost", "nonesuch")
+                .unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        }
+
+        #[test]
+        fn not_found() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let e =
+                cap_net.connect_host("nonexistent.invalid", 80).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        }
+    }
+
+    mod happy_eyeballs {
+        use std::time::Duration;
+
+        use capsicum_net::{addrinfo::FamilyPreference, std::TcpStreamBuilder};
+
+        use super::*;
+
+        /// Only the IPv4 address is listening
+        #[test]
+        fn dual_stack() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let bad = SocketAddr::from((Ipv6Addr::LOCALHOST, want.port()));
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let client_socket = TcpStreamBuilder::new()
+                .attempt_delay(Duration::from_millis(10))
+                .connect_happy_eyeballs(&mut cap_net, &[bad, want][..])
+                .unwrap();
+            assert_eq!(want, client_socket.peer_addr().unwrap());
+        }
+
+        /// An address that fails immediately doesn't delay the next
+        #[test]
+        fn eaddrnotavail() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let bad: SocketAddr = SocketAddrV4::new(
+                Ipv4Addr::new(127, 100, 0, 1),
+                crate::next_port(),
+            )
+            .into();
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let client_socket = TcpStreamBuilder::new()
+                .attempt_delay(Duration::from_secs(60))
+                .connect_happy_eyeballs(&mut cap_net, &[bad, want][..])
+                .unwrap();
+            assert_eq!(want, client_socket.peer_addr().unwrap());
+        }
+
+        /// A family preference can skip the IPv6 address entirely
+        #[test]
+        fn ipv4_only() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let bad = get_local_in6();
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let client_socket = TcpStreamBuilder::new()
+                .attempt_delay(Duration::from_secs(60))
+                .family_preference(FamilyPreference::Ipv4Only)
+                .connect_happy_eyeballs(&mut cap_net, &[bad, want][..])
+                .unwrap();
+            assert_eq!(want, client_socket.peer_addr().unwrap());
+            let e = TcpStreamBuilder::new()
+                .family_preference(FamilyPreference::Ipv6Only)
+                .connect_happy_eyeballs(&mut cap_net, want)
+                .unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        #[test]
+        fn all_fail() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let a = get_local_in();
+            let b = get_local_in6();
+            let e = TcpStreamBuilder::new()
+                .connect_happy_eyeballs(&mut cap_net, &[a, b][..])
+                .unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+        }
+    }
+
+    mod builder {
+        use capsicum_net::std::TcpStreamBuilder;
+
+        use super::*;
+
+        #[test]
+        fn prefer_tempaddr() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in6();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let client_socket = TcpStreamBuilder::new()
+                .prefer_tempaddr(true)
+                .connect(&mut cap_net, want)
+                .unwrap();
+            assert_eq!(want, client_socket.peer_addr().unwrap());
+            // IPV6_PREFER_TEMPADDR
+            assert_eq!(
+                getsockopt_int(&client_socket, libc::IPPROTO_IPV6, 63),
+                1
+            );
+        }
+
+        #[test]
+        fn prefer_tempaddr_ipv4() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let client_socket = TcpStreamBuilder::new()
+                .prefer_tempaddr(true)
+                .connect(&mut cap_net, want)
+                .unwrap();
+            assert_eq!(want, client_socket.peer_addr().unwrap());
+        }
+
+        #[test]
+        fn congestion() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let client_socket = TcpStreamBuilder::new()
+                .congestion("newreno")
+                .connect(&mut cap_net, want)
+                .unwrap();
+            let cc = getsockopt_str(
+                &client_socket,
+                libc::IPPROTO_TCP,
+                libc::TCP_CONGESTION,
+            );
+            assert_eq!(cc, "newreno");
+        }
+
+        #[test]
+        fn congestion_unknown() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let _server_socket = TcpListener::bind(want).unwrap();
+            let err = TcpStreamBuilder::new()
+                .congestion("no_such_algorithm")
+                .connect(&mut cap_net, want)
+                .unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::ESRCH));
+        }
+
+        #[test]
+        fn connect_and_send() {
+            use std::io::Read;
+
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let server_socket = TcpListener::bind(want).unwrap();
+            let client_socket = TcpStreamBuilder::new()
+                .connect_and_send(&mut cap_net, want, b"hello")
+                .unwrap();
+            assert!(
+                getsockopt_int(
+                    &client_socket,
+                    libc::IPPROTO_TCP,
+                    libc::TCP_FASTOPEN
+                ) != 0
+            );
+            let (mut server_stream, _) = server_socket.accept().unwrap();
+            let mut buf = [0u8; 5];
+            server_stream.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"hello");
+        }
+    }
+}
+
+mod udp_socket {
+    use std::net::UdpSocket;
+
+    use capsicum_net::std::UdpSocketExt;
+
+    use super::*;
+
+    mod bind {
+        use super::*;
+
+        #[test]
+        fn eaddrinuse() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let _socket1 = UdpSocket::cap_bind(&mut cap_net, want).unwrap();
+            let err = UdpSocket::cap_bind(&mut cap_net, want).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EADDRINUSE));
+        }
+
+        #[test]
+        fn no_addresses() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let addrs: Vec<SocketAddr> = Vec::new();
+            let err =
+                UdpSocket::cap_bind(&mut cap_net, &addrs[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        #[test]
+        fn datagram() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let server =
+                UdpSocket::cap_bind(&mut cap_net, get_local_in()).unwrap();
+            let ty = getsockopt(&server, sockopt::SockType).unwrap();
+            assert_eq!(ty, nix::sys::socket::SockType::Datagram);
+
+            let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            client
+                .send_to(b"hello", server.local_addr().unwrap())
+                .unwrap();
+            let mut buf = [0u8; 8];
+            let (len, peer) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"hello");
+            assert_eq!(peer, client.local_addr().unwrap());
+        }
+
+        #[test]
+        fn ipv4() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let socket = UdpSocket::cap_bind(&mut cap_net, want).unwrap();
+            let bound = socket.local_addr().unwrap();
+            assert_eq!(want, bound);
+        }
+
+        #[test]
+        fn ipv6() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in6();
+            let socket = UdpSocket::cap_bind(&mut cap_net, want).unwrap();
+            let bound = socket.local_addr().unwrap();
+            assert_eq!(want, bound);
+        }
+    }
+
+    mod bind_dual_stack {
+        use super::*;
+
+        #[test]
+        fn unspecified() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let port = crate::next_port();
+            let socket =
+                UdpSocket::cap_bind_dual_stack(&mut cap_net, port).unwrap();
+            let local = socket.local_addr().unwrap();
+            assert_eq!(local, SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)));
+            let v6only =
+                getsockopt_int(&socket, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY);
+            assert_eq!(v6only, 0);
+        }
+    }
+
+    mod bind_ephemeral {
+        use super::*;
+
+        #[test]
+        fn ipv4() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let (socket, addr) = UdpSocket::cap_bind_ephemeral(
+                &mut cap_net,
+                Ipv4Addr::LOCALHOST,
+            )
+            .unwrap();
+            assert_eq!(addr.ip(), Ipv4Addr::LOCALHOST);
+            assert_ne!(addr.port(), 0);
+            assert_eq!(addr, socket.local_addr().unwrap());
+        }
+    }
+
+    mod builder {
+        use capsicum_net::std::UdpSocketBuilder;
+
+        use super::*;
+
+        #[test]
+        fn default() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let (socket, bound) = UdpSocketBuilder::new()
+                .bind_with_addr(&mut cap_net, want)
+                .unwrap();
+            assert_eq!(want, bound);
+            assert!(!getsockopt(&socket, ReuseAddr).unwrap());
+            assert!(!getsockopt(&socket, ReusePort).unwrap());
+        }
+
+        #[test]
+        fn reuse_addr() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let socket = UdpSocketBuilder::new()
+                .reuse_addr(true)
+                .bind(&mut cap_net, get_local_in())
+                .unwrap();
+            assert!(getsockopt(&socket, ReuseAddr).unwrap());
+        }
+
+        #[test]
+        fn reuse_port() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in6();
+            let mut builder = UdpSocketBuilder::new();
+            builder.reuse_port(true);
+            let socket1 = builder.bind(&mut cap_net, want).unwrap();
+            let socket2 = builder.bind(&mut cap_net, want).unwrap();
+            assert!(getsockopt(&socket1, ReusePort).unwrap());
+            assert_eq!(socket2.local_addr().unwrap(), want);
+        }
+    }
+
+    mod connect {
+        use super::*;
+
+        #[test]
+        fn ipv4() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+            socket.cap_connect(&mut cap_net, want).unwrap();
+            let connected = socket.peer_addr().unwrap();
+            assert_eq!(want, connected);
+        }
+
+        #[test]
+        fn ipv6() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in6();
+            let socket = UdpSocket::bind("[::0]:0").unwrap();
+            socket.cap_connect(&mut cap_net, want).unwrap();
+            let connected = socket.peer_addr().unwrap();
+            assert_eq!(want, connected);
+        }
+    }
+}
+
+mod unix_datagram {
+    use std::os::unix::net::UnixDatagram;
+
+    use capsicum_net::std::UnixDatagramExt;
+
+    use super::*;
+
+    mod bind {
+        use super::*;
+
+        #[test]
+        fn ok() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sock");
+            let socket = UnixDatagram::cap_bind(&mut cap_net, &path).unwrap();
+
+            // We can't use UnixDatagram::local_addr due to
+            // https://github.com/rust-lang/rust/issues/118925 , so use nix's
+            // gethostname instead.
+            let bound: nix::sys::socket::UnixAddr =
+                nix::sys::socket::getsockname(socket.as_raw_fd()).unwrap();
+            assert_eq!(path, bound.path().unwrap());
+        }
+    }
+
+    mod bind_with_mode {
+        use std::os::unix::fs::PermissionsExt;
+
+        use super::*;
+
+        #[test]
+        fn ok() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sock");
+            UnixDatagram::cap_bind_with_mode(&mut cap_net, &path, 0o600)
+                .unwrap();
+            let md = std::fs::metadata(&path).unwrap();
+            assert_eq!(md.permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    mod connect {
+        use super::*;
+
+        #[test]
+        fn ok() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sock");
+            let server = UnixDatagram::bind(&path).unwrap();
+            let socket = UnixDatagram::unbound().unwrap();
+            socket.cap_connect(&mut cap_net, &path).unwrap();
+            socket.send(b"hello").unwrap();
+            let mut buf = [0u8; 5];
+            server.recv(&mut buf).unwrap();
+            assert_eq!(&buf, b"hello");
+        }
+
+        #[test]
+        fn limited() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sock");
+            let other = dir.path().join("other");
+            let _server = UnixDatagram::bind(&path).unwrap();
+            let _other_server = UnixDatagram::bind(&other).unwrap();
+            let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+            limit.connect_unix(&path);
+            limit.limit().unwrap();
+
+            let socket = UnixDatagram::unbound().unwrap();
+            socket.cap_connect(&mut cap_net, &path).unwrap();
+            let socket = UnixDatagram::unbound().unwrap();
+            let err = socket.cap_connect(&mut cap_net, &other).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::ENOTCAPABLE));
+        }
+    }
+}
+
+mod unix_listener {
+    use std::os::unix::net::UnixListener;
+
+    use capsicum_net::std::UnixListenerExt;
+
+    use super::*;
+
+    mod bind {
+        use super::*;
+
+        #[test]
+        fn ok() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sock");
+            let socket = UnixListener::cap_bind(&mut cap_net, &path).unwrap();
+
+            // We can't use UnixListener::local_addr due to
+            // https://github.com/rust-lang/rust/issues/118925 , so use nix's
+            // gethostname instead.
+            let bound: nix::sys::socket::UnixAddr =
+                nix::sys::socket::getsockname(socket.as_raw_fd()).unwrap();
+            assert_eq!(path, bound.path().unwrap());
+            assert!(getsockopt(&socket, ListenQLimit).unwrap() > 0);
+        }
+
+        #[test]
+        fn limited() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sock");
+            let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+            limit.bind_unix(&path);
+            limit.limit().unwrap();
+
+            UnixListener::cap_bind(&mut cap_net, &path).unwrap();
+            let other = dir.path().join("other");
+            let err = UnixListener::cap_bind(&mut cap_net, other).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::ENOTCAPABLE));
+        }
+
+        /// A path too long for sockaddr_un is an error, not a panic
+        #[test]
+        fn too_long() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("s".repeat(200));
+            let err = UnixListener::cap_bind(&mut cap_net, path).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::ENAMETOOLONG));
+        }
+    }
+
+    mod bind_with_mode {
+        use std::os::unix::{fs::PermissionsExt, net::UnixStream};
+
+        use super::*;
+
+        #[test]
+        fn ok() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sock");
+            let listener =
+                UnixListener::cap_bind_with_mode(&mut cap_net, &path, 0o640)
+                    .unwrap();
+            let md = std::fs::metadata(&path).unwrap();
+            assert_eq!(md.permissions().mode() & 0o777, 0o640);
+            let _client = UnixStream::connect(&path).unwrap();
+            listener.accept().unwrap();
+        }
+    }
+
+    mod bind_with_backlog {
+        use capsicum_net::std::Backlog;
+
+        use super::*;
+
+        #[test]
+        fn explicit() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sock");
+            let socket = UnixListener::cap_bind_with_backlog(
+                &mut cap_net,
+                &path,
+                Backlog::Explicit(7),
+            )
+            .unwrap();
+            assert_eq!(getsockopt(&socket, ListenQLimit).unwrap(), 7);
+        }
+    }
+}
+
+mod unix_stream {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    use capsicum_net::std::UnixStreamExt;
+
+    use super::*;
+
+    mod connect {
+        use super::*;
+
+        #[test]
+        fn ok() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sock");
+            let _listener = UnixListener::bind(&path).unwrap();
+            let stream = UnixStream::cap_connect(&mut cap_net, &path).unwrap();
+            let peer: nix::sys::socket::UnixAddr =
+                nix::sys::socket::getpeername(stream.as_raw_fd()).unwrap();
+            assert_eq!(path, peer.path().unwrap());
+        }
+
+        #[test]
+        fn limited() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("sock");
+            let other = dir.path().join("other");
+            let _listener = UnixListener::bind(&path).unwrap();
+            let _other_listener = UnixListener::bind(&other).unwrap();
+            let mut limit = cap_net.limit(LimitFlags::CONNECT).unwrap();
+            limit.connect_unix(&path);
+            limit.limit().unwrap();
+
+            UnixStream::cap_connect(&mut cap_net, &path).unwrap();
+            let err = UnixStream::cap_connect(&mut cap_net, other).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::ENOTCAPABLE));
+        }
+    }
+}
+
+mod bind_many_unix {
+    use std::{
+        fs::File,
+        os::unix::{fs::PermissionsExt, net::UnixStream},
+    };
+
+    use capsicum_net::std::bind_many_unix;
+    use tempfile::TempDir;
+
+    #[test]
+    fn ok() {
+        let dir = TempDir::new().unwrap();
+        let dirf = File::open(dir.path()).unwrap();
+        let listeners =
+            bind_many_unix(&dirf, &["control.sock", "status.sock"], 0o600)
+                .unwrap();
+        assert_eq!(listeners.len(), 2);
+        for name in ["control.sock", "status.sock"] {
+            let path = dir.path().join(name);
+            let md = std::fs::metadata(&path).unwrap();
+            assert_eq!(md.permissions().mode() & 0o777, 0o600);
+            let _client = UnixStream::connect(&path).unwrap();
+            listeners[name].accept().unwrap();
+        }
+    }
+
+    #[test]
+    fn cleanup_on_error() {
+        let dir = TempDir::new().unwrap();
+        let dirf = File::open(dir.path()).unwrap();
+        File::create(dir.path().join("taken.sock")).unwrap();
+        let e = bind_many_unix(&dirf, &["first.sock", "taken.sock"], 0o600)
+            .unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EADDRINUSE));
+        assert!(!dir.path().join("first.sock").exists());
+    }
+}
+
+mod remove_stale_unix_at {
+    use std::{
+        fs::File,
+        os::unix::net::{UnixDatagram, UnixListener},
+    };
+
+    use capsicum_net::std::{remove_stale_unix_at, UnixListenerExt};
+
+    use super::*;
+
+    #[test]
+    fn stale() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        let dir = TempDir::new().unwrap();
+        let dirf = File::open(dir.path()).unwrap();
+        let path = dir.path().join("sock");
+        drop(UnixListener::bind(&path).unwrap());
+        let e = UnixListener::cap_bind(&mut cap_net, &path).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EADDRINUSE));
+
+        assert!(remove_stale_unix_at(&dirf, "sock").unwrap());
+        UnixListener::cap_bind(&mut cap_net, &path).unwrap();
+    }
+
+    #[test]
+    fn stale_datagram() {
+        let dir = TempDir::new().unwrap();
+        let dirf = File::open(dir.path()).unwrap();
+        drop(UnixDatagram::bind(dir.path().join("sock")).unwrap());
+        assert!(remove_stale_unix_at(&dirf, "sock").unwrap());
+        assert!(!dir.path().join("sock").exists());
+    }
+
+    #[test]
+    fn live() {
+        let dir = TempDir::new().unwrap();
+        let dirf = File::open(dir.path()).unwrap();
+        let _listener = UnixListener::bind(dir.path().join("sock")).unwrap();
+        let e = remove_stale_unix_at(&dirf, "sock").unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EADDRINUSE));
+        assert!(dir.path().join("sock").exists());
+    }
+
+    // A listener whose accept backlog is full refuses connections, but it
+    // isn't stale.
+    #[test]
+    fn busy() {
+        use std::{os::unix::net::UnixStream, thread, time::Duration};
+
+        use nix::sys::socket::{
+            accept,
+            bind,
+            listen,
+            socket,
+            AddressFamily,
+            Backlog,
+            SockFlag,
+            SockType,
+            UnixAddr,
+        };
+
+        let dir = TempDir::new().unwrap();
+        let dirf = File::open(dir.path()).unwrap();
+        let path = dir.path().join("sock");
+        let listener = socket(
+            AddressFamily::Unix,
+            SockType::Stream,
+            SockFlag::empty(),
+            None,
+        )
+        .unwrap();
+        bind(listener.as_raw_fd(), &UnixAddr::new(&path).unwrap()).unwrap();
+        listen(&listener, Backlog::new(0).unwrap()).unwrap();
+        // Fill the backlog
+        let _client = UnixStream::connect(&path).unwrap();
+        let acceptor = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(30));
+            accept(listener.as_raw_fd()).unwrap();
+            listener
+        });
+
+        let e = remove_stale_unix_at(&dirf, "sock").unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EADDRINUSE));
+        assert!(path.exists());
+        acceptor.join().unwrap();
+    }
+
+    #[test]
+    fn missing() {
+        let dir = TempDir::new().unwrap();
+        let dirf = File::open(dir.path()).unwrap();
+        assert!(!remove_stale_unix_at(&dirf, "sock").unwrap());
+    }
+
+    #[test]
+    fn not_a_socket() {
+        let dir = TempDir::new().unwrap();
+        let dirf = File::open(dir.path()).unwrap();
+        File::create(dir.path().join("file")).unwrap();
+        let e = remove_stale_unix_at(&dirf, "file").unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOTSOCK));
+        assert!(dir.path().join("file").exists());
+    }
+}
+
+mod net_limited {
+    use std::net::{TcpListener, TcpStream};
+
+    use capsicum_net::std::{TcpListenerExt, TcpStreamExt};
+
+    use super::*;
+
+    #[test]
+    fn bind() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper
+                .net_limited(LimitFlags::BIND, &[get_local_in()])
+                .unwrap()
+        };
+        let e =
+            TcpListener::cap_bind(&mut cap_net, get_local_in()).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+    }
+
+    #[test]
+    fn bind_and_connect() {
+        let want = get_local_in();
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper
+                .net_limited(LimitFlags::BIND | LimitFlags::CONNECT, &[want])
+                .unwrap()
+        };
+        let _listener = TcpListener::cap_bind(&mut cap_net, want).unwrap();
+        TcpStream::cap_connect(&mut cap_net, want).unwrap();
+        cap_net.resolve("localhost", 80).unwrap_err();
+    }
+
+    // With no addresses, cap_net would allow any
+    #[test]
+    fn no_addrs() {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        for flags in [LimitFlags::BIND, LimitFlags::CONNECT] {
+            let e = casper.net_limited(flags, &[]).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    // Other operations don't take addresses
+    #[test]
+    fn no_addrs_name2addr() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net_limited(LimitFlags::NAME2ADDR, &[]).unwrap()
+        };
+        cap_net.resolve("localhost", 80).unwrap();
     }
 }
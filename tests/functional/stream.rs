@@ -0,0 +1,42 @@
+// vim: tw=80
+use capsicum::{FileRights, Right};
+use capsicum_net::{stream::Incoming, CasperExt};
+use futures::StreamExt;
+use tempfile::TempDir;
+use tokio::net::{TcpStream, UnixStream};
+
+use crate::{std::get_local_in, CASPER};
+
+#[tokio::test]
+async fn tcp() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let addr = get_local_in();
+    let mut incoming = Incoming::cap_bind_tcp(&mut cap_net, addr).unwrap();
+    let client = TcpStream::connect(addr).await.unwrap();
+    let stream = incoming.next().await.unwrap().unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), client.local_addr().unwrap());
+    let rights = FileRights::from_file(&stream).unwrap();
+    assert!(rights.is_set(Right::Event));
+    assert!(!rights.is_set(Right::Getpeername));
+}
+
+#[tokio::test]
+async fn unix() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("sock");
+    let mut incoming = Incoming::cap_bind_unix(&mut cap_net, &path).unwrap();
+    let _client = UnixStream::connect(&path).await.unwrap();
+    let stream = incoming.next().await.unwrap().unwrap();
+    let rights = FileRights::from_file(&stream).unwrap();
+    assert!(rights.is_set(Right::Read));
+    assert!(!rights.is_set(Right::Connect));
+}
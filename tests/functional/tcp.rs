@@ -0,0 +1,163 @@
+// vim: tw=80
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream, UdpSocket},
+    thread,
+    time::Duration,
+};
+
+use capsicum_net::{std::TcpStreamExt, tcp::tcp_info, CasperExt};
+
+use crate::{std::get_local_in, CASPER};
+
+/// From netinet/tcp_fsm.h
+const TCPS_ESTABLISHED: u8 = 4;
+/// From netinet/tcp.h
+const TCPI_OPT_WSCALE: u8 = 0x04;
+/// From netinet/tcp.h
+const TCP_MAX_WINSHIFT: u8 = 14;
+
+#[test]
+fn established() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let want = get_local_in();
+    let _server_socket = TcpListener::bind(want).unwrap();
+    let client_socket = TcpStream::cap_connect(&mut cap_net, want).unwrap();
+    let info = tcp_info(&client_socket).unwrap();
+    assert_eq!(info.state, TCPS_ESTABLISHED);
+    assert!(info.snd_mss > 0);
+    assert!(info.snd_cwnd > 0);
+}
+
+/// Both window scales are decoded from the same packed byte
+#[test]
+fn wscale() {
+    let want = get_local_in();
+    let listener = TcpListener::bind(want).unwrap();
+    let client = TcpStream::connect(want).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    for sock in [&client, &server] {
+        let info = tcp_info(sock).unwrap();
+        assert!(info.options & TCPI_OPT_WSCALE != 0, "{info:?}");
+        assert!(info.snd_wscale <= TCP_MAX_WINSHIFT, "{info:?}");
+        assert!(info.rcv_wscale <= TCP_MAX_WINSHIFT, "{info:?}");
+        // The default socket buffers are too big for an unscaled window
+        assert!(info.rcv_wscale > 0, "{info:?}");
+    }
+    // Over loopback, each end's send scale is the other's receive scale
+    let client_info = tcp_info(&client).unwrap();
+    let server_info = tcp_info(&server).unwrap();
+    assert_eq!(client_info.snd_wscale, server_info.rcv_wscale);
+    assert_eq!(client_info.rcv_wscale, server_info.snd_wscale);
+}
+
+/// last_data_recv is reported on the same scale as the other durations
+#[test]
+fn last_data_recv() {
+    let want = get_local_in();
+    let listener = TcpListener::bind(want).unwrap();
+    let mut client = TcpStream::connect(want).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+    client.write_all(b"x").unwrap();
+    let mut buf = [0u8; 1];
+    server.read_exact(&mut buf).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let info = tcp_info(&server).unwrap();
+    assert!(info.last_data_recv >= Duration::from_millis(50), "{info:?}");
+    assert!(info.last_data_recv < Duration::from_secs(10), "{info:?}");
+}
+
+#[test]
+fn not_tcp() {
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    tcp_info(&sock).unwrap_err();
+}
+
+mod send_file {
+    use std::os::fd::OwnedFd;
+
+    use capsicum_net::tcp::send_file;
+
+    use super::*;
+
+    fn pair() -> (TcpStream, TcpStream) {
+        let addr = get_local_in();
+        let listener = TcpListener::bind(addr).unwrap();
+        let client = TcpStream::connect(addr).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    fn contents() -> Vec<u8> {
+        (0..200_000u32).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn whole_file() {
+        let data = contents();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&data).unwrap();
+        let (mut client, server) = pair();
+        let reader = std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            client.read_to_end(&mut buf).unwrap();
+            buf
+        });
+        let sent = send_file(&server, &file, 0, None).unwrap();
+        assert_eq!(sent, data.len() as u64);
+        drop(server);
+        assert_eq!(reader.join().unwrap(), data);
+    }
+
+    #[test]
+    fn range() {
+        let data = contents();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&data).unwrap();
+        let (mut client, server) = pair();
+        let sent = send_file(&server, &file, 1000, Some(5000)).unwrap();
+        assert_eq!(sent, 5000);
+        drop(server);
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, &data[1000..6000]);
+    }
+
+    /// sendfile can't read from a pipe, so this exercises the fallback.
+    #[test]
+    fn pipe() {
+        let (rd, wr): (OwnedFd, OwnedFd) = nix::unistd::pipe().unwrap();
+        let mut wr = std::fs::File::from(wr);
+        wr.write_all(b"from a pipe").unwrap();
+        drop(wr);
+        let (mut client, server) = pair();
+        let sent = send_file(&server, &rd, 0, None).unwrap();
+        assert_eq!(sent, 11);
+        drop(server);
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"from a pipe");
+    }
+
+    /// A full socket buffer ends a fallback transfer with a short count.
+    #[test]
+    fn pipe_nonblocking() {
+        let (rd, wr): (OwnedFd, OwnedFd) = nix::unistd::pipe().unwrap();
+        let mut wr = std::fs::File::from(wr);
+        let writer = std::thread::spawn(move || {
+            // Fails once the reader gives up
+            let _ = wr.write_all(&vec![0u8; 16 << 20]);
+        });
+        let (_client, server) = pair();
+        server.set_nonblocking(true).unwrap();
+        let sent = send_file(&server, &rd, 0, None).unwrap();
+        assert!(sent > 0);
+        assert!(sent < 16 << 20, "{sent}");
+        drop(rd);
+        writer.join().unwrap();
+    }
+}
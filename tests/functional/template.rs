@@ -0,0 +1,196 @@
+// vim: tw=80
+use std::net::{TcpListener, TcpStream};
+
+use capsicum_net::{
+    limits,
+    sockaddr,
+    std::{TcpListenerExt, TcpStreamExt},
+    template::LimitTemplate,
+    CasperExt,
+    LimitFlags,
+};
+
+use crate::{std::get_local_in, CASPER};
+
+#[test]
+fn apply_many() {
+    let bind_ok = get_local_in();
+    let mut template = LimitTemplate::new(LimitFlags::BIND);
+    template.bind_std(bind_ok);
+
+    let mut agents = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        [casper.net().unwrap(), casper.net().unwrap()]
+    };
+    for agent in agents.iter_mut() {
+        template.apply(agent).unwrap();
+    }
+    for agent in agents.iter_mut() {
+        let e = TcpListener::cap_bind(agent, get_local_in()).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+        agent.resolve("localhost", 80).unwrap_err();
+    }
+    TcpListener::cap_bind(&mut agents[0], bind_ok).unwrap();
+}
+
+#[test]
+fn connect() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let target = get_local_in();
+    let other = get_local_in();
+    let _target_listener = TcpListener::bind(target).unwrap();
+    let _other_listener = TcpListener::bind(other).unwrap();
+    let mut template = LimitTemplate::new(LimitFlags::CONNECT);
+    template.connect_std(target);
+    assert_eq!(template.flags(), LimitFlags::CONNECT);
+    template.clone().apply(&mut cap_net).unwrap();
+
+    TcpStream::cap_connect(&mut cap_net, target).unwrap();
+    let e = TcpStream::cap_connect(&mut cap_net, other).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+}
+
+#[test]
+fn name2addr() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let mut template = LimitTemplate::new(LimitFlags::NAME2ADDR);
+    template.name2addr("localhost", None);
+    template.apply(&mut cap_net).unwrap();
+
+    cap_net.resolve("localhost", 80).unwrap();
+    cap_net.resolve("127.0.0.1", 80).unwrap_err();
+}
+
+#[test]
+fn name2addr_nul() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let mut template = LimitTemplate::new(LimitFlags::NAME2ADDR);
+    template.name2addr("local\0host", None);
+    let e = template.apply(&mut cap_net).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+mod limits_macro {
+    use super::*;
+
+    #[test]
+    fn addresses() {
+        let template = limits! {
+            bind: ["127.0.0.1:80", "[::1]:80"],
+            connect: ["10.0.0.1:5432",],
+        };
+        assert_eq!(template.flags(), LimitFlags::BIND | LimitFlags::CONNECT);
+        let mut want = LimitTemplate::new(template.flags());
+        want.bind_std(([127, 0, 0, 1], 80))
+            .bind_std((std::net::Ipv6Addr::LOCALHOST, 80))
+            .connect_std(([10, 0, 0, 1], 5432));
+        assert_eq!(template, want);
+    }
+
+    #[test]
+    fn empty() {
+        let template = limits! { connect: [] };
+        assert_eq!(template, LimitTemplate::new(LimitFlags::CONNECT));
+        let template = limits! {};
+        assert_eq!(template.flags(), LimitFlags::empty());
+    }
+
+    #[test]
+    fn apply() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        limits! { bind: ["127.0.0.1:0"] }
+            .apply(&mut cap_net)
+            .unwrap();
+        TcpListener::cap_bind(&mut cap_net, "127.0.0.1:0").unwrap();
+        let e =
+            TcpListener::cap_bind(&mut cap_net, get_local_in()).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOTCAPABLE));
+    }
+}
+
+mod applied_limits {
+    use super::*;
+
+    #[test]
+    fn unlimited() {
+        let cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+        assert!(cap_net.applied_limits().is_none());
+    }
+
+    #[test]
+    fn recorded() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let mut limit = cap_net
+            .limit(LimitFlags::CONNECT | LimitFlags::NAME2ADDR)
+            .unwrap();
+        limit.connect_std(want).name2addr("localhost", Some(80));
+        limit.limit().unwrap();
+
+        let applied = cap_net.applied_limits().unwrap();
+        assert_eq!(applied.flags, LimitFlags::CONNECT | LimitFlags::NAME2ADDR);
+        assert!(applied.bind.is_empty());
+        assert_eq!(applied.connect, vec![sockaddr::from_std(want)]);
+        assert_eq!(applied.name2addr, vec![("localhost".to_owned(), Some(80))]);
+    }
+
+    /// A limit that fails to apply isn't recorded
+    #[test]
+    fn failed() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
+        cap_net
+            .limit(LimitFlags::BIND)
+            .unwrap()
+            .limit()
+            .unwrap_err();
+        assert_eq!(
+            cap_net.applied_limits().unwrap().flags,
+            LimitFlags::CONNECT
+        );
+    }
+
+    /// Another agent can be given the same limits
+    #[test]
+    fn to_template() {
+        let (mut cap_net, mut other) = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            (casper.net().unwrap(), casper.net().unwrap())
+        };
+
+        let mut limit = cap_net.limit(LimitFlags::BIND).unwrap();
+        limit.bind_std(get_local_in());
+        limit.limit().unwrap();
+
+        let template =
+            LimitTemplate::from(cap_net.applied_limits().unwrap().clone());
+        template.apply(&mut other).unwrap();
+        assert_eq!(other.applied_limits(), cap_net.applied_limits());
+    }
+}
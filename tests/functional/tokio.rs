@@ -9,6 +9,136 @@ use crate::{
     CASPER,
 };
 
+mod cap_connect {
+    use std::{
+        io,
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use capsicum_net::tokio::{cap_connect, cap_connect_with, Cancellation};
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn ok() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let addr = get_local_in();
+        let listener = TcpListener::bind(addr).unwrap();
+        let stream = cap_connect(&mut cap_net, addr).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn cancelled() {
+        let cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            Arc::new(Mutex::new(casper.net().unwrap()))
+        };
+
+        let addr = get_local_in();
+        let _listener = TcpListener::bind(addr).unwrap();
+        let token = CancellationToken::new();
+        token.cancel();
+        let cancel = Cancellation::new().token(token);
+        let err = cap_connect_with(&cap_net, addr, &cancel).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    }
+
+    #[tokio::test]
+    async fn expired() {
+        let cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            Arc::new(Mutex::new(casper.net().unwrap()))
+        };
+
+        let addr = get_local_in();
+        let _listener = TcpListener::bind(addr).unwrap();
+        let cancel = Cancellation::new().timeout(Duration::ZERO);
+        let err = cap_connect_with(&cap_net, addr, &cancel).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    // Cancellation interrupts a hostname lookup that is still in progress
+    #[tokio::test]
+    // The lock is held deliberately, to stall the lookup
+    #[allow(clippy::await_holding_lock)]
+    async fn cancelled_while_resolving() {
+        let cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            Arc::new(Mutex::new(casper.net().unwrap()))
+        };
+
+        let guard = cap_net.lock().unwrap();
+        let token = CancellationToken::new();
+        let cancel = Cancellation::new().token(token.clone());
+        let (res, ()) = tokio::join!(
+            cap_connect_with(&cap_net, "localhost:80", &cancel),
+            async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                token.cancel();
+            }
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        drop(guard);
+    }
+}
+
+mod lookup_addr {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::{Arc, Mutex},
+    };
+
+    use capsicum_net::tokio::lookup_addr;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn localhost() {
+        let cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            Arc::new(Mutex::new(casper.net().unwrap()))
+        };
+
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let name = lookup_addr(&cap_net, ip).await.unwrap();
+        assert!(name.starts_with("localhost"), "{name}");
+    }
+}
+
+mod resolved_addrs {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use capsicum_net::tokio::ResolvedAddrs;
+    use tokio::net::UdpSocket;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn connect() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let addrs =
+            ResolvedAddrs::resolve(&mut cap_net, "127.0.0.1", 5353).unwrap();
+        let want = SocketAddr::from((Ipv4Addr::LOCALHOST, 5353));
+        assert_eq!(addrs.first(), want);
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(addrs.as_slice()).await.unwrap();
+        assert_eq!(socket.peer_addr().unwrap(), want);
+    }
+}
+
 mod tcp_socket {
     use capsicum_net::tokio::TcpSocketExt;
     use tokio::net::TcpSocket;
@@ -59,6 +189,86 @@ mod tcp_socket {
             assert_eq!(want, bound);
         }
     }
+
+    mod bind_ephemeral {
+        use std::net::Ipv4Addr;
+
+        use super::*;
+
+        #[tokio::test]
+        async fn ipv4() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let socket = TcpSocket::new_v4().unwrap();
+            let addr = socket
+                .cap_bind_ephemeral(&mut cap_net, Ipv4Addr::LOCALHOST)
+                .unwrap();
+            assert_eq!(addr.ip(), Ipv4Addr::LOCALHOST);
+            assert_ne!(addr.port(), 0);
+            assert_eq!(addr, socket.local_addr().unwrap());
+        }
+    }
+}
+
+mod tcp_listener {
+    use capsicum::{FileRights, Right};
+    use capsicum_net::{
+        std::default_stream_rights,
+        tokio::{
+            accept_limited,
+            accept_limited_with,
+            Cancellation,
+            TcpSocketExt,
+        },
+    };
+    use tokio::net::{TcpSocket, TcpStream};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn accept_limited_ok() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.cap_bind(&mut cap_net, want).unwrap();
+        let listener = socket.listen(16).unwrap();
+        let client = TcpStream::connect(want).await.unwrap();
+        let (stream, peer) =
+            accept_limited(&listener, &default_stream_rights())
+                .await
+                .unwrap();
+        assert_eq!(peer, client.local_addr().unwrap());
+        let rights = FileRights::from_file(&stream).unwrap();
+        assert!(rights.is_set(Right::Event));
+        assert!(!rights.is_set(Right::Getpeername));
+    }
+
+    #[tokio::test]
+    async fn accept_limited_timeout() {
+        let mut cap_net = {
+            let mut casper = CASPER.get().unwrap().lock().unwrap();
+            casper.net().unwrap()
+        };
+
+        let want = get_local_in();
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.cap_bind(&mut cap_net, want).unwrap();
+        let listener = socket.listen(16).unwrap();
+        let cancel =
+            Cancellation::new().timeout(std::time::Duration::from_millis(50));
+        let err =
+            accept_limited_with(&listener, &default_stream_rights(), &cancel)
+                .await
+                .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
 }
 
 mod udp_socket {
@@ -96,6 +306,52 @@ mod udp_socket {
             assert_eq!(want, bound);
         }
     }
+
+    mod bind_ephemeral {
+        use std::net::Ipv6Addr;
+
+        use super::*;
+
+        #[tokio::test]
+        async fn ipv6() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let (socket, addr) = UdpSocket::cap_bind_ephemeral(
+                &mut cap_net,
+                Ipv6Addr::LOCALHOST,
+            )
+            .unwrap();
+            assert_eq!(addr.ip(), Ipv6Addr::LOCALHOST);
+            assert_ne!(addr.port(), 0);
+            assert_eq!(addr, socket.local_addr().unwrap());
+        }
+    }
+
+    mod bind_with {
+        use capsicum_net::std::UdpSocketBuilder;
+        use nix::sys::socket::{getsockopt, sockopt::ReusePort};
+
+        use super::*;
+
+        #[tokio::test]
+        async fn reuse_port() {
+            let mut cap_net = {
+                let mut casper = CASPER.get().unwrap().lock().unwrap();
+                casper.net().unwrap()
+            };
+
+            let want = get_local_in();
+            let mut builder = UdpSocketBuilder::new();
+            builder.reuse_port(true);
+            let socket =
+                UdpSocket::cap_bind_with(&mut cap_net, want, &builder).unwrap();
+            assert_eq!(want, socket.local_addr().unwrap());
+            assert!(getsockopt(&socket, ReusePort).unwrap());
+        }
+    }
 }
 
 mod unix_datagram {
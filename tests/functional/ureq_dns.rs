@@ -0,0 +1,42 @@
+// vim: tw=80
+use capsicum_net::{ureq::CapResolver, CasperExt, LimitFlags};
+use ureq::Resolver;
+
+use crate::CASPER;
+
+#[test]
+fn localhost() {
+    let cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let resolver = CapResolver::new(cap_net);
+    let addrs = resolver.resolve("localhost:80").unwrap();
+    assert!(!addrs.is_empty());
+    assert!(addrs.iter().all(|a| a.ip().is_loopback() && a.port() == 80));
+}
+
+#[test]
+fn ipv6_literal() {
+    let cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let resolver = CapResolver::new(cap_net);
+    let addrs = resolver.resolve("[::1]:443").unwrap();
+    assert_eq!(addrs, vec!["[::1]:443".parse().unwrap()]);
+}
+
+#[test]
+fn limited() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    cap_net.limit(LimitFlags::CONNECT).unwrap().limit().unwrap();
+    let resolver = CapResolver::new(cap_net);
+    resolver.resolve("localhost:80").unwrap_err();
+}
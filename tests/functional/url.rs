@@ -0,0 +1,74 @@
+// vim: tw=80
+use std::{io, net::TcpListener};
+
+use capsicum_net::{
+    url::{cap_connect_url, default_port},
+    CasperExt,
+};
+use url::Url;
+
+use crate::{
+    std::{get_local_in, get_local_in6},
+    CASPER,
+};
+
+#[test]
+fn default_ports() {
+    assert_eq!(default_port("https"), Some(443));
+    assert_eq!(default_port("smtp"), Some(25));
+    assert_eq!(default_port("imaps"), Some(993));
+    assert_eq!(default_port("nonesuch"), None);
+}
+
+#[test]
+fn hostname() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let want = get_local_in();
+    let _server_socket = TcpListener::bind(want).unwrap();
+    let url =
+        Url::parse(&format!("http://localhost:{}/", want.port())).unwrap();
+    let stream = cap_connect_url(&mut cap_net, &url).unwrap();
+    assert_eq!(want, stream.peer_addr().unwrap());
+}
+
+#[test]
+fn ipv6() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let want = get_local_in6();
+    let _server_socket = TcpListener::bind(want).unwrap();
+    let url = Url::parse(&format!("smtp://{want}")).unwrap();
+    let stream = cap_connect_url(&mut cap_net, &url).unwrap();
+    assert_eq!(want, stream.peer_addr().unwrap());
+}
+
+#[test]
+fn no_port() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let url = Url::parse("nonesuch://localhost/").unwrap();
+    let e = cap_connect_url(&mut cap_net, &url).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn no_host() {
+    let mut cap_net = {
+        let mut casper = CASPER.get().unwrap().lock().unwrap();
+        casper.net().unwrap()
+    };
+
+    let url = Url::parse("mailto:root@localhost").unwrap();
+    let e = cap_connect_url(&mut cap_net, &url).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+}